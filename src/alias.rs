@@ -0,0 +1,524 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Alias analysis of the memory accesses of the canonical statements of a function, used to keep
+//! the values loaded or stored in temporaries instead of loading them again.
+//!
+//! Two accesses cannot overlap when:
+//!  * they are at different constant offsets from the same address, like two fields of a record or
+//!    two elements of an array at constant indices;
+//!  * they are in the static data of different labels;
+//!  * one is a slot of the frame of the function and the other is in the static data, or behind a
+//!    pointer while the address of the frame is only used to access its slots.
+
+use std::collections::{HashMap, HashSet};
+
+use frame::Frame;
+use ir::{BinOp, Exp, Statement, _Statement};
+use temp::{Label, Temp};
+
+/// Memory read or written by a `Mem` or a `MemByte`: `size` bytes at `offset` from `base`.
+struct Access<'a> {
+    base: Base<'a>,
+    offset: i64,
+    size: i64,
+}
+
+#[derive(PartialEq)]
+enum Base<'a> {
+    /// The frame of the function.
+    Frame,
+    /// Address computed by any other expression, like a pointer to a record or an array.
+    Pointer(&'a Exp),
+    /// The static data at a label.
+    Static(&'a Label),
+}
+
+struct AliasAnalysis {
+    fp: Temp,
+    /// Temporaries assigned once to an address in the frame, at this offset from the frame pointer.
+    frame_temps: HashMap<Temp, i64>,
+    /// Whether the frame can only be accessed through the frame pointer, so that the pointers
+    /// cannot point to its slots.
+    private_frame: bool,
+    word_size: i64,
+}
+
+impl AliasAnalysis {
+    fn new(fp: Temp, word_size: i64, statements: &[Statement]) -> Self {
+        let mut definitions = HashMap::new();
+        for statement in statements {
+            if let _Statement::Move(Exp::Temp(temp), ref source) = statement.statement {
+                let (base, offset) = split(source);
+                let frame_offset = (*base == Exp::Temp(fp)).then_some(offset);
+                definitions.entry(temp).or_insert_with(Vec::new).push(frame_offset);
+            }
+        }
+        let frame_temps = definitions.into_iter()
+            .filter_map(|(temp, offsets)|
+                match *offsets.as_slice() {
+                    [Some(offset)] => Some((temp, offset)),
+                    _ => None,
+                })
+            .collect();
+        let mut analysis = Self {
+            fp,
+            frame_temps,
+            private_frame: false,
+            word_size,
+        };
+        analysis.private_frame = !statements.iter().any(|statement| analysis.leaks_frame(statement));
+        analysis
+    }
+
+    fn access<'a>(&self, memory: &'a Exp) -> Option<Access<'a>> {
+        let (address, size) =
+            match *memory {
+                Exp::Mem(ref address) => (address, self.word_size),
+                Exp::MemByte(ref address) => (address, 1),
+                _ => return None,
+            };
+        let (base, offset) = split(address);
+        let (base, offset) =
+            match *base {
+                Exp::Name(ref label) => (Base::Static(label), offset),
+                Exp::Temp(temp) if temp == self.fp => (Base::Frame, offset),
+                Exp::Temp(temp) =>
+                    match self.frame_temps.get(&temp) {
+                        Some(&frame_offset) => (Base::Frame, frame_offset.wrapping_add(offset)),
+                        None => (Base::Pointer(base), offset),
+                    },
+                _ => (Base::Pointer(base), offset),
+            };
+        Some(Access {
+            base,
+            offset,
+            size,
+        })
+    }
+
+    /// Whether writing the memory `store` can change the value of the expression.
+    fn clobbers(&self, store: &Exp, expr: &Exp) -> bool {
+        match self.access(store) {
+            Some(store) => self.reads(expr, &store),
+            None => true,
+        }
+    }
+
+    fn is_frame_address(&self, temp: Temp) -> bool {
+        temp == self.fp || self.frame_temps.contains_key(&temp)
+    }
+
+    /// Whether the expression uses the address of the frame other than to access one of its slots.
+    fn leaks_frame_address(&self, expr: &Exp) -> bool {
+        match *expr {
+            Exp::Const(_) | Exp::Error | Exp::Name(_) => false,
+            Exp::Temp(temp) => self.is_frame_address(temp),
+            Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+                self.leaks_frame_address(left) || self.leaks_frame_address(right),
+            Exp::Mem(ref address) | Exp::MemByte(ref address) =>
+                match *split(address).0 {
+                    Exp::Temp(temp) if self.is_frame_address(temp) => false,
+                    ref base => self.leaks_frame_address(base),
+                },
+            Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+                self.leaks_frame_address(left) || self.leaks_frame_address(right) ||
+                    self.leaks_frame_address(if_true) || self.leaks_frame_address(if_false),
+            Exp::Call { ref arguments, ref function_expr, .. } =>
+                self.leaks_frame_address(function_expr) ||
+                    arguments.iter().any(|argument| self.leaks_frame_address(argument)),
+            Exp::ExpSequence(_, _) => true,
+        }
+    }
+
+    fn leaks_frame(&self, statement: &Statement) -> bool {
+        match statement.statement {
+            _Statement::Move(Exp::Temp(temp), ref source) =>
+                temp == self.fp || !self.frame_temps.contains_key(&temp) && self.leaks_frame_address(source),
+            _Statement::Move(ref destination, ref source) =>
+                self.leaks_frame_address(destination) || self.leaks_frame_address(source),
+            _Statement::Exp(ref expr) | _Statement::Jump(ref expr, _) => self.leaks_frame_address(expr),
+            _Statement::CondJump { ref left, ref right, .. } =>
+                self.leaks_frame_address(left) || self.leaks_frame_address(right),
+            _Statement::Sequence(ref statement1, ref statement2) =>
+                self.leaks_frame(statement1) || self.leaks_frame(statement2),
+            _Statement::Label(_) | _Statement::Position(_) => false,
+        }
+    }
+
+    fn may_alias(&self, access1: &Access, access2: &Access) -> bool {
+        if access1.base == access2.base {
+            return access1.offset < access2.offset.wrapping_add(access2.size) &&
+                access2.offset < access1.offset.wrapping_add(access1.size);
+        }
+        match (&access1.base, &access2.base) {
+            (&Base::Frame, &Base::Static(_)) | (&Base::Static(_), &Base::Frame) | (&Base::Static(_), &Base::Static(_)) =>
+                false,
+            (&Base::Frame, &Base::Pointer(_)) | (&Base::Pointer(_), &Base::Frame) => !self.private_frame,
+            _ => true,
+        }
+    }
+
+    /// Whether the expression reads memory which could be written by `store`.
+    fn reads(&self, expr: &Exp, store: &Access) -> bool {
+        match *expr {
+            Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => false,
+            Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+                self.reads(left, store) || self.reads(right, store),
+            Exp::Mem(ref address) | Exp::MemByte(ref address) =>
+                self.access(expr).is_some_and(|access| self.may_alias(&access, store)) || self.reads(address, store),
+            Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+                self.reads(left, store) || self.reads(right, store) || self.reads(if_true, store) ||
+                    self.reads(if_false, store),
+            Exp::Call { .. } | Exp::ExpSequence(_, _) => true,
+        }
+    }
+}
+
+/// Replace the loads of memory whose value is already in a temporary: it was loaded or stored by a
+/// previous statement of the same basic block, and no store or call to a function which is not one
+/// of `pure_functions` in between could change it.
+pub fn eliminate_redundant_loads<F: Frame>(statements: Vec<Statement>, pure_functions: &HashSet<Label>)
+    -> Vec<Statement>
+{
+    let analysis = AliasAnalysis::new(F::fp().temp(), F::WORD_SIZE, &statements);
+    let registers = F::temp_map();
+    // The instructions can write the machine registers without a move in the IR.
+    let reusable = |temp: Temp| !registers.contains_key(&temp);
+    let cacheable = |memory: &Exp| memory.temps().into_iter().all(|temp| temp == F::fp().temp() || reusable(temp));
+    // Memory whose value is in a temporary.
+    let mut available: Vec<(Exp, Temp)> = vec![];
+    let mut result = Vec::with_capacity(statements.len());
+    for statement in statements {
+        let stack_var = statement.stack_var;
+        let statement =
+            match statement.statement {
+                _Statement::Move(Exp::Temp(temp), source) => {
+                    let source = replace_loads(source, &available);
+                    if calls_impure_function(&source, pure_functions) {
+                        available.clear();
+                    }
+                    available.retain(|&(ref memory, value)| value != temp && !memory.temps().contains(&temp));
+                    if source == Exp::Temp(temp) && stack_var.is_none() {
+                        continue;
+                    }
+                    if let Exp::Mem(_) | Exp::MemByte(_) = source {
+                        if reusable(temp) && cacheable(&source) && !source.temps().contains(&temp) {
+                            available.push((source.clone(), temp));
+                        }
+                    }
+                    _Statement::Move(Exp::Temp(temp), source)
+                },
+                _Statement::Move(destination @ (Exp::Mem(_) | Exp::MemByte(_)), source) => {
+                    let destination = replace_address_loads(destination, &available);
+                    let source = replace_loads(source, &available);
+                    if calls_impure_function(&destination, pure_functions) ||
+                        calls_impure_function(&source, pure_functions)
+                    {
+                        available.clear();
+                    }
+                    available.retain(|load| !analysis.clobbers(&destination, &load.0));
+                    // A byte store truncates the value, so only the stored words can be reused.
+                    if let (&Exp::Mem(_), &Exp::Temp(value)) = (&destination, &source) {
+                        if reusable(value) && cacheable(&destination) {
+                            available.push((destination.clone(), value));
+                        }
+                    }
+                    _Statement::Move(destination, source)
+                },
+                _Statement::Exp(expr) => {
+                    let expr = replace_loads(expr, &available);
+                    if calls_impure_function(&expr, pure_functions) {
+                        available.clear();
+                    }
+                    _Statement::Exp(expr)
+                },
+                _Statement::Jump(expr, labels) => _Statement::Jump(replace_loads(expr, &available), labels),
+                _Statement::CondJump { op, left, right, true_label, false_label } => _Statement::CondJump {
+                    op,
+                    left: replace_loads(left, &available),
+                    right: replace_loads(right, &available),
+                    true_label,
+                    false_label,
+                },
+                // Other blocks can jump to a label, with other values in memory.
+                statement @ (_Statement::Label(_) | _Statement::Move(_, _) | _Statement::Sequence(_, _)) => {
+                    available.clear();
+                    statement
+                },
+                statement @ _Statement::Position(_) => statement,
+            };
+        result.push(Statement {
+            stack_var,
+            statement,
+        });
+    }
+    result
+}
+
+/// Whether the expression calls a function which is not one of `pure_functions`, which could write
+/// memory or allocate, letting the collector move the objects.
+fn calls_impure_function(expr: &Exp, pure_functions: &HashSet<Label>) -> bool {
+    match *expr {
+        Exp::Call { ref arguments, ref function_expr, .. } =>
+            !matches!(**function_expr, Exp::Name(ref function) if pure_functions.contains(function)) ||
+                arguments.iter().any(|argument| calls_impure_function(argument, pure_functions)),
+        Exp::ExpSequence(_, _) => true,
+        Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => false,
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+            calls_impure_function(left, pure_functions) || calls_impure_function(right, pure_functions),
+        Exp::Mem(ref expr) | Exp::MemByte(ref expr) => calls_impure_function(expr, pure_functions),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+            calls_impure_function(left, pure_functions) || calls_impure_function(right, pure_functions) ||
+                calls_impure_function(if_true, pure_functions) || calls_impure_function(if_false, pure_functions),
+    }
+}
+
+/// Replace the loads of the address of the memory, but not the memory itself, which is written.
+fn replace_address_loads(memory: Exp, available: &[(Exp, Temp)]) -> Exp {
+    match memory {
+        Exp::Mem(address) => Exp::Mem(Box::new(replace_loads(*address, available))),
+        Exp::MemByte(address) => Exp::MemByte(Box::new(replace_loads(*address, available))),
+        memory => memory,
+    }
+}
+
+fn replace_loads(expr: Exp, available: &[(Exp, Temp)]) -> Exp {
+    if available.is_empty() {
+        return expr;
+    }
+    let expr =
+        match expr {
+            Exp::BinOp { op, left, right } => Exp::BinOp {
+                op,
+                left: Box::new(replace_loads(*left, available)),
+                right: Box::new(replace_loads(*right, available)),
+            },
+            Exp::Call { arguments, collectable_return_type, function_expr, return_label } => Exp::Call {
+                arguments: arguments.into_iter()
+                    .map(|argument| replace_loads(argument, available))
+                    .collect(),
+                collectable_return_type,
+                function_expr: Box::new(replace_loads(*function_expr, available)),
+                return_label,
+            },
+            Exp::Compare { op, left, right } => Exp::Compare {
+                op,
+                left: Box::new(replace_loads(*left, available)),
+                right: Box::new(replace_loads(*right, available)),
+            },
+            Exp::Mem(_) | Exp::MemByte(_) => replace_address_loads(expr, available),
+            Exp::Select { op, left, right, if_true, if_false } => Exp::Select {
+                op,
+                left: Box::new(replace_loads(*left, available)),
+                right: Box::new(replace_loads(*right, available)),
+                if_true: Box::new(replace_loads(*if_true, available)),
+                if_false: Box::new(replace_loads(*if_false, available)),
+            },
+            Exp::Const(_) | Exp::Error | Exp::ExpSequence(_, _) | Exp::Name(_) | Exp::Temp(_) => expr,
+        };
+    match expr {
+        Exp::Mem(_) | Exp::MemByte(_) =>
+            available.iter()
+                .find(|load| load.0 == expr)
+                .map_or(expr, |&(_, temp)| Exp::Temp(temp)),
+        expr => expr,
+    }
+}
+
+/// Value of the expression if it only computes with constants.
+fn constant(expr: &Exp) -> Option<i64> {
+    match *expr {
+        Exp::Const(value) => Some(value),
+        Exp::BinOp { ref op, ref left, ref right } => {
+            let left = constant(left)?;
+            let right = constant(right)?;
+            match *op {
+                BinOp::Minus => Some(left.wrapping_sub(right)),
+                BinOp::Mul => Some(left.wrapping_mul(right)),
+                BinOp::Plus => Some(left.wrapping_add(right)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Base of the address and the constant offset added to it.
+fn split(address: &Exp) -> (&Exp, i64) {
+    match *address {
+        Exp::BinOp { op: BinOp::Plus, ref left, ref right } => {
+            if let Some(offset) = constant(right) {
+                let (base, base_offset) = split(left);
+                return (base, base_offset.wrapping_add(offset));
+            }
+            if let Some(offset) = constant(left) {
+                let (base, base_offset) = split(right);
+                return (base, base_offset.wrapping_add(offset));
+            }
+        },
+        Exp::BinOp { op: BinOp::Minus, ref left, ref right } => {
+            if let Some(offset) = constant(right) {
+                let (base, base_offset) = split(left);
+                return (base, base_offset.wrapping_sub(offset));
+            }
+        },
+        _ => (),
+    }
+    (address, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use frame::Frame;
+    use frame::x86_64::X86_64;
+    use std::collections::HashSet;
+    use std::iter;
+
+    use ir::{BinOp, Exp, Statement, _Statement};
+    use temp::{Label, Temp};
+    use super::eliminate_redundant_loads;
+
+    fn temp(num: u32) -> Exp {
+        Exp::Temp(Temp::from_num(num))
+    }
+
+    fn mem(base: Exp, offset: i64) -> Exp {
+        Exp::Mem(Box::new(Exp::BinOp {
+            op: BinOp::Plus,
+            left: Box::new(base),
+            right: Box::new(Exp::Const(offset)),
+        }))
+    }
+
+    fn mov(destination: Exp, source: Exp) -> Statement {
+        _Statement::Move(destination, source).into()
+    }
+
+    fn call(arguments: Vec<Exp>) -> Statement {
+        _Statement::Exp(Exp::Call {
+            arguments,
+            collectable_return_type: false,
+            function_expr: Box::new(Exp::Name(Label::with_name("next"))),
+            return_label: Label::new(),
+        }).into()
+    }
+
+    /// Source of the last statement, after the redundant loads are replaced.
+    fn last_source(statements: Vec<Statement>) -> Exp {
+        last_source_with_pure_functions(statements, &HashSet::new())
+    }
+
+    fn last_source_with_pure_functions(statements: Vec<Statement>, pure_functions: &HashSet<Label>) -> Exp {
+        match eliminate_redundant_loads::<X86_64>(statements, pure_functions).pop().map(|statement| statement.statement) {
+            Some(_Statement::Move(_, source)) => source,
+            statement => panic!("expecting a move, found {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn record_fields() {
+        let record = temp(1000);
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(record.clone(), 16)),
+            mov(mem(record.clone(), 24), temp(1002)),
+            mov(temp(1003), mem(record.clone(), 16)),
+        ]), temp(1001));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(record.clone(), 16)),
+            mov(mem(temp(1004), 24), temp(1002)),
+            mov(temp(1003), mem(record.clone(), 16)),
+        ]), mem(record.clone(), 16));
+        assert_eq!(last_source(vec![
+            mov(mem(record.clone(), 24), temp(1002)),
+            mov(temp(1003), mem(record.clone(), 24)),
+        ]), temp(1002));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(record.clone(), 16)),
+            mov(record.clone(), temp(1004)),
+            mov(temp(1003), mem(record, 16)),
+        ]), mem(temp(1000), 16));
+    }
+
+    #[test]
+    fn array_elements() {
+        let array = temp(1000);
+        let element = |index| Exp::Mem(Box::new(Exp::BinOp {
+            op: BinOp::Plus,
+            left: Box::new(array.clone()),
+            right: Box::new(Exp::BinOp {
+                op: BinOp::Mul,
+                left: Box::new(Exp::BinOp {
+                    op: BinOp::Plus,
+                    left: Box::new(index),
+                    right: Box::new(Exp::Const(2)),
+                }),
+                right: Box::new(Exp::Const(X86_64::WORD_SIZE)),
+            }),
+        }));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), element(Exp::Const(3))),
+            mov(element(Exp::Const(4)), temp(1002)),
+            mov(temp(1003), element(Exp::Const(3))),
+        ]), temp(1001));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), element(Exp::Const(3))),
+            mov(element(temp(1004)), temp(1002)),
+            mov(temp(1003), element(Exp::Const(3))),
+        ]), element(Exp::Const(3)));
+    }
+
+    #[test]
+    fn frame_slots() {
+        let fp = Exp::Temp(X86_64::fp().temp());
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(fp.clone(), -16)),
+            mov(mem(fp.clone(), -8), temp(1002)),
+            mov(mem(temp(1004), 8), temp(1002)),
+            mov(temp(1003), mem(fp.clone(), -16)),
+        ]), temp(1001));
+        // The callee can keep the address of the frame, given as the static link.
+        assert_eq!(last_source(vec![
+            call(vec![fp.clone()]),
+            _Statement::Label(Label::new()).into(),
+            mov(temp(1001), mem(fp.clone(), -16)),
+            mov(mem(temp(1004), 8), temp(1002)),
+            mov(temp(1003), mem(fp.clone(), -16)),
+        ]), mem(fp.clone(), -16));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(fp.clone(), -16)),
+            call(vec![]),
+            mov(temp(1003), mem(fp.clone(), -16)),
+        ]), mem(fp.clone(), -16));
+        assert_eq!(last_source(vec![
+            mov(temp(1001), mem(fp.clone(), -16)),
+            _Statement::Label(Label::new()).into(),
+            mov(temp(1003), mem(fp.clone(), -16)),
+        ]), mem(Exp::Temp(X86_64::fp().temp()), -16));
+        // A pure function neither writes memory nor allocates.
+        let pure_functions = iter::once(Label::with_name("next")).collect();
+        assert_eq!(last_source_with_pure_functions(vec![
+            mov(temp(1001), mem(fp.clone(), -16)),
+            call(vec![temp(1001)]),
+            mov(temp(1003), mem(fp, -16)),
+        ], &pure_functions), temp(1001));
+    }
+}
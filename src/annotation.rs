@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::io::{self, Write};
+
+use asm::{Instruction, Subroutine};
+use frame::Frame;
+use temp::Temp;
+
+/// Width of the instructions, after which their comment starts.
+const INSTRUCTION_WIDTH: usize = 32;
+
+/// Write the instructions of a subroutine, each followed by a comment naming the source line and
+/// the IR statement it was generated from, and the registers allocated to the temporaries of this
+/// statement.
+pub fn write_subroutine<F: Frame, W: Write>(file: &mut W, subroutine: &Subroutine) -> io::Result<()> {
+    let mut current_line = None;
+    let mut current_node = None;
+    writeln!(file, "{}", subroutine.prolog)?;
+    for instruction in &subroutine.body {
+        match *instruction {
+            Instruction::Node { ref temps, ref text } => current_node = Some(node_comment::<F>(text, temps)),
+            Instruction::Position { line } => current_line = Some(line),
+            Instruction::Label { .. } => writeln!(file, "    {}", instruction.to_string::<F>())?,
+            Instruction::Call { .. } | Instruction::Move { .. } | Instruction::Operation { .. } => {
+                let instruction = instruction.to_string::<F>();
+                if instruction.is_empty() {
+                    continue;
+                }
+                let mut comment = vec![];
+                if let Some(line) = current_line {
+                    comment.push(format!("line {}", line));
+                }
+                if let Some(ref node) = current_node {
+                    comment.push(node.clone());
+                }
+                if comment.is_empty() {
+                    writeln!(file, "    {}", instruction)?;
+                }
+                else {
+                    writeln!(file, "    {:<width$} ; {}", instruction, comment.join(" | "), width = INSTRUCTION_WIDTH)?;
+                }
+            },
+        }
+    }
+    writeln!(file, "    {}", subroutine.epilog)
+}
+
+fn node_comment<F: Frame>(text: &str, temps: &[(Temp, Option<Temp>)]) -> String {
+    if temps.is_empty() {
+        return text.to_string();
+    }
+    let allocation = temps.iter()
+        .map(|&(temp, register)| {
+            let location =
+                match register {
+                    Some(register) => register.to_string::<F>(),
+                    None => "stack".to_string(),
+                };
+            format!("{}={}", temp.to_string::<F>(), location)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} | {}", text, allocation)
+}
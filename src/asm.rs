@@ -19,21 +19,50 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use frame::Frame;
+use frame::{Frame, Register};
 use temp::{Label, Temp};
 
+/// Operand of an instruction: a temporary to allocate or a machine register it must use.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operand {
+    Register(Register),
+    Temp(Temp),
+}
+
+impl Operand {
+    /// Operand of a temporary of the IR, which is a machine register if the frame precolored it.
+    pub fn new<F: Frame>(temp: Temp) -> Self {
+        F::registers().into_iter()
+            .find(|register| register.temp() == temp)
+            .map_or(Operand::Temp(temp), Operand::Register)
+    }
+
+    pub fn temp(&self) -> Temp {
+        match *self {
+            Operand::Register(register) => register.temp(),
+            Operand::Temp(temp) => temp,
+        }
+    }
+}
+
+impl From<Register> for Operand {
+    fn from(register: Register) -> Self {
+        Operand::Register(register)
+    }
+}
+
 #[derive(Debug)]
 pub enum Instruction {
     Call {
         assembly: String,
-        destination: Vec<Temp>,
-        source: Vec<Temp>,
+        destination: Vec<Operand>,
+        source: Vec<Operand>,
         return_label: Label,
     },
     Operation {
         assembly: String,
-        destination: Vec<Temp>,
-        source: Vec<Temp>,
+        destination: Vec<Operand>,
+        source: Vec<Operand>,
         stack_destination: Vec<i64>,
         stack_source: Vec<i64>,
         jump: Option<Vec<Label>>,
@@ -44,27 +73,38 @@ pub enum Instruction {
     },
     Move {
         assembly: String,
-        destination: Vec<Temp>,
-        source: Vec<Temp>,
+        destination: Vec<Operand>,
+        source: Vec<Operand>,
         stack_destination: Vec<i64>,
         stack_source: Vec<i64>,
     },
+    /// IR statement the next instructions are generated from, which is only emitted in annotated
+    /// assembly, with the register allocated to each of its temporaries (none if it is spilled).
+    Node {
+        temps: Vec<(Temp, Option<Temp>)>,
+        text: String,
+    },
+    /// Source line of the next instructions, which is not emitted.
+    Position {
+        line: u32,
+    },
 }
 
 impl Instruction {
     pub fn to_string<F: Frame>(&self) -> String {
         match *self {
             Instruction::Label { ref assembly, .. } => assembly.clone(),
+            Instruction::Node { .. } | Instruction::Position { .. } => String::new(),
             Instruction::Call { ref assembly, ref destination, ref source, .. } |
                 Instruction::Move { ref assembly, ref destination, ref source, .. } |
                 Instruction::Operation { ref assembly, ref destination, ref source, .. } =>
             {
                 let mut result = assembly.clone();
-                for (index, temp) in destination.iter().enumerate() {
-                    result = result.replace(&format!("'d{}", index), &temp.to_string::<F>());
+                for (index, operand) in destination.iter().enumerate() {
+                    result = result.replace(&format!("'d{}", index), &operand.temp().to_string::<F>());
                 }
-                for (index, temp) in source.iter().enumerate() {
-                    result = result.replace(&format!("'s{}", index), &temp.to_string::<F>());
+                for (index, operand) in source.iter().enumerate() {
+                    result = result.replace(&format!("'s{}", index), &operand.temp().to_string::<F>());
                 }
                 result
             },
@@ -19,9 +19,12 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::collections::HashSet;
 use std::marker::PhantomData;
+use std::rc::Rc;
 
-use asm::Instruction;
+use asm::{Instruction, Operand};
+use env::SYSCALL;
 use frame::Frame;
 use frame::x86_64::X86_64;
 use ir::{
@@ -31,9 +34,12 @@ use ir::{
     Statement,
     _Statement,
 };
-use temp::Temp;
+use temp::{Label, Temp};
 
 pub struct Gen<F> {
+    /// Symbols which can be defined in another module, reached through the GOT and the PLT when
+    /// generating position-independent code.
+    global_symbols: Option<Rc<HashSet<String>>>,
     instructions: Vec<Instruction>,
     _phantom: PhantomData<F>,
 }
@@ -41,16 +47,35 @@ pub struct Gen<F> {
 impl<F: Frame> Gen<F> {
     pub fn new() -> Self {
         Self {
+            global_symbols: None,
             instructions: vec![],
             _phantom: PhantomData,
         }
     }
 
+    /// Generate code which can be loaded at any address, like in a shared library.
+    pub fn set_position_independent(&mut self, global_symbols: Rc<HashSet<String>>) {
+        self.global_symbols = Some(global_symbols);
+    }
+
+    fn is_global(&self, label: &Label) -> bool {
+        self.global_symbols.as_ref().is_some_and(|symbols| symbols.contains(&label.to_string()))
+    }
+
+    /// Instruction loading the address of the label.
+    fn label_address(&self, label: &Label) -> String {
+        match self.global_symbols {
+            None => format!("mov 'd0, {}", label),
+            Some(_) if self.is_global(label) => format!("mov 'd0, [rel {} wrt ..gotpcrel]", label),
+            Some(_) => format!("lea 'd0, [rel {}]", label),
+        }
+    }
+
     pub fn emit(&mut self, instruction: Instruction) {
         self.instructions.push(instruction);
     }
 
-    fn munch_args(&mut self, arguments: Vec<Exp>) -> Vec<Temp> {
+    fn munch_args(&mut self, arguments: Vec<Exp>) -> Vec<Operand> {
         let mut temps = vec![];
 
         let mut arguments = arguments.into_iter();
@@ -63,10 +88,10 @@ impl<F: Frame> Gen<F> {
                         source: vec![self.munch_expression(argument)],
                         stack_source: vec![],
                         stack_destination: vec![],
-                        destination: vec![register],
+                        destination: vec![register.into()],
                     };
                     self.emit(instruction);
-                    temps.push(register);
+                    temps.push(register.into());
                 },
                 None => break,
             }
@@ -75,8 +100,8 @@ impl<F: Frame> Gen<F> {
         let instructions: Vec<_> = arguments.map(|argument| {
             Instruction::Operation {
                 assembly: "push 's0".to_string(),
-                source: vec![self.munch_expression(argument), X86_64::rsp()],
-                destination: vec![X86_64::rsp()],
+                source: vec![self.munch_expression(argument), X86_64::rsp().into()],
+                destination: vec![X86_64::rsp().into()],
                 jump: None,
                 stack_destination: vec![],
                 stack_source: vec![],
@@ -92,8 +117,326 @@ impl<F: Frame> Gen<F> {
         temps
     }
 
-    pub fn munch_expression(&mut self, expr: Exp) -> Temp {
-        let temp = Temp::new();
+    fn munch_syscall(&mut self, arguments: Vec<Exp>, destination: Operand) {
+        // Evaluate every argument before setting the registers since the evaluation of an argument
+        // could overwrite them.
+        let arguments: Vec<_> = arguments.into_iter()
+            .map(|argument| self.munch_expression(argument))
+            .collect();
+        let registers = X86_64::syscall_registers();
+        for (&argument, &register) in arguments.iter().zip(&registers) {
+            self.emit(Instruction::Move {
+                assembly: "mov 'd0, 's0".to_string(),
+                destination: vec![register.into()],
+                source: vec![argument],
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+        }
+
+        self.emit(Instruction::Operation {
+            assembly: "syscall".to_string(),
+            destination: X86_64::syscall_defs().into_iter().map(Operand::Register).collect(),
+            jump: None,
+            source: registers.into_iter().take(arguments.len()).map(Operand::Register).collect(),
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+
+        self.emit(Instruction::Move {
+            assembly: "mov 'd0, 's0".to_string(),
+            destination: vec![destination],
+            source: vec![X86_64::rax().into()],
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+    }
+
+    /// Munch the two operands of an operation, starting with the one needing the most registers
+    /// (Sethi-Ullman ordering) to reduce the number of temporaries live at the same time.
+    fn munch_operands(&mut self, left: Exp, right: Exp) -> (Operand, Operand) {
+        // After canonicalization, expressions do not have side effects, except calls, so they can
+        // be evaluated in any order.
+        if !contains_call(&left) && !contains_call(&right) && register_need(&right) > register_need(&left) {
+            let right = self.munch_expression(right);
+            let left = self.munch_expression(left);
+            (left, right)
+        }
+        else {
+            let left = self.munch_expression(left);
+            let right = self.munch_expression(right);
+            (left, right)
+        }
+    }
+
+    fn munch_comparison(&mut self, left: Exp, right: Exp) {
+        let (left, right) = self.munch_operands(left, right);
+        let instruction =
+            Instruction::Operation {
+                assembly: "cmp 's0, 's1".to_string(),
+                source: vec![left, right],
+                destination: vec![],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            };
+        self.emit(instruction);
+    }
+
+    /// Divide rax by `divisor` and move the quotient to `destination`.
+    fn emit_signed_division(&mut self, divisor: Operand, destination: Operand) {
+        // Sign-extend rax into rdx.
+        let instruction = Instruction::Operation {
+            assembly: "cqo".to_string(),
+            source: vec![X86_64::rax().into()],
+            destination: vec![X86_64::rdx().into()],
+            jump: None,
+            stack_destination: vec![],
+            stack_source: vec![],
+        };
+        self.emit(instruction);
+        let instruction = Instruction::Operation {
+            assembly: "idiv 's0".to_string(),
+            source: vec![divisor, X86_64::rax().into(), X86_64::rdx().into()],
+            destination: vec![X86_64::rax().into(), X86_64::rdx().into()],
+            jump: None,
+            stack_destination: vec![],
+            stack_source: vec![],
+        };
+        self.emit(instruction);
+        let instruction = Instruction::Move {
+            assembly: "mov 'd0, 's0".to_string(),
+            source: vec![X86_64::rax().into()],
+            destination: vec![destination],
+            stack_destination: vec![],
+            stack_source: vec![],
+        };
+        self.emit(instruction);
+    }
+
+    fn munch_multiplication_by_constant(&mut self, expr: Exp, num: i64, destination: Operand) {
+        let source = self.munch_expression(expr);
+        let instruction =
+            if num > 0 && (num as u64).is_power_of_two() {
+                self.emit(Instruction::Move {
+                    assembly: "mov 'd0, 's0".to_string(),
+                    source: vec![source],
+                    destination: vec![destination],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                });
+                Instruction::Operation {
+                    assembly: format!("sal 'd0, {}", num.trailing_zeros()),
+                    source: vec![destination],
+                    destination: vec![destination],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                }
+            }
+            else if num == 3 || num == 5 || num == 9 {
+                Instruction::Operation {
+                    assembly: format!("lea 'd0, ['s0 + 's0 * {}]", num - 1),
+                    source: vec![source],
+                    destination: vec![destination],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                }
+            }
+            else if num >= i32::MIN as i64 && num <= i32::MAX as i64 {
+                Instruction::Operation {
+                    assembly: format!("imul 'd0, 's0, {}", num),
+                    source: vec![source],
+                    destination: vec![destination],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                }
+            }
+            else {
+                self.emit(Instruction::Move {
+                    assembly: format!("mov 'd0, {}", num),
+                    source: vec![],
+                    destination: vec![destination],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                });
+                Instruction::Operation {
+                    assembly: "imul 'd0, 's0".to_string(),
+                    source: vec![source, destination],
+                    destination: vec![destination],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                }
+            };
+        self.emit(instruction);
+    }
+
+    fn munch_division_by_constant(&mut self, expr: Exp, num: i64, destination: Operand) {
+        let dividend = self.munch_expression(expr);
+        let instruction = Instruction::Move {
+            assembly: "mov 'd0, 's0".to_string(),
+            source: vec![dividend],
+            destination: vec![destination],
+            stack_destination: vec![],
+            stack_source: vec![],
+        };
+        self.emit(instruction);
+
+        if num == 1 {
+            return;
+        }
+
+        if num == -1 {
+            let instruction = Instruction::Operation {
+                assembly: "neg 'd0".to_string(),
+                source: vec![destination],
+                destination: vec![destination],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            };
+            self.emit(instruction);
+            return;
+        }
+
+        if num > 0 && (num as u64).is_power_of_two() {
+            // Add 2^k - 1 to negative dividends so that the shift rounds toward zero.
+            let shift = num.trailing_zeros();
+            let bias = Operand::Temp(Temp::new());
+            self.emit(Instruction::Move {
+                assembly: "mov 'd0, 's0".to_string(),
+                source: vec![dividend],
+                destination: vec![bias],
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+            for assembly in &["sar 'd0, 63".to_string(), format!("shr 'd0, {}", 64 - shift)] {
+                self.emit(Instruction::Operation {
+                    assembly: assembly.clone(),
+                    source: vec![bias],
+                    destination: vec![bias],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                });
+            }
+            self.emit(Instruction::Operation {
+                assembly: "add 'd0, 's0".to_string(),
+                source: vec![bias, destination],
+                destination: vec![destination],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+            self.emit(Instruction::Operation {
+                assembly: format!("sar 'd0, {}", shift),
+                source: vec![destination],
+                destination: vec![destination],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+            return;
+        }
+
+        if num == 0 || num == i64::MIN {
+            let divisor = Operand::Temp(Temp::new());
+            self.emit(Instruction::Move {
+                assembly: format!("mov 'd0, {}", num),
+                source: vec![],
+                destination: vec![divisor],
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+            self.emit(Instruction::Move {
+                assembly: "mov 'd0, 's0".to_string(),
+                source: vec![dividend],
+                destination: vec![X86_64::rax().into()],
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+            self.emit_signed_division(divisor, destination);
+            return;
+        }
+
+        // Multiply by the reciprocal: the quotient is in the high half of the product.
+        let (magic, shift) = division_magic(num);
+        self.emit(Instruction::Move {
+            assembly: format!("mov 'd0, {}", magic),
+            source: vec![],
+            destination: vec![X86_64::rax().into()],
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+        self.emit(Instruction::Operation {
+            assembly: "imul 's0".to_string(),
+            source: vec![dividend, X86_64::rax().into()],
+            destination: vec![X86_64::rax().into(), X86_64::rdx().into()],
+            jump: None,
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+        if num > 0 && magic < 0 {
+            self.emit(Instruction::Operation {
+                assembly: "add 'd0, 's0".to_string(),
+                source: vec![dividend, X86_64::rdx().into()],
+                destination: vec![X86_64::rdx().into()],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+        }
+        else if num < 0 && magic > 0 {
+            self.emit(Instruction::Operation {
+                assembly: "sub 'd0, 's0".to_string(),
+                source: vec![dividend, X86_64::rdx().into()],
+                destination: vec![X86_64::rdx().into()],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+        }
+        if shift > 0 {
+            self.emit(Instruction::Operation {
+                assembly: format!("sar 'd0, {}", shift),
+                source: vec![X86_64::rdx().into()],
+                destination: vec![X86_64::rdx().into()],
+                jump: None,
+                stack_destination: vec![],
+                stack_source: vec![],
+            });
+        }
+        // Add one to negative quotients to round toward zero.
+        self.emit(Instruction::Move {
+            assembly: "mov 'd0, 's0".to_string(),
+            source: vec![X86_64::rdx().into()],
+            destination: vec![destination],
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+        self.emit(Instruction::Operation {
+            assembly: "shr 'd0, 63".to_string(),
+            source: vec![X86_64::rdx().into()],
+            destination: vec![X86_64::rdx().into()],
+            jump: None,
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+        self.emit(Instruction::Operation {
+            assembly: "add 'd0, 's0".to_string(),
+            source: vec![X86_64::rdx().into(), destination],
+            destination: vec![destination],
+            jump: None,
+            stack_destination: vec![],
+            stack_source: vec![],
+        });
+    }
+
+    pub fn munch_expression(&mut self, expr: Exp) -> Operand {
+        let temp = Operand::Temp(Temp::new());
         match expr {
             // Error cases:
             Exp::Error | Exp::ExpSequence(_, _) | Exp::BinOp { left: box Exp::Error, .. }
@@ -102,7 +445,7 @@ impl<F: Frame> Gen<F> {
 
             Exp::BinOp { op: BinOp::Plus, left: box Exp::Name(label), right } => {
                 let instruction = Instruction::Move {
-                    assembly: format!("mov 'd0, {}", label),
+                    assembly: self.label_address(&label),
                     source: vec![],
                     destination: vec![temp],
                     stack_destination: vec![],
@@ -122,7 +465,7 @@ impl<F: Frame> Gen<F> {
 
             Exp::Name(ref label) => {
                 let instruction = Instruction::Move {
-                    assembly: format!("mov 'd0, {}", label),
+                    assembly: self.label_address(label),
                     source: vec![],
                     destination: vec![temp],
                     stack_destination: vec![],
@@ -133,7 +476,7 @@ impl<F: Frame> Gen<F> {
             Exp::Mem(box Exp::BinOp { op: BinOp::Plus, left: expr, right: box Exp::Const(num) }) |
                 Exp::Mem(box Exp::BinOp { op: BinOp::Plus, left: box Exp::Const(num), right: expr }) => {
                 let stack_source =
-                    if *expr == Exp::Temp(F::fp()) {
+                    if *expr == Exp::Temp(F::fp().temp()) {
                         vec![num]
                     }
                     else {
@@ -148,6 +491,26 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
+            Exp::Mem(box Exp::Name(label)) if self.is_global(&label) => {
+                let instruction = Instruction::Move {
+                    assembly: "mov 'd0, ['s0]".to_string(),
+                    source: vec![self.munch_expression(Exp::Name(label))],
+                    destination: vec![temp],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+            },
+            Exp::Mem(box Exp::Name(ref label)) => {
+                let instruction = Instruction::Move {
+                    assembly: format!("mov 'd0, [rel {}]", label),
+                    source: vec![],
+                    destination: vec![temp],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+            },
             Exp::Mem(box Exp::Const(num)) => {
                 let instruction = Instruction::Move {
                     assembly: format!("mov 'd0, [{}]", num),
@@ -168,6 +531,17 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
+            Exp::MemByte(expr) => {
+                let instruction = Instruction::Operation {
+                    assembly: "movzx 'd0, byte ['s0]".to_string(),
+                    source: vec![self.munch_expression(*expr)],
+                    destination: vec![temp],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+            },
             Exp::BinOp { op: BinOp::Plus, left: expr, right: box Exp::Const(num) } |
                 Exp::BinOp { op: BinOp::Plus, left: box Exp::Const(num), right: expr } => {
                 let instruction = Instruction::Move {
@@ -227,119 +601,21 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
             },
             Exp::BinOp { op: BinOp::Mul, left: expr, right: box Exp::Const(num) } |
-                Exp::BinOp { op: BinOp::Mul, left: box Exp::Const(num), right: expr } => {
-                let instruction = Instruction::Move {
-                    assembly: format!("mov 'd0, {}", num),
-                    source: vec![],
-                    destination: vec![temp],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*expr)],
-                    destination: vec![X86_64::rax()],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Operation {
-                    assembly: "mul 's0".to_string(),
-                    source: vec![temp, X86_64::rax()],
-                    destination: vec![X86_64::rax(), X86_64::rdx()],
-                    jump: None,
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
-                    destination: vec![temp],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-            },
-            Exp::BinOp { op: BinOp::Div, left: expr, right: box Exp::Const(num) } => {
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 0".to_string(),
-                    source: vec![],
-                    destination: vec![X86_64::rdx()],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*expr)],
-                    destination: vec![X86_64::rax()],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let immediate = Temp::new();
-                let instruction = Instruction::Move {
-                    assembly: format!("mov 'd0, {}", num),
-                    source: vec![],
-                    destination: vec![immediate],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Operation {
-                    assembly: "idiv 's0".to_string(),
-                    source: vec![immediate, X86_64::rax(), X86_64::rdx()],
-                    destination: vec![X86_64::rax(), X86_64::rdx()],
-                    jump: None,
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
-                    destination: vec![temp],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-            },
+                Exp::BinOp { op: BinOp::Mul, left: box Exp::Const(num), right: expr } =>
+                self.munch_multiplication_by_constant(*expr, num, temp),
+            Exp::BinOp { op: BinOp::Div, left: expr, right: box Exp::Const(num) } =>
+                self.munch_division_by_constant(*expr, num, temp),
             Exp::BinOp { op: BinOp::Div, left: box Exp::Const(num), right: expr } => {
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 0".to_string(),
-                    source: vec![],
-                    destination: vec![X86_64::rdx()],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
+                let divisor = self.munch_expression(*expr);
                 let instruction = Instruction::Move {
                     assembly: format!("mov 'd0, {}", num),
                     source: vec![],
-                    destination: vec![X86_64::rax()],
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Operation {
-                    assembly: "idiv 's0".to_string(),
-                    source: vec![self.munch_expression(*expr), X86_64::rax(), X86_64::rdx()],
-                    destination: vec![X86_64::rax(), X86_64::rdx()],
-                    jump: None,
-                    stack_destination: vec![],
-                    stack_source: vec![],
-                };
-                self.emit(instruction);
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
-                    destination: vec![temp],
+                    destination: vec![X86_64::rax().into()],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
+                self.emit_signed_division(divisor, temp);
             },
             Exp::BinOp { op: BinOp::And, left: expr, right: box Exp::Const(num) } |
                 Exp::BinOp { op: BinOp::And, left: box Exp::Const(num), right: expr } => {
@@ -472,9 +748,10 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
             },
             Exp::BinOp { op: BinOp::Plus, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -482,7 +759,7 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
                     assembly: "add 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right), temp],
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -491,9 +768,10 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
             },
             Exp::BinOp { op: BinOp::Minus, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -501,7 +779,7 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
                     assembly: "sub 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right), temp],
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -510,9 +788,10 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
             },
             Exp::BinOp { op: BinOp::Mul, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -520,16 +799,16 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
-                    destination: vec![X86_64::rax()],
+                    source: vec![right],
+                    destination: vec![X86_64::rax().into()],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
                     assembly: "mul 's0".to_string(),
-                    source: vec![temp, X86_64::rax()],
-                    destination: vec![X86_64::rax(), X86_64::rdx()],
+                    source: vec![temp, X86_64::rax().into()],
+                    destination: vec![X86_64::rax().into(), X86_64::rdx().into()],
                     jump: None,
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -537,7 +816,7 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
+                    source: vec![X86_64::rax().into()],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -545,52 +824,72 @@ impl<F: Frame> Gen<F> {
                 self.emit(instruction);
             },
             Exp::BinOp { op: BinOp::Div, left, right } => {
+                // Both operands are computed before rax and rdx are set up, since their code could
+                // use these registers.
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 0".to_string(),
-                    source: vec![],
-                    destination: vec![X86_64::rdx()],
+                    assembly: "mov 'd0, 's0".to_string(),
+                    source: vec![left],
+                    destination: vec![X86_64::rax().into()],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
+                self.emit_signed_division(right, temp);
+            },
+            Exp::BinOp { op: BinOp::And, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
-                    destination: vec![X86_64::rax()],
+                    source: vec![left],
+                    destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "idiv 's0".to_string(),
-                    source: vec![self.munch_expression(*right), X86_64::rax(), X86_64::rdx()],
-                    destination: vec![X86_64::rax(), X86_64::rdx()],
+                    assembly: "and 'd0, 's0".to_string(),
+                    source: vec![right, temp],
+                    destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
+            },
+            Exp::BinOp { op: BinOp::Or, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
+                    source: vec![left],
+                    destination: vec![temp],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+                let instruction = Instruction::Operation {
+                    assembly: "or 'd0, 's0".to_string(),
+                    source: vec![right, temp],
                     destination: vec![temp],
+                    jump: None,
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::And, left, right } => {
+            Exp::BinOp { op: BinOp::ShiftLeft, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "and 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
+                    assembly: "sal 'd0, 's0".to_string(),
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -598,18 +897,19 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::Or, left, right } => {
+            Exp::BinOp { op: BinOp::ArithmeticShiftRight, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "or 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right), temp],
+                    assembly: "sar 'd0, 's0".to_string(),
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -617,18 +917,19 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::ShiftLeft, left, right } => {
+            Exp::BinOp { op: BinOp::ShiftRight, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "sal 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
+                    assembly: "shr 'd0, 's0".to_string(),
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -636,18 +937,19 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::ArithmeticShiftRight, left, right } => {
+            Exp::BinOp { op: BinOp::Xor, left, right } => {
+                let (left, right) = self.munch_operands(*left, *right);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![left],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "sar 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
+                    assembly: "xor 'd0, 's0".to_string(),
+                    source: vec![right, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -655,18 +957,22 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::ShiftRight, left, right } => {
-                let instruction = Instruction::Move {
-                    assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
-                    destination: vec![temp],
+            Exp::Compare { op, left, right } => {
+                // Materialize the flags without branching: setcc writes the low byte of rax, which
+                // is then zero-extended to the destination.
+                self.munch_comparison(*left, *right);
+                let instruction = Instruction::Operation {
+                    assembly: format!("set{} al", condition_code(op)),
+                    source: vec![],
+                    destination: vec![X86_64::rax().into()],
+                    jump: None,
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
                 let instruction = Instruction::Operation {
-                    assembly: "shr 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
+                    assembly: "movzx 'd0, al".to_string(),
+                    source: vec![X86_64::rax().into()],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -674,18 +980,25 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::BinOp { op: BinOp::Xor, left, right } => {
+            Exp::Select { op, left, right, if_true, if_false } => {
+                // Evaluate both values before the comparison since their evaluation could modify the
+                // flags.
+                let if_false = self.munch_expression(*if_false);
+                let if_true = self.munch_expression(*if_true);
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*left)],
+                    source: vec![if_false],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
                 };
                 self.emit(instruction);
+                self.munch_comparison(*left, *right);
+                // The destination is also a source since it keeps its value when the condition is
+                // false.
                 let instruction = Instruction::Operation {
-                    assembly: "xor 'd0, 's0".to_string(),
-                    source: vec![self.munch_expression(*right)],
+                    assembly: format!("cmov{} 'd0, 's0", condition_code(op)),
+                    source: vec![if_true, temp],
                     destination: vec![temp],
                     jump: None,
                     stack_destination: vec![],
@@ -693,14 +1006,17 @@ impl<F: Frame> Gen<F> {
                 };
                 self.emit(instruction);
             },
-            Exp::Temp(temp) => return temp,
+            Exp::Temp(temp) => return Operand::new::<F>(temp),
+            Exp::Call { function_expr: box Exp::Name(label), arguments, .. } if label == Label::with_name(SYSCALL) =>
+                self.munch_syscall(arguments, temp),
             Exp::Call { function_expr: box Exp::Name(label), arguments, return_label, .. } => {
                 let argument_count = arguments.len();
                 let source = self.munch_args(arguments);
+                let plt = if self.is_global(&label) { " wrt ..plt" } else { "" };
                 let instruction = Instruction::Call {
-                    assembly: format!("call {}", label),
+                    assembly: format!("call {}{}", label, plt),
                     source,
-                    destination: X86_64::calldefs(),
+                    destination: X86_64::calldefs().into_iter().map(Operand::Register).collect(),
                     return_label: return_label.clone(),
                 };
                 self.emit(instruction);
@@ -714,7 +1030,7 @@ impl<F: Frame> Gen<F> {
 
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
+                    source: vec![X86_64::rax().into()],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -725,8 +1041,8 @@ impl<F: Frame> Gen<F> {
                     let stack_arg_count = argument_count - X86_64::arg_registers().len();
                     let instruction = Instruction::Operation {
                         assembly: format!("add 'd0, {}", stack_arg_count as i64 * X86_64::WORD_SIZE),
-                        source: vec![X86_64::rsp()],
-                        destination: vec![X86_64::rsp()],
+                        source: vec![X86_64::rsp().into()],
+                        destination: vec![X86_64::rsp().into()],
                         jump: None,
                         stack_destination: vec![],
                         stack_source: vec![],
@@ -741,7 +1057,7 @@ impl<F: Frame> Gen<F> {
                 let instruction = Instruction::Call {
                     assembly: "call 's0".to_string(),
                     source,
-                    destination: X86_64::calldefs(),
+                    destination: X86_64::calldefs().into_iter().map(Operand::Register).collect(),
                     return_label: return_label.clone(),
                 };
                 self.emit(instruction);
@@ -755,7 +1071,7 @@ impl<F: Frame> Gen<F> {
 
                 let instruction = Instruction::Move {
                     assembly: "mov 'd0, 's0".to_string(),
-                    source: vec![X86_64::rax()],
+                    source: vec![X86_64::rax().into()],
                     destination: vec![temp],
                     stack_destination: vec![],
                     stack_source: vec![],
@@ -766,8 +1082,8 @@ impl<F: Frame> Gen<F> {
                     let stack_arg_count = argument_count - X86_64::arg_registers().len();
                     let instruction = Instruction::Operation {
                         assembly: format!("add 'd0, {}", stack_arg_count as i64 * X86_64::WORD_SIZE),
-                        source: vec![X86_64::rsp()],
-                        destination: vec![X86_64::rsp()],
+                        source: vec![X86_64::rsp().into()],
+                        destination: vec![X86_64::rsp().into()],
                         jump: None,
                         stack_destination: vec![],
                         stack_source: vec![],
@@ -797,7 +1113,7 @@ impl<F: Frame> Gen<F> {
                     right: memory_destination,
                 }), expr) => {
                 let mut stack_destination =
-                    if *memory_destination == Exp::Temp(F::fp()) {
+                    if *memory_destination == Exp::Temp(F::fp().temp()) {
                         vec![num]
                     }
                     else {
@@ -807,12 +1123,25 @@ impl<F: Frame> Gen<F> {
                     stack_destination.push(stack_dest);
                 }
                 let instruction =
-                    Instruction::Move {
-                        assembly: format!("mov ['s0 + {}], 's1", num), // FIXME: might be wrong if expr is in memory as Intel might not allow a move from memory to memory.
-                        source: vec![self.munch_expression(*memory_destination), self.munch_expression(expr)],
-                        destination: vec![],
-                        stack_destination,
-                        stack_source: vec![],
+                    match expr {
+                        Exp::Const(value) if value >= i32::MIN as i64 && value <= i32::MAX as i64 =>
+                            Instruction::Move {
+                                assembly: format!("mov qword ['s0 + {}], {}", num, value),
+                                source: vec![self.munch_expression(*memory_destination)],
+                                destination: vec![],
+                                stack_destination,
+                                stack_source: vec![],
+                            },
+                        expr => {
+                            let (memory_destination, expr) = self.munch_operands(*memory_destination, expr);
+                            Instruction::Move {
+                                assembly: format!("mov ['s0 + {}], 's1", num), // FIXME: might be wrong if expr is in memory as Intel might not allow a move from memory to memory.
+                                source: vec![memory_destination, expr],
+                                destination: vec![],
+                                stack_destination,
+                                stack_source: vec![],
+                            }
+                        },
                     };
                 self.emit(instruction);
             },
@@ -858,16 +1187,51 @@ impl<F: Frame> Gen<F> {
                         vec![]
                     };
                 let instruction =
-                    Instruction::Move {
-                        assembly: "mov ['s0], 's1".to_string(),
-                        source: vec![self.munch_expression(*destination), self.munch_expression(source)],
-                        destination: vec![],
-                        stack_destination,
-                        stack_source: vec![],
+                    match source {
+                        Exp::Const(value) if value >= i32::MIN as i64 && value <= i32::MAX as i64 =>
+                            Instruction::Move {
+                                assembly: format!("mov qword ['s0], {}", value),
+                                source: vec![self.munch_expression(*destination)],
+                                destination: vec![],
+                                stack_destination,
+                                stack_source: vec![],
+                            },
+                        source => {
+                            let (destination, source) = self.munch_operands(*destination, source);
+                            Instruction::Move {
+                                assembly: "mov ['s0], 's1".to_string(),
+                                source: vec![destination, source],
+                                destination: vec![],
+                                stack_destination,
+                                stack_source: vec![],
+                            }
+                        },
                     };
                 self.emit(instruction);
             },
+            _Statement::Move(Exp::MemByte(destination), source) => {
+                // Only the low byte of rax is named in the store, so the value goes through it.
+                let (destination, source) = self.munch_operands(*destination, source);
+                let instruction = Instruction::Move {
+                    assembly: "mov 'd0, 's0".to_string(),
+                    source: vec![source],
+                    destination: vec![X86_64::rax().into()],
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+                let instruction = Instruction::Operation {
+                    assembly: "mov byte ['s0], al".to_string(),
+                    source: vec![destination, X86_64::rax().into()],
+                    destination: vec![],
+                    jump: None,
+                    stack_destination: vec![],
+                    stack_source: vec![],
+                };
+                self.emit(instruction);
+            },
             _Statement::Move(Exp::Temp(temp), source) => {
+                let temp = Operand::new::<F>(temp);
                 let stack_destination =
                     if let Some(stack_dest) = statement.stack_var {
                         vec![stack_dest]
@@ -888,9 +1252,9 @@ impl<F: Frame> Gen<F> {
                 let instruction =
                     if let Exp::Mem(box Exp::BinOp { op: BinOp::Plus, left: expr, right: box Exp::Const(num) }) = source {
                         // TODO: should that optimization be removed in favor of loophole optimization?
-                        if <X86_64 as Frame>::registers().contains(&temp) {
+                        if let Operand::Register(_) = temp {
                             let stack_source =
-                                if *expr == Exp::Temp(F::fp()) {
+                                if *expr == Exp::Temp(F::fp().temp()) {
                                     vec![num]
                                 }
                                 else {
@@ -922,6 +1286,7 @@ impl<F: Frame> Gen<F> {
                     };
                 self.emit(instruction);
             },
+            _Statement::Position(line) => self.emit(Instruction::Position { line }),
             _Statement::Exp(Exp::Const(_)) =>
                 if let Some(stack_dest) = statement.stack_var {
                     let instruction = Instruction::Operation {
@@ -955,33 +1320,10 @@ impl<F: Frame> Gen<F> {
                 }
             },
             _Statement::CondJump { op, left, right, false_label, true_label } => {
+                self.munch_comparison(left, right);
                 let instruction =
                     Instruction::Operation {
-                        assembly: "cmp 's0, 's1".to_string(),
-                        source: vec![self.munch_expression(left), self.munch_expression(right)],
-                        destination: vec![],
-                        jump: None,
-                        stack_destination: vec![],
-                        stack_source: vec![],
-                    };
-                self.emit(instruction);
-
-                let opcode =
-                    match op {
-                        RelationalOp::Equal => "je",
-                        RelationalOp::NotEqual => "jne",
-                        RelationalOp::LesserThan => "jl",
-                        RelationalOp::GreaterThan => "jg",
-                        RelationalOp::LesserOrEqual => "jle",
-                        RelationalOp::GreaterOrEqual => "jge",
-                        RelationalOp::UnsignedLesserThan => "jb",
-                        RelationalOp::UnsignedLesserOrEqual => "jbe",
-                        RelationalOp::UnsignedGreaterThan => "ja",
-                        RelationalOp::UnsignedGreaterOrEqual => "jae",
-                    };
-                let instruction =
-                    Instruction::Operation {
-                        assembly: format!("{} {}", opcode, true_label),
+                        assembly: format!("j{} {}", condition_code(op), true_label),
                         source: vec![],
                         destination: vec![],
                         jump: Some(vec![false_label, true_label]),
@@ -994,6 +1336,7 @@ impl<F: Frame> Gen<F> {
             // Error cases:
             _Statement::Move(Exp::Const(_), _) | _Statement::Move(Exp::Error, _) | _Statement::Move(Exp::Name(_), _) |
                 _Statement::Move(Exp::BinOp { .. }, _) | _Statement::Move(Exp::Call { .. }, _) |
+                _Statement::Move(Exp::Compare { .. }, _) | _Statement::Move(Exp::Select { .. }, _) |
                 _Statement::Move(Exp::ExpSequence(_, _), _) => unreachable!("{:#?}", statement),
         }
     }
@@ -1002,3 +1345,128 @@ impl<F: Frame> Gen<F> {
         self.instructions
     }
 }
+
+/// Compute the magic number and the shift used to replace a signed division by `divisor` by a
+/// multiplication (Hacker's Delight, section 10-4).
+fn division_magic(divisor: i64) -> (i64, u32) {
+    const TWO_63: u64 = 1 << 63;
+    let absolute_divisor = divisor.unsigned_abs();
+    let t = TWO_63 + ((divisor as u64) >> 63);
+    let absolute_nc = t - 1 - t % absolute_divisor;
+    let mut shift = 63;
+    let mut q1 = TWO_63 / absolute_nc;
+    let mut r1 = TWO_63 - q1 * absolute_nc;
+    let mut q2 = TWO_63 / absolute_divisor;
+    let mut r2 = TWO_63 - q2 * absolute_divisor;
+    loop {
+        shift += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= absolute_nc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(absolute_nc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= absolute_divisor {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(absolute_divisor);
+        }
+        let delta = absolute_divisor - r2;
+        if q1 > delta || (q1 == delta && r1 != 0) {
+            break;
+        }
+    }
+    let magic = q2.wrapping_add(1) as i64;
+    let magic =
+        if divisor < 0 {
+            magic.wrapping_neg()
+        }
+        else {
+            magic
+        };
+    (magic, shift - 64)
+}
+
+/// Suffix of the jcc and setcc instructions for a comparison.
+fn condition_code(op: RelationalOp) -> &'static str {
+    match op {
+        RelationalOp::Equal => "e",
+        RelationalOp::NotEqual => "ne",
+        RelationalOp::LesserThan => "l",
+        RelationalOp::GreaterThan => "g",
+        RelationalOp::LesserOrEqual => "le",
+        RelationalOp::GreaterOrEqual => "ge",
+        RelationalOp::UnsignedLesserThan => "b",
+        RelationalOp::UnsignedLesserOrEqual => "be",
+        RelationalOp::UnsignedGreaterThan => "a",
+        RelationalOp::UnsignedGreaterOrEqual => "ae",
+    }
+}
+
+fn contains_call(expr: &Exp) -> bool {
+    match *expr {
+        Exp::Call { .. } | Exp::ExpSequence(_, _) => true,
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+            contains_call(left) || contains_call(right),
+        Exp::Mem(ref expr) | Exp::MemByte(ref expr) => contains_call(expr),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+            contains_call(left) || contains_call(right) || contains_call(if_true) || contains_call(if_false),
+        Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => false,
+    }
+}
+
+/// Number of registers needed to evaluate an expression without spilling, as labeled by the
+/// Sethi-Ullman algorithm.
+fn register_need(expr: &Exp) -> usize {
+    match *expr {
+        Exp::Temp(_) => 0,
+        Exp::Const(_) | Exp::Error | Exp::Name(_) => 1,
+        Exp::Mem(ref expr) | Exp::MemByte(ref expr) => register_need(expr).max(1),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } => {
+            // The values are evaluated first and kept in registers while evaluating the comparison.
+            let values = register_need(if_false).max(register_need(if_true) + 1);
+            values.max(register_need(left).max(register_need(right)) + 2)
+        },
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } => {
+            let left = register_need(left);
+            let right = register_need(right);
+            if left == right {
+                left + 1
+            }
+            else {
+                left.max(right)
+            }
+        },
+        Exp::Call { .. } | Exp::ExpSequence(_, _) => usize::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asm_gen::division_magic;
+
+    // Simulate the instruction sequence emitted for a division by a constant.
+    fn divide(dividend: i64, divisor: i64) -> i64 {
+        let (magic, shift) = division_magic(divisor);
+        let mut high = ((dividend as i128 * magic as i128) >> 64) as i64;
+        if divisor > 0 && magic < 0 {
+            high = high.wrapping_add(dividend);
+        }
+        else if divisor < 0 && magic > 0 {
+            high = high.wrapping_sub(dividend);
+        }
+        high >>= shift;
+        high + ((high as u64) >> 63) as i64
+    }
+
+    #[test]
+    fn division_by_constant() {
+        let dividends = [0, 1, -1, 2, -2, 6, -6, 7, -7, 100, -100, 12345678, -12345678, i64::MAX, i64::MIN + 1, i64::MIN];
+        for &divisor in &[3, -3, 5, 6, 7, -7, 10, 11, 100, 641, 1000000007, i64::MAX, -2, -8] {
+            for &dividend in &dividends {
+                assert_eq!(divide(dividend, divisor), dividend.wrapping_div(divisor), "{} / {}", dividend, divisor);
+            }
+        }
+    }
+}
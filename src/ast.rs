@@ -19,13 +19,77 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::mem;
+use std::ops::{Index, IndexMut};
+
 use position::WithPos;
 use symbol::{Symbol, SymbolWithPos};
 
+/// Expressions of the AST of a file, allocated in one vector instead of one box each: the
+/// expressions refer to their subexpressions by their handle in the arena, so copying an expression
+/// does not copy its subexpressions.
+#[derive(Clone, Debug, Default)]
+pub struct Arena {
+    exprs: Vec<ExprWithPos>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn alloc(&mut self, expr: ExprWithPos) -> ExprId {
+        self.exprs.push(expr);
+        ExprId(self.exprs.len() as u32 - 1)
+    }
+
+    /// Move the expression out of the arena, to build the one replacing it.
+    pub fn take(&mut self, id: ExprId) -> ExprWithPos {
+        mem::replace(&mut self[id], WithPos::dummy(Expr::Nil))
+    }
+}
+
+impl Index<ExprId> for Arena {
+    type Output = ExprWithPos;
+
+    fn index(&self, id: ExprId) -> &ExprWithPos {
+        &self.exprs[id.0 as usize]
+    }
+}
+
+impl IndexMut<ExprId> for Arena {
+    fn index_mut(&mut self, id: ExprId) -> &mut ExprWithPos {
+        &mut self.exprs[id.0 as usize]
+    }
+}
+
+/// AST of a source file: the arena of its expressions and the main expression.
+#[derive(Clone, Debug)]
+pub struct Ast {
+    pub arena: Arena,
+    pub root: ExprId,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaseArm {
+    pub body: ExprId,
+    pub label: String,
+}
+
+pub type CaseArmWithPos = WithPos<CaseArm>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassMember {
+    pub declaration: DeclarationWithPos,
+    // Member of the class itself instead of its instances.
+    pub is_static: bool,
+    pub visibility: Visibility,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Declaration {
     ClassDeclaration {
-        declarations: Vec<DeclarationWithPos>,
+        members: Vec<ClassMember>,
         name: SymbolWithPos,
         parent_class: SymbolWithPos,
     },
@@ -33,7 +97,8 @@ pub enum Declaration {
     Type(Vec<TypeDecWithPos>),
     VariableDeclaration {
         escape: bool,
-        init: ExprWithPos,
+        init: ExprId,
+        loop_variable: bool,
         name: Symbol,
         typ: Option<SymbolWithPos>,
     },
@@ -43,69 +108,87 @@ pub type DeclarationWithPos = WithPos<Declaration>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
+    /// Address of the variable `var`, only available in the unsafe dialect.
+    AddressOf {
+        var: ExprId,
+    },
     Array {
-        init: Box<ExprWithPos>,
-        size: Box<ExprWithPos>,
+        init: ExprId,
+        size: ExprId,
         typ: SymbolWithPos,
     },
     Assign {
-        expr: Box<ExprWithPos>,
-        var: Box<ExprWithPos>,
+        expr: ExprId,
+        var: ExprId,
     },
     Break,
     Call {
-        args: Vec<ExprWithPos>,
+        args: Vec<ExprId>,
         function: Symbol,
     },
+    /// Select the arm whose label is equal to the string `subject`.
+    Case {
+        arms: Vec<CaseArmWithPos>,
+        else_: Option<ExprId>,
+        subject: ExprId,
+    },
+    /// Value pointed to by `pointer`.
+    Deref {
+        pointer: ExprId,
+    },
     Field {
         ident: SymbolWithPos,
-        this: Box<ExprWithPos>,
+        this: ExprId,
     },
     If {
-        else_: Option<Box<ExprWithPos>>,
-        test: Box<ExprWithPos>,
-        then: Box<ExprWithPos>,
+        else_: Option<ExprId>,
+        test: ExprId,
+        then: ExprId,
     },
     Int {
         value: i64,
     },
     Let {
-        body: Box<ExprWithPos>,
+        body: ExprId,
         declarations: Vec<DeclarationWithPos>,
     },
     MethodCall {
-        args: Vec<ExprWithPos>,
+        args: Vec<ExprId>,
         method: SymbolWithPos,
-        this: Box<ExprWithPos>,
+        this: ExprId,
     },
     New {
         class_name: SymbolWithPos,
     },
     Nil,
     Oper {
-        left: Box<ExprWithPos>,
+        left: ExprId,
         oper: OperatorWithPos,
-        right: Box<ExprWithPos>,
+        right: ExprId,
     },
     Record {
         fields: Vec<RecordFieldWithPos>,
         typ: SymbolWithPos,
     },
-    Sequence(Vec<ExprWithPos>),
+    Sequence(Vec<ExprId>),
     Str {
         value: String,
     },
     Subscript {
-        expr: Box<ExprWithPos>,
-        this: Box<ExprWithPos>,
+        expr: ExprId,
+        this: ExprId,
     },
     Variable(SymbolWithPos),
     While {
-        body: Box<ExprWithPos>,
-        test: Box<ExprWithPos>,
+        body: ExprId,
+        test: ExprId,
     },
 }
 
+/// Handle of an expression in the arena of its AST.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ExprId(u32);
+
 pub type ExprWithPos = WithPos<Expr>;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -119,7 +202,7 @@ pub type FieldWithPos = WithPos<Field>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct FuncDeclaration {
-    pub body: ExprWithPos,
+    pub body: ExprId,
     pub name: SymbolWithPos,
     pub params: Vec<FieldWithPos>,
     pub result: Option<SymbolWithPos>,
@@ -147,7 +230,7 @@ pub type OperatorWithPos = WithPos<Operator>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct RecordField {
-    pub expr: ExprWithPos,
+    pub expr: ExprId,
     pub ident: Symbol,
 }
 
@@ -161,9 +244,17 @@ pub enum Ty {
     Name {
         ident: SymbolWithPos,
     },
+    /// Raw pointer to a value of type `ident` which is not tracked by the collector.
+    Pointer {
+        ident: SymbolWithPos,
+    },
     Record {
         fields: Vec<FieldWithPos>,
     },
+    /// Reference to a value of type `ident` which does not prevent its collection.
+    Weak {
+        ident: SymbolWithPos,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -176,6 +267,14 @@ pub type TypeDecWithPos = WithPos<TypeDec>;
 
 pub type TyWithPos = WithPos<Ty>;
 
-pub fn dummy_var_expr(symbol: Symbol) -> ExprWithPos {
-    WithPos::dummy(Expr::Variable(WithPos::dummy(symbol)))
+/// Where a field or a method of a class can be accessed from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    /// Only in the body of the class declaring it.
+    Private,
+    Public,
+}
+
+pub fn dummy_var_expr(arena: &mut Arena, symbol: Symbol) -> ExprId {
+    arena.alloc(WithPos::dummy(Expr::Variable(WithPos::dummy(symbol))))
 }
@@ -0,0 +1,354 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Tree of the AST, printed by `--dump-ast`.
+//!
+//! Each node is on its own line, below its parent and indented one level more, with the position
+//! it was parsed at. The children are named by their role when the kind of the node does not make
+//! it obvious:
+//!
+//! ```text
+//! If 1:1
+//!     test: Oper < 1:4
+//!         Variable a 1:4
+//!         Int 2 1:8
+//!     then: Call print 1:15
+//!         Str "small" 1:21
+//! ```
+
+use ast::{Arena, Ast, Declaration, DeclarationWithPos, Expr, ExprId, FieldWithPos, Ty, TyWithPos, Visibility};
+use position::Pos;
+use printer;
+use symbol::{Symbol, Symbols};
+
+const INDENT: &str = "    ";
+
+pub fn dump(ast: &Ast, symbols: &Symbols<()>) -> String {
+    let mut dumper = Dumper {
+        arena: &ast.arena,
+        depth: 0,
+        output: String::new(),
+        symbols,
+    };
+    dumper.expr("", ast.root);
+    dumper.output
+}
+
+struct Dumper<'a> {
+    arena: &'a Arena,
+    depth: usize,
+    output: String,
+    symbols: &'a Symbols<()>,
+}
+
+impl<'a> Dumper<'a> {
+    fn declaration(&mut self, role: &str, declaration: &DeclarationWithPos) {
+        match declaration.node {
+            Declaration::ClassDeclaration { ref members, ref name, ref parent_class } => {
+                let text = format!("Class {} extends {}", self.name(name.node), self.name(parent_class.node));
+                self.line(role, &text, declaration.pos);
+                self.depth += 1;
+                for member in members {
+                    let mut role = String::new();
+                    if member.visibility == Visibility::Private {
+                        role.push_str("private ");
+                    }
+                    if member.is_static {
+                        role.push_str("static ");
+                    }
+                    self.declaration(role.trim_end(), &member.declaration);
+                }
+                self.depth -= 1;
+            },
+            Declaration::Function(ref functions) => {
+                for function in functions {
+                    let mut text = format!("Function {}({})", self.name(function.node.name.node),
+                        self.fields(&function.node.params));
+                    if let Some(ref result) = function.node.result {
+                        text.push_str(&format!(": {}", self.name(result.node)));
+                    }
+                    self.line(role, &text, function.pos);
+                    self.children(|dumper| dumper.expr("", function.node.body));
+                }
+            },
+            Declaration::Type(ref types) => {
+                for typ in types {
+                    let text = format!("Type {} = {}", self.name(typ.node.name.node), self.ty(&typ.node.ty));
+                    self.line(role, &text, typ.pos);
+                }
+            },
+            Declaration::VariableDeclaration { escape, init, loop_variable, name, ref typ } => {
+                let mut text = format!("Var {}", self.name(name));
+                if let Some(ref typ) = *typ {
+                    text.push_str(&format!(": {}", self.name(typ.node)));
+                }
+                if escape {
+                    text.push_str(" escape");
+                }
+                if loop_variable {
+                    text.push_str(" loop");
+                }
+                self.line(role, &text, declaration.pos);
+                self.children(|dumper| dumper.expr("", init));
+            },
+        }
+    }
+
+    fn children<F: FnOnce(&mut Self)>(&mut self, dump: F) {
+        self.depth += 1;
+        dump(self);
+        self.depth -= 1;
+    }
+
+    fn expr(&mut self, role: &str, expr: ExprId) {
+        let arena = self.arena;
+        let pos = arena[expr].pos;
+        match arena[expr].node {
+            Expr::AddressOf { var } => {
+                self.line(role, "AddressOf", pos);
+                self.children(|dumper| dumper.expr("", var));
+            },
+            Expr::Array { init, size, ref typ } => {
+                let text = format!("Array {}", self.name(typ.node));
+                self.line(role, &text, pos);
+                self.children(|dumper| {
+                    dumper.expr("size", size);
+                    dumper.expr("init", init);
+                });
+            },
+            Expr::Assign { expr, var } => {
+                self.line(role, "Assign", pos);
+                self.children(|dumper| {
+                    dumper.expr("var", var);
+                    dumper.expr("value", expr);
+                });
+            },
+            Expr::Break => self.line(role, "Break", pos),
+            Expr::Call { ref args, function } => {
+                let text = format!("Call {}", self.name(function));
+                self.line(role, &text, pos);
+                self.children(|dumper| dumper.exprs(args));
+            },
+            Expr::Case { ref arms, else_, subject } => {
+                self.line(role, "Case", pos);
+                self.children(|dumper| {
+                    dumper.expr("subject", subject);
+                    for arm in arms {
+                        let role = arm.node.label.clone();
+                        dumper.expr(&role, arm.node.body);
+                    }
+                    if let Some(else_) = else_ {
+                        dumper.expr("else", else_);
+                    }
+                });
+            },
+            Expr::Deref { pointer } => {
+                self.line(role, "Deref", pos);
+                self.children(|dumper| dumper.expr("", pointer));
+            },
+            Expr::Field { ref ident, this } => {
+                let text = format!("Field {}", self.name(ident.node));
+                self.line(role, &text, pos);
+                self.children(|dumper| dumper.expr("", this));
+            },
+            Expr::If { else_, test, then } => {
+                self.line(role, "If", pos);
+                self.children(|dumper| {
+                    dumper.expr("test", test);
+                    dumper.expr("then", then);
+                    if let Some(else_) = else_ {
+                        dumper.expr("else", else_);
+                    }
+                });
+            },
+            Expr::Int { value } => self.line(role, &format!("Int {}", value), pos),
+            Expr::Let { body, ref declarations } => {
+                self.line(role, "Let", pos);
+                self.children(|dumper| {
+                    for declaration in declarations {
+                        dumper.declaration("", declaration);
+                    }
+                    dumper.expr("in", body);
+                });
+            },
+            Expr::MethodCall { ref args, ref method, this } => {
+                let text = format!("MethodCall {}", self.name(method.node));
+                self.line(role, &text, pos);
+                self.children(|dumper| {
+                    dumper.expr("this", this);
+                    dumper.exprs(args);
+                });
+            },
+            Expr::New { ref class_name } => {
+                let text = format!("New {}", self.name(class_name.node));
+                self.line(role, &text, pos);
+            },
+            Expr::Nil => self.line(role, "Nil", pos),
+            Expr::Oper { left, ref oper, right } => {
+                self.line(role, &format!("Oper {}", printer::operator(oper.node)), pos);
+                self.children(|dumper| {
+                    dumper.expr("", left);
+                    dumper.expr("", right);
+                });
+            },
+            Expr::Record { ref fields, ref typ } => {
+                let text = format!("Record {}", self.name(typ.node));
+                self.line(role, &text, pos);
+                self.children(|dumper| {
+                    for field in fields {
+                        let role = dumper.name(field.node.ident);
+                        dumper.expr(&role, field.node.expr);
+                    }
+                });
+            },
+            Expr::Sequence(ref exprs) => {
+                self.line(role, "Sequence", pos);
+                self.children(|dumper| dumper.exprs(exprs));
+            },
+            Expr::Str { ref value } => self.line(role, &format!("Str {:?}", value), pos),
+            Expr::Subscript { expr, this } => {
+                self.line(role, "Subscript", pos);
+                self.children(|dumper| {
+                    dumper.expr("", this);
+                    dumper.expr("index", expr);
+                });
+            },
+            Expr::Variable(ref name) => {
+                let text = format!("Variable {}", self.name(name.node));
+                self.line(role, &text, pos);
+            },
+            Expr::While { body, test } => {
+                self.line(role, "While", pos);
+                self.children(|dumper| {
+                    dumper.expr("test", test);
+                    dumper.expr("do", body);
+                });
+            },
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[ExprId]) {
+        for &expr in exprs {
+            self.expr("", expr);
+        }
+    }
+
+    fn fields(&self, fields: &[FieldWithPos]) -> String {
+        fields.iter()
+            .map(|field| format!("{}: {}", self.name(field.node.name), self.name(field.node.typ.node)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Write a node, with its position unless the rewriter created it.
+    fn line(&mut self, role: &str, text: &str, pos: Pos) {
+        for _ in 0..self.depth {
+            self.output.push_str(INDENT);
+        }
+        if !role.is_empty() {
+            self.output.push_str(role);
+            self.output.push_str(": ");
+        }
+        self.output.push_str(text);
+        if !pos.is_dummy() {
+            self.output.push_str(&format!(" {}:{}", pos.line, pos.column));
+        }
+        self.output.push('\n');
+    }
+
+    fn name(&self, symbol: Symbol) -> String {
+        self.symbols.name(symbol)
+    }
+
+    fn ty(&self, ty: &TyWithPos) -> String {
+        match ty.node {
+            Ty::Array { ref ident } => format!("array of {}", self.name(ident.node)),
+            Ty::Name { ref ident } => self.name(ident.node),
+            Ty::Pointer { ref ident } => format!("pointer {}", self.name(ident.node)),
+            Ty::Record { ref fields } => format!("{{{}}}", self.fields(fields)),
+            Ty::Weak { ref ident } => format!("weak {}", self.name(ident.node)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dialect::Dialect;
+    use lexer::Lexer;
+    use parser::Parser;
+    use symbol::{Strings, Symbols};
+    use super::dump;
+
+    fn dump_source(source: &str) -> String {
+        let mut symbols = Symbols::new(Arc::new(Strings::new()));
+        let file_symbol = symbols.symbol("no_file");
+        // The lexer expects a character after the last token.
+        let source = format!("{}\n", source);
+        let lexer = Lexer::new(source.as_bytes(), file_symbol);
+        let mut parser = Parser::new(lexer, &mut symbols);
+        parser.set_dialect(Dialect::Unsafe);
+        let ast = parser.parse().expect("parse");
+        dump(&ast, &symbols)
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!(dump_source("if a < 2 then print(\"small\")"), "\
+If 1:1
+    test: Oper < 1:4
+        Variable a 1:4
+        Int 2 1:8
+    then: Call print 1:15
+        Str \"small\" 1:21
+");
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(dump_source("a := 1 - 2 * 3 | b"), "\
+Assign 1:1
+    var: Variable a 1:1
+    value: Oper | 1:6
+        Oper - 1:6
+            Int 1 1:6
+            Oper * 1:10
+                Int 2 1:10
+                Int 3 1:14
+        Variable b 1:18
+");
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(dump_source("let class C extends Object { private static var n := 0 method get(): int = n } in 0 end"), "\
+Let 1:1
+    Class C extends Object 1:5
+        private static: Var n 1:45
+            Int 0 1:54
+        Function get(): int 1:56
+            Variable n 1:76
+    in: Sequence 1:83
+        Int 0 1:83
+");
+    }
+}
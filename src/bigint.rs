@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Arbitrary-precision integers.
+//!
+//! A big integer is stored in an array of integers managed by the garbage collector: the first
+//! element is 1 for a negative number and 0 otherwise, followed by the limbs in base 10^9, least
+//! significant first, without leading zero limbs.
+//! The operands are copied before allocating the result since an allocation can move the heap.
+//! Objects in the heap are not necessarily aligned, hence the unaligned accesses.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Add, Mul, Neg, Sub};
+use std::ptr;
+
+use collector::{Layout, GARBAGE_COLLECTOR};
+use data_layout::ARRAY_DATA_LAYOUT_SIZE;
+use WORD_SIZE;
+
+const BASE: u64 = 1_000_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigInt {
+    limbs: Vec<u64>,
+    negative: bool,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut limbs: Vec<u64>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        // Zero is never negative.
+        let negative = negative && !limbs.is_empty();
+        Self {
+            limbs,
+            negative,
+        }
+    }
+
+    pub fn read(ptr: i64) -> Self {
+        let ptr = ptr as *const u64;
+        unsafe {
+            let length = ptr::read_unaligned(ptr.add(1)) as usize / WORD_SIZE;
+            let elements = ptr.add(ARRAY_DATA_LAYOUT_SIZE);
+            let limbs = (1..length).map(|index| ptr::read_unaligned(elements.add(index))).collect();
+            Self::new(ptr::read_unaligned(elements) != 0, limbs)
+        }
+    }
+
+    pub fn allocate(&self) -> i64 {
+        let length = self.limbs.len() + 1;
+        let ptr = GARBAGE_COLLECTOR.with(|collector| {
+            collector.borrow_mut().allocate(Layout::Array(length, false))
+        });
+        unsafe {
+            let elements = (ptr as *mut u64).add(ARRAY_DATA_LAYOUT_SIZE);
+            ptr::write_unaligned(elements, self.negative as u64);
+            for (index, &limb) in self.limbs.iter().enumerate() {
+                ptr::write_unaligned(elements.add(index + 1), limb);
+            }
+        }
+        ptr
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(num: i64) -> Self {
+        let mut magnitude = num.unsigned_abs();
+        let mut limbs = vec![];
+        while magnitude != 0 {
+            limbs.push(magnitude % BASE);
+            magnitude /= BASE;
+        }
+        Self::new(num < 0, limbs)
+    }
+}
+
+fn compare_magnitudes(left: &[u64], right: &[u64]) -> Ordering {
+    left.len().cmp(&right.len())
+        .then_with(|| left.iter().rev().cmp(right.iter().rev()))
+}
+
+fn add_magnitudes(left: &[u64], right: &[u64]) -> Vec<u64> {
+    let mut result = vec![];
+    let mut carry = 0;
+    for index in 0..left.len().max(right.len()) {
+        let sum = left.get(index).unwrap_or(&0) + right.get(index).unwrap_or(&0) + carry;
+        result.push(sum % BASE);
+        carry = sum / BASE;
+    }
+    result.push(carry);
+    result
+}
+
+// The magnitude of `left` must be greater or equal to the magnitude of `right`.
+fn sub_magnitudes(left: &[u64], right: &[u64]) -> Vec<u64> {
+    let mut result = vec![];
+    let mut borrow = 0;
+    for (index, &limb) in left.iter().enumerate() {
+        let subtrahend = right.get(index).unwrap_or(&0) + borrow;
+        if limb >= subtrahend {
+            result.push(limb - subtrahend);
+            borrow = 0;
+        }
+        else {
+            result.push(limb + BASE - subtrahend);
+            borrow = 1;
+        }
+    }
+    result
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitudes(&self.limbs, &other.limbs));
+        }
+        match compare_magnitudes(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::new(other.negative, sub_magnitudes(&other.limbs, &self.limbs)),
+            _ => BigInt::new(self.negative, sub_magnitudes(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.limbs.clone())
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut result = vec![0; self.limbs.len() + other.limbs.len()];
+        for (i, &left) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &right) in other.limbs.iter().enumerate() {
+                let product = result[i + j] + left * right + carry;
+                result[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            result[i + other.limbs.len()] += carry;
+        }
+        BigInt::new(self.negative != other.negative, result)
+    }
+}
+
+impl Eq for BigInt {
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.limbs, &other.limbs),
+            (true, true) => compare_magnitudes(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self.limbs.split_last() {
+            None => write!(formatter, "0"),
+            Some((most_significant, rest)) => {
+                if self.negative {
+                    write!(formatter, "-")?;
+                }
+                write!(formatter, "{}", most_significant)?;
+                for limb in rest.iter().rev() {
+                    write!(formatter, "{:09}", limb)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
@@ -19,22 +19,79 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::cmp::Reverse;
 use std::collections::{
     HashMap,
     HashSet,
     VecDeque,
 };
 use std::mem;
+use std::slice;
 
+use effects::{check_order, effects};
+use frame::Frame;
 use ir::{
+    BinOp,
     Exp,
     RelationalOp,
     Statement,
     _Statement,
 };
+use opt_level::OptLevel;
 use temp::{Label, Temp};
 
+/// Functions which end the program, so that the blocks calling them are rarely executed.
+const COLD_FUNCTIONS: &[&str] = &["exit"];
+
+/// Static estimate of how likely each edge of a conditional jump is taken, used to choose the
+/// block following the jump in a trace when there is no profile data. The edge with the biggest
+/// sum of weights is preferred, and the false edge wins ties.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BranchWeights {
+    /// Added to an edge to a block calling a function which ends the program.
+    pub cold_call: i32,
+    /// Added to the edge taken when an operand is equal to a constant.
+    pub equal_constant: i32,
+    /// Added to an edge from which the jump can be reached again, i.e. staying in a loop.
+    pub loop_edge: i32,
+}
+
+impl BranchWeights {
+    /// Parse weights overriding the default ones, like `loop-edge=4,cold-call=-8`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut weights = Self::default();
+        for item in spec.split(',') {
+            let (name, value) = item.split_once('=')?;
+            let value = value.trim().parse().ok()?;
+            match name.trim() {
+                "cold-call" => weights.cold_call = value,
+                "equal-constant" => weights.equal_constant = value,
+                "loop-edge" => weights.loop_edge = value,
+                _ => return None,
+            }
+        }
+        Some(weights)
+    }
+}
+
+impl Default for BranchWeights {
+    fn default() -> Self {
+        Self {
+            cold_call: -8,
+            equal_constant: -2,
+            loop_edge: 4,
+        }
+    }
+}
+
 pub fn linearize(statement: Statement) -> Vec<Statement> {
+    let expected_effects =
+        if cfg!(debug_assertions) {
+            Some(effects(slice::from_ref(&statement)))
+        }
+        else {
+            None
+        };
     let statement = do_statement(statement);
 
     fn linear(statement: Statement, result: &mut Vec<Statement>) {
@@ -49,6 +106,9 @@ pub fn linearize(statement: Statement) -> Vec<Statement> {
 
     let mut result = vec![];
     linear(statement, &mut result);
+    if let Some(expected_effects) = expected_effects {
+        check_order(&expected_effects, &result);
+    }
     result
 }
 
@@ -99,7 +159,122 @@ pub fn basic_blocks(statements: Vec<Statement>) -> (Vec<Vec<Statement>>, Label)
     (basic_blocks, done)
 }
 
-pub fn trace_schedule(mut basic_blocks: Vec<Vec<Statement>>, done_label: Label) -> Vec<Statement> {
+/// Source line each basic block starts in, which is the last one marked before it. The trace
+/// scheduling moves the blocks away from the markers preceding them.
+pub fn block_lines(basic_blocks: &[Vec<Statement>]) -> HashMap<Label, u32> {
+    let mut lines = HashMap::new();
+    let mut line = None;
+    for basic_block in basic_blocks {
+        if let (Some(&Statement { statement: _Statement::Label(ref label), .. }), Some(line)) = (basic_block.first(), line) {
+            lines.insert(label.clone(), line);
+        }
+        for statement in basic_block {
+            if let _Statement::Position(position) = statement.statement {
+                line = Some(position);
+            }
+        }
+    }
+    lines
+}
+
+/// Make the conditional jumps to a block consisting only of a conditional jump on the same
+/// condition (`L1: if a < b goto L2 else L3`) target the destination that condition is known to
+/// take, and remove those blocks when they are no longer reached.
+pub fn thread_jumps(basic_blocks: Vec<Vec<Statement>>) -> Vec<Vec<Statement>> {
+    let mut branches = HashMap::new();
+    for basic_block in &basic_blocks {
+        // Position markers do not generate code, so they do not prevent the block from being skipped.
+        let mut code = basic_block.iter()
+            .filter(|statement|
+                match statement.statement {
+                    _Statement::Position(_) => false,
+                    _ => true,
+                }
+            );
+        if let (Some(label), Some(branch), None) = (code.next(), code.next(), code.next()) {
+            if let _Statement::Label(ref label) = label.statement {
+                if let _Statement::CondJump { ref op, ref left, ref right, ref true_label, ref false_label } = branch.statement {
+                    if !contains_call(left) && !contains_call(right) {
+                        let _ = branches.insert(label.clone(), (op.clone(), left, right, true_label, false_label));
+                    }
+                }
+            }
+        }
+    }
+
+    // Follow the branches as long as the outcome of the condition is known.
+    let resolve = |label: &Label, op: &RelationalOp, left: &Exp, right: &Exp, outcome: bool| -> Label {
+        let mut visited = HashSet::new();
+        let mut label = label.clone();
+        while let Some(&(ref branch_op, branch_left, branch_right, true_label, false_label)) = branches.get(&label) {
+            // Stop on cycles like `L1: if a < b goto L1 else L2`.
+            if branch_left != left || branch_right != right || !visited.insert(label.clone()) {
+                break;
+            }
+            label =
+                if branch_op == op {
+                    if outcome { true_label.clone() } else { false_label.clone() }
+                }
+                else if *branch_op == negate_condition(op.clone()) {
+                    if outcome { false_label.clone() } else { true_label.clone() }
+                }
+                else {
+                    break;
+                };
+        }
+        label
+    };
+
+    let threaded: Vec<Vec<Statement>> = basic_blocks.iter()
+        .map(|basic_block|
+            basic_block.iter()
+                .map(|statement|
+                    match statement.statement {
+                        _Statement::CondJump { ref op, ref left, ref right, ref true_label, ref false_label } =>
+                            Statement {
+                                stack_var: statement.stack_var,
+                                statement: _Statement::CondJump {
+                                    false_label: resolve(false_label, op, left, right, false),
+                                    left: left.clone(),
+                                    op: op.clone(),
+                                    right: right.clone(),
+                                    true_label: resolve(true_label, op, left, right, true),
+                                },
+                            },
+                        _ => statement.clone(),
+                    }
+                )
+                .collect()
+        )
+        .collect();
+
+    let mut referenced_labels = HashSet::new();
+    for statement in threaded.iter().flatten() {
+        match statement.statement {
+            _Statement::Jump(_, ref labels) => referenced_labels.extend(labels.iter().cloned()),
+            _Statement::CondJump { ref false_label, ref true_label, .. } => {
+                referenced_labels.insert(false_label.clone());
+                referenced_labels.insert(true_label.clone());
+            },
+            _ => (),
+        }
+    }
+
+    // The first block is the entry of the function, so it is always kept.
+    threaded.into_iter()
+        .enumerate()
+        .filter(|&(index, ref basic_block)|
+            match basic_block.first().expect("at least one statement in basic block").statement {
+                _Statement::Label(ref label) =>
+                    index == 0 || !branches.contains_key(label) || referenced_labels.contains(label),
+                _ => true,
+            }
+        )
+        .map(|(_, basic_block)| basic_block)
+        .collect()
+}
+
+pub fn trace_schedule(mut basic_blocks: Vec<Vec<Statement>>, done_label: Label, weights: &BranchWeights) -> Vec<Statement> {
     let mut label_mapping = HashMap::new();
     label_mapping.insert(&done_label, usize::max_value());
     for (index, basic_block) in basic_blocks.iter().enumerate() {
@@ -114,30 +289,62 @@ pub fn trace_schedule(mut basic_blocks: Vec<Vec<Statement>>, done_label: Label)
     let mut marks = HashSet::new();
     let mut traces = vec![];
     let mut current_trace;
-    for (mut index, basic_block) in basic_blocks.iter().enumerate() {
+    for start in 0..basic_blocks.len() {
+        let mut index = start;
         current_trace = vec![];
         while !marks.contains(&index) {
             marks.insert(index);
             current_trace.push(index);
 
-            match basic_block.last().expect("at least one instruction in basic block").statement {
-                _Statement::CondJump { ref true_label, ref false_label, .. } => {
-                    if let Some(&next_index) = marks.get(&label_mapping[false_label]) {
-                        index = next_index;
-                    }
-                    else if let Some(&next_index) = marks.get(&label_mapping[true_label]) {
-                        index = next_index;
-                    }
-                },
-                _Statement::Jump(_, ref labels) => {
-                    for label in labels {
-                        if let Some(&next_index) = marks.get(&label_mapping[label]) {
-                            index = next_index;
-                            break;
-                        }
-                    }
-                },
-                _ => panic!("Expected jump as last statement of basic blocks"),
+            // Prefer the likeliest label as the next block in the trace so that the conditional
+            // jump can fall through to it.
+            let next_index =
+                match basic_blocks[index].last().expect("at least one instruction in basic block").statement {
+                    _Statement::CondJump { ref op, ref left, ref right, ref true_label, ref false_label } => {
+                        let equal_constant =
+                            match (constant_value(left), constant_value(right)) {
+                                (None, None) => None,
+                                _ =>
+                                    match *op {
+                                        RelationalOp::Equal => Some(true),
+                                        RelationalOp::NotEqual => Some(false),
+                                        _ => None,
+                                    },
+                            };
+                        let weight = |label: &Label, outcome: bool| -> i32 {
+                            let target = label_mapping[label];
+                            if target == usize::MAX {
+                                return 0;
+                            }
+                            let mut weight = 0;
+                            if calls_cold_function(&basic_blocks[target]) {
+                                weight += weights.cold_call;
+                            }
+                            if equal_constant == Some(outcome) {
+                                weight += weights.equal_constant;
+                            }
+                            if reaches(&basic_blocks, &label_mapping, target, index) {
+                                weight += weights.loop_edge;
+                            }
+                            weight
+                        };
+                        let mut labels = [(false_label, weight(false_label, false)), (true_label, weight(true_label, true))];
+                        // The sort is stable, so the false label stays first on ties.
+                        labels.sort_by_key(|&(_, weight)| Reverse(weight));
+                        labels.iter()
+                            .map(|&(label, _)| label_mapping[label])
+                            .find(|index| *index != usize::MAX && !marks.contains(index))
+                    },
+                    _Statement::Jump(_, ref labels) =>
+                        labels.iter()
+                            .map(|label| label_mapping[label])
+                            .find(|index| *index != usize::MAX && !marks.contains(index)),
+                    _ => panic!("Expected jump as last statement of basic blocks"),
+                };
+
+            match next_index {
+                Some(next_index) => index = next_index,
+                None => break,
             }
         }
         if !current_trace.is_empty() {
@@ -155,6 +362,7 @@ pub fn trace_schedule(mut basic_blocks: Vec<Vec<Statement>>, done_label: Label)
             }
         }
     }
+    statements.push_back(_Statement::Label(done_label).into());
 
     let mut new_statements = vec![];
 
@@ -236,12 +444,378 @@ pub fn trace_schedule(mut basic_blocks: Vec<Vec<Statement>>, done_label: Label)
         current = statements.pop_front();
     }
 
-    new_statements.push(_Statement::Label(done_label).into());
+    new_statements
+}
+
+/// Check if the basic block calls a function ending the program.
+fn calls_cold_function(basic_block: &[Statement]) -> bool {
+    basic_block.iter()
+        .any(|statement|
+            match statement.statement {
+                _Statement::Exp(Exp::Call { function_expr: box Exp::Name(ref label), .. })
+                | _Statement::Move(_, Exp::Call { function_expr: box Exp::Name(ref label), .. }) =>
+                    COLD_FUNCTIONS.contains(&label.to_string().as_str()),
+                _ => false,
+            }
+        )
+}
+
+/// Check if the basic block at index `end` can be reached from the one at index `start`.
+fn reaches(basic_blocks: &[Vec<Statement>], label_mapping: &HashMap<&Label, usize>, start: usize, end: usize) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![start];
+    while let Some(index) = stack.pop() {
+        if index == end {
+            return true;
+        }
+        if index == usize::MAX || !visited.insert(index) {
+            continue;
+        }
+        match basic_blocks[index].last().expect("at least one instruction in basic block").statement {
+            _Statement::CondJump { ref true_label, ref false_label, .. } => {
+                stack.push(label_mapping[false_label]);
+                stack.push(label_mapping[true_label]);
+            },
+            _Statement::Jump(_, ref labels) => stack.extend(labels.iter().map(|label| label_mapping[label])),
+            _ => (),
+        }
+    }
+    false
+}
+
+/// Make the jumps target the end of the chains of labels aliased by an unconditional jump (`L1: jmp
+/// L2`) and remove the labels that are no longer referenced as well as the unreachable blocks.
+pub fn eliminate_jump_chains(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut aliases = HashMap::new();
+    // Position markers do not generate code, so they do not prevent a label from being an alias.
+    let code: Vec<_> = statements.iter()
+        .filter(|statement|
+            match statement.statement {
+                _Statement::Position(_) => false,
+                _ => true,
+            }
+        )
+        .collect();
+    for window in code.windows(2) {
+        if let _Statement::Label(ref label) = window[0].statement {
+            match window[1].statement {
+                _Statement::Label(ref target) => {
+                    let _ = aliases.insert(label.clone(), target.clone());
+                },
+                _Statement::Jump(Exp::Name(ref target), ref labels) if labels.len() == 1 => {
+                    let _ = aliases.insert(label.clone(), target.clone());
+                },
+                _ => (),
+            }
+        }
+    }
+
+    let resolve = |label: Label| -> Label {
+        let mut visited = HashSet::new();
+        let mut label = label;
+        while let Some(target) = aliases.get(&label) {
+            // Stop on cycles like `L1: jmp L1`.
+            if !visited.insert(label.clone()) {
+                break;
+            }
+            label = target.clone();
+        }
+        label
+    };
+
+    let statements: Vec<_> = statements.into_iter()
+        .map(|statement| {
+            let stack_var = statement.stack_var;
+            let new_statement =
+                match statement.statement {
+                    _Statement::Jump(Exp::Name(label), labels) => {
+                        if labels.len() == 1 {
+                            let label = resolve(label);
+                            _Statement::Jump(Exp::Name(label.clone()), vec![label])
+                        }
+                        else {
+                            _Statement::Jump(Exp::Name(label), labels)
+                        }
+                    },
+                    // The false label is kept since the code falls through to it.
+                    _Statement::CondJump { op, left, right, false_label, true_label } =>
+                        _Statement::CondJump {
+                            false_label,
+                            left,
+                            op,
+                            right,
+                            true_label: resolve(true_label),
+                        },
+                    statement => statement,
+                };
+            Statement {
+                statement: new_statement,
+                stack_var,
+            }
+        })
+        .collect();
+
+    let mut referenced_labels = HashSet::new();
+    for statement in &statements {
+        match statement.statement {
+            _Statement::Jump(_, ref labels) => referenced_labels.extend(labels.iter().cloned()),
+            _Statement::CondJump { ref false_label, ref true_label, .. } => {
+                referenced_labels.insert(false_label.clone());
+                referenced_labels.insert(true_label.clone());
+            },
+            _ => (),
+        }
+    }
+
+    let mut new_statements: Vec<Statement> = vec![];
+    let mut reachable = true;
+    for statement in statements {
+        match statement.statement {
+            _Statement::Label(ref label) => {
+                // Labels that are not the target of any jump only delimit an empty block.
+                if !referenced_labels.contains(label) {
+                    continue;
+                }
+                reachable = true;
+                // Remove unconditional jumps to the next statement.
+                let jumps_to_label =
+                    match new_statements.last() {
+                        Some(&Statement { statement: _Statement::Jump(Exp::Name(ref target), ref labels), .. }) =>
+                            labels.len() == 1 && target == label,
+                        _ => false,
+                    };
+                if jumps_to_label {
+                    new_statements.pop();
+                }
+            },
+            _Statement::Jump(_, _) => {
+                if !reachable {
+                    continue;
+                }
+                reachable = false;
+            },
+            _ => {
+                if !reachable {
+                    continue;
+                }
+            },
+        }
+        new_statements.push(statement);
+    }
 
     new_statements
 }
 
-fn negate_condition(op: RelationalOp) -> RelationalOp {
+/// Store directly the constants and the temporaries moved to a temporary which is only read by the
+/// next statement, storing it in memory. The fields of a record are initialized this way, so that
+/// their stores become consecutive stores at offsets of the record address, of immediates for the
+/// constant fields.
+pub fn combine_field_stores<F: Frame>(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut reads: HashMap<Temp, usize> = HashMap::new();
+    for statement in &statements {
+        let temps =
+            match statement.statement {
+                _Statement::Move(Exp::Temp(_), ref source) => source.temps(),
+                _ => statement.temps(),
+            };
+        for temp in temps {
+            *reads.entry(temp).or_default() += 1;
+        }
+    }
+
+    let mut result: Vec<Statement> = Vec::with_capacity(statements.len());
+    for statement in statements {
+        if let _Statement::Move(Exp::Mem(ref address), Exp::Temp(value)) = statement.statement {
+            let stored_value =
+                match result.last() {
+                    Some(&Statement {
+                        stack_var: None,
+                        statement: _Statement::Move(Exp::Temp(temp), ref source @ (Exp::Const(_) | Exp::Temp(_))),
+                    }) if temp == value && reads.get(&temp) == Some(&1) && F::special_name(temp).is_none() &&
+                        !address.temps().contains(&temp) => Some(source.clone()),
+                    _ => None,
+                };
+            if let Some(stored_value) = stored_value {
+                result.pop();
+                result.push(Statement {
+                    stack_var: statement.stack_var,
+                    statement: _Statement::Move(Exp::Mem(address.clone()), stored_value),
+                });
+                continue;
+            }
+        }
+        result.push(statement);
+    }
+    result
+}
+
+/// Schedule the statements of each basic block to reduce the number of temporaries live at the
+/// same time: the move to a temporary read by a single statement of the block is moved right
+/// before that statement, as long as it does not cross a statement changing its operands. Since
+/// the instruction selection evaluates the operand needing the most registers first, this orders
+/// the evaluation of the expressions split by the canonicalization like Sethi-Ullman would.
+pub fn schedule_for_pressure<F: Frame>(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut reads: HashMap<Temp, usize> = HashMap::new();
+    let mut definitions: HashMap<Temp, usize> = HashMap::new();
+    for statement in &statements {
+        let temps =
+            match statement.statement {
+                _Statement::Move(Exp::Temp(temp), ref source) => {
+                    *definitions.entry(temp).or_default() += 1;
+                    source.temps()
+                },
+                _ => statement.temps(),
+            };
+        for temp in temps {
+            *reads.entry(temp).or_default() += 1;
+        }
+    }
+
+    let mut result = Vec::with_capacity(statements.len());
+    let mut block = vec![];
+    for statement in statements {
+        let ends_block = matches!(statement.statement, _Statement::CondJump { .. } | _Statement::Jump(_, _));
+        if let _Statement::Label(_) = statement.statement {
+            result.extend(schedule_block::<F>(mem::take(&mut block), &reads, &definitions));
+        }
+        block.push(statement);
+        if ends_block {
+            result.extend(schedule_block::<F>(mem::take(&mut block), &reads, &definitions));
+        }
+    }
+    result.extend(schedule_block::<F>(block, &reads, &definitions));
+    result
+}
+
+fn schedule_block<F: Frame>(mut block: Vec<Statement>, reads: &HashMap<Temp, usize>,
+    definitions: &HashMap<Temp, usize>) -> Vec<Statement>
+{
+    // NOTE: going backward, the moves to the operands of a moved statement follow it.
+    for index in (0..block.len()).rev() {
+        let (temp, source) =
+            match block[index] {
+                Statement { stack_var: None, statement: _Statement::Move(Exp::Temp(temp), ref source) }
+                    if reads.get(&temp) == Some(&1) && definitions.get(&temp) == Some(&1) &&
+                        F::special_name(temp).is_none() && !contains_call(source) => (temp, source),
+                _ => continue,
+            };
+        let operands = source.temps();
+        let pure = is_pure(source);
+        let mut destination = None;
+        for (next_index, statement) in block.iter().enumerate().skip(index + 1) {
+            if statement.temps().contains(&temp) {
+                destination = Some(next_index);
+                break;
+            }
+            let changes_operands =
+                match statement.statement {
+                    _Statement::Move(Exp::Temp(defined), ref value) =>
+                        operands.contains(&defined) || contains_call(value),
+                    // A load or a division must not move after a store, which it could see or
+                    // which would happen before it faults.
+                    _Statement::Move(_, ref value) => !pure || contains_call(value),
+                    _Statement::Exp(ref expr) => contains_call(expr),
+                    _ => false,
+                };
+            if changes_operands {
+                break;
+            }
+        }
+        if let Some(destination) = destination {
+            let statement = block.remove(index);
+            block.insert(destination - 1, statement);
+        }
+    }
+    block
+}
+
+/// Run the passes of the optimization level on the linearized statements of a function.
+pub fn optimize<F: Frame>(statements: Vec<Statement>, opt_level: OptLevel) -> Vec<Statement> {
+    let mut statements = statements;
+    if opt_level >= OptLevel::O2 {
+        statements = fold_constant_jumps(statements);
+    }
+    if opt_level >= OptLevel::O1 {
+        statements = eliminate_dead_moves::<F>(statements);
+    }
+    statements
+}
+
+/// Replace the conditional jumps comparing constants by a jump to the label they always take, so
+/// that the other target becomes unreachable.
+fn fold_constant_jumps(statements: Vec<Statement>) -> Vec<Statement> {
+    statements.into_iter()
+        .map(|statement| {
+            if let _Statement::CondJump { ref op, ref left, ref right, ref true_label, ref false_label } = statement.statement {
+                if let (Some(left), Some(right)) = (constant_value(left), constant_value(right)) {
+                    let label = if compare(op, left, right) { true_label } else { false_label };
+                    return _Statement::Jump(Exp::Name(label.clone()), vec![label.clone()]).into();
+                }
+            }
+            statement
+        })
+        .collect()
+}
+
+/// Remove the moves to temporaries which are never read and the expression statements, when
+/// computing their value has no effect. The registers are read by the calls and the return, so
+/// the moves to them are kept.
+fn eliminate_dead_moves<F: Frame>(mut statements: Vec<Statement>) -> Vec<Statement> {
+    loop {
+        let mut used_temps = HashSet::new();
+        for statement in &statements {
+            match statement.statement {
+                _Statement::Move(Exp::Temp(_), ref source) => used_temps.extend(source.temps()),
+                _ => used_temps.extend(statement.temps()),
+            }
+        }
+        let statement_count = statements.len();
+        statements.retain(|statement| {
+            match statement.statement {
+                _Statement::Move(Exp::Temp(temp), ref source) if statement.stack_var.is_none() =>
+                    used_temps.contains(&temp) || F::special_name(temp).is_some() || !is_pure(source),
+                _Statement::Exp(ref expr) => !is_pure(expr),
+                _ => true,
+            }
+        });
+        // NOTE: removing a move can make the moves to its operands dead.
+        if statements.len() == statement_count {
+            return statements;
+        }
+    }
+}
+
+/// Whether computing the expression has no effect: it calls no function, reads no memory, which
+/// could fault, and does not divide, which could trap.
+fn is_pure(expr: &Exp) -> bool {
+    match *expr {
+        Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => true,
+        Exp::BinOp { op: BinOp::Div, .. } => false,
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+            is_pure(left) && is_pure(right),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+            is_pure(left) && is_pure(right) && is_pure(if_true) && is_pure(if_false),
+        Exp::Call { .. } | Exp::ExpSequence(_, _) | Exp::Mem(_) | Exp::MemByte(_) => false,
+    }
+}
+
+/// Result of the comparison of two constants.
+fn compare(op: &RelationalOp, left: i64, right: i64) -> bool {
+    match *op {
+        RelationalOp::Equal => left == right,
+        RelationalOp::GreaterOrEqual => left >= right,
+        RelationalOp::GreaterThan => left > right,
+        RelationalOp::LesserOrEqual => left <= right,
+        RelationalOp::LesserThan => left < right,
+        RelationalOp::NotEqual => left != right,
+        RelationalOp::UnsignedGreaterOrEqual => left as u64 >= right as u64,
+        RelationalOp::UnsignedGreaterThan => left as u64 > right as u64,
+        RelationalOp::UnsignedLesserOrEqual => left as u64 <= right as u64,
+        RelationalOp::UnsignedLesserThan => (left as u64) < right as u64,
+    }
+}
+
+pub fn negate_condition(op: RelationalOp) -> RelationalOp {
     match op {
         RelationalOp::Equal => RelationalOp::NotEqual,
         RelationalOp::GreaterOrEqual => RelationalOp::LesserThan,
@@ -256,19 +830,44 @@ fn negate_condition(op: RelationalOp) -> RelationalOp {
     }
 }
 
+pub fn contains_call(expr: &Exp) -> bool {
+    match *expr {
+        Exp::Call { .. } | Exp::ExpSequence(_, _) => true,
+        Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => false,
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+            contains_call(left) || contains_call(right),
+        Exp::Mem(ref expr) | Exp::MemByte(ref expr) => contains_call(expr),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+            contains_call(left) || contains_call(right) || contains_call(if_true) || contains_call(if_false),
+    }
+}
+
 fn commute(expr1: &Statement, expr2: &Exp) -> bool {
     match (&expr1.statement, expr2) {
-        (&_Statement::Exp(Exp::Const(_)), _) => true,
+        (statement, _) if is_nop(statement) => true,
         (_, &Exp::Name(_)) => true,
         (_, &Exp::Const(_)) => true,
         _ => false,
     }
 }
 
+/// Check if the statement only contains position markers, besides at most one constant expression.
+fn is_nop(statement: &_Statement) -> bool {
+    match *statement {
+        _Statement::Exp(Exp::Const(_)) | _Statement::Position(_) => true,
+        _Statement::Sequence(box Statement { statement: _Statement::Position(_), .. }, ref statement)
+        | _Statement::Sequence(ref statement, box Statement { statement: _Statement::Position(_), .. }) =>
+            is_nop(&statement.statement),
+        _ => false,
+    }
+}
+
 fn reorder1(expr: Exp) -> (Statement, Exp) {
     do_expression(expr)
 }
 
+/// Pull the statements out of two expressions while keeping the left-to-right evaluation order:
+/// `expr1` is saved in a temporary when the statements of `expr2` could change its value.
 fn reorder2(expr1: Exp, expr2: Exp) -> (Statement, Exp, Exp) {
     if let Exp::Call { .. } = expr1 {
         let temp = Temp::new();
@@ -298,6 +897,10 @@ fn reorder2(expr1: Exp, expr2: Exp) -> (Statement, Exp, Exp) {
     }
 }
 
+/// Pull the statements out of a list of expressions, like the function and the arguments of a
+/// call, while keeping the left-to-right evaluation order: the calls are saved in temporaries in
+/// order and an expression is saved in a temporary when the statements of the following ones could
+/// change its value.
 fn reorder(mut exprs: VecDeque<Exp>) -> (Statement, VecDeque<Exp>) {
     if exprs.is_empty() {
         return (_Statement::Exp(Exp::Const(0)).into(), VecDeque::new());
@@ -409,6 +1012,11 @@ fn do_statement(statement: Statement) -> Statement {
                 statement: _Statement::Move(Exp::Mem(Box::new(mem)), expr),
                 stack_var,
             }),
+        _Statement::Move(Exp::MemByte(mem), expr) =>
+            reorder_statement2(*mem, expr, |mem, expr| Statement {
+                statement: _Statement::Move(Exp::MemByte(Box::new(mem)), expr),
+                stack_var,
+            }),
         _Statement::Move(Exp::ExpSequence(statement, expr1), expr2) =>
             do_statement(Statement {
                 statement: _Statement::Sequence(statement, Box::new(_Statement::Move(*expr1, expr2).into())),
@@ -444,13 +1052,30 @@ fn do_statement(statement: Statement) -> Statement {
 fn do_expression(expr: Exp) -> (Statement, Exp) {
     match expr {
         Exp::BinOp { op, left, right } =>
-            reorder_expression2(*left, *right, |left, right| Exp::BinOp {
+            reorder_expression2(*left, *right, |left, right| reassociate(op, left, right)),
+        Exp::Mem(expr) =>
+            reorder_expression1(*expr, |expr| Exp::Mem(Box::new(expr))),
+        Exp::MemByte(expr) =>
+            reorder_expression1(*expr, |expr| Exp::MemByte(Box::new(expr))),
+        Exp::Compare { op, left, right } =>
+            reorder_expression2(*left, *right, |left, right| Exp::Compare {
                 op,
                 left: Box::new(left),
                 right: Box::new(right),
             }),
-        Exp::Mem(expr) =>
-            reorder_expression1(*expr, |expr| Exp::Mem(Box::new(expr))),
+        Exp::Select { op, left, right, if_true, if_false } => {
+            let exprs = vec![*left, *right, *if_true, *if_false].into_iter().collect();
+            reorder_expression(exprs, |mut exprs| {
+                let mut next = || Box::new(exprs.pop_front().expect("pop front"));
+                Exp::Select {
+                    op,
+                    left: next(),
+                    right: next(),
+                    if_true: next(),
+                    if_false: next(),
+                }
+            })
+        },
         Exp::ExpSequence(statement, expr) => {
             let statements1 = do_statement(*statement);
             let (statements2, expr) = do_expression(*expr);
@@ -475,6 +1100,71 @@ fn do_expression(expr: Exp) -> (Statement, Exp) {
     }
 }
 
+/// Fold the constants of a binary operation and reassociate it to move the constants to the right
+/// operand, so that (x + 1) + 2 becomes x + 3.
+fn reassociate(op: BinOp, left: Exp, right: Exp) -> Exp {
+    match (op, left, right) {
+        (op, Exp::Const(left), Exp::Const(right)) =>
+            match fold_constants(&op, left, right) {
+                Some(value) => Exp::Const(value),
+                None => Exp::BinOp {
+                    op,
+                    left: Box::new(Exp::Const(left)),
+                    right: Box::new(Exp::Const(right)),
+                },
+            },
+        (BinOp::Minus, left, Exp::Const(num)) if num != i64::MIN =>
+            reassociate(BinOp::Plus, left, Exp::Const(-num)),
+        (op, Exp::Const(num), right) if is_commutative(&op) =>
+            reassociate(op, right, Exp::Const(num)),
+        (BinOp::Plus, left, Exp::Const(0)) | (BinOp::Mul, left, Exp::Const(1)) => left,
+        (BinOp::Plus, Exp::BinOp { op: BinOp::Plus, left, right: box Exp::Const(num1) }, Exp::Const(num2)) =>
+            reassociate(BinOp::Plus, *left, Exp::Const(num1.wrapping_add(num2))),
+        (BinOp::Mul, Exp::BinOp { op: BinOp::Mul, left, right: box Exp::Const(num1) }, Exp::Const(num2)) =>
+            reassociate(BinOp::Mul, *left, Exp::Const(num1.wrapping_mul(num2))),
+        (op, left, right) => Exp::BinOp {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    }
+}
+
+fn is_commutative(op: &BinOp) -> bool {
+    match *op {
+        BinOp::And | BinOp::Mul | BinOp::Or | BinOp::Plus | BinOp::Xor => true,
+        BinOp::ArithmeticShiftRight | BinOp::Div | BinOp::Minus | BinOp::ShiftLeft | BinOp::ShiftRight => false,
+    }
+}
+
+/// Value of an expression made only of constants, if it can be computed at compile time.
+pub fn constant_value(expr: &Exp) -> Option<i64> {
+    match *expr {
+        Exp::Const(value) => Some(value),
+        Exp::BinOp { ref op, ref left, ref right } =>
+            fold_constants(op, constant_value(left)?, constant_value(right)?),
+        _ => None,
+    }
+}
+
+fn fold_constants(op: &BinOp, left: i64, right: i64) -> Option<i64> {
+    let value =
+        match *op {
+            BinOp::And => left & right,
+            BinOp::ArithmeticShiftRight => left.wrapping_shr(right as u32),
+            // Keep the division when it would trap at runtime.
+            BinOp::Div => return left.checked_div(right),
+            BinOp::Minus => left.wrapping_sub(right),
+            BinOp::Mul => left.wrapping_mul(right),
+            BinOp::Or => left | right,
+            BinOp::Plus => left.wrapping_add(right),
+            BinOp::ShiftLeft => left.wrapping_shl(right as u32),
+            BinOp::ShiftRight => (left as u64).wrapping_shr(right as u32) as i64,
+            BinOp::Xor => left ^ right,
+        };
+    Some(value)
+}
+
 fn append(statement1: Statement, statement2: Statement) -> Statement {
     match (&statement1.statement, &statement2.statement) {
         (&_Statement::Exp(Exp::Const(_)), _) => statement2,
@@ -485,8 +1175,21 @@ fn append(statement1: Statement, statement2: Statement) -> Statement {
 
 #[cfg(test)]
 mod tests {
-    use canon::linearize;
-    use ir::{Exp, _Statement};
+    use canon::{
+        BranchWeights,
+        combine_field_stores,
+        eliminate_jump_chains,
+        linearize,
+        optimize,
+        schedule_for_pressure,
+        thread_jumps,
+        trace_schedule,
+    };
+    use frame::Frame;
+    use frame::x86_64::X86_64;
+    use ir::{BinOp, Exp, RelationalOp, Statement, _Statement};
+    use opt_level::OptLevel;
+    use temp::{Label, Temp};
 
     #[test]
     fn test_rewrite_rules() {
@@ -497,4 +1200,314 @@ mod tests {
 
         println!("{:#?}", result);
     }
+
+    #[test]
+    fn test_reassociation() {
+        let temp = Temp::from_num(1000);
+        let plus = |left, right| Exp::BinOp { op: BinOp::Plus, left: Box::new(left), right: Box::new(right) };
+        let minus = |left, right| Exp::BinOp { op: BinOp::Minus, left: Box::new(left), right: Box::new(right) };
+
+        let expr = plus(Exp::Const(2), minus(plus(Exp::Temp(temp), Exp::Const(1)), Exp::Const(8)));
+        let result = linearize(_Statement::Exp(expr).into());
+        assert_eq!(result.last(), Some(&_Statement::Exp(plus(Exp::Temp(temp), Exp::Const(-5))).into()));
+
+        let expr = Exp::BinOp {
+            op: BinOp::Mul,
+            left: Box::new(Exp::Const(4)),
+            right: Box::new(Exp::BinOp { op: BinOp::Mul, left: Box::new(Exp::Temp(temp)), right: Box::new(Exp::Const(2)) }),
+        };
+        let result = linearize(_Statement::Exp(expr).into());
+        assert_eq!(result.last(), Some(&_Statement::Exp(Exp::BinOp {
+            op: BinOp::Mul,
+            left: Box::new(Exp::Temp(temp)),
+            right: Box::new(Exp::Const(8)),
+        }).into()));
+
+        let result = linearize(_Statement::Exp(minus(Exp::Const(10), plus(Exp::Const(3), Exp::Const(4)))).into());
+        assert_eq!(result.last(), Some(&_Statement::Exp(Exp::Const(3)).into()));
+    }
+
+    #[test]
+    fn test_optimize() {
+        let temp = |num| Exp::Temp(Temp::from_num(num));
+        let move_to = |num, source| -> Statement { _Statement::Move(temp(num), source).into() };
+        let true_label = Label::new();
+        let false_label = Label::new();
+        let statements = vec![
+            move_to(1000, Exp::Const(2)),
+            // Dead, and makes the move to t1000 dead.
+            move_to(1001, Exp::BinOp { op: BinOp::Plus, left: Box::new(temp(1000)), right: Box::new(Exp::Const(1)) }),
+            // A load can fault.
+            move_to(1002, Exp::Mem(Box::new(temp(1003)))),
+            _Statement::CondJump {
+                false_label: false_label.clone(),
+                left: Exp::Const(1),
+                op: RelationalOp::LesserThan,
+                right: Exp::Const(2),
+                true_label: true_label.clone(),
+            }.into(),
+            _Statement::Label(true_label.clone()).into(),
+            _Statement::Move(Exp::Temp(X86_64::return_value().temp()), Exp::Const(0)).into(),
+            _Statement::Label(false_label).into(),
+        ];
+
+        assert_eq!(optimize::<X86_64>(statements.clone(), OptLevel::O0), statements);
+        let mut expected = statements[2..].to_vec();
+        assert_eq!(optimize::<X86_64>(statements.clone(), OptLevel::O1), expected);
+        expected[1] = _Statement::Jump(Exp::Name(true_label.clone()), vec![true_label]).into();
+        assert_eq!(optimize::<X86_64>(statements, OptLevel::O2), expected);
+    }
+
+    #[test]
+    fn test_combine_field_stores() {
+        let temp = |num| Exp::Temp(Temp::from_num(num));
+        let field = |offset| Exp::Mem(Box::new(Exp::BinOp {
+            op: BinOp::Plus,
+            left: Box::new(temp(1000)),
+            right: Box::new(Exp::Const(offset)),
+        }));
+        let move_to = |destination, source| -> Statement { _Statement::Move(destination, source).into() };
+        let statements = vec![
+            move_to(temp(1001), Exp::Const(1)),
+            move_to(field(16), temp(1001)),
+            move_to(temp(1002), temp(1003)),
+            move_to(field(24), temp(1002)),
+            // Read again, so the value stays in the temporary.
+            move_to(temp(1004), Exp::Const(3)),
+            move_to(field(32), temp(1004)),
+            move_to(temp(1005), temp(1004)),
+            // Not only a constant or a temporary.
+            move_to(temp(1006), Exp::Mem(Box::new(temp(1003)))),
+            move_to(field(40), temp(1006)),
+        ];
+        let mut expected = vec![
+            move_to(field(16), Exp::Const(1)),
+            move_to(field(24), temp(1003)),
+        ];
+        expected.extend_from_slice(&statements[4..]);
+        assert_eq!(combine_field_stores::<X86_64>(statements), expected);
+    }
+
+    #[test]
+    fn test_schedule_for_pressure() {
+        let temp = |num| Exp::Temp(Temp::from_num(num));
+        let plus = |left, right| Exp::BinOp { op: BinOp::Plus, left: Box::new(left), right: Box::new(right) };
+        let move_to = |destination, source| -> Statement { _Statement::Move(destination, source).into() };
+        let load = |num| Exp::Mem(Box::new(temp(num)));
+        let statements = vec![
+            move_to(temp(1001), plus(temp(1000), Exp::Const(1))),
+            move_to(temp(1002), plus(temp(1000), Exp::Const(2))),
+            move_to(temp(1003), load(1000)),
+            // The load cannot move after this store.
+            move_to(load(1004), Exp::Const(0)),
+            move_to(temp(1005), plus(temp(1002), temp(1003))),
+            move_to(temp(1006), plus(temp(1001), temp(1005))),
+            _Statement::Label(Label::new()).into(),
+            move_to(temp(1007), temp(1006)),
+        ];
+        let expected = vec![
+            statements[2].clone(),
+            statements[3].clone(),
+            statements[1].clone(),
+            statements[4].clone(),
+            statements[0].clone(),
+            // Not moved to the next block.
+            statements[5].clone(),
+            statements[6].clone(),
+            statements[7].clone(),
+        ];
+        assert_eq!(schedule_for_pressure::<X86_64>(statements), expected);
+    }
+
+    #[test]
+    fn test_trace_schedule() {
+        let labels: Vec<_> = (0..4).map(|_| Label::new()).collect();
+        let done = Label::new();
+        let label = |index: usize| -> Statement { _Statement::Label(labels[index].clone()).into() };
+        let jump = |label: &Label| -> Statement { _Statement::Jump(Exp::Name(label.clone()), vec![label.clone()]).into() };
+        let cond_jump = |op| -> Statement {
+            _Statement::CondJump {
+                false_label: labels[2].clone(),
+                left: Exp::Temp(Temp::from_num(1000)),
+                op,
+                right: Exp::Const(0),
+                true_label: labels[1].clone(),
+            }.into()
+        };
+
+        let basic_blocks = vec![
+            vec![label(0), cond_jump(RelationalOp::Equal)],
+            vec![label(1), jump(&labels[3])],
+            vec![label(2), jump(&labels[3])],
+            vec![label(3), jump(&done)],
+        ];
+        let result = trace_schedule(basic_blocks, done.clone(), &BranchWeights::default());
+        assert_eq!(result, vec![
+            label(0),
+            cond_jump(RelationalOp::Equal),
+            label(2),
+            label(3),
+            jump(&done),
+            label(1),
+            jump(&labels[3]),
+            _Statement::Label(done.clone()).into(),
+        ]);
+
+        // The condition is negated when only the true label can follow the jump.
+        let basic_blocks = vec![
+            vec![label(2), jump(&done)],
+            vec![label(0), cond_jump(RelationalOp::LesserThan)],
+            vec![label(1), jump(&done)],
+        ];
+        let result = trace_schedule(basic_blocks, done.clone(), &BranchWeights::default());
+        assert_eq!(result, vec![
+            label(2),
+            jump(&done),
+            label(0),
+            _Statement::CondJump {
+                false_label: labels[1].clone(),
+                left: Exp::Temp(Temp::from_num(1000)),
+                op: RelationalOp::GreaterOrEqual,
+                right: Exp::Const(0),
+                true_label: labels[2].clone(),
+            }.into(),
+            label(1),
+            _Statement::Label(done).into(),
+        ]);
+    }
+
+    #[test]
+    fn test_branch_weights() {
+        let labels: Vec<_> = (0..4).map(|_| Label::new()).collect();
+        let done = Label::new();
+        let temp = Exp::Temp(Temp::from_num(1000));
+        let label = |index: usize| -> Statement { _Statement::Label(labels[index].clone()).into() };
+        let jump = |label: &Label| -> Statement { _Statement::Jump(Exp::Name(label.clone()), vec![label.clone()]).into() };
+        let cond_jump = |op, right, true_label: usize, false_label: usize| -> Statement {
+            _Statement::CondJump {
+                false_label: labels[false_label].clone(),
+                left: temp.clone(),
+                op,
+                right,
+                true_label: labels[true_label].clone(),
+            }.into()
+        };
+        let exit: Statement = _Statement::Exp(Exp::Call {
+            arguments: vec![Exp::Const(1)],
+            collectable_return_type: false,
+            function_expr: Box::new(Exp::Name(Label::with_name("exit"))),
+            return_label: Label::new(),
+        }).into();
+
+        // The body of a loop follows its test.
+        let basic_blocks = vec![
+            vec![label(0), cond_jump(RelationalOp::LesserThan, Exp::Temp(Temp::from_num(1001)), 1, 2)],
+            vec![label(1), _Statement::Exp(temp.clone()).into(), jump(&labels[0])],
+            vec![label(2), jump(&done)],
+        ];
+        let result = trace_schedule(basic_blocks, done.clone(), &BranchWeights::default());
+        assert_eq!(result[..3], [
+            label(0),
+            cond_jump(RelationalOp::GreaterOrEqual, Exp::Temp(Temp::from_num(1001)), 2, 1),
+            label(1),
+        ]);
+
+        // A block ending the program, reached when an operand is equal to a constant, is moved out of the way.
+        let basic_blocks = vec![
+            vec![label(0), cond_jump(RelationalOp::NotEqual, Exp::Const(0), 1, 2)],
+            vec![label(1), jump(&labels[3])],
+            vec![label(2), exit.clone(), jump(&labels[3])],
+            vec![label(3), jump(&done)],
+        ];
+        let result = trace_schedule(basic_blocks.clone(), done.clone(), &BranchWeights::default());
+        assert_eq!(result[..3], [label(0), cond_jump(RelationalOp::Equal, Exp::Const(0), 2, 1), label(1)]);
+
+        // Without weights, the false label follows the jump.
+        let weights = BranchWeights::parse("cold-call=0, equal-constant=0").expect("weights");
+        let result = trace_schedule(basic_blocks, done, &weights);
+        assert_eq!(result[..3], [label(0), cond_jump(RelationalOp::NotEqual, Exp::Const(0), 1, 2), label(2)]);
+
+        assert_eq!(BranchWeights::parse("loop-edge"), None);
+        assert_eq!(BranchWeights::parse("hot=1"), None);
+    }
+
+    #[test]
+    fn test_thread_jumps() {
+        let labels: Vec<_> = (0..6).map(|_| Label::new()).collect();
+        let label = |index: usize| -> Statement { _Statement::Label(labels[index].clone()).into() };
+        let jump = |index: usize| -> Statement {
+            _Statement::Jump(Exp::Name(labels[index].clone()), vec![labels[index].clone()]).into()
+        };
+        let cond_jump = |op, true_label: usize, false_label: usize| -> Statement {
+            _Statement::CondJump {
+                false_label: labels[false_label].clone(),
+                left: Exp::Temp(Temp::from_num(1000)),
+                op,
+                right: Exp::Const(0),
+                true_label: labels[true_label].clone(),
+            }.into()
+        };
+
+        let basic_blocks = vec![
+            vec![label(0), cond_jump(RelationalOp::Equal, 1, 2)],
+            vec![label(1), _Statement::Position(3).into(), cond_jump(RelationalOp::Equal, 3, 4)],
+            vec![label(2), cond_jump(RelationalOp::NotEqual, 5, 4)],
+            vec![label(3), jump(5)],
+            vec![label(4), jump(5)],
+            vec![label(5), jump(0)],
+        ];
+        assert_eq!(thread_jumps(basic_blocks), vec![
+            vec![label(0), cond_jump(RelationalOp::Equal, 3, 5)],
+            vec![label(3), jump(5)],
+            vec![label(4), jump(5)],
+            vec![label(5), jump(0)],
+        ]);
+
+        // A branch on another condition is kept.
+        let basic_blocks = vec![
+            vec![label(0), cond_jump(RelationalOp::Equal, 1, 2)],
+            vec![label(1), cond_jump(RelationalOp::LesserThan, 3, 2)],
+            vec![label(2), jump(3)],
+            vec![label(3), jump(0)],
+        ];
+        assert_eq!(thread_jumps(basic_blocks.clone()), basic_blocks);
+    }
+
+    #[test]
+    fn test_eliminate_jump_chains() {
+        let labels: Vec<_> = (0..5).map(|_| Label::new()).collect();
+        let label = |index: usize| -> Statement { _Statement::Label(labels[index].clone()).into() };
+        let jump = |index: usize| -> Statement {
+            _Statement::Jump(Exp::Name(labels[index].clone()), vec![labels[index].clone()]).into()
+        };
+        let cond_jump = |true_label: usize| -> Statement {
+            _Statement::CondJump {
+                false_label: labels[1].clone(),
+                left: Exp::Temp(Temp::from_num(1000)),
+                op: RelationalOp::Equal,
+                right: Exp::Const(0),
+                true_label: labels[true_label].clone(),
+            }.into()
+        };
+        let exp = || -> Statement { _Statement::Exp(Exp::Temp(Temp::from_num(1000))).into() };
+
+        let statements = vec![
+            label(0),
+            cond_jump(2),
+            label(1),
+            jump(3),
+            label(2),
+            jump(3),
+            label(3),
+            jump(4),
+            label(4),
+            exp(),
+        ];
+        assert_eq!(eliminate_jump_chains(statements), vec![
+            cond_jump(4),
+            label(1),
+            label(4),
+            exp(),
+        ]);
+    }
 }
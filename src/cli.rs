@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Reading of the command line.
+//!
+//! The first argument can be a subcommand. An option takes its value from the next argument or
+//! after `=`, like `--target=x86_64-linux` or `--target x86_64-linux`, and the arguments after
+//! `--` are left to the caller.
+
+use std::iter::Peekable;
+
+pub struct Arguments<I: Iterator<Item = String>> {
+    args: Peekable<I>,
+    // Last option and the value given to it after `=`.
+    value: Option<(String, String)>,
+}
+
+impl<I: Iterator<Item = String>> Arguments<I> {
+    pub fn new(args: I) -> Self {
+        Arguments {
+            args: args.peekable(),
+            value: None,
+        }
+    }
+
+    /// The first argument, when it is one of the subcommands.
+    pub fn subcommand(&mut self, subcommands: &[(&str, &str)]) -> Option<String> {
+        self.args.next_if(|arg| subcommands.iter().any(|&(name, _)| name == arg))
+    }
+
+    /// The next option, without the value given to it after `=`, or file.
+    pub fn next(&mut self) -> Option<String> {
+        let arg = self.args.next()?;
+        if arg.starts_with("--") {
+            if let Some((option, value)) = arg.split_once('=') {
+                self.value = Some((option.to_string(), value.to_string()));
+                return Some(option.to_string());
+            }
+        }
+        Some(arg)
+    }
+
+    /// The arguments after `--`.
+    pub fn rest(&mut self) -> Vec<String> {
+        self.args.by_ref().collect()
+    }
+
+    /// The last option and the value given to it after `=`, when it takes none.
+    pub fn unused_value(&mut self) -> Option<(String, String)> {
+        self.value.take()
+    }
+
+    /// The value of the last option.
+    pub fn value(&mut self) -> Option<String> {
+        match self.value.take() {
+            Some((_, value)) => Some(value),
+            None => self.args.next(),
+        }
+    }
+}
+
+/// Text printed by `--help`, with a line for each subcommand and each option, whose name is
+/// followed by the placeholder of its value.
+pub fn usage(subcommands: &[(&str, &str)], options: &[(&str, &str)]) -> String {
+    let names = subcommands.iter().chain(options).map(|&(name, _)| name.len());
+    let width = names.max().unwrap_or(0);
+    let mut usage = "Usage: tiger [COMMAND] [OPTIONS] PROGRAM.tig [LIBRARY.tig...] [-- ARGS...]\n".to_string();
+    for &(title, entries) in &[("Commands", subcommands), ("Options", options)] {
+        usage.push_str(&format!("\n{}:\n", title));
+        for &(name, description) in entries {
+            usage.push_str(&format!("    {:width$}  {}\n", name, description, width = width));
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arguments, usage};
+
+    fn arguments(args: &[&str]) -> Arguments<impl Iterator<Item = String>> {
+        Arguments::new(args.iter().map(ToString::to_string).collect::<Vec<_>>().into_iter())
+    }
+
+    #[test]
+    fn test_values() {
+        let mut args = arguments(&["--target=x86_64-linux", "-o", "out", "--debug", "file.tig", "--", "--debug"]);
+        assert_eq!(args.next().as_deref(), Some("--target"));
+        assert_eq!(args.value().as_deref(), Some("x86_64-linux"));
+        assert_eq!(args.next().as_deref(), Some("-o"));
+        assert_eq!(args.value().as_deref(), Some("out"));
+        assert_eq!(args.next().as_deref(), Some("--debug"));
+        assert_eq!(args.unused_value(), None);
+        assert_eq!(args.next().as_deref(), Some("file.tig"));
+        assert_eq!(args.next().as_deref(), Some("--"));
+        assert_eq!(args.rest(), ["--debug"]);
+        assert_eq!(args.next(), None);
+
+        let mut args = arguments(&["--debug=yes"]);
+        assert_eq!(args.next().as_deref(), Some("--debug"));
+        assert_eq!(args.unused_value(), Some(("--debug".to_string(), "yes".to_string())));
+    }
+
+    #[test]
+    fn test_subcommand() {
+        let subcommands = [("build", ""), ("check", "")];
+        assert_eq!(arguments(&["check", "file.tig"]).subcommand(&subcommands).as_deref(), Some("check"));
+        assert_eq!(arguments(&["file.tig", "check"]).subcommand(&subcommands), None);
+    }
+
+    #[test]
+    fn test_usage() {
+        assert_eq!(usage(&[("run", "Run it")], &[("--color WHEN", "Color"), ("-c", "Object")]), "\
+Usage: tiger [COMMAND] [OPTIONS] PROGRAM.tig [LIBRARY.tig...] [-- ARGS...]
+
+Commands:
+    run           Run it
+
+Options:
+    --color WHEN  Color
+    -c            Object
+");
+    }
+}
@@ -21,38 +21,95 @@
 
 // TODO: use the list encoded in the code to navigate the stack instead of rbp?
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::CStr;
+use std::mem;
 use std::os::raw::{c_char, c_void};
 use std::ptr;
+use std::slice;
 use std::arch::asm;
+use std::time::Instant;
 
 use data_layout::{
     ARRAY_DATA_LAYOUT_SIZE,
+    ARRAY_TYPE,
     CLASS_DATA_LAYOUT_SIZE,
+    CONSERVATIVE_GC,
     RECORD_DATA_LAYOUT_SIZE,
+    RECORD_TYPE,
     STRING_DATA_LAYOUT_SIZE,
     STRING_TYPE,
+    VTABLE_OFFSET,
 };
+use stack_trace::runtime_error;
 use super::{string_offset, WORD_SIZE};
 
 const SHOW_STATS: bool = false;
 
+const CLASS_TYPE: usize = 3;
+
+// Memory reserved for the heap by the conservative collector, which cannot move the objects when
+// the heap grows since it does not know which words are pointers.
+const CONSERVATIVE_HEAP_RESERVE: usize = 1 << 30;
+
+// Number of words of unused stack cleared before entering the collector in conservative mode, which
+// covers the frames of the runtime down to the scan of the stack.
+const CLEARED_STACK_WORDS: usize = 1024;
+
+// Frame pointer of main, recorded when it calls checkVersions: the conservative collector scans the
+// stack up to it.
+static mut STACK_BASE: usize = 0;
+
+// Bump pointer of the heap and its end, used by the allocations inlined in the generated code: they
+// bump __tiger_heap_next themselves as long as it stays below __tiger_heap_limit and only enter
+// the runtime otherwise. Both are 0 until the collector is created, so that the first allocation
+// goes through the runtime.
+#[no_mangle]
+static mut __tiger_heap_next: usize = 0;
+#[no_mangle]
+static mut __tiger_heap_limit: usize = 0;
+
+// Site of the allocations made by the runtime itself, e.g. for string concatenation.
+const RUNTIME_SITE: &[u8] = b"runtime\0";
+
+/// Tables the compiler writes in each object file, found through the section gathering them.
+#[repr(C)]
+pub struct Module {
+    // data_layout::VERSION of the compiler, first so that it stays readable when the rest changes.
+    pub version: usize,
+    pub pointer_map: *const usize,
+    // Address ending the entries of the tables.
+    pub end_marker: usize,
+    pub static_roots: *const usize,
+    // Return address, function name and source line of each call site.
+    pub frame_info: *const usize,
+    pub source_file: *const c_char,
+    // data_layout::PRECISE_GC or data_layout::CONSERVATIVE_GC.
+    pub gc: usize,
+}
+
+/// The tables of all the object files of the program.
+pub fn modules() -> &'static [Module] {
+    unsafe {
+        let start = &__start_tiger_modules as *const Module;
+        let stop = &__stop_tiger_modules as *const Module;
+        slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    }
+}
+
 #[derive(Debug)]
 pub enum Layout {
     Array(usize, bool),
+    // Array of bytes packed one per byte instead of one per word.
+    ByteArray(usize),
     Class(*const c_char),
     Record(*const c_char),
     String(usize),
 }
 
-const ARRAY_TYPE: usize = 0;
-const RECORD_TYPE: usize = 1;
-const CLASS_TYPE: usize = 3;
-
 impl Layout {
-    fn write_repr(&self, mut ptr: *mut usize) {
+    pub fn write_repr(&self, mut ptr: *mut usize) {
         unsafe {
             match *self {
                 Layout::Array(length, is_pointer) => {
@@ -62,6 +119,13 @@ impl Layout {
                     ptr = ptr.offset(1);
                     ptr::write(ptr, is_pointer as usize);
                 },
+                Layout::ByteArray(length) => {
+                    ptr::write(ptr, ARRAY_TYPE);
+                    ptr = ptr.offset(1);
+                    ptr::write(ptr, length);
+                    ptr = ptr.offset(1);
+                    ptr::write(ptr, 0);
+                },
                 Layout::Class(data_layout) => {
                     ptr::write(ptr, CLASS_TYPE);
                     ptr = ptr.offset(1);
@@ -84,6 +148,7 @@ impl Layout {
     fn size(&self) -> usize {
         match *self {
             Layout::Array(length, _) => (length + ARRAY_DATA_LAYOUT_SIZE) * WORD_SIZE,
+            Layout::ByteArray(length) => length + ARRAY_DATA_LAYOUT_SIZE * WORD_SIZE,
             Layout::Class(data_layout) => {
                 let string_ptr = string_offset(data_layout);
                 let fields = unsafe { CStr::from_ptr(string_ptr) };
@@ -104,6 +169,7 @@ impl Layout {
 
 thread_local! {
     pub static GARBAGE_COLLECTOR: RefCell<Collector> = RefCell::new(Collector::new());
+    static IN_FINALIZER: Cell<bool> = const { Cell::new(false) };
 }
 
 #[derive(Clone, Debug)]
@@ -123,6 +189,14 @@ impl Stack {
 }
 
 pub struct Collector {
+    // Scan the stack and the registers for anything that looks like a pointer instead of using the
+    // pointer maps, because an object file was compiled without them.
+    conservative: bool,
+    // Unreachable objects whose finalizer was not called yet. They stay alive until it is.
+    finalization_queue: Vec<usize>,
+    // Objects whose finalizer was queued: their finalizer is not called again when they are
+    // resurrected by it and become unreachable later.
+    finalized: HashSet<usize>,
     // Free lists from size to index into the heap.
     freelists: BTreeMap<usize, Vec<usize>>,
     freelist_size: HashMap<usize, usize>, // Offset -> size.
@@ -130,6 +204,14 @@ pub struct Collector {
     heap_length: usize,
     marks: HashSet<usize>,
     pointer_map: HashMap<usize, Vec<Stack>>,
+    // Weak references, which are objects of the heap holding the address of their target without
+    // keeping it alive: it is reset to 0 when the target is collected. They are forgotten once
+    // they are unreachable themselves, so that the collector only tracks the live ones.
+    weak_references: Vec<usize>,
+    // Addresses of the static fields holding pointers.
+    static_roots: Vec<usize>,
+    // Print a line for each collection and heap growth when TIGER_GC_LOG is set.
+    log: bool,
 
     // Stats.
     allocated: usize,
@@ -149,13 +231,30 @@ impl Collector {
     fn new() -> Self {
         let pointer_map = fetch_pointer_map();
         let capacity = std::env::var("TIGER_GC_CAPACITY").map(|str| str.parse().expect("gc capacity")).unwrap_or(4096);
+        let conservative = modules().iter().any(|module| module.gc == CONSERVATIVE_GC);
+        let heap =
+            if conservative {
+                // NOTE: the heap grows in the memory reserved here, so that its objects never move.
+                let mut heap = Vec::with_capacity(CONSERVATIVE_HEAP_RESERVE.max(capacity));
+                heap.resize(capacity, 0);
+                heap
+            }
+            else {
+                vec![0; capacity]
+            };
         Self {
+            conservative,
+            finalization_queue: vec![],
+            finalized: HashSet::new(),
             freelists: BTreeMap::new(),
             freelist_size: HashMap::new(),
-            heap: vec![0; capacity],
+            heap,
             heap_length: 0,
+            log: std::env::var_os("TIGER_GC_LOG").is_some(),
             marks: HashSet::new(),
             pointer_map,
+            static_roots: fetch_static_roots(),
+            weak_references: vec![],
 
             allocated: 0,
             deallocated: 0,
@@ -163,12 +262,32 @@ impl Collector {
     }
 
     pub fn allocate(&mut self, data_layout: Layout) -> i64 {
+        self.allocate_at(data_layout, CStr::from_bytes_with_nul(RUNTIME_SITE).expect("runtime site"))
+    }
+
+    /// Allocate for the allocation site `site`, which is only used in the logs, so it is only
+    /// decoded when they are enabled.
+    pub fn allocate_at(&mut self, data_layout: Layout, site: &CStr) -> i64 {
+        self.take_inline_allocations();
         let size = data_layout.size();
         if !self.has_allocation_spot(size) {
-            self.collect();
+            let start = Instant::now();
+            let live_before = self.live_bytes();
+            let roots = self.collect();
+            if self.log {
+                eprintln!("gc event=collect reason=no_free_spot site={} size={} heap={} live_before={} live_after={} roots={} weak_references={} pause_us={}",
+                    site.to_string_lossy(), size, self.heap.len(), live_before, self.live_bytes(), roots, self.weak_references.len(),
+                    start.elapsed().as_micros());
+            }
         }
         while !self.has_allocation_spot(size) {
-            self.grow_heap();
+            let start = Instant::now();
+            let heap_before = self.heap.len();
+            let roots = self.grow_heap();
+            if self.log {
+                eprintln!("gc event=grow reason=heap_full site={} size={} heap_before={} heap_after={} roots={} pause_us={}",
+                    site.to_string_lossy(), size, heap_before, self.heap.len(), roots, start.elapsed().as_micros());
+            }
         }
         let offset = self.grab_allocation_spot(size);
         self.allocated += size;
@@ -176,21 +295,116 @@ impl Collector {
             let ptr = self.heap.as_ptr().add(offset) as *mut usize;
 
             data_layout.write_repr(ptr);
+            self.publish_bump_pointer();
             ptr as i64
         }
     }
 
-    fn collect(&mut self) {
+    /// Account for the objects allocated by the generated code since the last call to the runtime.
+    fn take_inline_allocations(&mut self) {
+        let next = unsafe { __tiger_heap_next };
+        if next != 0 {
+            let heap_length = next - self.heap.as_ptr() as usize;
+            self.allocated += heap_length - self.heap_length;
+            self.heap_length = heap_length;
+        }
+    }
+
+    /// Let the generated code allocate in the free space at the end of the heap.
+    fn publish_bump_pointer(&self) {
+        let start = self.heap.as_ptr() as usize;
+        unsafe {
+            __tiger_heap_next = start + self.heap_length;
+            __tiger_heap_limit = start + self.heap.len();
+        }
+    }
+
+    /// Create a weak reference to the object at `pointer`.
+    pub fn weak_reference(&mut self, pointer: usize) -> usize {
+        // NOTE: allocating the reference can grow the heap and move the target with it.
+        self.take_inline_allocations();
+        let target_offset =
+            if self.in_heap(pointer) {
+                Some(pointer - self.heap.as_ptr() as usize)
+            }
+            else {
+                None
+            };
+        let reference = self.allocate(Layout::Array(1, false)) as usize;
+        let target = target_offset.map_or(pointer, |offset| self.heap.as_ptr() as usize + offset);
+        unsafe {
+            ptr::write_unaligned(weak_target_address(reference), target);
+        }
+        self.weak_references.push(reference);
+        reference
+    }
+
+    /// The target of the weak reference, or nil (0) if it was collected.
+    pub fn weak_target(&self, reference: usize) -> usize {
+        unsafe {
+            ptr::read_unaligned(weak_target_address(reference))
+        }
+    }
+
+    fn live_bytes(&self) -> usize {
+        self.allocated - self.deallocated
+    }
+
+    /// Collect the unreachable objects and return the number of roots scanned.
+    fn collect(&mut self) -> usize {
         // Mark.
-        let addresses = stack_return_addresses();
-        for address in addresses {
-            if let Some(roots) = self.pointer_map.get(&(address.return_address as usize)).cloned() {
-                for root in roots {
-                    self.dfs(root.to_value(address.base_stack));
+        let mut root_count = self.static_roots.len();
+        for root in self.static_roots.clone() {
+            self.dfs(unsafe { *(root as *const usize) });
+        }
+        if self.conservative {
+            let roots = self.conservative_roots();
+            root_count += roots.len();
+            for root in roots {
+                self.dfs(root);
+            }
+        }
+        else {
+            let addresses = stack_return_addresses();
+            for address in addresses {
+                if let Some(roots) = self.pointer_map.get(&(address.return_address as usize)).cloned() {
+                    root_count += roots.len();
+                    for root in roots {
+                        self.dfs(root.to_value(address.base_stack));
+                    }
+                }
+            }
+        }
+        root_count += self.finalization_queue.len();
+        for pointer in self.finalization_queue.clone() {
+            self.dfs(pointer);
+        }
+
+        // Clear the weak references to the unreachable objects before the finalizers can resurrect
+        // them.
+        for &reference in &self.weak_references {
+            let target = self.weak_target(reference);
+            if self.in_heap(target) && !self.marks.contains(&target) {
+                unsafe {
+                    ptr::write_unaligned(weak_target_address(reference), 0);
                 }
             }
         }
 
+        // Queue the finalizers of the unreachable objects, which are kept alive, with the objects
+        // they point to, until their finalizer is called.
+        for pointer in self.unreachable_objects() {
+            if finalizer(pointer).is_some() && self.finalized.insert(pointer) {
+                self.finalization_queue.push(pointer);
+                self.dfs(pointer);
+            }
+        }
+
+        // Forget the unreachable weak references, which are swept below. This is done after the
+        // finalizers are queued, since they can keep weak references alive.
+        let marks = &self.marks;
+        self.weak_references.retain(|reference| marks.contains(reference));
+
         // Sweep.
         let start = self.heap.as_ptr() as usize;
         let end = start + self.heap_length - 1;
@@ -207,6 +421,7 @@ impl Collector {
             }
             else {
                 self.deallocated += size;
+                self.finalized.remove(&pointer);
                 self.freelists.entry(size)
                     .or_default()
                     .push(offset);
@@ -214,6 +429,78 @@ impl Collector {
             }
             pointer += size;
         }
+
+        root_count
+    }
+
+    /// The objects pointed to by the words of the stack and of the callee-saved registers, which
+    /// keep alive more objects than the pointer maps would, since an integer can look like a
+    /// pointer. A word pointing inside an object keeps it alive too.
+    #[inline(never)]
+    fn conservative_roots(&self) -> Vec<usize> {
+        let mut objects = BTreeMap::new();
+        let start = self.heap.as_ptr() as usize;
+        let mut pointer = start;
+        while pointer < start + self.heap_length {
+            let offset = pointer - start;
+            if let Some(size) = self.freelist_size.get(&offset) {
+                pointer += size;
+                continue;
+            }
+            let size = size_of(pointer);
+            objects.insert(pointer, pointer + size);
+            pointer += size;
+        }
+
+        // NOTE: the Tiger functions keep their values in the callee-saved registers across the
+        // calls: the frames of the runtime either left them there or saved them in the stack below
+        // the frame of this function.
+        let registers: [usize; 5];
+        let stack_pointer: usize;
+        unsafe {
+            let (rbx, r12, r13, r14, r15): (usize, usize, usize, usize, usize);
+            // NOTE: one register per block, so that an output cannot overwrite a register not read
+            // yet.
+            asm!("mov {0}, rbx", out(reg) rbx);
+            asm!("mov {0}, r12", out(reg) r12);
+            asm!("mov {0}, r13", out(reg) r13);
+            asm!("mov {0}, r14", out(reg) r14);
+            asm!("mov {0}, r15", out(reg) r15);
+            registers = [rbx, r12, r13, r14, r15];
+            asm!("mov {0}, rsp", out(reg) stack_pointer);
+        }
+        let mut words = registers.to_vec();
+        let mut address = stack_pointer;
+        while address < stack_base() {
+            words.push(unsafe { *(address as *const usize) });
+            address += WORD_SIZE;
+        }
+
+        words.into_iter()
+            .filter_map(|word| objects.range(..=word).next_back()
+                .filter(|&(_, &end)| word < end)
+                .map(|(&object, _)| object))
+            .collect()
+    }
+
+    /// The allocated objects that were not marked.
+    fn unreachable_objects(&self) -> Vec<usize> {
+        let mut objects = vec![];
+        let start = self.heap.as_ptr() as usize;
+        let end = start + self.heap_length - 1;
+        let mut pointer = start;
+        while pointer <= end {
+            let offset = pointer - start;
+            if let Some(size) = self.freelist_size.get(&offset) {
+                pointer += size;
+                continue;
+            }
+            if !self.marks.contains(&pointer) {
+                objects.push(pointer);
+            }
+            pointer += size_of(pointer);
+        }
+        objects
     }
 
     fn dfs(&mut self, pointer: usize) {
@@ -354,13 +641,39 @@ impl Collector {
         }
     }
 
-    fn grow_heap(&mut self) {
+    /// Double the size of the heap and return the number of roots scanned to move the objects.
+    fn grow_heap(&mut self) -> usize {
+        if self.conservative {
+            let size = (self.heap.len() * 2).min(self.heap.capacity());
+            if size == self.heap.len() {
+                runtime_error(&format!("out of memory: the heap cannot grow beyond {} bytes with the conservative \
+                    collector", self.heap.capacity()));
+            }
+            // NOTE: the heap stays in the memory reserved for it, so the objects do not move.
+            self.heap.resize(size, 0);
+            return 0;
+        }
+
         let old_heap = self.heap.as_ptr() as usize;
 
         let addresses = stack_return_addresses();
         let mut locations = HashMap::new();
+        let mut root_count = self.static_roots.len() + self.finalization_queue.len();
+        for root in self.static_roots.clone() {
+            let pointer = unsafe { *(root as *const usize) };
+            if self.in_heap(pointer) {
+                locations.insert(root, pointer);
+                self.dfs_locations(pointer, &mut locations);
+            }
+        }
+        for index in 0..self.finalization_queue.len() {
+            let pointer = self.finalization_queue[index];
+            locations.insert(&self.finalization_queue[index] as *const usize as usize, pointer);
+            self.dfs_locations(pointer, &mut locations);
+        }
         for address in &addresses {
             if let Some(roots) = self.pointer_map.get(&(address.return_address as usize)).cloned() {
+                root_count += roots.len();
                 for root in roots {
                     let pointer = root.to_value(address.base_stack);
                     if self.in_heap(pointer) {
@@ -377,6 +690,19 @@ impl Collector {
 
         let start = self.heap.as_ptr() as usize;
         if start != old_heap {
+            self.finalized = self.finalized.iter()
+                .map(|&pointer| pointer - old_heap + start)
+                .collect();
+            for reference in &mut self.weak_references {
+                *reference = *reference - old_heap + start;
+                let target_address = weak_target_address(*reference);
+                unsafe {
+                    let target = ptr::read_unaligned(target_address);
+                    if target >= old_heap && target < old_heap + self.heap_length {
+                        ptr::write_unaligned(target_address, target - old_heap + start);
+                    }
+                }
+            }
             for (&location, &pointer) in &locations {
                 let offset = pointer - old_heap;
                 unsafe {
@@ -395,9 +721,11 @@ impl Collector {
         }
 
         self.marks.clear();
+
+        root_count
     }
 
-    fn in_heap(&self, value: usize) -> bool {
+    pub fn in_heap(&self, value: usize) -> bool {
         let start = self.heap.as_ptr() as usize;
         let end = start + self.heap_length - 1;
         value >= start && value <= end
@@ -413,30 +741,108 @@ fn array_contains_pointers(ptr: usize) -> bool {
 
 fn fetch_pointer_map() -> HashMap<usize, Vec<Stack>> {
     let mut pointer_map = HashMap::new();
-    unsafe {
-        let end_marker = &__tiger_pointer_map_end as *const _ as usize;
-        let mut pointer = &__tiger_pointer_map as *const usize;
-        loop {
+    for module in modules() {
+        unsafe {
+            let end_marker = module.end_marker;
+            let mut pointer = module.pointer_map;
+            loop {
             let address =
-                if *pointer == end_marker {
-                    break;
+                    if *pointer == end_marker {
+                        break;
+                    }
+                    else {
+                        *pointer
+                    };
+                pointer = pointer.offset(1);
+                let mut pointers = vec![];
+                while *pointer != end_marker {
+                    pointers.push(Stack(*pointer as i64));
+                    pointer = pointer.offset(1);
                 }
-                else {
-                    *pointer
-                };
-            pointer = pointer.offset(1);
-            let mut pointers = vec![];
-            while *pointer != end_marker {
-                pointers.push(Stack(*pointer as i64));
                 pointer = pointer.offset(1);
+                pointer_map.insert(address, pointers);
             }
-            pointer = pointer.offset(1);
-            pointer_map.insert(address, pointers);
         }
     }
     pointer_map
 }
 
+/// The finalizer of the object at `ptr` and its static link, if it is an instance of a class
+/// declaring `finalize`. The compiler writes two words before the vtable: the index of the
+/// finalizer in the vtable plus one, or 0 when there is none, and the index of the field holding
+/// its static link.
+fn finalizer(ptr: usize) -> Option<(usize, usize)> {
+    unsafe {
+        if *(ptr as *const usize) != CLASS_TYPE {
+            return None;
+        }
+        let vtable = *(ptr as *const usize).add(VTABLE_OFFSET) as *const usize;
+        let method = *vtable.offset(-2);
+        if method == 0 {
+            return None;
+        }
+        let link_field = *vtable.offset(-1);
+        Some((*vtable.add(method - 1), class_field(ptr, link_field)))
+    }
+}
+
+/// Call the finalizers queued by the collections.
+/// Like the signal handlers, they only run when the runtime is entered at a safepoint since they
+/// can allocate.
+pub fn run_finalizers() {
+    if IN_FINALIZER.with(|in_finalizer| in_finalizer.replace(true)) {
+        return;
+    }
+    loop {
+        // NOTE: the object stays in the queue, which is a root, while its finalizer runs.
+        let last = GARBAGE_COLLECTOR.with(|collector| {
+            let collector = collector.borrow();
+            collector.finalization_queue.last().map(|&object| (collector.finalization_queue.len() - 1, object))
+        });
+        let (index, object) =
+            match last {
+                Some(last) => last,
+                None => break,
+            };
+        if let Some((function, static_link)) = finalizer(object) {
+            // Methods take self as their first parameter and their static link as their last.
+            let function: extern "C" fn(usize, usize) = unsafe { mem::transmute(function) };
+            function(object, static_link);
+        }
+        GARBAGE_COLLECTOR.with(|collector| collector.borrow_mut().finalization_queue.remove(index));
+    }
+    IN_FINALIZER.with(|in_finalizer| in_finalizer.set(false));
+}
+
+/// Zero the unused stack below the caller, where the frames of an allocation are about to be pushed,
+/// so that the conservative collector does not find there the stale pointers left by the earlier
+/// calls into the runtime and keep their objects alive.
+#[inline(never)]
+pub fn clear_stack() {
+    if !GARBAGE_COLLECTOR.with(|collector| collector.borrow().conservative) {
+        return;
+    }
+    let mut words = [0usize; CLEARED_STACK_WORDS];
+    for word in &mut words {
+        // NOTE: volatile so that the writes to this unused array are not removed.
+        unsafe { ptr::write_volatile(word, 0) };
+    }
+}
+
+fn fetch_static_roots() -> Vec<usize> {
+    let mut roots = vec![];
+    for module in modules() {
+        unsafe {
+            let mut pointer = module.static_roots;
+            while *pointer != module.end_marker {
+                roots.push(*pointer);
+                pointer = pointer.offset(1);
+            }
+        }
+    }
+    roots
+}
+
 fn class_field(ptr: usize, index: usize) -> usize {
     unsafe {
         *class_field_address(ptr, index)
@@ -450,6 +856,11 @@ fn class_field_address(ptr: usize, index: usize) -> *const usize {
     }
 }
 
+/// Address of the target in a weak reference, which is an array of one integer.
+fn weak_target_address(reference: usize) -> *mut usize {
+    (reference + ARRAY_DATA_LAYOUT_SIZE * WORD_SIZE) as *mut usize
+}
+
 fn field(ptr: usize, index: usize) -> usize {
     unsafe {
         *field_address(ptr, index)
@@ -499,7 +910,7 @@ fn size_of(ptr: usize) -> usize {
     }
 }
 
-fn rbp() -> usize {
+pub fn rbp() -> usize {
     let result: usize;
     unsafe {
         asm!("mov {0}, rbp", out(reg) result)
@@ -513,6 +924,35 @@ struct StackAddresses {
     return_address: *const c_void,
 }
 
+/// Record the frame of main, which calls the caller of this function.
+#[inline(never)]
+pub fn record_stack_base() {
+    let frame_pointer: usize;
+    unsafe {
+        asm!("mov {0}, rbp", out(reg) frame_pointer);
+        let caller_frame_pointer = *(frame_pointer as *const usize);
+        STACK_BASE = *(caller_frame_pointer as *const usize);
+    }
+}
+
+/// The end of the frames to scan: the frame of main, or else, when main is not a Tiger program, like
+/// in a shared library, the outermost frame reached through the frame pointers.
+fn stack_base() -> usize {
+    let base = unsafe { STACK_BASE };
+    if base != 0 {
+        return base;
+    }
+    let mut base = rbp();
+    for address in stack_return_addresses() {
+        let frame_pointer = address.base_stack as usize;
+        if frame_pointer <= base {
+            break;
+        }
+        base = frame_pointer;
+    }
+    base
+}
+
 fn stack_return_addresses() -> Vec<StackAddresses> {
     let mut addresses = vec![];
     let mut rbp = rbp() as *const usize;
@@ -532,6 +972,6 @@ fn stack_return_addresses() -> Vec<StackAddresses> {
 }
 
 extern "C" {
-    static __tiger_pointer_map: usize;
-    static __tiger_pointer_map_end: usize;
+    static __start_tiger_modules: Module;
+    static __stop_tiger_modules: Module;
 }
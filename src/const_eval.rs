@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Compile-time evaluation of the calls to pure functions with constant arguments.
+//!
+//! A function can be evaluated when its parameters and its result are integers and its body only
+//! does arithmetic on its parameters and its local variables, tests them with `if` and calls
+//! functions which can be evaluated. The semantic analysis records these functions and evaluates
+//! their calls whose arguments are constants, within a budget of steps and of nested calls, so that
+//! recursion is bounded. A call whose evaluation fails, like one dividing by zero, is left to run.
+
+use std::collections::HashMap;
+
+use ast::{Arena, Declaration, Expr, ExprId, Operator};
+use symbol::Symbol;
+
+// Evaluating a call gives up after this many expressions or nested calls.
+const MAX_DEPTH: usize = 256;
+const MAX_STEPS: usize = 1_000_000;
+
+struct EvaluableFunction {
+    body: ExprId,
+    // Index of the function called by each name in the body.
+    callees: HashMap<Symbol, usize>,
+    params: Vec<Symbol>,
+}
+
+#[derive(Default)]
+pub struct EvaluableFunctions {
+    functions: Vec<EvaluableFunction>,
+}
+
+impl EvaluableFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a function which can be evaluated, returning its index. `callees` gives the index of
+    /// the functions called in its body, as returned by `evaluable_calls`.
+    pub fn add(&mut self, params: Vec<Symbol>, body: ExprId, callees: HashMap<Symbol, usize>) -> usize {
+        self.functions.push(EvaluableFunction {
+            body,
+            callees,
+            params,
+        });
+        self.functions.len() - 1
+    }
+
+    /// Number of functions recorded, which is the index of the next one.
+    pub fn count(&self) -> usize {
+        self.functions.len()
+    }
+
+    /// Value of the call of the function with these arguments, unless its evaluation fails or
+    /// exceeds the budget. The bodies of the functions are in `arena`.
+    pub fn evaluate(&self, arena: &Arena, function: usize, args: Vec<i64>) -> Option<i64> {
+        let mut evaluation = Evaluation {
+            arena,
+            depth: 0,
+            functions: self,
+            steps: 0,
+        };
+        evaluation.call(function, args)
+    }
+}
+
+/// Names of the functions called by the body of a function with these parameters, if the body can
+/// be evaluated provided that these functions can.
+pub fn evaluable_calls(arena: &Arena, params: &[Symbol], body: ExprId) -> Option<Vec<Symbol>> {
+    let mut calls = vec![];
+    let mut variables = params.to_vec();
+    if is_evaluable(arena, body, &mut variables, &mut calls) {
+        Some(calls)
+    }
+    else {
+        None
+    }
+}
+
+fn is_evaluable(arena: &Arena, expr: ExprId, variables: &mut Vec<Symbol>, calls: &mut Vec<Symbol>) -> bool {
+    match arena[expr].node {
+        Expr::Call { ref args, function } => {
+            calls.push(function);
+            args.iter().all(|&arg| is_evaluable(arena, arg, variables, calls))
+        },
+        // Without else, the value is unit.
+        Expr::If { else_: Some(else_), test, then } =>
+            is_evaluable(arena, test, variables, calls) && is_evaluable(arena, then, variables, calls) &&
+                is_evaluable(arena, else_, variables, calls),
+        Expr::Int { .. } => true,
+        Expr::Let { body, ref declarations } => {
+            let scope = variables.len();
+            let mut evaluable = true;
+            for declaration in declarations {
+                match declaration.node {
+                    Declaration::VariableDeclaration { init, name, .. } if is_evaluable(arena, init, variables, calls) =>
+                        variables.push(name),
+                    _ => {
+                        evaluable = false;
+                        break;
+                    },
+                }
+            }
+            evaluable = evaluable && is_evaluable(arena, body, variables, calls);
+            variables.truncate(scope);
+            evaluable
+        },
+        Expr::Oper { left, right, .. } =>
+            is_evaluable(arena, left, variables, calls) && is_evaluable(arena, right, variables, calls),
+        Expr::Sequence(ref exprs) =>
+            !exprs.is_empty() && exprs.iter().all(|&expr| is_evaluable(arena, expr, variables, calls)),
+        // Only the local variables: the others can be assigned.
+        Expr::Variable(ref name) => variables.contains(&name.node),
+        _ => false,
+    }
+}
+
+struct Evaluation<'a> {
+    arena: &'a Arena,
+    depth: usize,
+    functions: &'a EvaluableFunctions,
+    steps: usize,
+}
+
+impl<'a> Evaluation<'a> {
+    fn call(&mut self, function: usize, args: Vec<i64>) -> Option<i64> {
+        let function = &self.functions.functions[function];
+        if self.depth == MAX_DEPTH || function.params.len() != args.len() {
+            return None;
+        }
+        self.depth += 1;
+        let mut variables = function.params.iter().cloned().zip(args).collect();
+        let result = self.expr(function, function.body, &mut variables);
+        self.depth -= 1;
+        result
+    }
+
+    fn expr(&mut self, function: &EvaluableFunction, expr: ExprId, variables: &mut Vec<(Symbol, i64)>)
+        -> Option<i64>
+    {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return None;
+        }
+        let arena = self.arena;
+        match arena[expr].node {
+            Expr::Call { ref args, function: name } => {
+                let callee = *function.callees.get(&name)?;
+                let args = args.iter()
+                    .map(|&arg| self.expr(function, arg, variables))
+                    .collect::<Option<Vec<_>>>()?;
+                self.call(callee, args)
+            },
+            Expr::If { else_: Some(else_), test, then } => {
+                if self.expr(function, test, variables)? != 0 {
+                    self.expr(function, then, variables)
+                }
+                else {
+                    self.expr(function, else_, variables)
+                }
+            },
+            Expr::Int { value } => Some(value),
+            Expr::Let { body, ref declarations } => {
+                let scope = variables.len();
+                for declaration in declarations {
+                    if let Declaration::VariableDeclaration { init, name, .. } = declaration.node {
+                        let value = self.expr(function, init, variables)?;
+                        variables.push((name, value));
+                    }
+                }
+                let result = self.expr(function, body, variables);
+                variables.truncate(scope);
+                result
+            },
+            Expr::Oper { left, ref oper, right } => {
+                let left = self.expr(function, left, variables)?;
+                let right = self.expr(function, right, variables)?;
+                operation(oper.node, left, right)
+            },
+            Expr::Sequence(ref exprs) => {
+                let mut value = None;
+                for &expr in exprs {
+                    value = Some(self.expr(function, expr, variables)?);
+                }
+                value
+            },
+            Expr::Variable(ref name) =>
+                variables.iter().rev()
+                    .find(|&&(variable, _)| variable == name.node)
+                    .map(|&(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Value of the operation as computed by the generated code, or None if it faults.
+fn operation(oper: Operator, left: i64, right: i64) -> Option<i64> {
+    let value =
+        match oper {
+            Operator::And => left & right,
+            Operator::Divide => {
+                if right == 0 {
+                    return None;
+                }
+                left.checked_div(right)?
+            },
+            Operator::Equal => (left == right) as i64,
+            Operator::Ge => (left >= right) as i64,
+            Operator::Gt => (left > right) as i64,
+            Operator::Le => (left <= right) as i64,
+            Operator::Lt => (left < right) as i64,
+            Operator::Minus => left.wrapping_sub(right),
+            Operator::Neq => (left != right) as i64,
+            Operator::Or => left | right,
+            Operator::Plus => left.wrapping_add(right),
+            Operator::Times => left.wrapping_mul(right),
+        };
+    Some(value)
+}
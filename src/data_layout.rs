@@ -19,6 +19,8 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+// The sizes are in words, so they do not depend on Frame::WORD_SIZE.
+
 // Type, Size, Is pointer.
 pub const ARRAY_DATA_LAYOUT_SIZE: usize = 3;
 
@@ -31,4 +33,47 @@ pub const RECORD_DATA_LAYOUT_SIZE: usize = 2;
 // Type, Size.
 pub const STRING_DATA_LAYOUT_SIZE: usize = 2;
 
+pub const ARRAY_TYPE: usize = 0;
+pub const RECORD_TYPE: usize = 1;
 pub const STRING_TYPE: usize = 2;
+
+// Offset 2, because offset 0 is the object type (class) and offset 1 is the data layout.
+pub const VTABLE_OFFSET: usize = 2;
+
+// How the collector finds the roots in the stack, written by the compiler in the tables of each
+// object file: with the pointer maps, or by scanning every word when there are none.
+pub const CONSERVATIVE_GC: usize = 1;
+pub const PRECISE_GC: usize = 0;
+
+// Format of the tables the compiler writes in each object file for the runtime (see Module in
+// collector.rs): increment it when they change.
+const TABLES_FORMAT: usize = 1;
+
+/// Checksum of the data layouts and of the format of the tables, written by the compiler in each
+/// object file and checked by the runtime at startup, so that a program linked with the runtime
+/// of another version stops with an error instead of misreading its objects.
+pub const VERSION: usize = checksum(&[
+    TABLES_FORMAT,
+    ARRAY_DATA_LAYOUT_SIZE,
+    CLASS_DATA_LAYOUT_SIZE,
+    RECORD_DATA_LAYOUT_SIZE,
+    STRING_DATA_LAYOUT_SIZE,
+    ARRAY_TYPE,
+    RECORD_TYPE,
+    STRING_TYPE,
+    VTABLE_OFFSET,
+    CONSERVATIVE_GC,
+    PRECISE_GC,
+]);
+
+// FNV-1a hash of the values.
+const fn checksum(values: &[usize]) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut index = 0;
+    while index < values.len() {
+        hash ^= values[index] as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        index += 1;
+    }
+    hash as usize
+}
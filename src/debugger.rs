@@ -0,0 +1,284 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Source-level debugger of `--debug`, which runs the program in the interpreter.
+//!
+//! The program stops before its first line and then where the commands read on the standard input
+//! say: at a breakpoint, at the next line with `step`, at the next line of the same function with
+//! `next` or after the function returns with `finish`. When it is stopped, `print` shows the
+//! variables in scope.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write, stdin, stdout};
+
+use ast::Ast;
+use interpreter::{self, Flow, Frame, Observer, Value};
+use position::Pos;
+use symbol::Symbols;
+
+const HELP: &str = "\
+break LINE     stop at LINE (b)
+delete [LINE]  remove the breakpoint at LINE, or all of them (d)
+continue       run until a breakpoint (c)
+step           run until the next line, entering the calls (s)
+next           run until the next line of this function (n)
+finish         run until this function returns
+print NAME     show the variable NAME, with .field and [index] (p)
+locals         show the variables in scope
+backtrace      show the calls in progress (bt)
+list           show the lines around the current one (l)
+quit           stop the program (q)
+An empty line repeats the previous command.";
+
+const PROMPT: &str = "(tiger) ";
+
+/// Number of lines shown by `list` on each side of the current one.
+const LIST_CONTEXT: usize = 2;
+
+/// Where the program stops next, besides the breakpoints.
+#[derive(Clone, Copy)]
+enum Mode {
+    Continue,
+    /// After the call at depth `depth` returns.
+    Finish {
+        depth: usize,
+    },
+    /// At the next line of a call at depth `depth` or less.
+    Next {
+        depth: usize,
+    },
+    Step,
+}
+
+pub fn debug(ast: &Ast, filename: &str, symbols: &Symbols<()>) -> io::Result<()> {
+    let source = fs::read_to_string(filename)?;
+    let mut debugger = Debugger {
+        breakpoints: BTreeSet::new(),
+        current_lines: vec![],
+        lines: source.lines().map(ToString::to_string).collect(),
+        mode: Mode::Step,
+        previous_command: String::new(),
+        symbols,
+    };
+    match interpreter::run(ast, symbols, &mut debugger)? {
+        Some(code) => println!("Program exited with code {}.", code),
+        None => println!("Program stopped."),
+    }
+    Ok(())
+}
+
+struct Debugger<'a> {
+    breakpoints: BTreeSet<u32>,
+    // Line being run in each frame, from the outermost.
+    current_lines: Vec<u32>,
+    lines: Vec<String>,
+    mode: Mode,
+    previous_command: String,
+    symbols: &'a Symbols<()>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Run the commands until one resumes the program.
+    fn prompt(&mut self, frames: &[Frame]) -> Result<(), Flow> {
+        loop {
+            print!("{}", PROMPT);
+            let _ = stdout().flush();
+            let mut line = String::new();
+            match stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return Err(Flow::Quit),
+                Ok(_) => (),
+            }
+            let mut line = line.trim().to_string();
+            if line.is_empty() {
+                line = self.previous_command.clone();
+            }
+            self.previous_command = line.clone();
+            let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+            let argument = argument.trim();
+            match command {
+                "" => (),
+                "b" | "break" =>
+                    match self.line_number(argument) {
+                        Some(line) => {
+                            self.breakpoints.insert(line);
+                            println!("Breakpoint at line {}.", line);
+                        },
+                        None => println!("Expecting a line between 1 and {}.", self.lines.len()),
+                    },
+                "backtrace" | "bt" => self.backtrace(frames),
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                },
+                "d" | "delete" => {
+                    if argument.is_empty() {
+                        self.breakpoints.clear();
+                    }
+                    else if !argument.parse().is_ok_and(|line| self.breakpoints.remove(&line)) {
+                        println!("No breakpoint at line {}.", argument);
+                    }
+                },
+                "finish" => {
+                    if frames.len() == 1 {
+                        println!("`finish` is not meaningful in the outermost frame.");
+                    }
+                    else {
+                        self.mode = Mode::Finish { depth: frames.len() };
+                        return Ok(());
+                    }
+                },
+                "help" | "h" => println!("{}", HELP),
+                "l" | "list" => self.list(),
+                "locals" => {
+                    for (name, value) in frames.last().expect("frame").env.variables() {
+                        let name = self.symbols.name(name);
+                        // The variables introduced by the parser start with __.
+                        if !name.starts_with("__") {
+                            println!("{} = {}", name, value.show(self.symbols));
+                        }
+                    }
+                },
+                "n" | "next" => {
+                    self.mode = Mode::Next { depth: frames.len() };
+                    return Ok(());
+                },
+                "p" | "print" =>
+                    match self.value(argument, frames) {
+                        Ok(value) => println!("{} = {}", argument, value.show(self.symbols)),
+                        Err(message) => println!("{}", message),
+                    },
+                "q" | "quit" => return Err(Flow::Quit),
+                "s" | "step" => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                },
+                _ => println!("Unknown command `{}`. Type help for the commands.", command),
+            }
+        }
+    }
+
+    fn backtrace(&self, frames: &[Frame]) {
+        for (index, frame) in frames.iter().rev().enumerate() {
+            let line = self.current_lines.get(frames.len() - 1 - index).copied().unwrap_or(0);
+            println!("#{} {} at line {}", index, frame.function, line);
+        }
+    }
+
+    fn current_line(&self) -> u32 {
+        self.current_lines.last().copied().unwrap_or(0)
+    }
+
+    fn line_number(&self, argument: &str) -> Option<u32> {
+        argument.parse().ok()
+            .filter(|&line| line >= 1 && line as usize <= self.lines.len())
+    }
+
+    fn list(&self) {
+        let current = self.current_line() as usize;
+        let first = current.saturating_sub(LIST_CONTEXT).max(1);
+        let last = (current + LIST_CONTEXT).min(self.lines.len());
+        for line in first..=last {
+            let marker = if line == current { "=>" } else { "  " };
+            println!("{} {}\t{}", marker, line, self.lines[line - 1]);
+        }
+    }
+
+    fn show_line(&self, line: u32) {
+        let text = self.lines.get(line as usize - 1).map_or("", String::as_str);
+        println!("{}\t{}", line, text);
+    }
+
+    /// Value of a path like `points[2].x` in the innermost frame.
+    fn value(&self, path: &str, frames: &[Frame]) -> Result<Value, String> {
+        let end = path.find(['.', '[']).unwrap_or(path.len());
+        let (name, mut rest) = path.split_at(end);
+        if name.is_empty() {
+            return Err("Expecting the name of a variable.".to_string());
+        }
+        let env = &frames.last().expect("frame").env;
+        let mut value = env.value(name, self.symbols)
+            .ok_or_else(|| format!("No variable `{}` in scope.", name))?;
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                let field = &after_dot[..end];
+                value = value.field(field, self.symbols).map_err(capitalize)?;
+                rest = &after_dot[end..];
+            }
+            else {
+                let end = rest.find(']').ok_or_else(|| "Expecting `]`.".to_string())?;
+                let index = rest[1..end].trim().parse()
+                    .map_err(|_| format!("Expecting an integer index, found `{}`.", &rest[1..end]))?;
+                value = value.element(index).map_err(capitalize)?;
+                rest = &rest[end + 1..];
+            }
+        }
+        Ok(value)
+    }
+
+}
+
+impl<'a> Observer for Debugger<'a> {
+    fn evaluate(&mut self, pos: Pos, frames: &[Frame]) -> Result<(), Flow> {
+        let depth = frames.len();
+        self.current_lines.resize(depth, 0);
+        if self.current_lines[depth - 1] == pos.line {
+            return Ok(());
+        }
+        self.current_lines[depth - 1] = pos.line;
+        let breakpoint = self.breakpoints.contains(&pos.line);
+        let stop =
+            match self.mode {
+                Mode::Continue | Mode::Finish { .. } => breakpoint,
+                Mode::Next { depth: next_depth } => breakpoint || depth <= next_depth,
+                Mode::Step => true,
+            };
+        if stop {
+            if breakpoint {
+                println!("Breakpoint at line {}, in {}.", pos.line, frames[depth - 1].function);
+            }
+            self.show_line(pos.line);
+            self.prompt(frames)?;
+        }
+        Ok(())
+    }
+
+    fn returned(&mut self, callee: &Frame, value: &Value, frames: &[Frame]) -> Result<(), Flow> {
+        if let Mode::Finish { depth } = self.mode {
+            if frames.len() + 1 == depth {
+                println!("{} returned {}.", callee.function, value.show(self.symbols));
+                self.current_lines.truncate(frames.len());
+                self.show_line(callee.call_pos.line);
+                self.prompt(frames)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn capitalize(message: String) -> String {
+    let mut chars = message.chars();
+    match chars.next() {
+        Some(first) => format!("{}{}.", first.to_uppercase(), chars.as_str()),
+        None => message,
+    }
+}
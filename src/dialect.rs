@@ -0,0 +1,60 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::fmt::{self, Display, Formatter};
+
+/// Set of language features accepted by the compiler, ordered from the smallest to the biggest.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Dialect {
+    /// The language described in the Tiger reference manual.
+    Tiger,
+    /// Tiger with classes.
+    ObjectTiger,
+    /// Object Tiger with the additional types and runtime functions of this compiler.
+    Extended,
+    /// Extended Tiger with raw pointers, which the garbage collector does not track.
+    Unsafe,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tiger" => Some(Dialect::Tiger),
+            "object-tiger" => Some(Dialect::ObjectTiger),
+            "extended" => Some(Dialect::Extended),
+            "unsafe" => Some(Dialect::Unsafe),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let name =
+            match *self {
+                Dialect::Tiger => "tiger",
+                Dialect::ObjectTiger => "object-tiger",
+                Dialect::Extended => "extended",
+                Dialect::Unsafe => "unsafe",
+            };
+        write!(formatter, "{}", name)
+    }
+}
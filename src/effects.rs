@@ -0,0 +1,164 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Side effects of the IR in evaluation order.
+//!
+//! The canonicalization pulls the calls and the statements out of the expressions, which must not
+//! change the order in which the calls happen and the memory is written. In debug builds, the
+//! effects of every function body are recorded before the canonicalization and compared with the
+//! effects of the resulting statements, so that a pass reordering them is caught by any program
+//! compiled by the test suite.
+
+use ir::{Exp, Statement, _Statement};
+use temp::Label;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// Call identified by its return label, which is unique to its call site.
+    Call(Label),
+    /// Write to memory.
+    Store,
+}
+
+/// Effects of the statements, in the order they happen when the statements are executed in
+/// sequence.
+pub fn effects(statements: &[Statement]) -> Vec<Effect> {
+    let mut effects = vec![];
+    for statement in statements {
+        statement_effects(statement, &mut effects);
+    }
+    effects
+}
+
+/// Panic if the effects of the statements differ from the expected ones.
+pub fn check_order(expected: &[Effect], statements: &[Statement]) {
+    let actual = effects(statements);
+    if let Some(index) = expected.iter().zip(&actual).position(|(expected, actual)| expected != actual) {
+        panic!("side effect {} reordered: expected {:?}, found {:?}", index, expected[index], actual[index]);
+    }
+    assert_eq!(expected.len(), actual.len(), "side effects added or removed");
+}
+
+fn statement_effects(statement: &Statement, effects: &mut Vec<Effect>) {
+    match statement.statement {
+        _Statement::Move(Exp::Mem(ref address), ref source) | _Statement::Move(Exp::MemByte(ref address), ref source) => {
+            expression_effects(address, effects);
+            expression_effects(source, effects);
+            effects.push(Effect::Store);
+        },
+        _Statement::Move(ref destination, ref source) => {
+            expression_effects(destination, effects);
+            expression_effects(source, effects);
+        },
+        _Statement::Exp(ref expr) | _Statement::Jump(ref expr, _) => expression_effects(expr, effects),
+        _Statement::CondJump { ref left, ref right, .. } => {
+            expression_effects(left, effects);
+            expression_effects(right, effects);
+        },
+        _Statement::Sequence(ref statement1, ref statement2) => {
+            statement_effects(statement1, effects);
+            statement_effects(statement2, effects);
+        },
+        _Statement::Label(_) | _Statement::Position(_) => (),
+    }
+}
+
+fn expression_effects(expr: &Exp, effects: &mut Vec<Effect>) {
+    match *expr {
+        Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => (),
+        Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } => {
+            expression_effects(left, effects);
+            expression_effects(right, effects);
+        },
+        Exp::Mem(ref expr) | Exp::MemByte(ref expr) => expression_effects(expr, effects),
+        Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } => {
+            expression_effects(left, effects);
+            expression_effects(right, effects);
+            expression_effects(if_true, effects);
+            expression_effects(if_false, effects);
+        },
+        Exp::Call { ref arguments, ref function_expr, ref return_label, .. } => {
+            expression_effects(function_expr, effects);
+            for argument in arguments {
+                expression_effects(argument, effects);
+            }
+            effects.push(Effect::Call(return_label.clone()));
+        },
+        Exp::ExpSequence(ref statement, ref expr) => {
+            statement_effects(statement, effects);
+            expression_effects(expr, effects);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::{BinOp, Exp, Statement, _Statement};
+    use temp::{Label, Temp};
+    use super::{Effect, check_order, effects};
+
+    #[test]
+    fn effect_order() {
+        let call = |label: &Label| Exp::Call {
+            arguments: vec![],
+            collectable_return_type: false,
+            function_expr: Box::new(Exp::Name(Label::with_name("next"))),
+            return_label: label.clone(),
+        };
+        let first = Label::new();
+        let second = Label::new();
+        let store = |source| -> Statement {
+            _Statement::Move(Exp::Mem(Box::new(Exp::Temp(Temp::from_num(1000)))), source).into()
+        };
+        let sequence = |statement1, statement2| -> Statement {
+            _Statement::Sequence(Box::new(statement1), Box::new(statement2)).into()
+        };
+
+        let original = store(Exp::BinOp {
+            op: BinOp::Minus,
+            left: Box::new(call(&first)),
+            right: Box::new(Exp::ExpSequence(Box::new(store(Exp::Const(1))), Box::new(call(&second)))),
+        });
+        let expected = effects(&[original]);
+        assert_eq!(expected, [Effect::Call(first.clone()), Effect::Store, Effect::Call(second.clone()), Effect::Store]);
+
+        let temp = Exp::Temp(Temp::from_num(1001));
+        check_order(&expected, &[
+            _Statement::Move(temp.clone(), call(&first)).into(),
+            sequence(store(Exp::Const(1)), _Statement::Exp(call(&second)).into()),
+            store(temp),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "side effect 0 reordered")]
+    fn reordered_effects() {
+        let call = Exp::Call {
+            arguments: vec![],
+            collectable_return_type: false,
+            function_expr: Box::new(Exp::Name(Label::with_name("next"))),
+            return_label: Label::new(),
+        };
+        let store: Statement = _Statement::Move(Exp::Mem(Box::new(Exp::Temp(Temp::from_num(1000)))), Exp::Const(1)).into();
+        let expected = effects(&[store.clone(), _Statement::Exp(call.clone()).into()]);
+        check_order(&expected, &[_Statement::Exp(call).into(), store]);
+    }
+}
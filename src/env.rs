@@ -20,15 +20,15 @@
  */
 
 use std::collections::BTreeMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use escape::EscapeEnv;
 use frame::Frame;
 use gen;
-use gen::{Access, Level};
+use gen::{Access, Level, NestedAccess};
 use symbol::{Strings, Symbol, Symbols};
 use temp::Label;
-use types::Type;
+use types::{Type, Unique};
 
 #[derive(Clone, Debug)]
 pub enum Entry<F: Clone + Frame> {
@@ -44,6 +44,8 @@ pub enum Entry<F: Clone + Frame> {
     },
     Var {
         access: Access<F>,
+        // Variable of a for loop, which cannot be assigned in the body of the loop.
+        loop_variable: bool,
         typ: Type,
     },
 }
@@ -55,14 +57,24 @@ pub struct Env<F: Clone + Frame> {
 }
 
 impl<F: Clone + Frame> Env<F> {
-    pub fn new(strings: &Rc<Strings>, escape_env: EscapeEnv) -> Self {
-        let mut type_env = Symbols::new(Rc::clone(strings));
+    pub fn new(strings: &Arc<Strings>, escape_env: EscapeEnv) -> Self {
+        let mut type_env = Symbols::new(Arc::clone(strings));
+        let bigint_symbol = type_env.symbol("bigint");
+        type_env.enter(bigint_symbol, Type::BigInt);
+        let bytes_symbol = type_env.symbol("bytes");
+        type_env.enter(bytes_symbol, bytes_type());
+        let byte_symbol = type_env.symbol("byte");
+        type_env.enter(byte_symbol, Type::Byte);
+        let char_symbol = type_env.symbol("char");
+        type_env.enter(char_symbol, Type::Char);
         let int_symbol = type_env.symbol("int");
         type_env.enter(int_symbol, Type::Int);
+        let socket_symbol = type_env.symbol("socket");
+        type_env.enter(socket_symbol, SOCKET);
         let string_symbol = type_env.symbol("string");
         type_env.enter(string_symbol, Type::String);
 
-        let var_env = Symbols::new(Rc::clone(strings));
+        let var_env = Symbols::new(Arc::clone(strings));
         let mut env = Self {
             escape_env,
             type_env,
@@ -80,14 +92,29 @@ impl<F: Clone + Frame> Env<F> {
         let symbol = self.var_env.symbol(name);
         let entry = Entry::Fun {
             external: true,
-            label: Label::with_name(name),
-            level: gen::outermost(), // FIXME: Might want to create a new level.
+            label: Label::with_name(runtime_name(name)),
+            level: gen::outermost(NestedAccess::StaticLink), // FIXME: Might want to create a new level.
             parameters,
             result,
         };
         self.var_env.enter(symbol, entry);
     }
 
+    /// Declare a function exported by a library. Like the functions of the libraries, it is
+    /// declared at the top level.
+    pub fn import_function(&mut self, name: Symbol, parameters: Vec<Type>, result: Type) {
+        let label = Label::with_name(&self.var_env.name(name));
+        let level = Level::new(&gen::outermost(NestedAccess::StaticLink), label.clone(), vec![false; parameters.len()]);
+        let entry = Entry::Fun {
+            external: false,
+            label,
+            level,
+            parameters,
+            result,
+        };
+        self.var_env.enter(name, entry);
+    }
+
     pub fn begin_scope(&mut self) {
         self.type_env.begin_scope();
         self.var_env.begin_scope();
@@ -135,25 +162,112 @@ impl<F: Clone + Frame> Env<F> {
     pub fn var_name(&self, symbol: Symbol) -> String {
         self.var_env.name(symbol)
     }
+
+    pub fn var_symbol(&mut self, string: &str) -> Symbol {
+        self.var_env.symbol(string)
+    }
 }
 
+/// Name of the runtime function checking that the object files of the program were compiled for
+/// this runtime.
+pub const CHECK_VERSIONS: &str = "checkVersions";
+
+/// Name of the runtime function registering a Tiger function as a signal handler.
+pub const ON_SIGNAL: &str = "on_signal";
+
+/// Functions of the standard library described in the Tiger reference manual.
+pub const STANDARD_FUNCTIONS: [&str; 10] =
+    ["chr", "concat", "exit", "flush", "getchar", "not", "ord", "print", "size", "substring"];
+
+/// Standard functions that the programs can call but that the runtime does not provide yet.
+pub const UNIMPLEMENTED_FUNCTIONS: [&str; 4] = ["flush", "not", "size", "substring"];
+
+/// Name of the runtime function creating a weak reference.
+pub const WEAK: &str = "weak";
+
+/// Name of the runtime function returning the target of a weak reference, or nil if it was
+/// collected.
+pub const WEAK_GET: &str = "get";
+
+/// Name of the runtime function copying an array of chars in a new string.
+pub const CHARS_TO_STRING: &str = "chars_to_string";
+
+/// Name of the runtime function copying a string in an array of chars.
+pub const STRING_TO_CHARS: &str = "string_to_chars";
+
+/// Functions whose name is taken by the C library, with the name of the runtime function
+/// implementing them.
+const RUNTIME_NAMES: [(&str, &str); 1] = [("munmap", "munmap_file")];
+
+/// Name of the runtime function implementing the external function `name`.
+pub fn runtime_name(name: &str) -> &str {
+    RUNTIME_NAMES.iter()
+        .find(|&&(function, _)| function == name)
+        .map_or(name, |&(_, runtime_name)| runtime_name)
+}
+
+/// Array of bytes returned by `mmap_file`.
+pub fn bytes_type() -> Type {
+    Type::Array(Box::new(Type::Byte), Unique::predefined(0))
+}
+
+/// Client TCP connection returned by `tcp_connect`.
+pub const SOCKET: Type = Type::Opaque("socket");
+
+/// Name of the primitive lowered to a `syscall` instruction instead of a call.
+pub const SYSCALL: &str = "syscall";
+
 pub fn external_functions() -> BTreeMap<&'static str, (Vec<Type>, Type)> {
     let mut functions = BTreeMap::new();
     functions.insert("print", (vec![Type::String], Type::Unit));
     functions.insert("printi", (vec![Type::Int], Type::Unit));
+    functions.insert("print_hex", (vec![Type::Int], Type::Unit));
+    functions.insert("print_padded", (vec![Type::Int, Type::Int], Type::Unit));
+    functions.insert("int_to_string", (vec![Type::Int, Type::Int], Type::String));
     functions.insert("flush", (vec![], Type::Unit));
     functions.insert("getchar", (vec![], Type::String));
     functions.insert("ord", (vec![Type::String], Type::Int));
     functions.insert("chr", (vec![Type::Int], Type::String));
+    functions.insert("big_from_int", (vec![Type::Int], Type::BigInt));
+    functions.insert("big_add", (vec![Type::BigInt, Type::BigInt], Type::BigInt));
+    functions.insert("big_sub", (vec![Type::BigInt, Type::BigInt], Type::BigInt));
+    functions.insert("big_mul", (vec![Type::BigInt, Type::BigInt], Type::BigInt));
+    functions.insert("big_cmp", (vec![Type::BigInt, Type::BigInt], Type::Int));
+    functions.insert("big_to_string", (vec![Type::BigInt], Type::String));
+    functions.insert("clock_ms", (vec![], Type::Int));
+    functions.insert("mmap_file", (vec![Type::String], bytes_type()));
+    functions.insert("munmap", (vec![bytes_type()], Type::Unit));
+    functions.insert("bytes_length", (vec![bytes_type()], Type::Int));
+    functions.insert("sleep_ms", (vec![Type::Int], Type::Unit));
     functions.insert("size", (vec![Type::String], Type::Int));
     functions.insert("substring", (vec![Type::String, Type::Int, Type::Int], Type::String));
     functions.insert("concat", (vec![Type::String, Type::String], Type::String));
     functions.insert("not", (vec![Type::Int], Type::Int));
     functions.insert("exit", (vec![Type::Int], Type::Unit));
     functions.insert("stringEqual", (vec![Type::String, Type::String], Type::Int));
+    functions.insert("tcp_connect", (vec![Type::String, Type::Int], SOCKET));
+    functions.insert("sock_send", (vec![SOCKET, Type::String], Type::Int));
+    functions.insert("sock_recv", (vec![SOCKET, Type::Int], Type::String));
+    functions.insert("sock_close", (vec![SOCKET], Type::Unit));
+    functions.insert(SYSCALL, (vec![Type::Int; 7], Type::Int));
+    // The handler is checked by the semantic analyzer since functions are not values.
+    functions.insert(ON_SIGNAL, (vec![Type::Int, Type::Unit], Type::Unit));
+    // The weak references are generic, so their types are checked by the semantic analyzer.
+    functions.insert(WEAK, (vec![Type::Int], Type::Int));
+    functions.insert(WEAK_GET, (vec![Type::Int], Type::Int));
+    // So are the arrays of chars.
+    functions.insert(CHARS_TO_STRING, (vec![Type::Int], Type::String));
+    functions.insert(STRING_TO_CHARS, (vec![Type::String, Type::Int], Type::Int));
 
-    functions.insert("allocClass", (vec![Type::Int], Type::Int));
-    functions.insert("allocRecord", (vec![Type::Int], Type::Int));
-    functions.insert("initArray", (vec![Type::Int, Type::Int], Type::Int));
+    functions.insert(CHECK_VERSIONS, (vec![], Type::Unit));
+    functions.insert("allocClass", (vec![Type::Int, Type::String], Type::Int));
+    functions.insert("allocRecord", (vec![Type::Int, Type::String], Type::Int));
+    functions.insert("initArray", (vec![Type::Int, Type::Int, Type::String], Type::Int));
+    functions.insert("cloneRecord", (vec![Type::Int, Type::Int, Type::String], Type::Int));
+    functions.insert("cloneArray", (vec![Type::Int, Type::Int, Type::Int, Type::String], Type::Int));
+    functions.insert("initByteArray", (vec![Type::Int, Type::String], Type::Int));
+    functions.insert("stringCompare", (vec![Type::String, Type::String], Type::Int));
+    functions.insert("stringSwitch", (vec![Type::Int, Type::Int, Type::String], Type::Int));
+    functions.insert("divisionOverflow", (vec![Type::String], Type::Unit));
     functions
 }
@@ -20,49 +20,233 @@
  */
 
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
 use std::result;
+use std::sync::Mutex;
 
+use dialect::Dialect;
 use position::Pos;
 use self::Error::*;
 use symbol::Symbols;
 use terminal::Terminal;
 use token::Tok;
+use toml::parse_string;
 use types::{FunctionType, Type};
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Name of the program read from the standard input in the diagnostics.
+pub const STDIN_NAME: &str = "<stdin>";
+
+// The program read from the standard input, which cannot be read again to show its lines.
+static STDIN_SOURCE: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Keep the program read from the standard input to show its lines in the diagnostics.
+pub fn set_stdin_source(source: Vec<u8>) {
+    *STDIN_SOURCE.lock().expect("lock stdin source") = source;
+}
+
+/// English text of the diagnostics by key, where `{name}` is replaced by the argument `name`.
+const MESSAGES: [(&str, &str); 42] = [
+    ("address_of", "Can only take the address of a variable"),
+    ("assign", "Can only assign to variable, field or array element"),
+    ("break_outside_loop", "Break statement used outside of loop"),
+    ("cannot_deref", "Cannot dereference value of type `{type}`"),
+    ("cannot_index", "Cannot index value of type `{type}`"),
+    ("conversion", "Cannot convert a value of type `{from}` to `{to}`: only pointers and integers convert to each other"),
+    ("cycle", "Type cycle detected:"),
+    ("duplicate_case", "Duplicate case {label}"),
+    ("duplicate_function", "Function `{ident}` is already declared in {file}"),
+    ("duplicate_param", "Duplicate param `{ident}`"),
+    ("eof", "end of file"),
+    ("error", "error"),
+    ("exported_type", "Function of a library cannot use type `{type}` declared in the library"),
+    ("extra_field", "Extra field `{ident}` in struct of type `{struct}`"),
+    ("finalizer", "Method `finalize` should take no parameters, return no value and not be static"),
+    ("function_type", "Overridden method should have the same type as the inherited method:\nunexpected {unexpected}\n expecting {expected}"),
+    ("invalid_character", "Invalid character `{character}` ({code})"),
+    ("invalid_escape", "Invalid escape \\{escape}"),
+    ("invalid_number_of_params", "Invalid number of parameters: expecting {expected}, but found {actual}"),
+    ("invalid_utf8", "Invalid UTF-8 byte {byte}"),
+    ("library_declaration", "A library can only declare types and functions"),
+    ("loop_variable_assign", "Cannot assign to loop variable `{ident}`"),
+    ("missing_field", "Missing field `{ident}` in struct of type `{struct}`"),
+    ("negative_array_size", "Array size cannot be negative, found {size}"),
+    ("no_value", "Expression produces no value"),
+    ("non_standard", "{feature} requires --dialect {dialect}"),
+    ("not_a_class", "Type `{type}` is not a class type"),
+    ("not_a_record_or_class", "Type `{type}` is not a struct or a class type"),
+    ("pointer_to_collected", "Cannot point to type `{type}` managed by the garbage collector"),
+    ("private", "{item} `{ident}` is private to class `{class}`"),
+    ("record_type", "Expecting type when value is nil"),
+    ("signal_handler", "Signal handler should be a function of type (int) -> unit"),
+    ("signal_handler_level", "Signal handler `{ident}` should be declared at the top level of the program, not in another function"),
+    ("static_self", "Cannot use `self` or instance fields in a static method"),
+    ("type", "Unexpected type {unexpected}, expecting {expected}"),
+    ("unclosed", "Unclosed {token}"),
+    ("undefined", "Undefined {item} `{ident}`"),
+    ("unexpected_field", "Unexpected field `{ident}` in struct of type `{struct}`"),
+    ("unexpected_token", "Unexpected token {unexpected}, expecting {expected}"),
+    ("unexpected_type", "Expecting {kind} type"),
+    ("unicode_identifier", "Character `{character}` ({code}) in identifier requires --unicode-idents"),
+    ("unknown_token", "Unexpected start of token `{start}`"),
+];
+
+/// Text of the diagnostics: English, unless a catalog translates them.
+pub struct Catalog {
+    translations: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn english() -> Self {
+        Self {
+            translations: HashMap::new(),
+        }
+    }
+
+    /// Load the translations from a TOML file of `key = "text"` lines, which can be under a
+    /// `[messages]` table.
+    pub fn load(filename: &str) -> Result<Self> {
+        let mut content = String::new();
+        File::open(filename)?.read_to_string(&mut content)?;
+        let mut translations = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let error = |message: String| Msg(format!("{}:{}: {}", filename, index + 1, message));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line == "[messages]" {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| error("expecting key = \"text\"".to_string()))?;
+            let key = key.trim().trim_matches('"');
+            let english = MESSAGES.iter()
+                .find(|&&(name, _)| name == key)
+                .map(|&(_, english)| english)
+                .ok_or_else(|| error(format!("unknown message `{}`", key)))?;
+            let text = parse_string(value.trim())
+                .ok_or_else(|| error(format!("expecting a string for message `{}`", key)))?;
+            for placeholder in placeholders(&text) {
+                if !english.contains(&format!("{{{}}}", placeholder)) {
+                    return Err(error(format!("message `{}` has no argument `{}`", key, placeholder)));
+                }
+            }
+            translations.insert(key.to_string(), text);
+        }
+        Ok(Self {
+            translations,
+        })
+    }
+
+    pub fn message(&self, key: &str, arguments: &[(&str, String)]) -> String {
+        let mut text =
+            match self.translations.get(key) {
+                Some(text) => text.clone(),
+                None => MESSAGES.iter()
+                    .find(|&&(name, _)| name == key)
+                    .map(|&(_, english)| english.to_string())
+                    .expect("message key"),
+            };
+        for &(name, ref value) in arguments {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+}
+
+/// Names of the `{name}` placeholders of the text.
+fn placeholders(text: &str) -> Vec<&str> {
+    text.split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}'))
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// How the diagnostics are written, selected by `--error-format`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    /// One JSON object per diagnostic and per line, for the editors and the build tools.
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Error {
+    AddressOf {
+        pos: Pos,
+    },
     Assign {
         pos: Pos,
     },
     BreakOutsideLoop {
         pos: Pos,
     },
+    CannotDeref {
+        pos: Pos,
+        typ: Type,
+    },
     CannotIndex {
         pos: Pos,
         typ: Type,
     },
+    Conversion {
+        from: Type,
+        pos: Pos,
+        to: Type,
+    },
     Cycle {
         pos: Pos,
     },
+    DuplicateCase {
+        label: String,
+        pos: Pos,
+    },
+    DuplicateFunction {
+        file: String,
+        ident: String,
+        pos: Pos,
+    },
     DuplicateParam {
         ident: String,
         pos: Pos,
     },
     Eof,
+    ExportedType {
+        pos: Pos,
+        typ: Type,
+    },
     ExtraField {
         ident: String,
         pos: Pos,
         struct_name: String,
     },
+    Finalizer {
+        pos: Pos,
+    },
     FunctionType {
         expected: FunctionType,
         pos: Pos,
         unexpected: FunctionType,
     },
+    InvalidCharacter {
+        character: char,
+        // Whether the character is a letter that could be in an identifier with --unicode-idents.
+        identifier: bool,
+        pos: Pos,
+    },
     InvalidEscape {
         escape: String,
         pos: Pos,
@@ -72,6 +256,17 @@ pub enum Error {
         expected: usize,
         pos: Pos,
     },
+    InvalidUtf8 {
+        byte: u8,
+        pos: Pos,
+    },
+    LibraryDeclaration {
+        pos: Pos,
+    },
+    LoopVariableAssign {
+        ident: String,
+        pos: Pos,
+    },
     MissingField {
         ident: String,
         pos: Pos,
@@ -79,6 +274,18 @@ pub enum Error {
     },
     Msg(String),
     Multi(Vec<Error>),
+    NegativeArraySize {
+        pos: Pos,
+        size: i64,
+    },
+    NoValue {
+        pos: Pos,
+    },
+    NonStandard {
+        dialect: Dialect,
+        feature: String,
+        pos: Pos,
+    },
     NotAClass {
         pos: Pos,
         typ: Type,
@@ -87,9 +294,29 @@ pub enum Error {
         pos: Pos,
         typ: Type,
     },
+    PointerToCollected {
+        pos: Pos,
+        typ: Type,
+    },
+    Private {
+        class_name: String,
+        ident: String,
+        item: String,
+        pos: Pos,
+    },
     RecordType {
         pos: Pos,
     },
+    SignalHandler {
+        pos: Pos,
+    },
+    SignalHandlerLevel {
+        ident: String,
+        pos: Pos,
+    },
+    StaticSelf {
+        pos: Pos,
+    },
     Type {
         expected: Type,
         pos: Pos,
@@ -125,115 +352,195 @@ pub enum Error {
 }
 
 impl Error {
-    pub fn show(&self, symbols: &Symbols<()>, terminal: &Terminal) -> io::Result<()> {
+    /// Whether this error is caused by another one, which was already shown.
+    fn is_cascading(&self) -> bool {
+        match *self {
+            CannotDeref { ref typ, .. } | CannotIndex { ref typ, .. } | ExportedType { ref typ, .. } | NotAClass { ref typ, .. }
+            | NotARecordOrClass { ref typ, .. } | PointerToCollected { ref typ, .. } => typ.is_error(),
+            Conversion { ref from, ref to, .. } => from.is_error() || to.is_error(),
+            Error::FunctionType { ref expected, ref unexpected, .. } => expected.is_error() || unexpected.is_error(),
+            Error::Type { ref expected, ref unexpected, .. } => expected.is_error() || unexpected.is_error(),
+            _ => false,
+        }
+    }
+
+    /// Keys in the catalog of the messages of the errors that are shown, which identify them in the tests.
+    #[cfg(test)]
+    pub fn keys(&self) -> Vec<&'static str> {
+        if self.is_cascading() {
+            return vec![];
+        }
+        let key =
+            match *self {
+                AddressOf { .. } => "address_of",
+                Assign { .. } => "assign",
+                BreakOutsideLoop { .. } => "break_outside_loop",
+                CannotDeref { .. } => "cannot_deref",
+                CannotIndex { .. } => "cannot_index",
+                Conversion { .. } => "conversion",
+                Cycle { .. } => "cycle",
+                DuplicateCase { .. } => "duplicate_case",
+                DuplicateFunction { .. } => "duplicate_function",
+                DuplicateParam { .. } => "duplicate_param",
+                Eof => "eof",
+                ExportedType { .. } => "exported_type",
+                ExtraField { .. } => "extra_field",
+                Finalizer { .. } => "finalizer",
+                Error::FunctionType { .. } => "function_type",
+                InvalidCharacter { identifier: true, .. } => "unicode_identifier",
+                InvalidCharacter { identifier: false, .. } => "invalid_character",
+                InvalidEscape { .. } => "invalid_escape",
+                InvalidNumberOfParams { .. } => "invalid_number_of_params",
+                InvalidUtf8 { .. } => "invalid_utf8",
+                LibraryDeclaration { .. } => "library_declaration",
+                LoopVariableAssign { .. } => "loop_variable_assign",
+                MissingField { .. } => "missing_field",
+                Msg(_) => "msg",
+                Multi(ref errors) => return errors.iter().rev().flat_map(Error::keys).collect(),
+                NegativeArraySize { .. } => "negative_array_size",
+                NoValue { .. } => "no_value",
+                NonStandard { .. } => "non_standard",
+                NotAClass { .. } => "not_a_class",
+                NotARecordOrClass { .. } => "not_a_record_or_class",
+                PointerToCollected { .. } => "pointer_to_collected",
+                Private { .. } => "private",
+                Error::RecordType { .. } => "record_type",
+                SignalHandler { .. } => "signal_handler",
+                SignalHandlerLevel { .. } => "signal_handler_level",
+                StaticSelf { .. } => "static_self",
+                Error::Type { .. } => "type",
+                Unclosed { .. } => "unclosed",
+                Undefined { .. } => "undefined",
+                UnexpectedField { .. } => "unexpected_field",
+                UnexpectedToken { .. } => "unexpected_token",
+                UnexpectedType { .. } => "unexpected_type",
+                UnknownToken { .. } => "unknown_token",
+            };
+        vec![key]
+    }
+
+    /// Message of the error, in the language of the catalog.
+    fn message(&self, symbols: &Symbols<()>, catalog: &Catalog) -> String {
+        match *self {
+            AddressOf { .. } => catalog.message("address_of", &[]),
+            Assign { .. } => catalog.message("assign", &[]),
+            BreakOutsideLoop { .. } => catalog.message("break_outside_loop", &[]),
+            CannotDeref { ref typ, .. } => catalog.message("cannot_deref", &[("type", typ.show(symbols))]),
+            CannotIndex { ref typ, .. } => catalog.message("cannot_index", &[("type", typ.show(symbols))]),
+            Conversion { ref from, ref to, .. } =>
+                catalog.message("conversion", &[("from", from.show(symbols)), ("to", to.show(symbols))]),
+            Cycle { .. } => catalog.message("cycle", &[]),
+            DuplicateCase { ref label, .. } => catalog.message("duplicate_case", &[("label", format!("{:?}", label))]),
+            DuplicateFunction { ref file, ref ident, .. } => catalog.message("duplicate_function", &[("file", file.clone()), ("ident", ident.clone())]),
+            DuplicateParam { ref ident, .. } => catalog.message("duplicate_param", &[("ident", ident.clone())]),
+            Eof => catalog.message("eof", &[]),
+            ExportedType { ref typ, .. } => catalog.message("exported_type", &[("type", typ.show(symbols))]),
+            ExtraField { ref ident, ref struct_name, .. } => catalog.message("extra_field", &[("ident", ident.clone()), ("struct", struct_name.clone())]),
+            Finalizer { .. } => catalog.message("finalizer", &[]),
+            Error::FunctionType { ref expected, ref unexpected, .. } => catalog.message("function_type", &[("expected", expected.show(symbols)), ("unexpected", unexpected.show(symbols))]),
+            InvalidCharacter { character, identifier, .. } => {
+                let key = if identifier { "unicode_identifier" } else { "invalid_character" };
+                let code = format!("U+{:04X}", character as u32);
+                catalog.message(key, &[("character", character.to_string()), ("code", code)])
+            },
+            InvalidEscape { ref escape, .. } => catalog.message("invalid_escape", &[("escape", escape.clone())]),
+            InvalidNumberOfParams { actual, expected, .. } => catalog.message("invalid_number_of_params", &[("actual", actual.to_string()), ("expected", expected.to_string())]),
+            InvalidUtf8 { byte, .. } => catalog.message("invalid_utf8", &[("byte", format!("0x{:02X}", byte))]),
+            LibraryDeclaration { .. } => catalog.message("library_declaration", &[]),
+            LoopVariableAssign { ref ident, .. } => catalog.message("loop_variable_assign", &[("ident", ident.clone())]),
+            MissingField { ref ident, ref struct_name, .. } => catalog.message("missing_field", &[("ident", ident.clone()), ("struct", struct_name.clone())]),
+            Msg(ref string) => string.clone(),
+            Multi(_) => unreachable!(),
+            NegativeArraySize { size, .. } => catalog.message("negative_array_size", &[("size", size.to_string())]),
+            NoValue { .. } => catalog.message("no_value", &[]),
+            NonStandard { dialect, ref feature, .. } => catalog.message("non_standard", &[("dialect", dialect.to_string()), ("feature", feature.clone())]),
+            NotAClass { ref typ, .. } => catalog.message("not_a_class", &[("type", typ.show(symbols))]),
+            NotARecordOrClass { ref typ, .. } => catalog.message("not_a_record_or_class", &[("type", typ.show(symbols))]),
+            PointerToCollected { ref typ, .. } => catalog.message("pointer_to_collected", &[("type", typ.show(symbols))]),
+            Private { ref class_name, ref ident, ref item, .. } => catalog.message("private", &[("class", class_name.clone()), ("ident", ident.clone()), ("item", item.clone())]),
+            Error::RecordType { .. } => catalog.message("record_type", &[]),
+            SignalHandler { .. } => catalog.message("signal_handler", &[]),
+            SignalHandlerLevel { ref ident, .. } => catalog.message("signal_handler_level", &[("ident", ident.clone())]),
+            StaticSelf { .. } => catalog.message("static_self", &[]),
+            Error::Type { ref expected, ref unexpected, .. } => catalog.message("type", &[("expected", expected.show(symbols)), ("unexpected", unexpected.show(symbols))]),
+            Unclosed { token, .. } => catalog.message("unclosed", &[("token", token.to_string())]),
+            Undefined { ref ident, ref item, .. } => catalog.message("undefined", &[("ident", ident.clone()), ("item", item.clone())]),
+            UnexpectedField { ref ident, ref struct_name, .. } => catalog.message("unexpected_field", &[("ident", ident.clone()), ("struct", struct_name.clone())]),
+            UnexpectedToken { ref expected, ref unexpected, .. } => catalog.message("unexpected_token", &[("expected", expected.clone()), ("unexpected", unexpected.to_string())]),
+            UnexpectedType { ref kind, .. } => catalog.message("unexpected_type", &[("kind", kind.clone())]),
+            UnknownToken { ref start, .. } => catalog.message("unknown_token", &[("start", start.to_string())]),
+        }
+    }
+
+    /// Position of the error in the source, if it comes from a specific place.
+    fn pos(&self) -> Option<Pos> {
+        match *self {
+            AddressOf { pos, .. } | Assign { pos, .. } | BreakOutsideLoop { pos, .. } | CannotDeref { pos, .. }
+            | CannotIndex { pos, .. } | Conversion { pos, .. } | Cycle { pos, .. } | DuplicateCase { pos, .. }
+            | DuplicateFunction { pos, .. } | DuplicateParam { pos, .. } | ExportedType { pos, .. } | ExtraField { pos, .. }
+            | Finalizer { pos, .. } | Error::FunctionType { pos, .. } | InvalidCharacter { pos, .. } | InvalidEscape { pos, .. }
+            | InvalidNumberOfParams { pos, .. } | InvalidUtf8 { pos, .. } | LibraryDeclaration { pos, .. }
+            | LoopVariableAssign { pos, .. } | MissingField { pos, .. } | NegativeArraySize { pos, .. }
+            | NoValue { pos, .. } | NonStandard { pos, .. } | NotAClass { pos, .. } | NotARecordOrClass { pos, .. }
+            | PointerToCollected { pos, .. } | Private { pos, .. } | Error::RecordType { pos, .. }
+            | SignalHandler { pos, .. } | SignalHandlerLevel { pos, .. } | StaticSelf { pos, .. }
+            | Error::Type { pos, .. } | Unclosed { pos, .. } | Undefined { pos, .. } | UnexpectedField { pos, .. }
+            | UnexpectedToken { pos, .. } | UnexpectedType { pos, .. } | UnknownToken { pos, .. } => Some(pos),
+            Eof | Msg(_) | Multi(_) => None,
+        }
+    }
+
+    /// Whether the line of the error is shown below it.
+    fn highlights_line(&self) -> bool {
+        match *self {
+            BreakOutsideLoop { .. } | CannotIndex { .. } | Cycle { .. } | ExtraField { .. } | MissingField { .. }
+            | Error::RecordType { .. } | UnexpectedField { .. } | UnexpectedType { .. } | Eof | Msg(_) | Multi(_) => false,
+            _ => true,
+        }
+    }
+
+    pub fn show(&self, symbols: &Symbols<()>, terminal: &Terminal, catalog: &Catalog) -> io::Result<()> {
         if let Multi(ref errors) = *self {
             for error in errors.iter().rev() {
-                error.show(symbols, terminal)?;
+                error.show(symbols, terminal, catalog)?;
             }
             return Ok(());
         }
-        eprint!("{}{}error: {}", terminal.bold(), terminal.red(), terminal.reset_color());
-        match *self {
-            Assign { pos } => {
-                eprintln!("Can only assign to variable, field or array element{}", terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            BreakOutsideLoop { pos } => {
-                eprintln!("Break statement used outside of loop{}", terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            CannotIndex { pos, ref typ } => {
-                eprintln!("Cannot index value of type `{}`{}", typ.show(symbols), terminal.end_bold());
-                pos.show(symbols, terminal)
-            },
-            Cycle { pos } => {
-                eprintln!("Type cycle detected:{}", terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            DuplicateParam { ref ident, pos } => {
-                eprintln!("Duplicate param `{}`{}", ident, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            Eof => eprintln!("end of file"),
-            ExtraField { ref ident, pos, ref struct_name } => {
-                eprintln!("Extra field `{}` in struct of type `{}`{}", ident, struct_name, terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            Error::FunctionType { ref expected, pos, ref unexpected } => {
-                eprintln!("Overridden method should have the same type as the inherited method:\nunexpected {}\n expecting {}{}", unexpected.show(symbols), expected.show(symbols), terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            InvalidEscape { ref escape, pos } => {
-                eprintln!("Invalid escape \\{}{}", escape, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            InvalidNumberOfParams { actual, expected, pos } => {
-                eprintln!("Invalid number of parameters: expecting {}, but found {}{}", expected, actual, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            MissingField { ref ident, pos, ref struct_name } => {
-                eprintln!("Missing field `{}` in struct of type `{}`{}", ident, struct_name, terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            Msg(ref string) => eprintln!("{}", string),
-            Multi(_) => unreachable!(),
-            NotAClass { pos, ref typ } => {
-                eprintln!("Type `{}` is not a class type{}", typ.show(symbols), terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            NotARecordOrClass { pos, ref typ } => {
-                eprintln!("Type `{}` is not a struct or a class type{}", typ.show(symbols), terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            Error::RecordType { pos } => {
-                eprintln!("Expecting type when value is nil{}", terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            Error::Type { ref expected, pos, ref unexpected } => {
-                eprintln!("Unexpected type {}, expecting {}{}", unexpected.show(symbols), expected.show(symbols), terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            Unclosed { pos, token } => {
-                eprintln!("Unclosed {}{}", token, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            Undefined { ref ident, ref item, pos } => {
-                eprintln!("Undefined {} `{}`{}", item, ident, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            UnexpectedField { ref ident, pos, ref struct_name } => {
-                eprintln!("Unexpected field `{}` in struct of type `{}`{}", ident, struct_name, terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            UnexpectedToken { ref expected, pos, ref unexpected } => {
-                eprintln!("Unexpected token {}, expecting {}{}", unexpected, expected, terminal.end_bold());
-                pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
-            },
-            UnexpectedType { ref kind, pos } => {
-                eprintln!("Expecting {} type{}", kind, terminal.end_bold());
-                pos.show(symbols, terminal);
-            },
-            UnknownToken { pos, ref start } => {
-                eprintln!("Unexpected start of token `{}`{}", start, terminal.end_bold());
+        if self.is_cascading() {
+            return Ok(());
+        }
+        eprint!("{}{}{}: {}", terminal.bold(), terminal.red(), catalog.message("error", &[]), terminal.reset_color());
+        let message = self.message(symbols, catalog);
+        match self.pos() {
+            Some(pos) => {
+                eprintln!("{}{}", message, terminal.end_bold());
                 pos.show(symbols, terminal);
-                highlight_line(pos, symbols, terminal)?;
+                if self.highlights_line() {
+                    highlight_line(pos, symbols, terminal)?;
+                }
             },
+            None => eprintln!("{}", message),
         }
         eprintln!();
 
         Ok(())
     }
+
+    /// Write the errors as JSON lines, for `--error-format json`.
+    pub fn show_json(&self, symbols: &Symbols<()>, catalog: &Catalog) {
+        if let Multi(ref errors) = *self {
+            for error in errors.iter().rev() {
+                error.show_json(symbols, catalog);
+            }
+            return;
+        }
+        if self.is_cascading() {
+            return;
+        }
+        let file = self.pos().map(|pos| symbols.name(pos.file));
+        let location = file.as_deref().zip(self.pos());
+        show_json_diagnostic("error", &self.message(symbols, catalog), location);
+    }
 }
 
 impl From<io::Error> for Error {
@@ -248,15 +555,55 @@ impl<'a> From<&'a Error> for Error {
     }
 }
 
+/// Write a diagnostic as a JSON object on one line:
+/// `{"severity":"error","message":"...","file":"a.tig","line":1,"column":5,"span":{"byte":4,"length":3}}`,
+/// where the position is `null` for the diagnostics that do not come from a place in the source.
+pub fn show_json_diagnostic(severity: &str, message: &str, location: Option<(&str, Pos)>) {
+    let location =
+        match location {
+            Some((file, pos)) => format!("\"file\":{},\"line\":{},\"column\":{},\"span\":{{\"byte\":{},\"length\":{}}}",
+                json_string(file), pos.line, pos.column, pos.byte, pos.length),
+            None => "\"file\":null,\"line\":null,\"column\":null,\"span\":null".to_string(),
+        };
+    eprintln!("{{\"severity\":{},\"message\":{},{}}}", json_string(severity), json_string(message), location);
+}
+
+fn json_string(string: &str) -> String {
+    let mut result = "\"".to_string();
+    for char in string.chars() {
+        match char {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            char if (char as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", char as u32)),
+            char => result.push(char),
+        }
+    }
+    result.push('"');
+    result
+}
+
 fn highlight_line(pos: Pos, symbols: &Symbols<()>, terminal: &Terminal) -> io::Result<()> {
     let filename = symbols.name(pos.file);
-    let mut file = File::open(filename)?;
     // TODO: support longer lines.
     const LENGTH: i64 = 4096;
     let mut buffer = [0; LENGTH as usize];
     let start = max(0, pos.byte as i64 - LENGTH / 2);
-    file.seek(SeekFrom::Start(start as u64))?;
-    let size_read = file.read(&mut buffer)?;
+    let size_read =
+        if filename == STDIN_NAME {
+            let source = STDIN_SOURCE.lock().expect("lock stdin source");
+            let source = &source[min(start as usize, source.len())..];
+            let size = min(source.len(), buffer.len());
+            buffer[..size].copy_from_slice(&source[..size]);
+            size
+        }
+        else {
+            let mut file = File::open(filename)?;
+            file.seek(SeekFrom::Start(start as u64))?;
+            file.read(&mut buffer)?
+        };
     let buffer = &buffer[..size_read];
     let current_pos = min(pos.byte as usize - start as usize, buffer.len());
     let start_of_line = buffer[..current_pos].iter().rposition(|byte| *byte == b'\n')
@@ -19,13 +19,14 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ast::{
+    Arena,
     Declaration,
     DeclarationWithPos,
     Expr,
-    ExprWithPos,
+    ExprId,
     FuncDeclaration,
     Operator,
 };
@@ -39,31 +40,33 @@ pub struct DepthEscape {
 
 pub type EscapeEnv = Symbols<DepthEscape>;
 
-struct EscapeFinder {
+struct EscapeFinder<'a> {
+    arena: &'a Arena,
     env: EscapeEnv,
 }
 
-impl EscapeFinder {
-    fn new(strings: Rc<Strings>) -> Self {
+impl<'a> EscapeFinder<'a> {
+    fn new(arena: &'a Arena, strings: Arc<Strings>) -> Self {
         Self {
+            arena,
             env: Symbols::new(strings),
         }
     }
 
-    fn visit_binary_op(&mut self, left: &ExprWithPos, right: &ExprWithPos, depth: u32) {
+    fn visit_binary_op(&mut self, left: ExprId, right: ExprId, depth: u32) {
         self.visit_exp(left, depth);
         self.visit_exp(right, depth);
     }
 
     fn visit_dec(&mut self, declaration: &DeclarationWithPos, depth: u32) {
         match declaration.node {
-            Declaration::ClassDeclaration { ref declarations, .. } => {
-                for declaration in declarations {
-                    self.visit_dec(declaration, depth + 1);
+            Declaration::ClassDeclaration { ref members, .. } => {
+                for member in members {
+                    self.visit_dec(&member.declaration, depth + 1);
                 }
             },
             Declaration::Function(ref declarations) => {
-                for &WithPos { node: FuncDeclaration { ref params, ref body, .. }, .. } in declarations {
+                for &WithPos { node: FuncDeclaration { ref params, body, .. }, .. } in declarations {
                     for param in params {
                         self.env.enter(param.node.name, DepthEscape {
                             depth,
@@ -74,7 +77,7 @@ impl EscapeFinder {
                 }
             },
             Declaration::Type(_) => (),
-            Declaration::VariableDeclaration { ref init, name, .. } => {
+            Declaration::VariableDeclaration { init, name, .. } => {
                 self.visit_exp(init, depth + 1);
                 self.env.enter(name, DepthEscape {
                     depth,
@@ -84,23 +87,45 @@ impl EscapeFinder {
         }
     }
 
-    fn visit_exp(&mut self, expr: &ExprWithPos, depth: u32) {
-        match expr.node {
-            Expr::Array { ref init, ref size, .. } => {
+    fn visit_exp(&mut self, expr: ExprId, depth: u32) {
+        let arena = self.arena;
+        match arena[expr].node {
+            // NOTE: a variable whose address is taken must live in the frame.
+            Expr::AddressOf { var } => {
+                if let Expr::Variable(ref ident) = arena[var].node {
+                    if let Some(ref mut var) = self.env.look_mut(ident.node) {
+                        var.escape = true;
+                    }
+                }
+                else {
+                    self.visit_exp(var, depth);
+                }
+            },
+            Expr::Array { init, size, .. } => {
                 self.visit_exp(size, depth);
                 self.visit_exp(init, depth);
             },
-            Expr::Assign { ref expr, ref var } => {
+            Expr::Assign { expr, var } => {
                 self.visit_exp(var, depth);
                 self.visit_exp(expr, depth);
             },
             Expr::Break => {
             },
             Expr::Call { ref args, .. } => {
-                for arg in args {
+                for &arg in args {
                     self.visit_exp(arg, depth);
                 }
             },
+            Expr::Case { ref arms, else_, subject } => {
+                self.visit_exp(subject, depth);
+                for arm in arms {
+                    self.visit_exp(arm.node.body, depth);
+                }
+                if let Some(else_) = else_ {
+                    self.visit_exp(else_, depth);
+                }
+            },
+            Expr::Deref { pointer } => self.visit_exp(pointer, depth),
             Expr::Field { ref ident, .. } |
                 Expr::Variable(ref ident) => {
                 if let Some(ref mut var) = self.env.look_mut(ident.node) {
@@ -109,62 +134,63 @@ impl EscapeFinder {
                     }
                 }
             },
-            Expr::If { ref else_, ref test, ref then } => {
+            Expr::If { else_, test, then } => {
                 self.visit_exp(test, depth);
                 self.visit_exp(then, depth);
-                if let Some(ref else_) = *else_ {
-                    self.visit_exp(&else_, depth);
+                if let Some(else_) = else_ {
+                    self.visit_exp(else_, depth);
                 }
             },
             Expr::Int { .. } => (),
-            Expr::Let { ref body, ref declarations } => {
+            Expr::Let { body, ref declarations } => {
                 for declaration in declarations {
                     self.visit_dec(declaration, depth);
                 }
                 self.visit_exp(body, depth);
             },
-            Expr::MethodCall { ref args, .. } => {
-                for arg in args {
+            Expr::MethodCall { ref args, this, .. } => {
+                self.visit_exp(this, depth);
+                for &arg in args {
                     self.visit_exp(arg, depth);
                 }
             },
             Expr::New { .. } => (),
             Expr::Nil => (),
-            Expr::Oper { ref left, oper: WithPos { node: Operator::Plus, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Minus, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Times, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Lt, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Gt, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::And, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Or, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Ge, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Le, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Divide, .. }, ref right } =>
+            Expr::Oper { left, oper: WithPos { node: Operator::Plus, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Minus, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Times, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Lt, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Gt, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::And, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Or, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Ge, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Le, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Divide, .. }, right } =>
                 self.visit_binary_op(left, right, depth),
-            Expr::Oper { ref left, oper: WithPos { node: Operator::Equal, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: Operator::Neq, .. }, ref right } => {
+            Expr::Oper { left, oper: WithPos { node: Operator::Equal, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: Operator::Neq, .. }, right } => {
                 self.visit_exp(left, depth);
                 self.visit_exp(right, depth);
             },
             Expr::Record { ref fields, .. } => {
                 for field in fields {
-                    self.visit_exp(&field.node.expr, depth);
+                    self.visit_exp(field.node.expr, depth);
                 }
             },
             Expr::Sequence(ref exprs) => {
                 if let Some((last_expr, exprs)) = exprs.split_last() {
-                    for expr in exprs {
+                    for &expr in exprs {
                         self.visit_exp(expr, depth);
                     }
-                    self.visit_exp(last_expr, depth)
+                    self.visit_exp(*last_expr, depth)
                 }
             },
             Expr::Str { .. } => (),
-            Expr::Subscript { ref expr, ref this } => {
+            Expr::Subscript { expr, this } => {
                 self.visit_exp(this, depth);
                 self.visit_exp(expr, depth);
             },
-            Expr::While { ref body, ref test } => {
+            Expr::While { body, test } => {
                 self.visit_exp(test, depth);
                 self.visit_exp(body, depth);
             },
@@ -172,8 +198,8 @@ impl EscapeFinder {
     }
 }
 
-pub fn find_escapes(exp: &ExprWithPos, strings: Rc<Strings>) -> EscapeEnv {
-    let mut finder = EscapeFinder::new(strings);
+pub fn find_escapes(arena: &Arena, exp: ExprId, strings: Arc<Strings>) -> EscapeEnv {
+    let mut finder = EscapeFinder::new(arena, strings);
     finder.visit_exp(exp, 0);
     finder.env
 }
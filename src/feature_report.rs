@@ -0,0 +1,260 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Report of the language features used by a program, printed by `--feature-report`, to track
+//! what is missing to compile bigger Tiger programs.
+
+use std::collections::{BTreeMap, HashSet};
+
+use ast::{Arena, Ast, Declaration, Expr, ExprId, FuncDeclaration, Ty, TypeDec, Visibility};
+use dialect::Dialect;
+use env::{STANDARD_FUNCTIONS, UNIMPLEMENTED_FUNCTIONS, external_functions};
+use position::WithPos;
+use symbol::{Symbol, Symbols};
+
+struct Usage {
+    count: usize,
+    dialect: Dialect,
+    implemented: bool,
+}
+
+pub struct FeatureReport {
+    // The functions declared by the program hide the runtime functions of the same name.
+    declared_functions: HashSet<Symbol>,
+    called_functions: Vec<Symbol>,
+    features: BTreeMap<String, Usage>,
+}
+
+impl FeatureReport {
+    pub fn new(ast: &Ast, symbols: &Symbols<()>) -> Self {
+        let mut report = Self {
+            declared_functions: HashSet::new(),
+            called_functions: vec![],
+            features: BTreeMap::new(),
+        };
+        report.visit_exp(&ast.arena, ast.root, 0);
+
+        let runtime_functions = external_functions();
+        for function in report.called_functions.clone() {
+            let name = symbols.name(function);
+            if report.declared_functions.contains(&function) || !runtime_functions.contains_key(name.as_str()) {
+                continue;
+            }
+            let dialect =
+                if STANDARD_FUNCTIONS.contains(&name.as_str()) {
+                    Dialect::Tiger
+                }
+                else {
+                    Dialect::Extended
+                };
+            let implemented = !UNIMPLEMENTED_FUNCTIONS.contains(&name.as_str());
+            report.add(&format!("function `{}`", name), dialect, implemented);
+        }
+        report
+    }
+
+    pub fn print(&self) {
+        println!("{:<28} {:>5}  {:<13} status", "feature", "uses", "dialect");
+        for (name, usage) in &self.features {
+            let status = if usage.implemented { "implemented" } else { "missing from the runtime" };
+            println!("{:<28} {:>5}  {:<13} {}", name, usage.count, usage.dialect.to_string(), status);
+        }
+        let dialect = self.features.values()
+            .map(|usage| usage.dialect)
+            .max()
+            .unwrap_or(Dialect::Tiger);
+        let implemented = self.features.values()
+            .filter(|usage| usage.implemented)
+            .count();
+        println!();
+        println!("{} of {} features implemented, requires --dialect {}", implemented, self.features.len(), dialect);
+        let missing = self.features.iter()
+            .filter(|&(_, usage)| !usage.implemented)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            println!("to do: {}", missing.join(", "));
+        }
+    }
+
+    fn add(&mut self, feature: &str, dialect: Dialect, implemented: bool) {
+        self.features.entry(feature.to_string())
+            .or_insert(Usage {
+                count: 0,
+                dialect,
+                implemented,
+            })
+            .count += 1;
+    }
+
+    fn add_feature(&mut self, feature: &str, dialect: Dialect) {
+        self.add(feature, dialect, true);
+    }
+
+    /// `depth` is the number of functions the declaration is nested in.
+    fn visit_dec(&mut self, arena: &Arena, declaration: &WithPos<Declaration>, depth: u32) {
+        match declaration.node {
+            Declaration::ClassDeclaration { ref members, .. } => {
+                self.add_feature("classes", Dialect::ObjectTiger);
+                for member in members {
+                    if member.is_static {
+                        self.add_feature("static members", Dialect::ObjectTiger);
+                    }
+                    if member.visibility == Visibility::Private {
+                        self.add_feature("private members", Dialect::ObjectTiger);
+                    }
+                    // NOTE: the methods are not nested in the functions the class is declared in.
+                    self.visit_dec(arena, &member.declaration, 0);
+                }
+            },
+            Declaration::Function(ref declarations) => {
+                for &WithPos { node: FuncDeclaration { body, ref name, .. }, .. } in declarations {
+                    self.declared_functions.insert(name.node);
+                    self.add_feature("functions", Dialect::Tiger);
+                    if depth > 0 {
+                        self.add_feature("nested functions", Dialect::Tiger);
+                    }
+                    self.visit_exp(arena, body, depth + 1);
+                }
+            },
+            Declaration::Type(ref declarations) => {
+                for &WithPos { node: TypeDec { ref ty, .. }, .. } in declarations {
+                    match ty.node {
+                        Ty::Array { .. } => self.add_feature("arrays", Dialect::Tiger),
+                        Ty::Name { .. } => self.add_feature("type aliases", Dialect::Tiger),
+                        Ty::Pointer { .. } => self.add_feature("pointers", Dialect::Unsafe),
+                        Ty::Record { .. } => self.add_feature("records", Dialect::Tiger),
+                        Ty::Weak { .. } => self.add_feature("weak references", Dialect::Extended),
+                    }
+                }
+            },
+            Declaration::VariableDeclaration { init, loop_variable, .. } => {
+                if loop_variable {
+                    self.add_feature("for loops", Dialect::Tiger);
+                }
+                self.visit_exp(arena, init, depth);
+            },
+        }
+    }
+
+    fn visit_exp(&mut self, arena: &Arena, expr: ExprId, depth: u32) {
+        // NOTE: the for loops are converted to while loops whose nodes have no position, which
+        // are not counted.
+        let written = !arena[expr].pos.is_dummy();
+        match arena[expr].node {
+            Expr::AddressOf { var } => {
+                self.add_feature("pointers", Dialect::Unsafe);
+                self.visit_exp(arena, var, depth);
+            },
+            Expr::Array { init, size, .. } => {
+                self.add_feature("arrays", Dialect::Tiger);
+                self.visit_exp(arena, size, depth);
+                self.visit_exp(arena, init, depth);
+            },
+            Expr::Assign { expr, var } => {
+                if written {
+                    self.add_feature("assignments", Dialect::Tiger);
+                }
+                self.visit_exp(arena, var, depth);
+                self.visit_exp(arena, expr, depth);
+            },
+            Expr::Break => {
+                if written {
+                    self.add_feature("break", Dialect::Tiger);
+                }
+            },
+            Expr::Call { ref args, function } => {
+                self.called_functions.push(function);
+                for &arg in args {
+                    self.visit_exp(arena, arg, depth);
+                }
+            },
+            Expr::Case { ref arms, else_, subject } => {
+                self.add_feature("case expressions", Dialect::Extended);
+                self.visit_exp(arena, subject, depth);
+                for arm in arms {
+                    self.visit_exp(arena, arm.node.body, depth);
+                }
+                if let Some(else_) = else_ {
+                    self.visit_exp(arena, else_, depth);
+                }
+            },
+            Expr::Deref { pointer } => {
+                self.add_feature("pointers", Dialect::Unsafe);
+                self.visit_exp(arena, pointer, depth);
+            },
+            Expr::Field { this, .. } => self.visit_exp(arena, this, depth),
+            Expr::If { else_, test, then } => {
+                if written {
+                    self.add_feature("conditionals", Dialect::Tiger);
+                }
+                self.visit_exp(arena, test, depth);
+                self.visit_exp(arena, then, depth);
+                if let Some(else_) = else_ {
+                    self.visit_exp(arena, else_, depth);
+                }
+            },
+            Expr::Int { .. } | Expr::Variable(_) => (),
+            Expr::Let { body, ref declarations } => {
+                for declaration in declarations {
+                    self.visit_dec(arena, declaration, depth);
+                }
+                self.visit_exp(arena, body, depth);
+            },
+            Expr::MethodCall { ref args, this, .. } => {
+                self.add_feature("method calls", Dialect::ObjectTiger);
+                self.visit_exp(arena, this, depth);
+                for &arg in args {
+                    self.visit_exp(arena, arg, depth);
+                }
+            },
+            Expr::New { .. } => self.add_feature("object creation", Dialect::ObjectTiger),
+            Expr::Nil => self.add_feature("nil", Dialect::Tiger),
+            Expr::Oper { left, right, .. } => {
+                self.visit_exp(arena, left, depth);
+                self.visit_exp(arena, right, depth);
+            },
+            Expr::Record { ref fields, .. } => {
+                self.add_feature("records", Dialect::Tiger);
+                for field in fields {
+                    self.visit_exp(arena, field.node.expr, depth);
+                }
+            },
+            Expr::Sequence(ref exprs) => {
+                for &expr in exprs {
+                    self.visit_exp(arena, expr, depth);
+                }
+            },
+            Expr::Str { .. } => self.add_feature("strings", Dialect::Tiger),
+            Expr::Subscript { expr, this } => {
+                self.visit_exp(arena, this, depth);
+                self.visit_exp(arena, expr, depth);
+            },
+            Expr::While { body, test } => {
+                if written {
+                    self.add_feature("while loops", Dialect::Tiger);
+                }
+                self.visit_exp(arena, test, depth);
+                self.visit_exp(arena, body, depth);
+            },
+        }
+    }
+}
@@ -21,7 +21,7 @@
 
 use std::collections::{BTreeSet, HashMap};
 
-use asm::Instruction;
+use asm::{Instruction, Operand};
 use graph::{self, Entry, Graph};
 use temp::{Label, Temp};
 
@@ -76,13 +76,13 @@ impl<'a> GraphBuilder<'a> {
         let defines =
             match *instruction {
                 Instruction::Call { ref destination, .. } | Instruction::Move { ref destination, .. } | Instruction::Operation { ref destination, .. } =>
-                    destination.iter().cloned().collect(),
+                    destination.iter().map(Operand::temp).collect(),
                 _ => BTreeSet::new(),
             };
         let uses =
             match *instruction {
                 Instruction::Call { ref source, .. } | Instruction::Move { ref source, .. } | Instruction::Operation { ref source, .. } =>
-                    source.iter().cloned().collect(),
+                    source.iter().map(Operand::temp).collect(),
                 _ => BTreeSet::new(),
             };
         let stack_defines =
@@ -0,0 +1,742 @@
+/*
+ * Copyright (c) 2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Textual form of the fragments, which is what the front end hands to the back end.
+//!
+//! A fragment file (`.frag`) is a list of s-expressions, one per fragment:
+//!
+//! ```text
+//! (function NAME (formals ACCESS...) (pointer OFFSET) (escaping OFFSET...)
+//!     (vars OFFSET...) (temps TEMP...)
+//!     STATEMENT)
+//! (display LABEL SIZE)
+//! (pure LABEL...)
+//! (static-fields LABEL "LAYOUT")
+//! (string LABEL "TEXT")
+//! (template LABEL (N...))
+//! (vtable LABEL (LABEL...) [(finalizer METHOD LINK-FIELD)])
+//! ```
+//!
+//! An access is `(stack OFFSET)` or `(reg TEMP)`. `pointer` is the offset of the last local
+//! allocated in the frame, `escaping` lists the stack variables that escape, and `vars` and
+//! `temps` are the variables holding pointers.
+//!
+//! A statement is one of:
+//!
+//! ```text
+//! (move EXP EXP)  (exp EXP)  (jump EXP LABEL...)  (cjump RELOP EXP EXP LABEL LABEL)
+//! (seq STATEMENT STATEMENT...)  (label LABEL)  (position LINE)  (var OFFSET STATEMENT)
+//! ```
+//!
+//! where `(seq a b c)` stands for `(seq a (seq b c))` and `var` marks a statement writing to a
+//! stack variable. An expression is one of:
+//!
+//! ```text
+//! (const N)  (error)  (name LABEL)  (temp TEMP)  (binop BINOP EXP EXP)  (mem EXP)
+//! (compare RELOP EXP EXP)  (select RELOP EXP EXP EXP EXP)
+//! (call RETURN-LABEL COLLECTABLE EXP ARG...)  (eseq STATEMENT EXP)
+//! ```
+//!
+//! A temp is a register name (`rax`) or `t` followed by a number, a label is `l` followed by a
+//! number or a name, `COLLECTABLE` is `true` or `false`, `BINOP` is one of `plus`, `minus`,
+//! `mul`, `div`, `and`, `or`, `shl`, `shr`, `sar` and `xor`, and `RELOP` is one of `eq`, `ne`,
+//! `lt`, `gt`, `le`, `ge`, `ult`, `ule`, `ugt` and `uge`.
+//! In strings, `\"`, `\\`, `\n`, `\t` and `\DDD` (decimal character code) are escaped.
+//! Comments start with `;` and go until the end of the line.
+//! The numbers of the temps and labels are only meaningful within a file: they are renumbered
+//! when the file is read.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::result;
+use std::str::Chars;
+
+use error::{Error, Result};
+use frame::{Finalizer, Fragment, Frame};
+use frame::x86_64::{Access, X86_64};
+use ir::{BinOp, Exp, RelationalOp, Statement, _Statement};
+use temp::{DataLabel, Label, Temp, TempMap};
+
+const INDENT: &str = "    ";
+
+pub fn write<W: Write>(file: &mut W, fragments: &[Fragment<X86_64>]) -> io::Result<()> {
+    for fragment in fragments {
+        match *fragment {
+            Fragment::Constant(ref label, value) => writeln!(file, "(constant {} {})", label, value)?,
+            Fragment::Display { ref name, size } => writeln!(file, "(display {} {})", name, size)?,
+            Fragment::Function { ref body, ref escaping_vars, ref frame, ref temp_map } => {
+                let frame = frame.borrow();
+                let formals = frame.formals().iter()
+                    .map(|formal|
+                        match *formal {
+                            Access::InFrame(offset) => format!("(stack {})", offset),
+                            Access::InReg(temp) => format!("(reg {})", temp.to_string::<X86_64>()),
+                        });
+                let temps = temp_map.temps().iter()
+                    .map(Temp::to_string::<X86_64>);
+                writeln!(file, "(function {} (formals{}) (pointer {}) (escaping{})", frame.name(), spaced(formals),
+                    frame.pointer(), spaced(escaping_vars))?;
+                writeln!(file, "{}(vars{}) (temps{})", INDENT, spaced(temp_map.stack_vars()), spaced(temps))?;
+                writeln!(file, "{}{})", INDENT, statement_text(body, Some(1)))?;
+            },
+            Fragment::PureFunctions(ref functions) => writeln!(file, "(pure{})", spaced(functions))?,
+            Fragment::StaticFields { ref data_layout, ref name } =>
+                writeln!(file, "(static-fields {} {})", name, quote(data_layout))?,
+            Fragment::Str(ref label, ref string) => writeln!(file, "(string {} {})", label, quote(string))?,
+            Fragment::StringTable { ref name, ref strings } =>
+                writeln!(file, "(string-table {} ({}))", name, spaced(strings).trim_start())?,
+            Fragment::Template { ref name, ref values } =>
+                writeln!(file, "(template {} ({}))", name, spaced(values).trim_start())?,
+            Fragment::VTable { ref class, finalizer, ref methods } => {
+                write!(file, "(vtable {} ({})", class, spaced(methods).trim_start())?;
+                if let Some(finalizer) = finalizer {
+                    write!(file, " (finalizer {} {})", finalizer.method, finalizer.link_field)?;
+                }
+                writeln!(file, ")")?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Items preceded by a space each.
+fn spaced<T: Display>(items: impl IntoIterator<Item=T>) -> String {
+    items.into_iter()
+        .map(|item| format!(" {}", item))
+        .collect()
+}
+
+fn quote(string: &str) -> String {
+    let mut result = "\"".to_string();
+    for char in string.chars() {
+        match char {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            _ if char.is_control() => result.push_str(&format!("\\{:03}", char as u32)),
+            _ => result.push(char),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Text of a statement, with its sub-statements on their own line indented at the depth, if
+/// specified, or on the same line otherwise.
+pub fn statement_text(statement: &Statement, depth: Option<usize>) -> String {
+    let nested_depth = depth.map(|depth| depth + 1);
+    let separator = separator(nested_depth);
+    match statement.stack_var {
+        Some(stack_var) =>
+            format!("(var {}{}{})", stack_var, separator, node_text(&statement.statement, nested_depth)),
+        None => node_text(&statement.statement, depth),
+    }
+}
+
+/// Text preceding a sub-statement.
+fn separator(depth: Option<usize>) -> String {
+    match depth {
+        Some(depth) => format!("\n{}", INDENT.repeat(depth)),
+        None => " ".to_string(),
+    }
+}
+
+fn node_text(statement: &_Statement, depth: Option<usize>) -> String {
+    let nested_depth = depth.map(|depth| depth + 1);
+    let separator = separator(nested_depth);
+    match *statement {
+        _Statement::Move(ref destination, ref source) =>
+            format!("(move {} {})", exp_text(destination, depth), exp_text(source, depth)),
+        _Statement::Exp(ref expr) => format!("(exp {})", exp_text(expr, depth)),
+        _Statement::Jump(ref expr, ref labels) => format!("(jump {}{})", exp_text(expr, depth), spaced(labels)),
+        _Statement::CondJump { ref op, ref left, ref right, ref true_label, ref false_label } =>
+            format!("(cjump {} {} {} {} {})", relational_op_name(op), exp_text(left, depth), exp_text(right, depth),
+                true_label, false_label),
+        _Statement::Sequence(ref statement1, ref statement2) => {
+            let mut text = format!("(seq{}{}", separator, statement_text(statement1, nested_depth));
+            // Write the right spine of the sequence as a flat list.
+            let mut rest = statement2;
+            while let Statement { stack_var: None, statement: _Statement::Sequence(ref statement1, ref statement2) } = **rest {
+                text.push_str(&separator);
+                text.push_str(&statement_text(statement1, nested_depth));
+                rest = statement2;
+            }
+            text.push_str(&separator);
+            text.push_str(&statement_text(rest, nested_depth));
+            text.push(')');
+            text
+        },
+        _Statement::Label(ref label) => format!("(label {})", label),
+        _Statement::Position(line) => format!("(position {})", line),
+    }
+}
+
+/// Text of an expression, written on a single line except for the statements it contains.
+fn exp_text(expr: &Exp, depth: Option<usize>) -> String {
+    let text = |expr: &Exp| exp_text(expr, depth);
+    match *expr {
+        Exp::Const(value) => format!("(const {})", value),
+        Exp::Error => "(error)".to_string(),
+        Exp::Name(ref label) => format!("(name {})", label),
+        Exp::Temp(temp) => format!("(temp {})", temp.to_string::<X86_64>()),
+        Exp::BinOp { ref op, ref left, ref right } =>
+            format!("(binop {} {} {})", bin_op_name(op), text(left), text(right)),
+        Exp::Mem(ref expr) => format!("(mem {})", text(expr)),
+        Exp::MemByte(ref expr) => format!("(memb {})", text(expr)),
+        Exp::Compare { ref op, ref left, ref right } =>
+            format!("(compare {} {} {})", relational_op_name(op), text(left), text(right)),
+        Exp::Select { ref op, ref left, ref right, ref if_true, ref if_false } =>
+            format!("(select {} {} {} {} {})", relational_op_name(op), text(left), text(right), text(if_true),
+                text(if_false)),
+        Exp::Call { ref arguments, collectable_return_type, ref function_expr, ref return_label } =>
+            format!("(call {} {} {}{})", return_label, collectable_return_type, text(function_expr),
+                spaced(arguments.iter().map(text))),
+        Exp::ExpSequence(ref statement, ref expr) => {
+            let nested_depth = depth.map(|depth| depth + 1);
+            let separator = separator(nested_depth);
+            format!("(eseq{}{}{}{})", separator, statement_text(statement, nested_depth), separator,
+                exp_text(expr, nested_depth))
+        },
+    }
+}
+
+const BIN_OPS: [(BinOp, &str); 10] = [
+    (BinOp::Plus, "plus"),
+    (BinOp::Minus, "minus"),
+    (BinOp::Mul, "mul"),
+    (BinOp::Div, "div"),
+    (BinOp::And, "and"),
+    (BinOp::Or, "or"),
+    (BinOp::ShiftLeft, "shl"),
+    (BinOp::ShiftRight, "shr"),
+    (BinOp::ArithmeticShiftRight, "sar"),
+    (BinOp::Xor, "xor"),
+];
+
+const RELATIONAL_OPS: [(RelationalOp, &str); 10] = [
+    (RelationalOp::Equal, "eq"),
+    (RelationalOp::NotEqual, "ne"),
+    (RelationalOp::LesserThan, "lt"),
+    (RelationalOp::GreaterThan, "gt"),
+    (RelationalOp::LesserOrEqual, "le"),
+    (RelationalOp::GreaterOrEqual, "ge"),
+    (RelationalOp::UnsignedLesserThan, "ult"),
+    (RelationalOp::UnsignedLesserOrEqual, "ule"),
+    (RelationalOp::UnsignedGreaterThan, "ugt"),
+    (RelationalOp::UnsignedGreaterOrEqual, "uge"),
+];
+
+fn bin_op_name(op: &BinOp) -> &'static str {
+    BIN_OPS.iter()
+        .find(|entry| entry.0 == *op)
+        .map(|&(_, name)| name)
+        .expect("bin op name")
+}
+
+fn relational_op_name(op: &RelationalOp) -> &'static str {
+    RELATIONAL_OPS.iter()
+        .find(|entry| entry.0 == *op)
+        .map(|&(_, name)| name)
+        .expect("relational op name")
+}
+
+pub fn read(text: &str) -> Result<Vec<Fragment<X86_64>>> {
+    let mut chars = text.chars().peekable();
+    let mut reader = Reader::new();
+    let mut fragments = vec![];
+    while let Some(sexp) = parse_sexp(&mut chars).map_err(Error::Msg)? {
+        fragments.push(reader.fragment(&sexp).map_err(Error::Msg)?);
+    }
+    Ok(fragments)
+}
+
+/// Result whose error is the message explaining why the file is invalid.
+type ParseResult<T> = result::Result<T, String>;
+
+#[derive(Debug)]
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+    Str(String),
+}
+
+impl Display for Sexp {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Sexp::Atom(ref atom) => write!(formatter, "{}", atom),
+            Sexp::List(ref items) => {
+                let items = items.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                write!(formatter, "({})", items.join(" "))
+            },
+            Sexp::Str(ref string) => write!(formatter, "{}", quote(string)),
+        }
+    }
+}
+
+fn error(expected: &str, found: &dyn Display) -> String {
+    format!("invalid fragment file: expected {}, found `{}`", expected, found)
+}
+
+fn skip_blanks(chars: &mut Peekable<Chars>) {
+    while let Some(&char) = chars.peek() {
+        if char == ';' {
+            while chars.peek().is_some_and(|&char| char != '\n') {
+                chars.next();
+            }
+        }
+        else if char.is_whitespace() {
+            chars.next();
+        }
+        else {
+            break;
+        }
+    }
+}
+
+/// Parse the next s-expression, if there is one.
+fn parse_sexp(chars: &mut Peekable<Chars>) -> ParseResult<Option<Sexp>> {
+    skip_blanks(chars);
+    let sexp =
+        match chars.next() {
+            None => return Ok(None),
+            Some('(') => {
+                let mut items = vec![];
+                loop {
+                    skip_blanks(chars);
+                    if chars.peek() == Some(&')') {
+                        chars.next();
+                        break;
+                    }
+                    match parse_sexp(chars)? {
+                        Some(item) => items.push(item),
+                        None => return Err(error("`)`", &"end of file")),
+                    }
+                }
+                Sexp::List(items)
+            },
+            Some(')') => return Err(error("an expression", &")")),
+            Some('"') => Sexp::Str(parse_string(chars)?),
+            Some(char) => {
+                let mut atom = char.to_string();
+                while let Some(&char) = chars.peek() {
+                    if char.is_whitespace() || char == '(' || char == ')' || char == '"' || char == ';' {
+                        break;
+                    }
+                    atom.push(char);
+                    chars.next();
+                }
+                Sexp::Atom(atom)
+            },
+        };
+    Ok(Some(sexp))
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> ParseResult<String> {
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            None => return Err(error("`\"`", &"end of file")),
+            Some('"') => return Ok(string),
+            Some('\\') =>
+                match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(digit) if digit.is_ascii_digit() => {
+                        let mut code = digit.to_string();
+                        for _ in 0..2 {
+                            code.extend(chars.next());
+                        }
+                        let char = code.parse().ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| error("a character code", &code))?;
+                        string.push(char);
+                    },
+                    Some(char) => return Err(error("an escape sequence", &char)),
+                    None => return Err(error("an escape sequence", &"end of file")),
+                },
+            Some(char) => string.push(char),
+        }
+    }
+}
+
+/// Rebuild the fragments, giving fresh numbers to the temps and labels of the file.
+struct Reader {
+    labels: HashMap<u32, Label>,
+    registers: HashMap<&'static str, Temp>,
+    temps: HashMap<u32, Temp>,
+}
+
+// The slice patterns take explicit references because of `pattern_type_mismatch`.
+#[allow(clippy::needless_borrowed_reference)]
+impl Reader {
+    fn new() -> Self {
+        let registers = X86_64::temp_map().into_iter()
+            .map(|(register, name)| (name, register.temp()))
+            .collect();
+        Self {
+            labels: HashMap::new(),
+            registers,
+            temps: HashMap::new(),
+        }
+    }
+
+    fn fragment(&mut self, sexp: &Sexp) -> ParseResult<Fragment<X86_64>> {
+        let fragment =
+            match form(sexp)? {
+                ("constant", &[ref label, ref value]) =>
+                    Fragment::Constant(DataLabel::from_label(self.label(label)?), number(value)?),
+                ("display", &[ref name, ref size]) => Fragment::Display {
+                    name: DataLabel::from_label(self.label(name)?),
+                    size: number(size)?,
+                },
+                ("function", &[ref name, ref formals, ref pointer, ref escaping_vars, ref vars, ref temps, ref body]) => {
+                    let formals = arguments(formals, "formals")?.iter()
+                        .map(|formal| self.access(formal))
+                        .collect::<ParseResult<_>>()?;
+                    let pointer =
+                        match *arguments(pointer, "pointer")? {
+                            [ref offset] => number(offset)?,
+                            _ => return Err(error("(pointer OFFSET)", pointer)),
+                        };
+                    let frame = X86_64::from_layout(self.label(name)?, formals, pointer);
+                    let escaping_vars = arguments(escaping_vars, "escaping")?.iter()
+                        .map(number)
+                        .collect::<ParseResult<_>>()?;
+                    let mut temp_map = TempMap::new();
+                    for var in arguments(vars, "vars")? {
+                        temp_map.insert_var(number(var)?);
+                    }
+                    for temp in arguments(temps, "temps")? {
+                        temp_map.insert_temp(self.temp(temp)?);
+                    }
+                    Fragment::Function {
+                        body: self.statement(body)?,
+                        escaping_vars,
+                        frame: Rc::new(RefCell::new(frame)),
+                        temp_map,
+                    }
+                },
+                ("pure", functions) => Fragment::PureFunctions(functions.iter()
+                    .map(|function| self.label(function))
+                    .collect::<ParseResult<_>>()?),
+                ("static-fields", &[ref name, Sexp::Str(ref data_layout)]) => Fragment::StaticFields {
+                    data_layout: data_layout.clone(),
+                    name: DataLabel::from_label(self.label(name)?),
+                },
+                ("string", &[ref label, Sexp::Str(ref string)]) =>
+                    Fragment::Str(DataLabel::from_label(self.label(label)?), string.clone()),
+                ("string-table", &[ref name, ref strings]) => Fragment::StringTable {
+                    name: DataLabel::from_label(self.label(name)?),
+                    strings: list(strings)?.iter()
+                        .map(|string| self.label(string).map(DataLabel::from_label))
+                        .collect::<ParseResult<_>>()?,
+                },
+                ("template", &[ref name, ref values]) => Fragment::Template {
+                    name: DataLabel::from_label(self.label(name)?),
+                    values: list(values)?.iter()
+                        .map(number)
+                        .collect::<ParseResult<_>>()?,
+                },
+                ("vtable", &[ref class, ref methods, ref finalizer @ ..]) if finalizer.len() <= 1 => Fragment::VTable {
+                    class: DataLabel::from_label(self.label(class)?),
+                    finalizer:
+                        match *finalizer {
+                            [ref finalizer] =>
+                                match *arguments(finalizer, "finalizer")? {
+                                    [ref method, ref link_field] => Some(Finalizer {
+                                        link_field: number(link_field)?,
+                                        method: number(method)?,
+                                    }),
+                                    _ => return Err(error("(finalizer METHOD LINK-FIELD)", finalizer)),
+                                },
+                            _ => None,
+                        },
+                    methods: list(methods)?.iter()
+                        .map(|method| self.label(method))
+                        .collect::<ParseResult<_>>()?,
+                },
+                _ => return Err(error("a fragment", sexp)),
+            };
+        Ok(fragment)
+    }
+
+    fn access(&mut self, sexp: &Sexp) -> ParseResult<Access> {
+        match form(sexp)? {
+            ("reg", &[ref temp]) => Ok(Access::InReg(self.temp(temp)?)),
+            ("stack", &[ref offset]) => Ok(Access::InFrame(number(offset)?)),
+            _ => Err(error("an access", sexp)),
+        }
+    }
+
+    fn statement(&mut self, sexp: &Sexp) -> ParseResult<Statement> {
+        let statement =
+            match form(sexp)? {
+                ("cjump", &[ref op, ref left, ref right, ref true_label, ref false_label]) => _Statement::CondJump {
+                    op: relational_op(op)?,
+                    left: self.exp(left)?,
+                    right: self.exp(right)?,
+                    true_label: self.label(true_label)?,
+                    false_label: self.label(false_label)?,
+                },
+                ("exp", &[ref expr]) => _Statement::Exp(self.exp(expr)?),
+                ("jump", &[ref expr, ref labels @ ..]) => _Statement::Jump(self.exp(expr)?, labels.iter()
+                    .map(|label| self.label(label))
+                    .collect::<ParseResult<_>>()?),
+                ("label", &[ref label]) => _Statement::Label(self.label(label)?),
+                ("move", &[ref destination, ref source]) => _Statement::Move(self.exp(destination)?, self.exp(source)?),
+                ("position", &[ref line]) => _Statement::Position(number(line)?),
+                ("seq", &[ref first, ref rest @ ..]) if !rest.is_empty() => {
+                    let mut statements = vec![self.statement(first)?];
+                    for statement in rest {
+                        statements.push(self.statement(statement)?);
+                    }
+                    let mut statement = statements.pop().expect("last statement");
+                    while let Some(previous) = statements.pop() {
+                        statement = _Statement::Sequence(Box::new(previous), Box::new(statement)).into();
+                    }
+                    return Ok(statement);
+                },
+                ("var", &[ref stack_var, ref statement]) => {
+                    let mut statement = self.statement(statement)?;
+                    statement.stack_var = Some(number(stack_var)?);
+                    return Ok(statement);
+                },
+                _ => return Err(error("a statement", sexp)),
+            };
+        Ok(statement.into())
+    }
+
+    fn exp(&mut self, sexp: &Sexp) -> ParseResult<Exp> {
+        let expr =
+            match form(sexp)? {
+                ("binop", &[ref op, ref left, ref right]) => Exp::BinOp {
+                    op: bin_op(op)?,
+                    left: Box::new(self.exp(left)?),
+                    right: Box::new(self.exp(right)?),
+                },
+                ("call", &[ref return_label, ref collectable_return_type, ref function_expr, ref arguments @ ..]) => Exp::Call {
+                    arguments: arguments.iter()
+                        .map(|argument| self.exp(argument))
+                        .collect::<ParseResult<_>>()?,
+                    collectable_return_type: atom(collectable_return_type)?.parse()
+                        .map_err(|_| error("`true` or `false`", collectable_return_type))?,
+                    function_expr: Box::new(self.exp(function_expr)?),
+                    return_label: self.label(return_label)?,
+                },
+                ("compare", &[ref op, ref left, ref right]) => Exp::Compare {
+                    op: relational_op(op)?,
+                    left: Box::new(self.exp(left)?),
+                    right: Box::new(self.exp(right)?),
+                },
+                ("const", &[ref value]) => Exp::Const(number(value)?),
+                ("error", &[]) => Exp::Error,
+                ("eseq", &[ref statement, ref expr]) =>
+                    Exp::ExpSequence(Box::new(self.statement(statement)?), Box::new(self.exp(expr)?)),
+                ("mem", &[ref expr]) => Exp::Mem(Box::new(self.exp(expr)?)),
+                ("memb", &[ref expr]) => Exp::MemByte(Box::new(self.exp(expr)?)),
+                ("name", &[ref label]) => Exp::Name(self.label(label)?),
+                ("select", &[ref op, ref left, ref right, ref if_true, ref if_false]) => Exp::Select {
+                    op: relational_op(op)?,
+                    left: Box::new(self.exp(left)?),
+                    right: Box::new(self.exp(right)?),
+                    if_true: Box::new(self.exp(if_true)?),
+                    if_false: Box::new(self.exp(if_false)?),
+                },
+                ("temp", &[ref temp]) => Exp::Temp(self.temp(temp)?),
+                _ => return Err(error("an expression", sexp)),
+            };
+        Ok(expr)
+    }
+
+    fn label(&mut self, sexp: &Sexp) -> ParseResult<Label> {
+        let name = atom(sexp)?;
+        match numbered(name, 'l') {
+            Some(num) => Ok(self.labels.entry(num).or_insert_with(Label::new).clone()),
+            None => Ok(Label::with_name(name)),
+        }
+    }
+
+    fn temp(&mut self, sexp: &Sexp) -> ParseResult<Temp> {
+        let name = atom(sexp)?;
+        if let Some(&register) = self.registers.get(name) {
+            return Ok(register);
+        }
+        let num = numbered(name, 't').ok_or_else(|| error("a temp", sexp))?;
+        Ok(*self.temps.entry(num).or_insert_with(Temp::new))
+    }
+}
+
+/// Number of a name made of the prefix followed by a number.
+fn numbered(name: &str, prefix: char) -> Option<u32> {
+    if name.starts_with(prefix) {
+        name[1..].parse().ok()
+    }
+    else {
+        None
+    }
+}
+
+fn atom(sexp: &Sexp) -> ParseResult<&str> {
+    match *sexp {
+        Sexp::Atom(ref atom) => Ok(atom),
+        _ => Err(error("an atom", sexp)),
+    }
+}
+
+fn number<T: ::std::str::FromStr>(sexp: &Sexp) -> ParseResult<T> {
+    atom(sexp)?.parse()
+        .map_err(|_| error("a number", sexp))
+}
+
+fn list(sexp: &Sexp) -> ParseResult<&[Sexp]> {
+    match *sexp {
+        Sexp::List(ref items) => Ok(items),
+        _ => Err(error("a list", sexp)),
+    }
+}
+
+/// Keyword and arguments of a list starting with a keyword.
+fn form(sexp: &Sexp) -> ParseResult<(&str, &[Sexp])> {
+    match *list(sexp)? {
+        [ref keyword, ref arguments @ ..] => Ok((atom(keyword)?, arguments)),
+        [] => Err(error("a keyword", sexp)),
+    }
+}
+
+/// Arguments of a list starting with the specified keyword.
+fn arguments<'a>(sexp: &'a Sexp, keyword: &str) -> ParseResult<&'a [Sexp]> {
+    match form(sexp)? {
+        (actual_keyword, arguments) if actual_keyword == keyword => Ok(arguments),
+        _ => Err(error(&format!("({} ...)", keyword), sexp)),
+    }
+}
+
+fn bin_op(sexp: &Sexp) -> ParseResult<BinOp> {
+    let name = atom(sexp)?;
+    BIN_OPS.iter()
+        .find(|&&(_, op_name)| op_name == name)
+        .map(|entry| entry.0.clone())
+        .ok_or_else(|| error("a binary operator", sexp))
+}
+
+fn relational_op(sexp: &Sexp) -> ParseResult<RelationalOp> {
+    let name = atom(sexp)?;
+    RELATIONAL_OPS.iter()
+        .find(|&&(_, op_name)| op_name == name)
+        .map(|entry| entry.0.clone())
+        .ok_or_else(|| error("a relational operator", sexp))
+}
+
+#[cfg(test)]
+mod tests {
+    use frame::{Finalizer, Fragment};
+    use temp::{DataLabel, Label};
+    use super::{read, write};
+
+    #[test]
+    fn data_fragments() {
+        let fragments = vec![
+            Fragment::Str(DataLabel::with_name("greeting"), "\"Hi\"\n\tthere\\\u{1}".to_string()),
+            Fragment::VTable {
+                class: DataLabel::with_name("Point_vtable"),
+                finalizer: Some(Finalizer {
+                    link_field: 2,
+                    method: 1,
+                }),
+                methods: vec![Label::with_name("Point_move"), Label::with_name("Point_show")],
+            },
+            Fragment::Display {
+                name: DataLabel::with_name("__tiger_display"),
+                size: 3,
+            },
+            Fragment::StringTable {
+                name: DataLabel::with_name("commands"),
+                strings: vec![DataLabel::with_name("add"), DataLabel::with_name("quit")],
+            },
+            Fragment::Constant(DataLabel::with_name("big"), -10_000_000_000),
+            Fragment::Template {
+                name: DataLabel::with_name("origin"),
+                values: vec![0, -1, 7],
+            },
+        ];
+        let mut buffer = vec![];
+        write(&mut buffer, &fragments).expect("write");
+        let text = String::from_utf8(buffer).expect("utf-8");
+        assert_eq!(text, "(string greeting \"\\\"Hi\\\"\\n\\tthere\\\\\\001\")\n\
+            (vtable Point_vtable (Point_move Point_show) (finalizer 1 2))\n\
+            (display __tiger_display 3)\n\
+            (string-table commands (add quit))\n\
+            (constant big -10000000000)\n\
+            (template origin (0 -1 7))\n");
+
+        let fragments = read(&text).expect("read");
+        match fragments[0] {
+            Fragment::Str(ref label, ref string) => {
+                assert_eq!(label.to_string(), "greeting");
+                assert_eq!(string, "\"Hi\"\n\tthere\\\u{1}");
+            },
+            _ => panic!("Expected string"),
+        }
+        match fragments[1] {
+            Fragment::VTable { finalizer, ref methods, .. } => {
+                assert_eq!(finalizer, Some(Finalizer { link_field: 2, method: 1 }));
+                assert_eq!(methods, &[Label::with_name("Point_move"), Label::with_name("Point_show")]);
+            },
+            _ => panic!("Expected vtable"),
+        }
+        match fragments[2] {
+            Fragment::Display { size, .. } => assert_eq!(size, 3),
+            _ => panic!("Expected display"),
+        }
+        match fragments[3] {
+            Fragment::StringTable { ref strings, .. } =>
+                assert_eq!(strings, &[DataLabel::with_name("add"), DataLabel::with_name("quit")]),
+            _ => panic!("Expected string table"),
+        }
+        match fragments[4] {
+            Fragment::Constant(ref label, value) => {
+                assert_eq!(label.to_string(), "big");
+                assert_eq!(value, -10_000_000_000);
+            },
+            _ => panic!("Expected constant"),
+        }
+        match fragments[5] {
+            Fragment::Template { ref values, .. } => assert_eq!(values, &[0, -1, 7]),
+            _ => panic!("Expected template"),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert!(read("(string greeting)").is_err());
+        assert!(read("(display l1 3").is_err());
+        assert!(read("(vtable Point_vtable (Point_move)) ; comment\n(unknown)").is_err());
+    }
+}
@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! A 32-bit frame used to test that the code generation does not assume the word size
+//! of x86-64.
+
+use std::collections::HashMap;
+use std::sync::Once;
+
+use asm::{Instruction, Subroutine};
+use ir::BinOp::Plus;
+use ir::Exp::{self, BinOp, Call, Const, Mem, Name};
+use ir::Statement;
+use super::{Frame, Memory, Register};
+use temp::{Label, Temp};
+
+use self::Access::{InFrame, InReg};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mock32 {
+    formals: Vec<Access>,
+    name: Label,
+    pointer: i64,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Access {
+    InFrame(i64),
+    InReg(Temp),
+}
+
+impl Memory for Access {
+    fn as_stack(&self) -> Option<i64> {
+        match *self {
+            InFrame(stack_location) => Some(stack_location),
+            InReg(_) => None,
+        }
+    }
+
+    fn as_temp(&self) -> Option<&Temp> {
+        match *self {
+            InFrame(_) => None,
+            InReg(ref temp) => Some(temp),
+        }
+    }
+}
+
+static mut FP: Option<Register> = None;
+static mut RV: Option<Register> = None;
+static mut SP: Option<Register> = None;
+static ONCE: Once = Once::new();
+
+fn initialize() {
+    unsafe {
+        FP = Some(Register::new(Temp::new()));
+        RV = Some(Register::new(Temp::new()));
+        SP = Some(Register::new(Temp::new()));
+    }
+}
+
+impl Mock32 {
+    fn sp() -> Register {
+        ONCE.call_once(initialize);
+        unsafe { SP.expect("temp") }
+    }
+}
+
+impl Frame for Mock32 {
+    type Access = Access;
+
+    const WORD_SIZE: i64 = 4;
+
+    fn registers() -> Vec<Register> {
+        vec![Self::fp(), Self::return_value(), Self::sp()]
+    }
+
+    fn register_count() -> usize {
+        Self::registers().len() - [Self::fp(), Self::sp()].len()
+    }
+
+    fn temp_map() -> HashMap<Register, &'static str> {
+        let mut map = HashMap::new();
+        map.insert(Self::fp(), "fp");
+        map.insert(Self::return_value(), "rv");
+        map.insert(Self::sp(), "sp");
+        map
+    }
+
+    fn special_name(temp: Temp) -> Option<&'static str> {
+        Self::temp_map().get(&temp).copied()
+    }
+
+    fn fp() -> Register {
+        ONCE.call_once(initialize);
+        unsafe { FP.expect("temp") }
+    }
+
+    fn return_value() -> Register {
+        ONCE.call_once(initialize);
+        unsafe { RV.expect("temp") }
+    }
+
+    fn new(name: Label, formals: Vec<bool>) -> Self {
+        let mut frame = Mock32 {
+            formals: vec![],
+            name,
+            pointer: 0,
+        };
+        let formals = formals.iter()
+            .map(|&escape| frame.alloc_local(escape))
+            .collect();
+        frame.formals = formals;
+        frame
+    }
+
+    fn name(&self) -> Label {
+        self.name.clone()
+    }
+
+    fn formals(&self) -> &[Self::Access] {
+        &self.formals
+    }
+
+    fn alloc_local(&mut self, escape: bool) -> Self::Access {
+        if escape {
+            self.pointer -= Self::WORD_SIZE;
+            InFrame(self.pointer)
+        }
+        else {
+            InReg(Temp::new())
+        }
+    }
+
+    fn frame_size(&self) -> i64 {
+        let stack_size = -self.pointer;
+        // Align the stack of 8 bytes.
+        (stack_size + 0x7) & !0x7
+    }
+
+    fn exp(&self, access: Self::Access, stack_frame: Exp) -> Exp {
+        match access {
+            InFrame(pos) => {
+                Mem(Box::new(BinOp {
+                    op: Plus,
+                    left: Box::new(stack_frame),
+                    right: Box::new(Const(pos)),
+                }))
+            },
+            InReg(reg) => {
+                Exp::Temp(reg)
+            },
+        }
+    }
+
+    fn external_call(name: &str, arguments: Vec<Exp>, collectable_return_type: bool) -> Exp {
+        Call {
+            collectable_return_type,
+            function_expr: Box::new(Name(Label::with_name(name))),
+            arguments,
+            return_label: Label::new(),
+        }
+    }
+
+    fn proc_entry_exit1(&mut self, statement: Statement) -> Statement {
+        statement
+    }
+
+    fn saved_registers(&self) -> &[(Register, Temp)] {
+        &[]
+    }
+
+    fn proc_entry_exit2(&self, instructions: Vec<Instruction>, _escaping_vars: Vec<i64>) -> Vec<Instruction> {
+        instructions
+    }
+
+    fn proc_entry_exit3(&self, body: Vec<Instruction>) -> Subroutine {
+        Subroutine {
+            prolog: format!("{}:", self.name()),
+            body,
+            epilog: "ret".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame::{Frame, Memory};
+    use gen::{array_subscript, byte_array_subscript, field_access, num};
+    use ir::BinOp::{Mul, Plus};
+    use ir::Exp::{BinOp, Const, Mem, MemByte, Name};
+    use semant::FieldType;
+    use temp::Label;
+    use super::Mock32;
+
+    #[test]
+    fn word_size() {
+        assert_eq!(Mock32::word_directive(), "dd");
+        assert_eq!(Mock32::wrap_constant(0x1_0000_0001), 1);
+        assert_eq!(Mock32::wrap_constant(0xFFFF_FFFF), -1);
+        assert_eq!(num::<Mock32>(i32::MAX as i64 + 1), Const(i32::MIN as i64));
+    }
+
+    #[test]
+    fn locals() {
+        let mut frame = Mock32::new(Label::with_name("function"), vec![true, true]);
+        assert_eq!(frame.formals()[0].as_stack(), Some(-4));
+        assert_eq!(frame.formals()[1].as_stack(), Some(-8));
+        assert_eq!(frame.alloc_local(true).as_stack(), Some(-12));
+        assert_eq!(frame.frame_size(), 16);
+    }
+
+    #[test]
+    fn offsets() {
+        let var = Name(Label::with_name("var"));
+        assert_eq!(field_access::<Mock32>(var.clone(), 1, FieldType::Record), Mem(Box::new(BinOp {
+            op: Plus,
+            left: Box::new(var.clone()),
+            right: Box::new(Const(12)),
+        })));
+        assert_eq!(array_subscript::<Mock32>(var.clone(), Const(2)), Mem(Box::new(BinOp {
+            op: Plus,
+            left: Box::new(var.clone()),
+            right: Box::new(BinOp {
+                op: Mul,
+                left: Box::new(BinOp {
+                    op: Plus,
+                    left: Box::new(Const(2)),
+                    right: Box::new(Const(3)),
+                }),
+                right: Box::new(Const(4)),
+            }),
+        })));
+        assert_eq!(byte_array_subscript::<Mock32>(var.clone(), Const(2)), MemByte(Box::new(BinOp {
+            op: Plus,
+            left: Box::new(var),
+            right: Box::new(BinOp {
+                op: Plus,
+                left: Box::new(Const(2)),
+                right: Box::new(Const(12)),
+            }),
+        })));
+    }
+}
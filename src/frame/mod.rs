@@ -19,6 +19,7 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -27,8 +28,10 @@ use std::rc::Rc;
 
 use asm::{Instruction, Subroutine};
 use ir::{Exp, Statement};
-use temp::{Label, Temp, TempMap};
+use temp::{DataLabel, Label, Temp, TempMap};
 
+#[cfg(test)]
+pub mod mock;
 pub mod x86_64;
 
 pub enum Fragment<F: Frame> {
@@ -39,30 +42,117 @@ pub enum Fragment<F: Frame> {
         frame: Rc<RefCell<F>>,
         temp_map: TempMap,
     },
-    Str(Label, String),
+    /// Word-sized constant too large to be an immediate operand, loaded from memory instead.
+    Constant(DataLabel, i64),
+    /// Array of the frame pointers used by the display, indexed by nesting depth.
+    Display {
+        name: DataLabel,
+        size: usize,
+    },
+    /// Static fields of a class, one word each, with `data_layout` telling which ones are pointers.
+    StaticFields {
+        data_layout: String,
+        name: DataLabel,
+    },
+    /// Functions without side effects, whose calls with the same arguments can be reused.
+    PureFunctions(Vec<Label>),
+    Str(DataLabel, String),
+    /// Array of pointers to string literals.
+    StringTable {
+        name: DataLabel,
+        strings: Vec<DataLabel>,
+    },
+    /// Read-only words copied into a new record or array whose initial values are all constants.
+    /// It is not a heap object: the collector only sees the copies.
+    Template {
+        name: DataLabel,
+        values: Vec<i64>,
+    },
     VTable {
-        class: Label,
+        class: DataLabel,
+        finalizer: Option<Finalizer>,
         methods: Vec<Label>,
     },
 }
 
+/// Finalizer of a class, which the collector calls before reclaiming an instance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Finalizer {
+    // Index of the field holding the static link of the finalizer.
+    pub link_field: usize,
+    // Index of the finalizer in the vtable.
+    pub method: usize,
+}
+
 pub trait Memory {
     fn as_stack(&self) -> Option<i64>;
     fn as_temp(&self) -> Option<&Temp>;
 }
 
+/// A temporary precolored with a machine register.
+/// Only the frames create them, so that a virtual temporary cannot be used where a register is expected.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Register(Temp);
+
+impl Register {
+    fn new(temp: Temp) -> Self {
+        Register(temp)
+    }
+
+    #[cfg(test)]
+    pub fn from_num(num: u32) -> Self {
+        Register(Temp::from_num(num))
+    }
+
+    pub fn temp(&self) -> Temp {
+        self.0
+    }
+}
+
+impl Borrow<Temp> for Register {
+    fn borrow(&self) -> &Temp {
+        &self.0
+    }
+}
+
+impl From<Register> for Temp {
+    fn from(register: Register) -> Self {
+        register.0
+    }
+}
+
 pub trait Frame: Clone {
     type Access: Clone + Debug + Eq + Hash + Memory;
 
     const WORD_SIZE: i64;
 
-    fn registers() -> Vec<Temp>;
+    /// Assembler directive used to emit one word of data.
+    fn word_directive() -> &'static str {
+        match Self::WORD_SIZE {
+            4 => "dd",
+            8 => "dq",
+            size => panic!("Unsupported word size {}", size),
+        }
+    }
+
+    /// Wrap an integer constant to the word size, as the target arithmetic would.
+    fn wrap_constant(value: i64) -> i64 {
+        let shift = 64 - Self::WORD_SIZE * 8;
+        (value << shift) >> shift
+    }
+
+    /// Whether the constant can be encoded as an immediate operand of most instructions.
+    fn fits_immediate(value: i64) -> bool {
+        i64::from(value as i32) == value
+    }
+
+    fn registers() -> Vec<Register>;
     fn register_count() -> usize;
-    fn temp_map() -> HashMap<Temp, &'static str>;
+    fn temp_map() -> HashMap<Register, &'static str>;
     fn special_name(temp: Temp) -> Option<&'static str>;
 
-    fn fp() -> Temp;
-    fn return_value() -> Temp;
+    fn fp() -> Register;
+    fn return_value() -> Register;
 
     fn new(name: Label, formals: Vec<bool>) -> Self;
 
@@ -72,11 +162,16 @@ pub trait Frame: Clone {
 
     fn alloc_local(&mut self, escape: bool) -> Self::Access;
 
+    /// Number of bytes reserved on the stack for the locals and spills of this frame.
+    fn frame_size(&self) -> i64;
+
     fn exp(&self, access: Self::Access, stack_frame: Exp) -> Exp;
 
     fn external_call(name: &str, arguments: Vec<Exp>, collectable_return_type: bool) -> Exp;
 
     fn proc_entry_exit1(&mut self, statement: Statement) -> Statement;
+    /// Callee-saved registers, each with the temporary in which `proc_entry_exit1` saved it.
+    fn saved_registers(&self) -> &[(Register, Temp)];
     fn proc_entry_exit2(&self, instructions: Vec<Instruction>, escaping_vars: Vec<i64>) -> Vec<Instruction>;
     fn proc_entry_exit3(&self, body: Vec<Instruction>) -> Subroutine;
 }
@@ -22,7 +22,7 @@
 use std::collections::HashMap;
 use std::sync::Once;
 
-use asm::{Instruction, Subroutine};
+use asm::{Instruction, Operand, Subroutine};
 use ir::BinOp::Plus;
 use ir::Exp:: {
     self,
@@ -33,18 +33,19 @@ use ir::Exp:: {
     Name,
 };
 use ir::{Statement, _Statement};
-use super::{Frame, Memory};
+use super::{Frame, Memory, Register};
 use temp::{Label, Temp};
 
 use self::Access::{InFrame, InReg};
 
-const POINTER_SIZE: i64 = 8;
+const PAGE_SIZE: i64 = 4096;
 
 #[derive(Clone, Debug)]
 pub struct X86_64 {
     formals: Vec<Access>, // Representation of parameters.
     name: Label,
     pointer: i64,
+    saved_registers: Vec<(Register, Temp)>,
 }
 
 impl PartialEq for X86_64 {
@@ -75,145 +76,178 @@ impl Memory for Access {
     }
 }
 
-static mut RBP: Option<Temp> = None;
-static mut RSP: Option<Temp> = None;
-static mut RAX: Option<Temp> = None;
-static mut RBX: Option<Temp> = None;
-static mut RCX: Option<Temp> = None;
-static mut RDX: Option<Temp> = None;
-static mut RSI: Option<Temp> = None;
-static mut RDI: Option<Temp> = None;
-static mut R8: Option<Temp> = None;
-static mut R9: Option<Temp> = None;
-static mut R10: Option<Temp> = None;
-static mut R11: Option<Temp> = None;
-static mut R12: Option<Temp> = None;
-static mut R13: Option<Temp> = None;
-static mut R14: Option<Temp> = None;
-static mut R15: Option<Temp> = None;
+static mut RBP: Option<Register> = None;
+static mut RSP: Option<Register> = None;
+static mut RAX: Option<Register> = None;
+static mut RBX: Option<Register> = None;
+static mut RCX: Option<Register> = None;
+static mut RDX: Option<Register> = None;
+static mut RSI: Option<Register> = None;
+static mut RDI: Option<Register> = None;
+static mut R8: Option<Register> = None;
+static mut R9: Option<Register> = None;
+static mut R10: Option<Register> = None;
+static mut R11: Option<Register> = None;
+static mut R12: Option<Register> = None;
+static mut R13: Option<Register> = None;
+static mut R14: Option<Register> = None;
+static mut R15: Option<Register> = None;
 static ONCE: Once = Once::new();
 
 fn initialize() {
     unsafe {
-        RBP = Some(Temp::new());
-        RSP = Some(Temp::new());
-        RAX = Some(Temp::new());
-        RBX = Some(Temp::new());
-        RCX = Some(Temp::new());
-        RDX = Some(Temp::new());
-        RDI = Some(Temp::new());
-        RSI = Some(Temp::new());
-        R8 = Some(Temp::new());
-        R9 = Some(Temp::new());
-        R10 = Some(Temp::new());
-        R11 = Some(Temp::new());
-        R12 = Some(Temp::new());
-        R13 = Some(Temp::new());
-        R14 = Some(Temp::new());
-        R15 = Some(Temp::new());
+        RBP = Some(Register::new(Temp::new()));
+        RSP = Some(Register::new(Temp::new()));
+        RAX = Some(Register::new(Temp::new()));
+        RBX = Some(Register::new(Temp::new()));
+        RCX = Some(Register::new(Temp::new()));
+        RDX = Some(Register::new(Temp::new()));
+        RDI = Some(Register::new(Temp::new()));
+        RSI = Some(Register::new(Temp::new()));
+        R8 = Some(Register::new(Temp::new()));
+        R9 = Some(Register::new(Temp::new()));
+        R10 = Some(Register::new(Temp::new()));
+        R11 = Some(Register::new(Temp::new()));
+        R12 = Some(Register::new(Temp::new()));
+        R13 = Some(Register::new(Temp::new()));
+        R14 = Some(Register::new(Temp::new()));
+        R15 = Some(Register::new(Temp::new()));
     }
 }
 
 impl X86_64 {
-    pub fn arg_registers() -> Vec<Temp> {
+    /// Recreate a frame from its layout, as written in a fragment file.
+    pub fn from_layout(name: Label, formals: Vec<Access>, pointer: i64) -> Self {
+        X86_64 {
+            formals,
+            name,
+            pointer,
+            saved_registers: vec![],
+        }
+    }
+
+    /// Offset of the last local allocated in the frame.
+    pub fn pointer(&self) -> i64 {
+        self.pointer
+    }
+
+    /// Number of the register in the DWARF numbering of x86-64, used in the unwinding tables.
+    pub fn dwarf_number(register: Register) -> Option<usize> {
+        const DWARF_REGISTERS: [&str; 16] = ["rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10",
+            "r11", "r12", "r13", "r14", "r15"];
+        let name = Self::special_name(register.temp())?;
+        DWARF_REGISTERS.iter().position(|&register_name| register_name == name)
+    }
+
+    pub fn arg_registers() -> Vec<Register> {
         vec![Self::rdi(), Self::rsi(), Self::rdx(), Self::rcx(), Self::r8(), Self::r9()]
     }
 
-    fn callee_saved_registers() -> Vec<Temp> {
+    fn callee_saved_registers() -> Vec<Register> {
         vec![Self::rbx(), Self::r12(), Self::r13(), Self::r14(), Self::r15()]
     }
 
-    fn special_registers() -> Vec<Temp> {
+    fn special_registers() -> Vec<Register> {
         vec![Self::rax(), Self::rbp(), Self::rsp()]
     }
 
-    fn caller_saved_registers() -> Vec<Temp> {
+    fn caller_saved_registers() -> Vec<Register> {
         vec![Self::r10(), Self::r11()]
     }
 
-    pub fn calldefs() -> Vec<Temp> {
+    /// Registers holding the system call number followed by its arguments.
+    pub fn syscall_registers() -> Vec<Register> {
+        vec![Self::rax(), Self::rdi(), Self::rsi(), Self::rdx(), Self::r10(), Self::r8(), Self::r9()]
+    }
+
+    /// Registers overwritten by the `syscall` instruction.
+    pub fn syscall_defs() -> Vec<Register> {
+        vec![Self::rax(), Self::rcx(), Self::r11()]
+    }
+
+    pub fn calldefs() -> Vec<Register> {
         let mut registers = Self::caller_saved_registers();
         registers.extend(Self::arg_registers());
-        registers.push(Self::return_value());
+        registers.push(Self::rax());
         registers
     }
 
-    pub fn rsp() -> Temp {
+    pub fn rsp() -> Register {
         ONCE.call_once(initialize);
         unsafe { RSP.expect("temp") }
     }
 
-    fn rbp() -> Temp {
+    fn rbp() -> Register {
         ONCE.call_once(initialize);
         unsafe { RBP.expect("temp") }
     }
 
-    fn rdi() -> Temp {
+    fn rdi() -> Register {
         ONCE.call_once(initialize);
         unsafe { RDI.expect("temp") }
     }
 
-    fn rsi() -> Temp {
+    fn rsi() -> Register {
         ONCE.call_once(initialize);
         unsafe { RSI.expect("temp") }
     }
 
-    pub fn rax() -> Temp {
+    pub fn rax() -> Register {
         ONCE.call_once(initialize);
         unsafe { RAX.expect("temp") }
     }
 
-    fn rbx() -> Temp {
+    fn rbx() -> Register {
         ONCE.call_once(initialize);
         unsafe { RBX.expect("temp") }
     }
 
-    fn rcx() -> Temp {
+    fn rcx() -> Register {
         ONCE.call_once(initialize);
         unsafe { RCX.expect("temp") }
     }
 
-    pub fn rdx() -> Temp {
+    pub fn rdx() -> Register {
         ONCE.call_once(initialize);
         unsafe { RDX.expect("temp") }
     }
 
-    fn r8() -> Temp {
+    fn r8() -> Register {
         ONCE.call_once(initialize);
         unsafe { R8.expect("temp") }
     }
 
-    fn r9() -> Temp {
+    fn r9() -> Register {
         ONCE.call_once(initialize);
         unsafe { R9.expect("temp") }
     }
 
-    fn r10() -> Temp {
+    fn r10() -> Register {
         ONCE.call_once(initialize);
         unsafe { R10.expect("temp") }
     }
 
-    fn r11() -> Temp {
+    fn r11() -> Register {
         ONCE.call_once(initialize);
         unsafe { R11.expect("temp") }
     }
 
-    fn r12() -> Temp {
+    fn r12() -> Register {
         ONCE.call_once(initialize);
         unsafe { R12.expect("temp") }
     }
 
-    fn r13() -> Temp {
+    fn r13() -> Register {
         ONCE.call_once(initialize);
         unsafe { R13.expect("temp") }
     }
 
-    fn r14() -> Temp {
+    fn r14() -> Register {
         ONCE.call_once(initialize);
         unsafe { R14.expect("temp") }
     }
 
-    fn r15() -> Temp {
+    fn r15() -> Register {
         ONCE.call_once(initialize);
         unsafe { R15.expect("temp") }
     }
@@ -224,7 +258,7 @@ impl Frame for X86_64 {
 
     const WORD_SIZE: i64 = 8;
 
-    fn registers() -> Vec<Temp> {
+    fn registers() -> Vec<Register> {
         let mut registers = Self::arg_registers();
         registers.extend(Self::callee_saved_registers());
         registers.extend(Self::special_registers());
@@ -236,11 +270,11 @@ impl Frame for X86_64 {
         Self::registers().len() - [Self::rsp(), Self::rbp()].len()
     }
 
-    fn temp_map() -> HashMap<Temp, &'static str> {
+    fn temp_map() -> HashMap<Register, &'static str> {
         let mut map = HashMap::new();
         map.insert(Self::rbp(), "rbp");
         map.insert(Self::rsp(), "rsp");
-        map.insert(Self::return_value(), "rax");
+        map.insert(Self::rax(), "rax");
         map.insert(Self::rbx(), "rbx");
         map.insert(Self::rdi(), "rdi");
         map.insert(Self::rsi(), "rsi");
@@ -261,11 +295,11 @@ impl Frame for X86_64 {
         Self::temp_map().get(&temp).copied()
     }
 
-    fn fp() -> Temp {
+    fn fp() -> Register {
         Self::rbp()
     }
 
-    fn return_value() -> Temp {
+    fn return_value() -> Register {
         Self::rax()
     }
 
@@ -274,6 +308,7 @@ impl Frame for X86_64 {
             formals: vec![],
             name,
             pointer: 0,
+            saved_registers: vec![],
         };
         let formals = formals.iter()
             .map(|&escape| frame.alloc_local(escape))
@@ -292,7 +327,7 @@ impl Frame for X86_64 {
 
     fn alloc_local(&mut self, escape: bool) -> Self::Access {
         if escape {
-            self.pointer -= POINTER_SIZE;
+            self.pointer -= Self::WORD_SIZE;
             InFrame(self.pointer)
         }
         else {
@@ -300,6 +335,12 @@ impl Frame for X86_64 {
         }
     }
 
+    fn frame_size(&self) -> i64 {
+        let stack_size = -self.pointer;
+        // Align the stack of 16 bytes.
+        (stack_size + 0xF) & !0xF
+    }
+
     fn exp(&self, access: Self::Access, stack_frame: Exp) -> Exp {
         match access {
             InFrame(pos) => {
@@ -333,20 +374,21 @@ impl Frame for X86_64 {
             let local = Temp::new();
             let memory = Exp::Temp(local);
             saved_register_locations.push(memory.clone());
-            start_statements.push(_Statement::Move(memory, Exp::Temp(register)).into());
+            self.saved_registers.push((register, local));
+            start_statements.push(_Statement::Move(memory, Exp::Temp(register.temp())).into());
         }
 
         let arg_registers = Self::arg_registers();
         let arg_registers_len = arg_registers.len();
         for (formal, arg_register) in self.formals.iter().zip(arg_registers) {
-            let destination = self.exp(formal.clone(), Exp::Temp(Self::fp()));
-            start_statements.push(_Statement::Move(destination, Exp::Temp(arg_register)).into());
+            let destination = self.exp(formal.clone(), Exp::Temp(Self::fp().temp()));
+            start_statements.push(_Statement::Move(destination, Exp::Temp(arg_register.temp())).into());
         }
         for (index, formal) in self.formals.iter().skip(arg_registers_len).enumerate() {
-            let destination = self.exp(formal.clone(), Exp::Temp(Self::fp()));
+            let destination = self.exp(formal.clone(), Exp::Temp(Self::fp().temp()));
             start_statements.push(_Statement::Move(destination, Exp::Mem(Box::new(
                 Exp::BinOp {
-                    left: Box::new(Exp::Temp(Self::fp())),
+                    left: Box::new(Exp::Temp(Self::fp().temp())),
                     op: Plus,
                     right: Box::new(Exp::Const(Self::WORD_SIZE * (index + 2) as i64)), // TODO: explain why + 2. Maybe because + 1 is the return address?
                 }
@@ -354,7 +396,7 @@ impl Frame for X86_64 {
         }
 
         for (register, location) in Self::callee_saved_registers().into_iter().zip(saved_register_locations) {
-            end_statements.push(_Statement::Move(Exp::Temp(register), location).into());
+            end_statements.push(_Statement::Move(Exp::Temp(register.temp()), location).into());
         }
 
         let mut end_statement = _Statement::Exp(Exp::Const(0)).into();
@@ -369,13 +411,17 @@ impl Frame for X86_64 {
         _Statement::Sequence(Box::new(statement), Box::new(end_statement)).into()
     }
 
+    fn saved_registers(&self) -> &[(Register, Temp)] {
+        &self.saved_registers
+    }
+
     fn proc_entry_exit2(&self, mut instructions: Vec<Instruction>, escaping_vars: Vec<i64>) -> Vec<Instruction> {
         // TODO: explain why we push a new empty instruction with this source.
         let mut source = Self::callee_saved_registers();
         source.extend(Self::special_registers());
         let instruction = Instruction::Operation {
             assembly: String::new(),
-            source,
+            source: source.into_iter().map(Operand::Register).collect(),
             destination: vec![],
             jump: Some(vec![]),
             stack_destination: vec![],
@@ -392,7 +438,7 @@ impl Frame for X86_64 {
         let instruction = Instruction::Operation {
             assembly: String::new(),
             source: vec![],
-            destination,
+            destination: destination.into_iter().map(Operand::Register).collect(),
             jump: Some(vec![]),
             stack_destination: vec![],
             stack_source: vec![],
@@ -401,13 +447,13 @@ impl Frame for X86_64 {
 
         for instruction in instructions.iter_mut().rev() {
             match *instruction {
-                Instruction::Label { .. } => (),
+                Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } => (),
                 Instruction::Call { ref mut source, .. } |
                     Instruction::Move { ref mut source, .. } |
                     Instruction::Operation { ref mut source, .. } =>
                 {
-                    source.push(Self::rbp());
-                    source.push(Self::rsp());
+                    source.push(Self::rbp().into());
+                    source.push(Self::rsp().into());
                     break;
                 },
             }
@@ -417,17 +463,33 @@ impl Frame for X86_64 {
     }
 
     fn proc_entry_exit3(&self, body: Vec<Instruction>) -> Subroutine {
-        let mut stack_size = -self.pointer;
-        if stack_size % 16 != 0 {
-            // Align the stack of 16 bytes.
-            stack_size = (stack_size & !0xF) + 0x10;
-        }
+        let stack_size = self.frame_size();
 
-        Subroutine { // FIXME: saving to rbp is apparently not needed in 64-bit.
-            prolog: format!("{}:
+        // FIXME: saving to rbp is apparently not needed in 64-bit.
+        let mut prolog = format!("{}:
     push rbp
-    mov rbp, rsp
-    sub rsp, {}", self.name(), stack_size),
+    mov rbp, rsp", self.name());
+        if stack_size > PAGE_SIZE {
+            // Grow the stack one page at a time and touch each page so that a frame bigger than a
+            // page cannot skip over the guard page.
+            let probe_label = Label::new();
+            prolog.push_str(&format!("
+    mov r11, {pages}
+{label}:
+    sub rsp, {page_size}
+    or qword [rsp], 0
+    dec r11
+    jnz {label}", label = probe_label, pages = stack_size / PAGE_SIZE, page_size = PAGE_SIZE));
+            if stack_size % PAGE_SIZE != 0 {
+                prolog.push_str(&format!("\n    sub rsp, {}", stack_size % PAGE_SIZE));
+            }
+        }
+        else {
+            prolog.push_str(&format!("\n    sub rsp, {}", stack_size));
+        }
+
+        Subroutine {
+            prolog,
             body,
             epilog: "leave
     ret".to_string(),
@@ -19,16 +19,22 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
 use std::rc::Rc;
 
 use ast::Operator;
+use canon::constant_value;
 use data_layout::{
     ARRAY_DATA_LAYOUT_SIZE,
+    ARRAY_TYPE,
     CLASS_DATA_LAYOUT_SIZE,
     RECORD_DATA_LAYOUT_SIZE,
+    RECORD_TYPE,
+    VTABLE_OFFSET,
 };
-use frame::{Fragment, Frame, Memory};
+use frame::{Finalizer, Fragment, Frame, Memory};
 use ir;
 use ir::BinOp::{
     And,
@@ -42,9 +48,11 @@ use ir::Exp::{
     self,
     BinOp,
     Call,
+    Compare,
     Const,
     ExpSequence,
     Mem,
+    MemByte,
     Name,
 };
 use ir::RelationalOp::{
@@ -55,6 +63,7 @@ use ir::RelationalOp::{
     GreaterOrEqual,
     LesserThan,
     LesserOrEqual,
+    UnsignedGreaterThan,
 };
 use ir::Statement;
 use ir::_Statement::{
@@ -64,23 +73,68 @@ use ir::_Statement::{
     Move,
     Sequence,
 };
-use semant::{FieldType, VTABLE_OFFSET};
-use temp::{Label, Temp, TempMap};
+use semant::FieldType;
+use temp::{DataLabel, Label, Temp, TempMap};
 
 #[allow(type_alias_bounds)]
 pub type Access<F: Frame> = (Level<F>, F::Access);
 
+const DISPLAY_NAME: &str = "__tiger_display";
+/// Bump pointer and end of the free space of the heap, defined by the runtime.
+pub const HEAP_LIMIT_NAME: &str = "__tiger_heap_limit";
+pub const HEAP_NEXT_NAME: &str = "__tiger_heap_next";
+
+/// Number of uses in a function from which a constant too large for an immediate is loaded from
+/// the constant pool.
+const CONSTANT_POOL_MIN_USES: usize = 2;
+/// Bounds of the number of constant words from which a record or an array is copied from a
+/// template instead of being initialized word by word: smaller objects are quicker to allocate
+/// inline, and bigger arrays would take too much space in the executable.
+const TEMPLATE_MIN_WORDS: usize = 4;
+const TEMPLATE_MAX_WORDS: usize = 64;
+
+/// How a function accesses the variables of the functions it is nested in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NestedAccess {
+    /// Global array of the frame pointers indexed by nesting depth.
+    /// A signal handler nested in another function could read the entry of another call.
+    Display,
+    /// Chain of frame pointers passed as a hidden parameter.
+    StaticLink,
+}
+
+impl NestedAccess {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "display" => Some(NestedAccess::Display),
+            "static-link" => Some(NestedAccess::StaticLink),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Level<F> {
     pub current: Rc<RefCell<F>>,
+    depth: usize,
+    nested_access: NestedAccess,
     parent: Option<Box<Level<F>>>,
+    // Temporaries holding the frame pointers of the parent functions, to avoid following the
+    // static links at every access of a variable of a parent function.
+    static_links: Rc<RefCell<Vec<Temp>>>,
+    // Whether a nested function reads the entry of this function in the display.
+    uses_display: Rc<Cell<bool>>,
 }
 
 impl<F> Clone for Level<F> {
     fn clone(&self) -> Self {
         Self {
             current: self.current.clone(),
+            depth: self.depth,
+            nested_access: self.nested_access,
             parent: self.parent.clone(),
+            static_links: self.static_links.clone(),
+            uses_display: self.uses_display.clone(),
         }
     }
 }
@@ -91,10 +145,14 @@ impl<F: PartialEq> PartialEq for Level<F> {
     }
 }
 
-pub fn outermost<F: Frame>() -> Level<F> {
+pub fn outermost<F: Frame>(nested_access: NestedAccess) -> Level<F> {
     Level {
         current: Rc::new(RefCell::new(F::new(Label::new(), vec![]))),
+        depth: 0,
+        nested_access,
         parent: None,
+        static_links: Rc::new(RefCell::new(vec![])),
+        uses_display: Rc::new(Cell::new(false)),
     }
 }
 
@@ -103,7 +161,71 @@ impl<F: Frame> Level<F> {
         formals.push(true); // for the static link.
         Level {
             current: Rc::new(RefCell::new(F::new(name, formals))),
+            depth: parent.depth + 1,
+            nested_access: parent.nested_access,
             parent: Some(Box::new(parent.clone())),
+            static_links: Rc::new(RefCell::new(vec![])),
+            uses_display: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Frame pointer of the function `depth` levels up.
+    fn ancestor_frame(&self, depth: usize) -> Exp {
+        if depth == 0 {
+            return Exp::Temp(F::fp().temp());
+        }
+        if self.nested_access == NestedAccess::Display {
+            let mut level = self;
+            for _ in 0..depth {
+                level = level.parent.as_ref().expect("function level should have a parent");
+            }
+            level.uses_display.set(true);
+            return level.display_entry();
+        }
+        let mut static_links = self.static_links.borrow_mut();
+        while static_links.len() < depth {
+            static_links.push(Temp::new());
+        }
+        Exp::Temp(static_links[depth - 1])
+    }
+
+    /// Follow the static links once at the entry of the function to initialize the temporaries
+    /// returned by ancestor_frame().
+    fn static_link_chain(&self) -> Option<Statement> {
+        let mut level = self;
+        let mut frame_pointer = Exp::Temp(F::fp().temp());
+        let mut chain: Option<Statement> = None;
+        for &temp in self.static_links.borrow().iter() {
+            let frame = level.current.borrow();
+            let static_link = frame.formals().last().expect("static link").clone();
+            let statement = Move(Exp::Temp(temp), frame.exp(static_link, frame_pointer)).into();
+            chain = Some(match chain {
+                Some(chain) => Sequence(Box::new(chain), Box::new(statement)).into(),
+                None => statement,
+            });
+            frame_pointer = Exp::Temp(temp);
+            level = level.parent.as_ref().expect("function level should have a parent");
+        }
+        chain
+    }
+
+    /// Entry of the display holding the frame pointer of the current call of this function.
+    fn display_entry(&self) -> Exp {
+        Mem(Box::new(BinOp {
+            op: Plus,
+            left: Box::new(Name(Label::with_name(DISPLAY_NAME))),
+            right: Box::new(Const(F::WORD_SIZE * self.depth as i64)),
+        }))
+    }
+
+    /// Whether the function is declared in a library or at the top level of the program, and not
+    /// in another function, so that the frame its static link points to lives until the program
+    /// exits.
+    pub fn is_top_level(&self) -> bool {
+        match self.parent {
+            Some(ref parent) =>
+                parent.depth == 0 || parent.depth == 1 && parent.current.borrow().name() == Label::with_name("main"),
+            None => false,
         }
     }
 
@@ -130,13 +252,26 @@ pub fn array_subscript<F: Frame>(var: Exp, subscript: Exp) -> Exp {
                 BinOp {
                     op: Plus,
                     left: Box::new(subscript),
-                    right: Box::new(num(ARRAY_DATA_LAYOUT_SIZE as i64)),
+                    right: Box::new(num::<F>(ARRAY_DATA_LAYOUT_SIZE as i64)),
                 }),
             right: Box::new(Const(F::WORD_SIZE)),
         }),
     }))
 }
 
+/// Element of an array of bytes, which are stored one per byte after the header.
+pub fn byte_array_subscript<F: Frame>(var: Exp, subscript: Exp) -> Exp {
+    MemByte(Box::new(BinOp {
+        op: Plus,
+        left: Box::new(var),
+        right: Box::new(BinOp {
+            op: Plus,
+            left: Box::new(subscript),
+            right: Box::new(Const(ARRAY_DATA_LAYOUT_SIZE as i64 * F::WORD_SIZE)),
+        }),
+    }))
+}
+
 pub fn binary_oper(op: Operator, left: Exp, right: Exp) -> Exp {
     BinOp {
         op: to_ir_op(op),
@@ -145,6 +280,78 @@ pub fn binary_oper(op: Operator, left: Exp, right: Exp) -> Exp {
     }
 }
 
+/// Division reporting the overflow of the smallest integer divided by -1 by calling the runtime,
+/// instead of letting the division instruction fault.
+pub fn checked_division<F: Frame>(left: Exp, right: Exp, position: Exp) -> Exp {
+    let min = i64::MIN >> (64 - F::WORD_SIZE * 8);
+    match right {
+        // Dividing by any other constant cannot overflow.
+        Const(divisor) if divisor != -1 => return binary_oper(Operator::Divide, left, right),
+        _ => (),
+    }
+    let dividend = Exp::Temp(Temp::new());
+    let divisor = Exp::Temp(Temp::new());
+    let check_dividend_label = Label::new();
+    let overflow_label = Label::new();
+    let divide_label = Label::new();
+    ExpSequence(
+        Box::new(Sequence(
+            Box::new(Sequence(
+                Box::new(Move(dividend.clone(), left).into()),
+                Box::new(Move(divisor.clone(), right).into()),
+            ).into()),
+            Box::new(Sequence(
+                Box::new(Sequence(
+                    Box::new(CondJump {
+                        op: Equal,
+                        left: divisor.clone(),
+                        right: Const(-1),
+                        true_label: check_dividend_label.clone(),
+                        false_label: divide_label.clone(),
+                    }.into()),
+                    Box::new(Sequence(
+                        Box::new(_Statement::Label(check_dividend_label).into()),
+                        Box::new(CondJump {
+                            op: Equal,
+                            left: dividend.clone(),
+                            right: Const(min),
+                            true_label: overflow_label.clone(),
+                            false_label: divide_label.clone(),
+                        }.into()),
+                    ).into()),
+                ).into()),
+                Box::new(Sequence(
+                    Box::new(Sequence(
+                        Box::new(_Statement::Label(overflow_label).into()),
+                        Box::new(_Statement::Exp(F::external_call("divisionOverflow", vec![position], false)).into()),
+                    ).into()),
+                    Box::new(_Statement::Label(divide_label).into()),
+                ).into()),
+            ).into()),
+        ).into()),
+        Box::new(binary_oper(Operator::Divide, dividend, divisor)),
+    )
+}
+
+/// Look up the subject in the sorted table of labels and select the arm numbered by its rank, or
+/// the else arm if the subject is not in the table.
+pub fn case_expression<F: Clone + Frame>(subject: Exp, table: Exp, label_count: usize, arms: Vec<(usize, Exp)>,
+    else_expr: Option<Exp>, level: &Level<F>) -> Exp
+{
+    let index = Exp::Temp(Temp::new());
+    let find_label = F::external_call("stringSwitch", vec![table, Const(label_count as i64), subject], false);
+    let select = arms.into_iter()
+        .rev()
+        .fold(else_expr, |else_expr, (label_index, arm)| {
+            let test = relational_oper(Operator::Equal, index.clone(), Const(label_index as i64));
+            Some(if_expression(test, arm, else_expr, level))
+        });
+    ExpSequence(
+        Box::new(Move(index, find_label).into()),
+        Box::new(select.unwrap_or_else(unit)),
+    )
+}
+
 pub fn field_access<F: Frame>(var: Exp, field_index: usize, field_type: FieldType) -> Exp {
     let offset =
         match field_type {
@@ -161,41 +368,50 @@ pub fn field_access<F: Frame>(var: Exp, field_index: usize, field_type: FieldTyp
 fn call<F: Clone + Frame + PartialEq>(function: Exp, mut arguments: Vec<Exp>, parent_level: &Level<F>,
     current_level: &Level<F>, collectable_return_type: bool) -> Exp
 {
+    arguments.push(static_link(parent_level, current_level));
+    Call {
+        arguments,
+        collectable_return_type,
+        function_expr: Box::new(function),
+        return_label: Label::new(),
+    }
+}
+
+/// Static link to pass to a function whose level is `current_level` from `parent_level`.
+pub fn static_link<F: Clone + Frame + PartialEq>(parent_level: &Level<F>, current_level: &Level<F>) -> Exp {
     if *current_level == *parent_level {
         // For a recursive call, we simply pass the current static link, which represents the stack
         // frame of the parent function.
         let frame = current_level.current.borrow();
-        arguments.push(frame.exp(frame.formals().last().expect("static link").clone(), Exp::Temp(F::fp())));
+        frame.exp(frame.formals().last().expect("static link").clone(), Exp::Temp(F::fp().temp()))
+    }
+    else if current_level.depth == 1 {
+        // The functions declared at the top level of a file, like those of a library, are not
+        // nested in another function, so their static link is not used.
+        Const(0)
     }
     else if current_level.parent.as_deref() == Some(parent_level) {
         // When calling a function defined in the current frame, simply pass the current frame
         // pointer for the static link.
-        arguments.push(Exp::Temp(F::fp()));
+        Exp::Temp(F::fp().temp())
     }
     else {
         // When calling a function defined in a parent frame, go up throught the static links.
         let mut function_level = parent_level;
-        let mut var = Exp::Temp(F::fp());
+        let mut depth = 0;
         loop {
             if let Some(ref current_level) = current_level.parent {
                 if &**current_level == function_level {
                     break;
                 }
             }
-            let frame = function_level.current.borrow();
-            var = frame.exp(frame.formals().last().expect("static link").clone(), var);
+            depth += 1;
             match function_level.parent {
                 Some(ref parent) => function_level = parent,
                 None => break,
             }
         }
-        arguments.push(var);
-    }
-    Call {
-        arguments,
-        collectable_return_type,
-        function_expr: Box::new(function),
-        return_label: Label::new(),
+        parent_level.ancestor_frame(depth)
     }
 }
 
@@ -205,9 +421,20 @@ pub fn function_call<F: Clone + Frame + PartialEq>(label: &Label, arguments: Vec
     call(Name(label.clone()), arguments, parent_level, current_level, collectable_return_type)
 }
 
-pub fn method_call<F: Clone + Frame + PartialEq>(index: usize, arguments: Vec<Exp>, parent_level: &Level<F>,
+pub fn method_call<F: Clone + Frame + PartialEq>(index: usize, mut arguments: Vec<Exp>, parent_level: &Level<F>,
     current_level: &Level<F>, collectable_return_type: bool) -> Exp
 {
+    // The receiver is used both to find the method and as the self argument, so it is evaluated
+    // once, before the other arguments, unless reading it twice is free, like for a variable.
+    let receiver_init =
+        if is_speculable::<F>(&arguments[0]) {
+            None
+        }
+        else {
+            let receiver = Exp::Temp(Temp::new());
+            let value = mem::replace(&mut arguments[0], receiver.clone());
+            Some(Move(receiver, value))
+        };
     let vtable = Mem(Box::new(BinOp {
         op: Plus,
         left: Box::new(arguments[0].clone()),
@@ -218,7 +445,11 @@ pub fn method_call<F: Clone + Frame + PartialEq>(index: usize, arguments: Vec<Ex
         left: Box::new(vtable),
         right: Box::new(Const(F::WORD_SIZE * index as i64)),
     }));
-    call(function_ptr, arguments, parent_level, current_level, collectable_return_type)
+    let call = call(function_ptr, arguments, parent_level, current_level, collectable_return_type);
+    match receiver_init {
+        Some(receiver_init) => ExpSequence(Box::new(receiver_init.into()), Box::new(call)),
+        None => call,
+    }
 }
 
 pub fn goto(label: Label) -> Exp {
@@ -228,22 +459,117 @@ pub fn goto(label: Label) -> Exp {
     )
 }
 
+/// Jump to `true_label` if `test_expr` is 1 and to `false_label` otherwise, branching directly on
+/// the comparison when the test is one.
+fn cond_jump(test_expr: Exp, true_label: Label, false_label: Label) -> Statement {
+    match split_comparison(test_expr) {
+        Ok(Comparison { statement, op, left, right }) => {
+            let jump = CondJump {
+                op,
+                left: *left,
+                right: *right,
+                true_label,
+                false_label,
+            }.into();
+            match statement {
+                Some(statement) => Sequence(Box::new(statement), Box::new(jump)).into(),
+                None => jump,
+            }
+        },
+        Err(test_expr) => CondJump {
+            op: Equal,
+            left: test_expr,
+            right: Const(1),
+            true_label,
+            false_label,
+        }.into(),
+    }
+}
+
+/// Comparison ending an expression.
+struct Comparison {
+    // Statements evaluated before the comparison.
+    statement: Option<Statement>,
+    op: RelationalOp,
+    left: Box<Exp>,
+    right: Box<Exp>,
+}
+
+/// Split an expression ending with a comparison, or give it back if it does not.
+fn split_comparison(expr: Exp) -> Result<Comparison, Exp> {
+    match expr {
+        Compare { op, left, right } => Ok(Comparison {
+            statement: None,
+            op,
+            left,
+            right,
+        }),
+        ExpSequence(statement, expr) =>
+            match split_comparison(*expr) {
+                Ok(mut comparison) => {
+                    comparison.statement =
+                        match comparison.statement {
+                            Some(statement2) => Some(Sequence(statement, Box::new(statement2)).into()),
+                            None => Some(*statement),
+                        };
+                    Ok(comparison)
+                },
+                Err(expr) => Err(ExpSequence(statement, Box::new(expr))),
+            },
+        expr => Err(expr),
+    }
+}
+
+/// Whether an expression can be evaluated even when its branch is not taken: it must not have
+/// side effects nor be able to trap.
+fn is_speculable<F: Frame>(expr: &Exp) -> bool {
+    match *expr {
+        Const(_) | Exp::Name(_) | Exp::Temp(_) => true,
+        // Variables in the current frame.
+        Mem(box BinOp { op: Plus, left: box Exp::Temp(temp), right: box Const(_) }) => temp == F::fp().temp(),
+        BinOp { op: Div, .. } => false,
+        BinOp { ref left, ref right, .. } | Compare { ref left, ref right, .. } =>
+            is_speculable::<F>(left) && is_speculable::<F>(right),
+        _ => false,
+    }
+}
+
 pub fn if_expression<F: Clone + Frame>(test_expr: Exp, if_expr: Exp, else_expr: Option<Exp>, level: &Level<F>) -> Exp {
+    // If-conversion: select between two cheap values without branching.
+    let speculable = is_speculable::<F>(&if_expr) && else_expr.as_ref().is_some_and(is_speculable::<F>);
+    let test_expr =
+        match split_comparison(test_expr) {
+            Ok(Comparison { statement, op, left, right }) if speculable => {
+                let select = Exp::Select {
+                    op,
+                    left,
+                    right,
+                    if_true: Box::new(if_expr),
+                    if_false: Box::new(else_expr.expect("else expression")),
+                };
+                return match statement {
+                    Some(statement) => ExpSequence(Box::new(statement), Box::new(select)),
+                    None => select,
+                };
+            },
+            Ok(Comparison { statement, op, left, right }) => {
+                let compare = Compare { op, left, right };
+                match statement {
+                    Some(statement) => ExpSequence(Box::new(statement), Box::new(compare)),
+                    None => compare,
+                }
+            },
+            Err(test_expr) => test_expr,
+        };
     let result = alloc_local(level, false);
     let true_label = Label::new();
     let false_label = Label::new();
     let end_label = Label::new();
     let frame = level.current.borrow();
-    let result = frame.exp(result.1, Exp::Temp(F::fp()));
+    let result = frame.exp(result.1, Exp::Temp(F::fp().temp()));
     ExpSequence(
         Box::new(Sequence(
-            Box::new(CondJump {
-                op: Equal,
-                left: test_expr,
-                right: Const(1),
-                true_label: true_label.clone(),
-                false_label: false_label.clone(),
-            }.into()),
+            Box::new(cond_jump(test_expr, true_label.clone(), false_label.clone())),
             Box::new(Sequence(
                 Box::new(_Statement::Label(true_label).into()),
                 Box::new(Sequence(
@@ -265,8 +591,105 @@ pub fn if_expression<F: Clone + Frame>(test_expr: Exp, if_expr: Exp, else_expr:
     )
 }
 
-pub fn init_array<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_expr: Exp, is_pointer: Exp, init_expr: Exp, level: &Level<F>) -> Exp {
+/// Allocate an array and initialize its elements with `init_expr`. The elements of a `packed` array
+/// take a single byte, so they cannot be pointers.
+/// Allocate `size` bytes by bumping the heap pointer and write the `header` words of the object,
+/// or call `slow_path` when the free space at the end of the heap is too small. An array whose
+/// `length` is negative or too big also takes the slow path so that the runtime reports it.
+fn inline_allocation<F: Frame>(length: Option<Exp>, size: Exp, header: Vec<Exp>, slow_path: Exp) -> Exp {
+    let result = Exp::Temp(Temp::new());
+    let next = Exp::Temp(Temp::new());
+    let heap_next = || Mem(Box::new(Name(Label::with_name(HEAP_NEXT_NAME))));
+    let check_space_label = Label::new();
+    let fast_label = Label::new();
+    let slow_label = Label::new();
+    let end_label = Label::new();
+
+    let mut fast_path = Move(heap_next(), next.clone()).into();
+    for (index, word) in header.into_iter().enumerate() {
+        fast_path = Sequence(
+            Box::new(fast_path),
+            Box::new(Move(Mem(Box::new(BinOp {
+                op: Plus,
+                left: Box::new(result.clone()),
+                right: Box::new(Const(index as i64 * F::WORD_SIZE)),
+            })), word).into()),
+        ).into();
+    }
+    let max_length = i64::MAX / F::WORD_SIZE - ARRAY_DATA_LAYOUT_SIZE as i64;
+    let check_length =
+        match length {
+            Some(Const(length)) if (0..=max_length).contains(&length) =>
+                Jump(Name(check_space_label.clone()), vec![check_space_label.clone()]).into(),
+            Some(length) => CondJump {
+                op: UnsignedGreaterThan,
+                left: length,
+                right: Const(max_length),
+                true_label: slow_label.clone(),
+                false_label: check_space_label.clone(),
+            }.into(),
+            None => Jump(Name(check_space_label.clone()), vec![check_space_label.clone()]).into(),
+        };
+    ExpSequence(
+        Box::new(Sequence(
+            Box::new(Sequence(
+                Box::new(check_length),
+                Box::new(Sequence(
+                    Box::new(_Statement::Label(check_space_label).into()),
+                    Box::new(Sequence(
+                        Box::new(Sequence(
+                            Box::new(Move(result.clone(), heap_next()).into()),
+                            Box::new(Move(next.clone(), BinOp {
+                                op: Plus,
+                                left: Box::new(result.clone()),
+                                right: Box::new(size),
+                            }).into()),
+                        ).into()),
+                        Box::new(CondJump {
+                            op: UnsignedGreaterThan,
+                            left: next,
+                            right: Mem(Box::new(Name(Label::with_name(HEAP_LIMIT_NAME)))),
+                            true_label: slow_label.clone(),
+                            false_label: fast_label.clone(),
+                        }.into()),
+                    ).into()),
+                ).into()),
+            ).into()),
+            Box::new(Sequence(
+                Box::new(Sequence(
+                    Box::new(_Statement::Label(fast_label).into()),
+                    Box::new(Sequence(
+                        Box::new(fast_path),
+                        Box::new(Jump(Name(end_label.clone()), vec![end_label.clone()]).into()),
+                    ).into()),
+                ).into()),
+                Box::new(Sequence(
+                    Box::new(Sequence(
+                        Box::new(_Statement::Label(slow_label).into()),
+                        Box::new(Move(result.clone(), slow_path).into()),
+                    ).into()),
+                    Box::new(_Statement::Label(end_label).into()),
+                ).into()),
+            ).into()),
+        ).into()),
+        Box::new(result),
+    )
+}
+
+pub fn init_array<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_expr: Exp, is_pointer: Exp, packed: bool,
+    position: Exp, init_expr: Exp) -> Exp
+{
     // FIXME: it does many allocations for a 2D array.
+    // The size is used both to allocate the array and to stop the initialization loop, so it is
+    // evaluated once, before the initial values.
+    let (size_init, size_expr) =
+        match size_expr {
+            Const(_) => (None, size_expr),
+            _ => {
+                let size = Exp::Temp(Temp::new());
+                (Some(Move(size.clone(), size_expr)), size)
+            },
+        };
     let temp = Temp::new();
     let result =
         if let Some(var) = var.clone() {
@@ -276,14 +699,21 @@ pub fn init_array<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_exp
         else {
             Exp::Temp(temp)
         };
+    let element =
+        if packed {
+            byte_array_subscript::<F>
+        }
+        else {
+            array_subscript::<F>
+        };
     let loop_var = Exp::Temp(Temp::new());
-    let test_expr = relational_oper(Operator::Lt, loop_var.clone(), size_expr.clone(), level);
+    let test_expr = relational_oper(Operator::Lt, loop_var.clone(), size_expr.clone());
     let init_var = Exp::Temp(Temp::new());
     let body = Exp::ExpSequence(
         Box::new(_Statement::Sequence(
             Box::new(_Statement::Sequence(
                 Box::new(_Statement::Move(init_var.clone(), init_expr).into()),
-                Box::new(_Statement::Move(array_subscript::<F>(result.clone(), loop_var.clone()), init_var).into()),
+                Box::new(_Statement::Move(element(result.clone(), loop_var.clone()), init_var).into()),
             ).into()),
             Box::new(_Statement::Move(loop_var.clone(), BinOp {
                 op: Plus,
@@ -293,12 +723,43 @@ pub fn init_array<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_exp
         ).into()),
         Box::new(unit())
     );
+    let allocation =
+        if packed {
+            F::external_call("initByteArray", vec![size_expr, position], true)
+        }
+        else {
+            let slow_path = F::external_call("initArray", vec![size_expr.clone(), is_pointer.clone(), position], true);
+            let size = BinOp {
+                op: Mul,
+                left: Box::new(BinOp {
+                    op: Plus,
+                    left: Box::new(size_expr.clone()),
+                    right: Box::new(Const(ARRAY_DATA_LAYOUT_SIZE as i64)),
+                }),
+                right: Box::new(Const(F::WORD_SIZE)),
+            };
+            let header = vec![
+                Const(ARRAY_TYPE as i64),
+                BinOp {
+                    op: Mul,
+                    left: Box::new(size_expr.clone()),
+                    right: Box::new(Const(F::WORD_SIZE)),
+                },
+                is_pointer,
+            ];
+            inline_allocation::<F>(Some(size_expr.clone()), size, header, slow_path)
+        };
     let init =
         if let Some(var) = var {
-            var_dec(&var, F::external_call("initArray", vec![size_expr, is_pointer], true))
+            var_dec(&var, allocation)
         }
         else {
-            Move(result.clone(), F::external_call("initArray", vec![size_expr, is_pointer], true)).into()
+            Move(result.clone(), allocation).into()
+        };
+    let init =
+        match size_init {
+            Some(size_init) => Sequence(Box::new(size_init.into()), Box::new(init)).into(),
+            None => init,
         };
     let sequence = ExpSequence(
         Box::new(Sequence(
@@ -313,22 +774,40 @@ pub fn init_array<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_exp
     )
 }
 
-pub fn num(number: i64) -> Exp {
-    Const(number)
+/// Allocate an array whose elements are copied from `template`, as returned by
+/// `Gen::array_template`.
+pub fn array_clone<F: Clone + Frame + PartialEq>(var: Option<Access<F>>, size_expr: Exp, is_pointer: Exp,
+    position: Exp, template: Exp) -> Exp
+{
+    let allocation = F::external_call("cloneArray", vec![size_expr, is_pointer, template, position], true);
+    match var {
+        Some(var) => {
+            let level = var.0.clone();
+            ExpSequence(
+                Box::new(var_dec(&var, allocation)),
+                Box::new(simple_var(var, &level)),
+            )
+        },
+        None => allocation,
+    }
+}
+
+pub fn num<F: Frame>(number: i64) -> Exp {
+    Const(F::wrap_constant(number))
 }
 
-pub fn class_create<F: Frame + PartialEq>(var: Access<F>, data_layout: Exp, fields: Vec<Exp>, vtable_name: Label) -> Exp {
+pub fn class_create<F: Frame + PartialEq>(var: Access<F>, data_layout: Exp, position: Exp, fields: Vec<Exp>, vtable_name: DataLabel) -> Exp {
     let level = var.0.clone();
     let result = simple_var(var, &level);
 
-    let mut sequence = Move(result.clone(), F::external_call("allocClass", vec![data_layout], true)).into();
+    let mut sequence = Move(result.clone(), F::external_call("allocClass", vec![data_layout, position], true)).into();
     sequence = Sequence(
         Box::new(sequence),
         Box::new(Move(Mem(Box::new(BinOp {
             op: Plus,
             left: Box::new(result.clone()),
             right: Box::new(Const(VTABLE_OFFSET as i64 * F::WORD_SIZE)),
-        })), Exp::Name(vtable_name)).into())
+        })), Exp::Name(vtable_name.to_label())).into())
     ).into();
     for (index, field) in fields.into_iter().enumerate() {
         let index = index + CLASS_DATA_LAYOUT_SIZE;
@@ -355,13 +834,16 @@ pub fn class_create<F: Frame + PartialEq>(var: Access<F>, data_layout: Exp, fiel
     )
 }
 
-pub fn record_create<F: Frame>(data_layout: Exp, fields: Vec<Exp>) -> Exp {
+pub fn record_create<F: Frame>(data_layout: Exp, position: Exp, fields: Vec<Exp>) -> Exp {
     if fields.is_empty() {
         return unit();
     }
     let temp = Temp::new();
     let result = Exp::Temp(temp);
-    let mut sequence = Move(result.clone(), F::external_call("allocRecord", vec![data_layout], true)).into();
+    let size = Const(((fields.len() + RECORD_DATA_LAYOUT_SIZE) as i64) * F::WORD_SIZE);
+    let header = vec![Const(RECORD_TYPE as i64), data_layout.clone()];
+    let slow_path = F::external_call("allocRecord", vec![data_layout, position], true);
+    let mut sequence = Move(result.clone(), inline_allocation::<F>(None, size, header, slow_path)).into();
     for (index, field) in fields.into_iter().enumerate() {
         let index = index + RECORD_DATA_LAYOUT_SIZE;
         let temp = Exp::Temp(Temp::new());
@@ -387,67 +869,59 @@ pub fn record_create<F: Frame>(data_layout: Exp, fields: Vec<Exp>) -> Exp {
     )
 }
 
-pub fn relational_oper<F: Clone + Frame>(op: Operator, left: Exp, right: Exp, level: &Level<F>) -> Exp {
-    let result = alloc_local(level, false);
-    let true_label = Label::new();
-    let false_label = Label::new();
-    let end_label = Label::new();
-    let frame = level.current.borrow();
-    let result = frame.exp(result.1, Exp::Temp(F::fp()));
-    ExpSequence(
-        Box::new(Sequence(
-            Box::new(CondJump {
-                op: to_ir_rel_op(op),
-                left,
-                right,
-                true_label: true_label.clone(),
-                false_label: false_label.clone(),
-            }.into()),
-            Box::new(Sequence(
-                Box::new(_Statement::Label(true_label).into()),
-                Box::new(Sequence(
-                    Box::new(Move(result.clone(), Const(1)).into()),
-                    Box::new(Sequence(
-                        Box::new(Jump(Name(end_label.clone()), vec![end_label.clone()]).into()),
-                        Box::new(Sequence(
-                            Box::new(_Statement::Label(false_label).into()),
-                            Box::new(Sequence(
-                                Box::new(Move(result.clone(), Const(0)).into()),
-                                Box::new(_Statement::Label(end_label).into()),
-                            ).into())
-                        ).into()),
-                    ).into()),
-                ).into()),
-            ).into()),
-        ).into()),
-        Box::new(result),
-    )
+/// Allocate a record whose fields are copied from `template`, as returned by
+/// `Gen::record_template`.
+pub fn record_clone<F: Frame>(data_layout: Exp, position: Exp, template: Exp) -> Exp {
+    F::external_call("cloneRecord", vec![data_layout, template, position], true)
+}
+
+pub fn relational_oper(op: Operator, left: Exp, right: Exp) -> Exp {
+    Compare {
+        op: to_ir_rel_op(op),
+        left: Box::new(left),
+        right: Box::new(right),
+    }
 }
 
 pub fn simple_var<F: Clone + Frame + PartialEq>(access: Access<F>, level: &Level<F>) -> Exp {
     let mut function_level = level;
     let var_level = access.0;
-    let frame = level.current.borrow();
-    let mut var = Exp::Temp(F::fp());
-    // Add the offset of each parent frames (static link).
+    let mut depth = 0;
+    // Count the static links to follow to get to the frame of the variable.
     while function_level.current != var_level.current {
-        var = frame.exp(function_level.current.borrow().formals().last().expect("static link").clone(), var);
+        depth += 1;
         function_level = function_level.parent.as_ref().unwrap_or_else(|| panic!("function level should have a parent"));
     }
-    var = frame.exp(access.1, var);
-    var
+    let frame = level.current.borrow();
+    frame.exp(access.1, level.ancestor_frame(depth))
 }
 
-pub fn string_equality<F: Frame>(oper: Operator, left: Exp, right: Exp) -> Exp {
-    let exp = F::external_call("stringEqual", vec![left, right], false);
+/// Static field `field_index` of the class whose static fields are stored at `static_data`.
+pub fn static_field<F: Frame>(static_data: &DataLabel, field_index: usize) -> Exp {
+    Mem(Box::new(BinOp {
+        op: Plus,
+        left: Box::new(Name(static_data.to_label())),
+        right: Box::new(Const(F::WORD_SIZE * field_index as i64)),
+    }))
+}
+
+pub fn static_fields_init<F: Frame>(static_data: &DataLabel, values: Vec<Exp>) -> Option<Statement> {
+    values.into_iter()
+        .enumerate()
+        .map(|(index, value)| Move(static_field::<F>(static_data, index), value).into())
+        .reduce(|statements, statement| Sequence(Box::new(statements), Box::new(statement)).into())
+}
+
+/// Compare the strings for equality, or else by the sign of their lexicographic comparison.
+pub fn string_comparison<F: Frame>(oper: Operator, left: Exp, right: Exp) -> Exp {
     match oper {
-        Operator::Equal => exp,
+        Operator::Equal => F::external_call("stringEqual", vec![left, right], false),
         Operator::Neq => BinOp {
             op: Minus,
             left: Box::new(Const(1)),
-            right: Box::new(exp),
+            right: Box::new(F::external_call("stringEqual", vec![left, right], false)),
         },
-        _ => panic!("unexpected operator {:?}", oper),
+        _ => relational_oper(oper, F::external_call("stringCompare", vec![left, right], false), Const(0)),
     }
 }
 
@@ -458,7 +932,7 @@ pub fn unit() -> Exp {
 pub fn var_dec<F: Frame>(access: &Access<F>, value: Exp) -> Statement {
     let var_level = &access.0;
     let frame = var_level.current.borrow();
-    let dec = Move(frame.exp(access.1.clone(), Exp::Temp(F::fp())), value);
+    let dec = Move(frame.exp(access.1.clone(), Exp::Temp(F::fp().temp())), value);
     if let Some(pos) = access.1.as_stack() {
         // This is for the purpose of stack variable liveness analysis.
         Statement {
@@ -505,13 +979,7 @@ pub fn while_loop(done_label: &Label, test_expr: Exp, body: Exp) -> Exp {
                 Box::new(_Statement::Label(test_label.clone()).into()),
                 Box::new(Sequence(
                     Box::new(Sequence(
-                        Box::new(CondJump {
-                            op: NotEqual,
-                            left: test_expr,
-                            right: Const(1),
-                            true_label: done_label.clone(),
-                            false_label: after_check_label.clone(),
-                        }.into()),
+                        Box::new(cond_jump(test_expr, after_check_label.clone(), done_label.clone())),
                         Box::new(_Statement::Label(after_check_label).into()),
                     ).into()),
                     Box::new(Sequence(
@@ -551,22 +1019,64 @@ fn to_ir_rel_op(op: Operator) -> RelationalOp {
 }
 
 pub struct Gen<F: Frame> {
+    constants: HashMap<i64, DataLabel>,
+    display_size: usize,
     fragments: Vec<Fragment<F>>,
+    templates: HashMap<Vec<i64>, DataLabel>,
 }
 
 impl<F:Frame> Gen<F> {
     pub fn new() -> Self {
         Self {
+            constants: HashMap::new(),
+            display_size: 0,
             fragments: vec![],
+            templates: HashMap::new(),
         }
     }
 
-    pub fn get_result(self) -> Vec<Fragment<F>> {
+    pub fn get_result(mut self) -> Vec<Fragment<F>> {
+        if self.display_size > 0 {
+            self.fragments.push(Fragment::Display {
+                name: DataLabel::with_name(DISPLAY_NAME),
+                size: self.display_size,
+            });
+        }
         self.fragments
     }
 
     pub fn proc_entry_exit(&mut self, level: &Level<F>, body: Exp, temp_map: TempMap, escaping_vars: Vec<i64>) {
-        let body = Move(Exp::Temp(F::return_value()), body).into();
+        let body =
+            match level.static_link_chain() {
+                Some(chain) => ExpSequence(Box::new(chain), Box::new(body)),
+                None => body,
+            };
+        let body =
+            if level.uses_display.get() {
+                // Save the entry of the display of this nesting depth to restore it when
+                // returning, since it may belong to another call of a function at the same depth.
+                self.display_size = self.display_size.max(level.depth + 1);
+                let entry = level.display_entry();
+                let saved_entry = Exp::Temp(Temp::new());
+                let result = Exp::Temp(Temp::new());
+                ExpSequence(
+                    Box::new(Sequence(
+                        Box::new(Move(saved_entry.clone(), entry.clone()).into()),
+                        Box::new(Move(entry.clone(), Exp::Temp(F::fp().temp())).into()),
+                    ).into()),
+                    Box::new(ExpSequence(
+                        Box::new(Sequence(
+                            Box::new(Move(result.clone(), body).into()),
+                            Box::new(Move(entry, saved_entry).into()),
+                        ).into()),
+                        Box::new(result),
+                    )),
+                )
+            }
+            else {
+                body
+            };
+        let body = self.pool_constants(Move(Exp::Temp(F::return_value().temp()), body).into());
         self.fragments.push(Fragment::Function {
             body,
             escaping_vars,
@@ -575,15 +1085,114 @@ impl<F:Frame> Gen<F> {
         });
     }
 
+    /// Load the large constants used repeatedly in the function body from the constant pool
+    /// instead of moving the same full-width immediate at each use.
+    fn pool_constants(&mut self, body: Statement) -> Statement {
+        let mut uses = HashMap::new();
+        for value in body.constants() {
+            if !F::fits_immediate(value) {
+                *uses.entry(value).or_insert(0) += 1;
+            }
+        }
+        if uses.values().all(|&count| count < CONSTANT_POOL_MIN_USES) {
+            return body;
+        }
+        body.map_constants(&mut |value| {
+            if uses.get(&value).is_some_and(|&count| count >= CONSTANT_POOL_MIN_USES) {
+                self.pooled_constant(value)
+            }
+            else {
+                Const(value)
+            }
+        })
+    }
+
+    fn pooled_constant(&mut self, value: i64) -> Exp {
+        let fragments = &mut self.fragments;
+        let label = self.constants.entry(value)
+            .or_insert_with(|| {
+                let label = DataLabel::new();
+                fragments.push(Fragment::Constant(label.clone(), value));
+                label
+            });
+        Mem(Box::new(Name(label.to_label())))
+    }
+
+    pub fn static_fields(&mut self, name: DataLabel, data_layout: String) {
+        if !data_layout.is_empty() {
+            self.fragments.push(Fragment::StaticFields {
+                data_layout,
+                name,
+            });
+        }
+    }
+
     pub fn string_literal(&mut self, string: String) -> Exp {
-        let label = Label::new();
-        self.fragments.push(Fragment::Str(label.clone(), string));
-        Name(label)
+        let label = DataLabel::new();
+        let name = Name(label.to_label());
+        self.fragments.push(Fragment::Str(label, string));
+        name
+    }
+
+    /// Table of pointers to the given string literals.
+    pub fn string_table(&mut self, strings: Vec<String>) -> Exp {
+        let strings = strings.into_iter()
+            .map(|string| {
+                let label = DataLabel::new();
+                self.fragments.push(Fragment::Str(label.clone(), string));
+                label
+            })
+            .collect();
+        let name = DataLabel::new();
+        let exp = Name(name.to_label());
+        self.fragments.push(Fragment::StringTable {
+            name,
+            strings,
+        });
+        exp
+    }
+
+    /// Template of an array of `size` elements initialized with `init`, if both are constants.
+    pub fn array_template(&mut self, size: &Exp, init: &Exp) -> Option<Exp> {
+        let size = constant_value(size)?;
+        let value = constant_value(init)?;
+        (TEMPLATE_MIN_WORDS as i64..=TEMPLATE_MAX_WORDS as i64).contains(&size)
+            .then(|| self.template(vec![value; size as usize]))
+    }
+
+    /// Template of a record whose fields are initialized with `fields`, if they are all constants.
+    pub fn record_template(&mut self, fields: &[Exp]) -> Option<Exp> {
+        if fields.len() < TEMPLATE_MIN_WORDS {
+            return None;
+        }
+        let values = fields.iter()
+            .map(constant_value)
+            .collect::<Option<Vec<_>>>()?;
+        Some(self.template(values))
+    }
+
+    fn template(&mut self, values: Vec<i64>) -> Exp {
+        let fragments = &mut self.fragments;
+        let label = self.templates.entry(values)
+            .or_insert_with_key(|values| {
+                let name = DataLabel::new();
+                fragments.push(Fragment::Template {
+                    name: name.clone(),
+                    values: values.clone(),
+                });
+                name
+            });
+        Name(label.to_label())
+    }
+
+    pub fn pure_functions(&mut self, functions: Vec<Label>) {
+        self.fragments.push(Fragment::PureFunctions(functions));
     }
 
-    pub fn vtable(&mut self, class: Label, methods: Vec<Label>) {
+    pub fn vtable(&mut self, class: DataLabel, methods: Vec<Label>, finalizer: Option<Finalizer>) {
         self.fragments.push(Fragment::VTable {
             class,
+            finalizer,
             methods,
         });
     }
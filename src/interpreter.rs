@@ -0,0 +1,655 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Interpreter of the AST, which runs the program without compiling it, for `--debug`.
+//!
+//! The values are those of the compiled programs, except that the integers do not need a tag and
+//! the records and arrays are reference counted instead of collected. An `Observer` is told about
+//! each expression before it is evaluated and about each call that returns, which is how the
+//! debugger stops the program. Only the core language is supported: the classes, the pointers and
+//! the functions of the runtime which are not in the standard library are reported as errors when
+//! they are reached.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write, stdin, stdout};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ast::{Arena, Ast, Declaration, DeclarationWithPos, Expr, ExprId, FuncDeclarationWithPos, Operator};
+use env::ON_SIGNAL;
+use position::Pos;
+use symbol::{Symbol, Symbols};
+
+/// Number of nested calls after which the program is stopped, before the interpreter overflows
+/// its own stack.
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Depth after which the nested records and arrays are elided when showing a value, so that the
+/// cyclic ones are shown.
+const MAX_SHOW_DEPTH: usize = 3;
+
+/// Name of the frame of the main expression of the program.
+pub const MAIN_FRAME: &str = "main";
+
+#[derive(Clone)]
+pub enum Value {
+    Array(Rc<RefCell<Vec<Value>>>),
+    Int(i64),
+    Nil,
+    Record(Rc<Record>),
+    Str(Rc<str>),
+    Unit,
+}
+
+impl Value {
+    pub fn show(&self, symbols: &Symbols<()>) -> String {
+        self.show_nested(symbols, MAX_SHOW_DEPTH)
+    }
+
+    fn show_nested(&self, symbols: &Symbols<()>, depth: usize) -> String {
+        match *self {
+            Value::Array(ref elements) => {
+                if depth == 0 {
+                    return "[...]".to_string();
+                }
+                let elements: Vec<_> = elements.borrow().iter()
+                    .map(|element| element.show_nested(symbols, depth - 1))
+                    .collect();
+                format!("[{}]", elements.join(", "))
+            },
+            Value::Int(value) => value.to_string(),
+            Value::Nil => "nil".to_string(),
+            Value::Record(ref record) => {
+                let name = symbols.name(record.typ);
+                if depth == 0 {
+                    return format!("{} {{...}}", name);
+                }
+                let fields: Vec<_> = record.fields.borrow().iter()
+                    .map(|&(field, ref value)| format!("{} = {}", symbols.name(field), value.show_nested(symbols, depth - 1)))
+                    .collect();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            },
+            Value::Str(ref string) => format!("{:?}", string),
+            Value::Unit => "()".to_string(),
+        }
+    }
+
+    /// Element `index` of the array, or the error shown when the value is not an array or the
+    /// index is out of its bounds.
+    pub fn element(&self, index: i64) -> Result<Value, String> {
+        match *self {
+            Value::Array(ref elements) => {
+                let elements = elements.borrow();
+                usize::try_from(index).ok()
+                    .and_then(|index| elements.get(index))
+                    .cloned()
+                    .ok_or_else(|| format!("index {} out of bounds of array of length {}", index, elements.len()))
+            },
+            _ => Err("not an array".to_string()),
+        }
+    }
+
+    /// Field `name` of the record, or the error shown when the value is nil or not a record.
+    pub fn field(&self, name: &str, symbols: &Symbols<()>) -> Result<Value, String> {
+        self.find_field(|field| symbols.name(field) == name)
+    }
+
+    fn find_field<F: Fn(Symbol) -> bool>(&self, is_field: F) -> Result<Value, String> {
+        match *self {
+            Value::Nil => Err("nil record dereference".to_string()),
+            Value::Record(ref record) =>
+                record.fields.borrow().iter()
+                    .find(|entry| is_field(entry.0))
+                    .map(|entry| entry.1.clone())
+                    .ok_or_else(|| "no such field".to_string()),
+            _ => Err("not a record".to_string()),
+        }
+    }
+
+    fn int(&self) -> i64 {
+        match *self {
+            Value::Int(value) => value,
+            _ => panic!("expecting an int"),
+        }
+    }
+
+    fn string(&self) -> Rc<str> {
+        match *self {
+            Value::Str(ref string) => Rc::clone(string),
+            _ => panic!("expecting a string"),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match *self {
+            Value::Array(ref array1) => matches!(*other, Value::Array(ref array2) if Rc::ptr_eq(array1, array2)),
+            Value::Int(value1) => matches!(*other, Value::Int(value2) if value1 == value2),
+            Value::Nil => matches!(*other, Value::Nil),
+            Value::Record(ref record1) => matches!(*other, Value::Record(ref record2) if Rc::ptr_eq(record1, record2)),
+            Value::Str(ref string1) => matches!(*other, Value::Str(ref string2) if string1 == string2),
+            Value::Unit => matches!(*other, Value::Unit),
+        }
+    }
+}
+
+pub struct Record {
+    fields: RefCell<Vec<(Symbol, Value)>>,
+    typ: Symbol,
+}
+
+enum Binding<'a> {
+    Functions(&'a [FuncDeclarationWithPos]),
+    Variable(Symbol, RefCell<Value>),
+}
+
+struct Scope<'a> {
+    binding: Binding<'a>,
+    parent: Env<'a>,
+}
+
+/// Variables and functions in scope, from the innermost declaration.
+#[derive(Clone, Default)]
+pub struct Env<'a> {
+    scope: Option<Rc<Scope<'a>>>,
+}
+
+impl<'a> Env<'a> {
+    fn with(&self, binding: Binding<'a>) -> Self {
+        Self {
+            scope: Some(Rc::new(Scope {
+                binding,
+                parent: self.clone(),
+            })),
+        }
+    }
+
+    fn scopes(&self) -> impl Iterator<Item=&Scope<'a>> {
+        let mut env = self;
+        std::iter::from_fn(move || {
+            let scope = env.scope.as_deref()?;
+            env = &scope.parent;
+            Some(scope)
+        })
+    }
+
+    /// The function `name` with the environment it was declared in.
+    fn function(&self, name: Symbol) -> Option<(&'a FuncDeclarationWithPos, Env<'a>)> {
+        let mut env = self;
+        while let Some(ref scope) = env.scope {
+            if let Binding::Functions(functions) = scope.binding {
+                if let Some(function) = functions.iter().find(|function| function.node.name.node == name) {
+                    return Some((function, env.clone()));
+                }
+            }
+            env = &scope.parent;
+        }
+        None
+    }
+
+    fn variable(&self, name: Symbol) -> Option<&RefCell<Value>> {
+        self.scopes()
+            .find_map(|scope| match scope.binding {
+                Binding::Variable(variable, ref value) if variable == name => Some(value),
+                _ => None,
+            })
+    }
+
+    /// Value of the variable named `name`.
+    pub fn value(&self, name: &str, symbols: &Symbols<()>) -> Option<Value> {
+        self.scopes()
+            .find_map(|scope| match scope.binding {
+                Binding::Variable(variable, ref value) if symbols.name(variable) == name => Some(value.borrow().clone()),
+                _ => None,
+            })
+    }
+
+    /// Variables in scope which are not shadowed, from the innermost one.
+    pub fn variables(&self) -> Vec<(Symbol, Value)> {
+        let mut variables: Vec<(Symbol, Value)> = vec![];
+        for scope in self.scopes() {
+            if let Binding::Variable(name, ref value) = scope.binding {
+                if variables.iter().all(|variable| variable.0 != name) {
+                    variables.push((name, value.borrow().clone()));
+                }
+            }
+        }
+        variables
+    }
+}
+
+/// Call in progress.
+pub struct Frame<'a> {
+    /// Position of the call, which is dummy for the main expression.
+    pub call_pos: Pos,
+    pub env: Env<'a>,
+    pub function: String,
+}
+
+/// Why the evaluation of an expression stopped before producing a value.
+pub enum Flow {
+    Break,
+    /// Runtime error, with the position of the expression causing it.
+    Error(Pos, String),
+    Exit(i64),
+    /// The observer stopped the program.
+    Quit,
+}
+
+/// Notified of the progress of the program, which it can stop by returning `Flow::Quit`.
+pub trait Observer {
+    /// Called before evaluating the expression at `pos`, with the calls in progress, from the
+    /// outermost one.
+    fn evaluate(&mut self, pos: Pos, frames: &[Frame]) -> Result<(), Flow>;
+
+    /// Called when the call `callee` returned `value` to the innermost of `frames`.
+    fn returned(&mut self, callee: &Frame, value: &Value, frames: &[Frame]) -> Result<(), Flow>;
+}
+
+/// Run the program and return its exit code, or None if the observer stopped it.
+pub fn run<O: Observer>(ast: &Ast, symbols: &Symbols<()>, observer: &mut O) -> io::Result<Option<i64>> {
+    let mut interpreter = Interpreter {
+        arena: &ast.arena,
+        frames: vec![Frame {
+            call_pos: Pos::dummy(),
+            env: Env::default(),
+            function: MAIN_FRAME.to_string(),
+        }],
+        observer,
+        symbols,
+    };
+    match interpreter.expr(ast.root) {
+        Ok(_) => Ok(Some(0)),
+        Err(Flow::Break) => unreachable!("break outside loop"),
+        Err(Flow::Error(pos, message)) => {
+            let position = format!("{}:{}:{}", symbols.name(pos.file), pos.line, pos.column);
+            Err(io::Error::other(format!("{}: {}", position, message)))
+        },
+        Err(Flow::Exit(code)) => Ok(Some(code)),
+        Err(Flow::Quit) => Ok(None),
+    }
+}
+
+struct Interpreter<'a, 'o, O: Observer> {
+    arena: &'a Arena,
+    frames: Vec<Frame<'a>>,
+    observer: &'o mut O,
+    symbols: &'a Symbols<()>,
+}
+
+impl<'a, 'o, O: Observer> Interpreter<'a, 'o, O> {
+    fn builtin(&mut self, name: &str, args: Vec<Value>, pos: Pos) -> Result<Value, Flow> {
+        let error = |message: String| Flow::Error(pos, message);
+        let value =
+            match (name, args.as_slice()) {
+                ("chr", &[Value::Int(code)]) => {
+                    if !(0..=127).contains(&code) {
+                        return Err(error(format!("chr: {} is not an ASCII code", code)));
+                    }
+                    Value::Str((code as u8 as char).to_string().into())
+                },
+                ("clock_ms", &[]) => {
+                    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    Value::Int(time.as_millis() as i64)
+                },
+                ("concat", &[Value::Str(ref string1), Value::Str(ref string2)]) => Value::Str(format!("{}{}", string1, string2).into()),
+                ("exit", &[Value::Int(code)]) => return Err(Flow::Exit(code)),
+                ("flush", &[]) => {
+                    let _ = stdout().flush();
+                    Value::Unit
+                },
+                ("getchar", &[]) => {
+                    let _ = stdout().flush();
+                    let mut byte = [0];
+                    match stdin().read(&mut byte) {
+                        Ok(1) => Value::Str((byte[0] as char).to_string().into()),
+                        _ => Value::Str("".into()),
+                    }
+                },
+                ("int_to_string", &[Value::Int(num), Value::Int(base)]) => {
+                    if !(2..=36).contains(&base) {
+                        return Err(error(format!("int_to_string: base {} is not between 2 and 36", base)));
+                    }
+                    Value::Str(format_int(num, base as u64).into())
+                },
+                ("not", &[Value::Int(value)]) => Value::Int((value == 0) as i64),
+                ("ord", &[Value::Str(ref string)]) => Value::Int(string.chars().next().map_or(-1, |char| char as i64)),
+                ("print", &[Value::Str(ref string)]) => {
+                    print!("{}", string);
+                    let _ = stdout().flush();
+                    Value::Unit
+                },
+                ("print_hex", &[Value::Int(num)]) => {
+                    print!("{}", format_int(num, 16));
+                    let _ = stdout().flush();
+                    Value::Unit
+                },
+                ("print_padded", &[Value::Int(num), Value::Int(width)]) => {
+                    print!("{:>width$}", num, width = width.max(0) as usize);
+                    let _ = stdout().flush();
+                    Value::Unit
+                },
+                ("printi", &[Value::Int(num)]) => {
+                    println!("{}", num as i32);
+                    Value::Unit
+                },
+                ("size", &[Value::Str(ref string)]) => Value::Int(string.len() as i64),
+                ("sleep_ms", &[Value::Int(milliseconds)]) => {
+                    thread::sleep(Duration::from_millis(milliseconds.max(0) as u64));
+                    Value::Unit
+                },
+                ("stringEqual", &[Value::Str(ref string1), Value::Str(ref string2)]) => Value::Int((string1 == string2) as i64),
+                ("substring", &[Value::Str(ref string), Value::Int(first), Value::Int(length)]) => {
+                    let substring = usize::try_from(first).ok()
+                        .zip(usize::try_from(length).ok())
+                        .and_then(|(first, length)| string.get(first..first.checked_add(length)?));
+                    match substring {
+                        Some(substring) => Value::Str(substring.into()),
+                        None => return Err(error(format!("substring: {}..{} is out of the bounds of a string of size {}",
+                            first, first.saturating_add(length), string.len()))),
+                    }
+                },
+                _ => return Err(unsupported_function(name, pos)),
+            };
+        Ok(value)
+    }
+
+    fn call(&mut self, name: Symbol, args: &'a [ExprId], pos: Pos) -> Result<Value, Flow> {
+        let function = self.env().function(name);
+        let name = self.symbols.name(name);
+        // The handler is a function, which is not a value.
+        if function.is_none() && name == ON_SIGNAL {
+            return Err(unsupported_function(&name, pos));
+        }
+        let mut values = vec![];
+        for &arg in args {
+            values.push(self.expr(arg)?);
+        }
+        let (function, env) =
+            match function {
+                Some(function) => function,
+                None => return self.builtin(&name, values, pos),
+            };
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(Flow::Error(pos, format!("more than {} nested calls", MAX_CALL_DEPTH)));
+        }
+        let mut env = env;
+        for (param, value) in function.node.params.iter().zip(values) {
+            env = env.with(Binding::Variable(param.node.name, RefCell::new(value)));
+        }
+        self.frames.push(Frame {
+            call_pos: pos,
+            env,
+            function: name,
+        });
+        let result = self.expr(function.node.body);
+        let callee = self.frames.pop().expect("frame");
+        let value =
+            if function.node.result.is_some() {
+                result?
+            }
+            else {
+                result?;
+                Value::Unit
+            };
+        self.observer.returned(&callee, &value, &self.frames)?;
+        Ok(value)
+    }
+
+    fn declaration(&mut self, declaration: &'a DeclarationWithPos) -> Result<(), Flow> {
+        match declaration.node {
+            Declaration::ClassDeclaration { .. } => return Err(unsupported("classes", declaration.pos)),
+            Declaration::Function(ref functions) => {
+                let env = self.env().with(Binding::Functions(functions));
+                self.set_env(env);
+            },
+            Declaration::Type(_) => (),
+            Declaration::VariableDeclaration { init, name, .. } => {
+                let value = self.expr(init)?;
+                let env = self.env().with(Binding::Variable(name, RefCell::new(value)));
+                self.set_env(env);
+            },
+        }
+        Ok(())
+    }
+
+    fn env(&self) -> &Env<'a> {
+        &self.frames.last().expect("frame").env
+    }
+
+    fn set_env(&mut self, env: Env<'a>) {
+        self.frames.last_mut().expect("frame").env = env;
+    }
+
+    fn expr(&mut self, expr: ExprId) -> Result<Value, Flow> {
+        let arena = self.arena;
+        let pos = arena[expr].pos;
+        if !pos.is_dummy() {
+            self.observer.evaluate(pos, &self.frames)?;
+        }
+        let value =
+            match arena[expr].node {
+                Expr::AddressOf { .. } | Expr::Deref { .. } => return Err(unsupported("pointers", pos)),
+                Expr::Array { init, size, .. } => {
+                    let size = self.expr(size)?.int();
+                    let size = usize::try_from(size)
+                        .map_err(|_| Flow::Error(pos, format!("array size {} is negative", size)))?;
+                    // Like in the compiled programs, the initial value is evaluated for each element.
+                    let mut elements = Vec::with_capacity(size);
+                    for _ in 0..size {
+                        elements.push(self.expr(init)?);
+                    }
+                    Value::Array(Rc::new(RefCell::new(elements)))
+                },
+                Expr::Assign { expr, var } => {
+                    self.assign(var, expr)?;
+                    Value::Unit
+                },
+                Expr::Break => return Err(Flow::Break),
+                Expr::Call { ref args, function } => self.call(function, args, pos)?,
+                Expr::Case { ref arms, else_, subject } => {
+                    let subject = self.expr(subject)?.string();
+                    match arms.iter().find(|arm| *arm.node.label == *subject) {
+                        Some(arm) => self.expr(arm.node.body)?,
+                        None =>
+                            match else_ {
+                                Some(else_) => self.expr(else_)?,
+                                None => Value::Unit,
+                            },
+                    }
+                },
+                Expr::Field { ref ident, this } =>
+                    self.expr(this)?.find_field(|field| field == ident.node).map_err(|message| Flow::Error(pos, message))?,
+                Expr::If { else_, test, then } => {
+                    let test = self.expr(test)?.int();
+                    match else_ {
+                        Some(else_) =>
+                            if test != 0 {
+                                self.expr(then)?
+                            }
+                            else {
+                                self.expr(else_)?
+                            },
+                        None => {
+                            if test != 0 {
+                                self.expr(then)?;
+                            }
+                            Value::Unit
+                        },
+                    }
+                },
+                Expr::Int { value } => Value::Int(value),
+                Expr::Let { body, ref declarations } => {
+                    let env = self.env().clone();
+                    let result = declarations.iter()
+                        .try_for_each(|declaration| self.declaration(declaration))
+                        .and_then(|()| self.expr(body));
+                    // The environment is restored when a break leaves the let too.
+                    self.set_env(env);
+                    result?
+                },
+                Expr::MethodCall { .. } | Expr::New { .. } => return Err(unsupported("classes", pos)),
+                Expr::Nil => Value::Nil,
+                Expr::Oper { left, ref oper, right } => {
+                    let left = self.expr(left)?;
+                    let right = self.expr(right)?;
+                    operation(oper.node, &left, &right).map_err(|message| Flow::Error(pos, message))?
+                },
+                Expr::Record { ref fields, ref typ } => {
+                    let mut values = vec![];
+                    for field in fields {
+                        values.push((field.node.ident, self.expr(field.node.expr)?));
+                    }
+                    Value::Record(Rc::new(Record {
+                        fields: RefCell::new(values),
+                        typ: typ.node,
+                    }))
+                },
+                Expr::Sequence(ref exprs) => {
+                    let mut value = Value::Unit;
+                    for &expr in exprs {
+                        value = self.expr(expr)?;
+                    }
+                    value
+                },
+                Expr::Str { ref value } => Value::Str(value.as_str().into()),
+                Expr::Subscript { expr, this } => {
+                    let array = self.expr(this)?;
+                    let index = self.expr(expr)?.int();
+                    array.element(index).map_err(|message| Flow::Error(pos, message))?
+                },
+                Expr::Variable(ref name) => self.env().variable(name.node).expect("variable").borrow().clone(),
+                Expr::While { body, test } => {
+                    while self.expr(test)?.int() != 0 {
+                        match self.expr(body) {
+                            Ok(_) => (),
+                            Err(Flow::Break) => break,
+                            Err(flow) => return Err(flow),
+                        }
+                    }
+                    Value::Unit
+                },
+            };
+        Ok(value)
+    }
+
+    fn assign(&mut self, var: ExprId, expr: ExprId) -> Result<(), Flow> {
+        let arena = self.arena;
+        let pos = arena[var].pos;
+        match arena[var].node {
+            Expr::Field { ref ident, this } => {
+                let record = self.expr(this)?;
+                let value = self.expr(expr)?;
+                match record {
+                    Value::Record(ref record) => {
+                        let mut fields = record.fields.borrow_mut();
+                        let field = fields.iter_mut().find(|field| field.0 == ident.node).expect("field");
+                        field.1 = value;
+                    },
+                    _ => return Err(Flow::Error(pos, "nil record dereference".to_string())),
+                }
+            },
+            Expr::Subscript { expr: index, this } => {
+                let array = self.expr(this)?;
+                let index = self.expr(index)?.int();
+                let value = self.expr(expr)?;
+                // Check the bounds.
+                array.element(index).map_err(|message| Flow::Error(pos, message))?;
+                if let Value::Array(ref elements) = array {
+                    elements.borrow_mut()[index as usize] = value;
+                }
+            },
+            Expr::Variable(ref name) => {
+                let value = self.expr(expr)?;
+                *self.env().variable(name.node).expect("variable").borrow_mut() = value;
+            },
+            _ => return Err(unsupported("pointers", pos)),
+        }
+        Ok(())
+    }
+}
+
+fn operation(oper: Operator, left: &Value, right: &Value) -> Result<Value, String> {
+    let value =
+        if let (&Value::Int(left), &Value::Int(right)) = (left, right) {
+            match oper {
+                Operator::And => left & right,
+                Operator::Divide => {
+                    if right == 0 {
+                        return Err("division by zero".to_string());
+                    }
+                    left.checked_div(right).ok_or_else(|| "division overflow".to_string())?
+                },
+                Operator::Equal => (left == right) as i64,
+                Operator::Ge => (left >= right) as i64,
+                Operator::Gt => (left > right) as i64,
+                Operator::Le => (left <= right) as i64,
+                Operator::Lt => (left < right) as i64,
+                Operator::Minus => left.wrapping_sub(right),
+                Operator::Neq => (left != right) as i64,
+                Operator::Or => left | right,
+                Operator::Plus => left.wrapping_add(right),
+                Operator::Times => left.wrapping_mul(right),
+            }
+        }
+        else {
+            match oper {
+                Operator::Equal => (left == right) as i64,
+                Operator::Neq => (left != right) as i64,
+                // Only the strings are ordered, besides the integers.
+                Operator::Ge => (left.string() >= right.string()) as i64,
+                Operator::Gt => (left.string() > right.string()) as i64,
+                Operator::Le => (left.string() <= right.string()) as i64,
+                Operator::Lt => (left.string() < right.string()) as i64,
+                _ => panic!("{:?} expects integers", oper),
+            }
+        };
+    Ok(Value::Int(value))
+}
+
+fn unsupported(feature: &str, pos: Pos) -> Flow {
+    Flow::Error(pos, format!("{} are not supported by --debug", feature))
+}
+
+fn unsupported_function(name: &str, pos: Pos) -> Flow {
+    Flow::Error(pos, format!("function `{}` is not supported by --debug", name))
+}
+
+/// Digits of `num` in `base`, like the runtime writes them.
+fn format_int(num: i64, base: u64) -> String {
+    let mut magnitude = num.unsigned_abs();
+    let mut digits = vec![];
+    loop {
+        let digit = (magnitude % base) as u32;
+        digits.push(char::from_digit(digit, base as u32).expect("digit"));
+        magnitude /= base;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if num < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
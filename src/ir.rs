@@ -36,6 +36,22 @@ pub enum Exp {
         right: Box<Exp>,
     },
     Mem(Box<Exp>),
+    /// Byte at the address, zero-extended when it is read and truncated when it is written.
+    MemByte(Box<Exp>),
+    /// 1 if the comparison is true, 0 otherwise.
+    Compare {
+        op: RelationalOp,
+        left: Box<Exp>,
+        right: Box<Exp>,
+    },
+    /// `if_true` if the comparison is true, `if_false` otherwise. Both are evaluated.
+    Select {
+        op: RelationalOp,
+        left: Box<Exp>,
+        right: Box<Exp>,
+        if_true: Box<Exp>,
+        if_false: Box<Exp>,
+    },
     Call {
         arguments: Vec<Exp>,
         collectable_return_type: bool,
@@ -45,12 +61,223 @@ pub enum Exp {
     ExpSequence(Box<Statement>, Box<Exp>),
 }
 
+impl Exp {
+    /// Number of nodes of the expression tree.
+    pub fn node_count(&self) -> usize {
+        match *self {
+            Exp::Const(_) | Exp::Error | Exp::Name(_) | Exp::Temp(_) => 1,
+            Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } =>
+                1 + left.node_count() + right.node_count(),
+            Exp::Mem(ref expr) | Exp::MemByte(ref expr) => 1 + expr.node_count(),
+            Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } =>
+                1 + left.node_count() + right.node_count() + if_true.node_count() + if_false.node_count(),
+            Exp::Call { ref arguments, ref function_expr, .. } =>
+                1 + function_expr.node_count() + arguments.iter().map(Exp::node_count).sum::<usize>(),
+            Exp::ExpSequence(ref statement, ref expr) => 1 + statement.node_count() + expr.node_count(),
+        }
+    }
+
+    /// Temporaries used by the expression, in the order they first appear.
+    pub fn temps(&self) -> Vec<Temp> {
+        let mut temps = vec![];
+        self.collect_temps(&mut temps);
+        temps
+    }
+
+    fn collect_temps(&self, temps: &mut Vec<Temp>) {
+        match *self {
+            Exp::Const(_) | Exp::Error | Exp::Name(_) => (),
+            Exp::Temp(temp) => {
+                if !temps.contains(&temp) {
+                    temps.push(temp);
+                }
+            },
+            Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } => {
+                left.collect_temps(temps);
+                right.collect_temps(temps);
+            },
+            Exp::Mem(ref expr) | Exp::MemByte(ref expr) => expr.collect_temps(temps),
+            Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } => {
+                left.collect_temps(temps);
+                right.collect_temps(temps);
+                if_true.collect_temps(temps);
+                if_false.collect_temps(temps);
+            },
+            Exp::Call { ref arguments, ref function_expr, .. } => {
+                function_expr.collect_temps(temps);
+                for argument in arguments {
+                    argument.collect_temps(temps);
+                }
+            },
+            Exp::ExpSequence(ref statement, ref expr) => {
+                statement.collect_temps(temps);
+                expr.collect_temps(temps);
+            },
+        }
+    }
+
+    fn collect_constants(&self, constants: &mut Vec<i64>) {
+        match *self {
+            Exp::Const(value) => constants.push(value),
+            Exp::Error | Exp::Name(_) | Exp::Temp(_) => (),
+            Exp::BinOp { ref left, ref right, .. } | Exp::Compare { ref left, ref right, .. } => {
+                left.collect_constants(constants);
+                right.collect_constants(constants);
+            },
+            Exp::Mem(ref expr) | Exp::MemByte(ref expr) => expr.collect_constants(constants),
+            Exp::Select { ref left, ref right, ref if_true, ref if_false, .. } => {
+                left.collect_constants(constants);
+                right.collect_constants(constants);
+                if_true.collect_constants(constants);
+                if_false.collect_constants(constants);
+            },
+            Exp::Call { ref arguments, ref function_expr, .. } => {
+                function_expr.collect_constants(constants);
+                for argument in arguments {
+                    argument.collect_constants(constants);
+                }
+            },
+            Exp::ExpSequence(ref statement, ref expr) => {
+                statement.collect_constants(constants);
+                expr.collect_constants(constants);
+            },
+        }
+    }
+
+    fn map_constants<F: FnMut(i64) -> Exp>(self, function: &mut F) -> Exp {
+        match self {
+            Exp::Const(value) => function(value),
+            Exp::Error | Exp::Name(_) | Exp::Temp(_) => self,
+            Exp::BinOp { op, left, right } => Exp::BinOp {
+                op,
+                left: Box::new(left.map_constants(function)),
+                right: Box::new(right.map_constants(function)),
+            },
+            Exp::Compare { op, left, right } => Exp::Compare {
+                op,
+                left: Box::new(left.map_constants(function)),
+                right: Box::new(right.map_constants(function)),
+            },
+            Exp::Mem(expr) => Exp::Mem(Box::new(expr.map_constants(function))),
+            Exp::MemByte(expr) => Exp::MemByte(Box::new(expr.map_constants(function))),
+            Exp::Select { op, left, right, if_true, if_false } => Exp::Select {
+                op,
+                left: Box::new(left.map_constants(function)),
+                right: Box::new(right.map_constants(function)),
+                if_true: Box::new(if_true.map_constants(function)),
+                if_false: Box::new(if_false.map_constants(function)),
+            },
+            Exp::Call { arguments, collectable_return_type, function_expr, return_label } => Exp::Call {
+                arguments: arguments.into_iter()
+                    .map(|argument| argument.map_constants(function))
+                    .collect(),
+                collectable_return_type,
+                function_expr: Box::new(function_expr.map_constants(function)),
+                return_label,
+            },
+            Exp::ExpSequence(statement, expr) =>
+                Exp::ExpSequence(Box::new(statement.map_constants(function)), Box::new(expr.map_constants(function))),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Statement {
     pub stack_var: Option<i64>,
     pub statement: _Statement,
 }
 
+impl Statement {
+    /// Number of nodes of the statement tree.
+    pub fn node_count(&self) -> usize {
+        match self.statement {
+            _Statement::Move(ref destination, ref source) => 1 + destination.node_count() + source.node_count(),
+            _Statement::Exp(ref expr) | _Statement::Jump(ref expr, _) => 1 + expr.node_count(),
+            _Statement::CondJump { ref left, ref right, .. } => 1 + left.node_count() + right.node_count(),
+            _Statement::Sequence(ref statement1, ref statement2) => 1 + statement1.node_count() + statement2.node_count(),
+            _Statement::Label(_) | _Statement::Position(_) => 1,
+        }
+    }
+
+    /// Temporaries used by the statement, in the order they first appear.
+    pub fn temps(&self) -> Vec<Temp> {
+        let mut temps = vec![];
+        self.collect_temps(&mut temps);
+        temps
+    }
+
+    fn collect_temps(&self, temps: &mut Vec<Temp>) {
+        match self.statement {
+            _Statement::Move(ref destination, ref source) => {
+                destination.collect_temps(temps);
+                source.collect_temps(temps);
+            },
+            _Statement::Exp(ref expr) | _Statement::Jump(ref expr, _) => expr.collect_temps(temps),
+            _Statement::CondJump { ref left, ref right, .. } => {
+                left.collect_temps(temps);
+                right.collect_temps(temps);
+            },
+            _Statement::Sequence(ref statement1, ref statement2) => {
+                statement1.collect_temps(temps);
+                statement2.collect_temps(temps);
+            },
+            _Statement::Label(_) | _Statement::Position(_) => (),
+        }
+    }
+
+    /// Values of the constants used by the statement, once per use.
+    pub fn constants(&self) -> Vec<i64> {
+        let mut constants = vec![];
+        self.collect_constants(&mut constants);
+        constants
+    }
+
+    fn collect_constants(&self, constants: &mut Vec<i64>) {
+        match self.statement {
+            _Statement::Move(ref destination, ref source) => {
+                destination.collect_constants(constants);
+                source.collect_constants(constants);
+            },
+            _Statement::Exp(ref expr) | _Statement::Jump(ref expr, _) => expr.collect_constants(constants),
+            _Statement::CondJump { ref left, ref right, .. } => {
+                left.collect_constants(constants);
+                right.collect_constants(constants);
+            },
+            _Statement::Sequence(ref statement1, ref statement2) => {
+                statement1.collect_constants(constants);
+                statement2.collect_constants(constants);
+            },
+            _Statement::Label(_) | _Statement::Position(_) => (),
+        }
+    }
+
+    /// Replace every constant of the statement by the expression returned by `function`.
+    pub fn map_constants<F: FnMut(i64) -> Exp>(self, function: &mut F) -> Statement {
+        let statement =
+            match self.statement {
+                _Statement::Move(destination, source) =>
+                    _Statement::Move(destination.map_constants(function), source.map_constants(function)),
+                _Statement::Exp(expr) => _Statement::Exp(expr.map_constants(function)),
+                _Statement::Jump(expr, labels) => _Statement::Jump(expr.map_constants(function), labels),
+                _Statement::CondJump { op, left, right, true_label, false_label } => _Statement::CondJump {
+                    op,
+                    left: left.map_constants(function),
+                    right: right.map_constants(function),
+                    true_label,
+                    false_label,
+                },
+                _Statement::Sequence(statement1, statement2) =>
+                    _Statement::Sequence(Box::new(statement1.map_constants(function)),
+                        Box::new(statement2.map_constants(function))),
+                statement @ (_Statement::Label(_) | _Statement::Position(_)) => statement,
+            };
+        Statement {
+            stack_var: self.stack_var,
+            statement,
+        }
+    }
+}
+
 impl From<_Statement> for Statement {
     fn from(statement: _Statement) -> Self {
         Self {
@@ -74,6 +301,8 @@ pub enum _Statement {
     },
     Sequence(Box<Statement>, Box<Statement>),
     Label(Label),
+    /// Source line of the next statements, which does not generate any code.
+    Position(u32),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -20,12 +20,15 @@
  */
 
 use std::char;
-use std::io::{Bytes, Read};
+use std::collections::VecDeque;
+use std::io::{self, Bytes, Read};
 use std::iter::Peekable;
+use std::mem;
 use std::result;
+use std::str;
 
 use error::num_text_size;
-use error::Error::{self, Eof, InvalidEscape, Msg, Unclosed, UnknownToken};
+use error::Error::{self, Eof, InvalidCharacter, InvalidEscape, InvalidUtf8, Msg, Unclosed, UnknownToken};
 use position::Pos;
 use symbol::Symbol;
 use token::{Tok, Token};
@@ -35,26 +38,54 @@ pub type Result<T> = result::Result<T, Error>;
 
 pub struct Lexer<R: Read> {
     bytes_iter: Peekable<Bytes<R>>,
+    // Errors of the comments and strings left open, which were closed to lex the rest of the file.
+    errors: Vec<Error>,
+    // Bytes lexed again before the next ones of the reader.
+    pending: VecDeque<io::Result<u8>>,
     pos: Pos,
+    // Bytes read since the start of the comment or string being lexed.
+    recording: Option<Vec<u8>>,
     saved_pos: Pos,
+    // Accept the Unicode letters and digits in identifiers, instead of only the ASCII ones.
+    unicode_idents: bool,
 }
 
 impl<R: Read> Lexer<R> {
     pub fn new(reader: R, filename: Symbol) -> Self {
         Lexer {
             bytes_iter: reader.bytes().peekable(),
+            errors: vec![],
+            pending: VecDeque::new(),
             pos: Pos::new(1, 1, 0, filename, 0),
+            recording: None,
             saved_pos: Pos::new(1, 1, 0, filename, 0),
+            unicode_idents: false,
         }
     }
 
+    pub fn set_unicode_idents(&mut self, unicode_idents: bool) {
+        self.unicode_idents = unicode_idents;
+    }
+
+    /// The errors of the comments and strings left open until the end of the file, which the lexer
+    /// closed at a plausible place to lex the rest of the file.
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        mem::take(&mut self.errors)
+    }
+
     fn advance(&mut self) -> Result<()> {
-        match self.bytes_iter.next() {
+        let byte = self.next_byte();
+        if let (Some(&Ok(byte)), Some(recording)) = (byte.as_ref(), self.recording.as_mut()) {
+            recording.push(byte);
+        }
+        match byte {
             Some(Ok(b'\n')) => {
                 self.pos.line += 1;
                 self.pos.column = 1;
                 self.pos.byte += 1;
             },
+            // The continuation bytes of a UTF-8 character do not start a new column.
+            Some(Ok(byte)) if byte & 0xC0 == 0x80 => self.pos.byte += 1,
             Some(Err(error)) => return Err(error.into()),
             None => return Err(Eof),
             _ => {
@@ -69,7 +100,8 @@ impl<R: Read> Lexer<R> {
         self.two_char_token(vec![('=', ColonEqual)], Colon)
     }
 
-    fn comment(&mut self) -> Result<()> {
+    /// Skip a comment, whose nested comments are skipped too unless `nested` is false.
+    fn comment(&mut self, nested: bool) -> Result<()> {
         let mut depth = 1;
         loop {
             self.advance()?;
@@ -78,7 +110,7 @@ impl<R: Read> Lexer<R> {
             if ch == '/' {
                 self.advance()?;
                 let ch = self.current_char()?;
-                if ch == '*' {
+                if ch == '*' && nested {
                     depth += 1;
                 }
             }
@@ -98,12 +130,20 @@ impl<R: Read> Lexer<R> {
         Ok(())
     }
 
+    /// Skip the rest of the line of a comment left open.
+    fn comment_line(&mut self) -> Result<()> {
+        while self.current_char()? != '\n' {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
     fn current_char(&mut self) -> Result<char> {
-        if let Some(&Ok(byte)) = self.bytes_iter.peek() {
+        if let Some(&Ok(byte)) = self.peek_byte() {
             return Ok(byte as char);
         }
 
-        match self.bytes_iter.next() {
+        match self.next_byte() {
             Some(Ok(_)) => unreachable!(),
             Some(Err(error)) => Err(error.into()),
             None => Err(Eof),
@@ -165,12 +205,34 @@ impl<R: Read> Lexer<R> {
     }
 
     fn identifier(&mut self) -> Result<Token> {
-        let ident = self.take_while(|ch| ch.is_alphanumeric() || ch == '_')?;
-        let len = ident.len();
+        self.save_start();
+        let mut ident = String::new();
+        let mut len = 0;
+        loop {
+            let ch = self.current_char()?;
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+                ident.push(ch);
+                self.advance()?;
+            }
+            else if self.unicode_idents && !ch.is_ascii() {
+                let pos = self.current_pos();
+                let ch = self.unicode_char()?;
+                let valid = if ident.is_empty() { ch.is_alphabetic() } else { ch.is_alphanumeric() };
+                if !valid {
+                    return Err(invalid_character(ch, false, pos));
+                }
+                ident.push(ch);
+            }
+            else {
+                break;
+            }
+            len += 1;
+        }
         let token =
             match ident.as_str() {
                 "array" => Array,
                 "break" => Break,
+                "case" => Case,
                 "class" => Class,
                 "do" => Do,
                 "else" => Else,
@@ -219,6 +281,28 @@ impl<R: Read> Lexer<R> {
         })
     }
 
+    fn next_byte(&mut self) -> Option<io::Result<u8>> {
+        match self.pending.pop_front() {
+            Some(byte) => Some(byte),
+            None => self.bytes_iter.next(),
+        }
+    }
+
+    fn peek_byte(&mut self) -> Option<&io::Result<u8>> {
+        if self.pending.is_empty() {
+            self.bytes_iter.peek()
+        }
+        else {
+            self.pending.front()
+        }
+    }
+
+    /// Go back to `pos`, to lex again the bytes read since then.
+    fn rewind(&mut self, bytes: &[u8], pos: Pos) {
+        self.pending = bytes.iter().map(|&byte| Ok(byte)).collect();
+        self.pos = pos;
+    }
+
     fn save_start(&mut self) {
         self.saved_pos = self.current_pos();
     }
@@ -255,20 +339,34 @@ impl<R: Read> Lexer<R> {
 
     fn slash_or_comment(&mut self) -> Result<Token> {
         self.save_start();
+        let mut pos = self.saved_pos;
+        pos.length = 2;
         self.advance()?;
         if self.current_char()? == '*' {
-            match self.comment() {
-                Err(Eof) => {
-                    let mut pos = self.saved_pos;
-                    pos.length = 2;
-                    return Err(Unclosed {
-                        pos,
-                        token: "comment",
-                    })
-                },
-                Err(error) => return Err(error),
-                _ => (),
+            let start = self.current_pos();
+            self.recording = Some(vec![]);
+            let mut result = self.comment(true);
+            let recorded = self.recording.take().unwrap_or_default();
+            if let Err(Eof) = result {
+                // A comment left open usually ends at the first `*/`, when its opening was mistaken for
+                // a nested comment, or else at the end of its first line.
+                self.rewind(&recorded, start);
+                result = self.comment(false);
+                if let Err(Eof) = result {
+                    self.rewind(&recorded, start);
+                    result = self.comment_line();
+                }
+                let error = Unclosed {
+                    pos,
+                    token: "comment",
+                };
+                match result {
+                    Ok(()) => self.errors.push(error),
+                    Err(Eof) => return Err(error),
+                    Err(_) => (),
+                }
             }
+            result?;
             self.token()
         }
         else {
@@ -277,45 +375,64 @@ impl<R: Read> Lexer<R> {
     }
 
     fn string(&mut self) -> Result<Token> {
-        let result = (|| {
-            self.save_start();
-            let mut string = String::new();
-            let start = self.current_pos().byte;
-            self.eat('"')?;
-            let mut ch = self.current_char()?;
-            while ch != '"' {
-                // Look for escaped character.
-                if ch == '\\' {
-                    let pos = self.current_pos();
-                    self.advance()?;
-                    if self.current_char()?.is_whitespace() {
-                        self.skip_until_slash()?;
-                    }
-                    else {
-                        string.push(self.escape_char(pos)?);
-                    }
+        let start = self.current_pos();
+        self.recording = Some(vec![]);
+        let result = self.string_literal(false);
+        let recorded = self.recording.take().unwrap_or_default();
+        if let Err(Eof) = result {
+            let mut pos = start;
+            pos.length = 1;
+            let error = Unclosed {
+                pos,
+                token: "string",
+            };
+            // A string left open usually ends at the end of its line.
+            self.rewind(&recorded, start);
+            return match self.string_literal(true) {
+                Ok(token) => {
+                    self.errors.push(error);
+                    Ok(token)
+                },
+                Err(Eof) => Err(error),
+                Err(error) => Err(error),
+            };
+        }
+        result
+    }
+
+    /// Lex a string, which ends at the end of the line too when `line` is true.
+    fn string_literal(&mut self, line: bool) -> Result<Token> {
+        self.save_start();
+        let start = self.current_pos();
+        let mut string = String::new();
+        self.eat('"')?;
+        let mut ch = self.current_char()?;
+        while ch != '"' && !(line && ch == '\n') {
+            // Look for escaped character.
+            if ch == '\\' {
+                let pos = self.current_pos();
+                self.advance()?;
+                if self.current_char()?.is_whitespace() {
+                    self.skip_until_slash()?;
                 }
                 else {
-                    string.push(ch);
-                    self.advance()?;
+                    string.push(self.escape_char(pos)?);
                 }
-                ch = self.current_char()?;
             }
+            else if !ch.is_ascii() {
+                string.push(self.unicode_char()?);
+            }
+            else {
+                string.push(ch);
+                self.advance()?;
+            }
+            ch = self.current_char()?;
+        }
+        if ch == '"' {
             self.eat('"')?;
-            let len = self.current_pos().byte - start;
-            self.make_token(Str(string), len as usize)
-        })();
-        match result {
-            Err(Eof) => {
-                let mut pos = self.saved_pos;
-                pos.length = 1;
-                Err(Unclosed {
-                    pos,
-                    token: "string",
-                })
-            },
-            _ => result,
         }
+        let len = self.current_pos().byte - start.byte;
+        self.make_token(Str(string), len as usize)
     }
 
     fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> Result<String> {
@@ -334,7 +451,7 @@ impl<R: Read> Lexer<R> {
 
     #[allow(clippy::cognitive_complexity)]
     pub fn token(&mut self) -> Result<Token> {
-        if let Some(&Ok(ch)) = self.bytes_iter.peek() {
+        if let Some(&Ok(ch)) = self.peek_byte() {
             return match ch {
                 b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.identifier(),
                 b'0'..=b'9' => self.integer(),
@@ -342,8 +459,9 @@ impl<R: Read> Lexer<R> {
                     self.advance()?;
                     self.token()
                 }
-                b'=' => self.simple_token(Equal),
+                b'=' => self.two_char_token(vec![('>', Arrow)], Equal),
                 b'&' => self.simple_token(Ampersand),
+                b'^' => self.simple_token(Caret),
                 b'|' => self.simple_token(Pipe),
                 b'.' => self.simple_token(Dot),
                 b',' => self.simple_token(Comma),
@@ -362,6 +480,12 @@ impl<R: Read> Lexer<R> {
                 b'<' => self.lesser_or_lesser_equal_or_not_equal(),
                 b'/' => self.slash_or_comment(),
                 b'"' => self.string(),
+                0x80..=0xFF if self.unicode_idents => self.identifier(),
+                0x80..=0xFF => {
+                    let pos = self.current_pos();
+                    let ch = self.unicode_char()?;
+                    Err(invalid_character(ch, ch.is_alphabetic(), pos))
+                },
                 _ => {
                     let mut pos = self.current_pos();
                     pos.length = 1;
@@ -372,7 +496,7 @@ impl<R: Read> Lexer<R> {
                 },
             };
         }
-        match self.bytes_iter.next() {
+        match self.next_byte() {
             Some(Ok(_)) => unreachable!(),
             Some(Err(error)) => Err(error.into()),
             None => {
@@ -386,11 +510,39 @@ impl<R: Read> Lexer<R> {
         }
     }
 
+    /// Decode the UTF-8 character starting at the current byte.
+    fn unicode_char(&mut self) -> Result<char> {
+        let mut pos = self.current_pos();
+        pos.length = 1;
+        let first_byte = self.current_char()? as u8;
+        let length =
+            match first_byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => return Err(InvalidUtf8 {
+                    byte: first_byte,
+                    pos,
+                }),
+            };
+        let mut bytes = vec![];
+        for _ in 0..length {
+            bytes.push(self.current_char()? as u8);
+            self.advance()?;
+        }
+        str::from_utf8(&bytes).ok()
+            .and_then(|string| string.chars().next())
+            .ok_or(InvalidUtf8 {
+                byte: first_byte,
+                pos,
+            })
+    }
+
     fn two_char_token(&mut self, tokens: Vec<(char, Tok)>, default: Tok) -> Result<Token> {
         self.save_start();
         self.advance()?;
         let token =
-            match self.bytes_iter.peek() {
+            match self.peek_byte() {
                 Some(&Ok(byte)) => {
                     let mut token = None;
                     let next_char = byte as char;
@@ -414,3 +566,12 @@ impl<R: Read> Lexer<R> {
         self.make_token(token, len)
     }
 }
+
+fn invalid_character(character: char, identifier: bool, mut pos: Pos) -> Error {
+    pos.length = 1;
+    InvalidCharacter {
+        character,
+        identifier,
+        pos,
+    }
+}
@@ -32,23 +32,70 @@
                       -lgcc_s --no-as-needed /usr/lib/crtn.o
  */
 
+mod bigint;
 mod collector;
 mod data_layout;
+mod signal;
+mod stack_trace;
+mod unwind;
 
 use std::ffi::CStr;
-//use std::process;
-use std::io::{Read, Write, stdin, stdout};
-use std::os::raw::c_char;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write, stdin, stdout};
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+use std::os::raw::{c_char, c_int, c_long, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::process;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::Duration;
 
+use bigint::BigInt;
 use collector::{Layout, GARBAGE_COLLECTOR};
-use data_layout::STRING_DATA_LAYOUT_SIZE;
+use data_layout::{ARRAY_DATA_LAYOUT_SIZE, RECORD_DATA_LAYOUT_SIZE, STRING_DATA_LAYOUT_SIZE, VERSION};
+use stack_trace::runtime_error;
 
+const CLOCK_MONOTONIC: c_int = 1;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+const MAP_FIXED: c_int = 0x10;
+const MAP_PRIVATE: c_int = 0x02;
+// Biggest array whose size in bytes, including its header, does not overflow.
+const MAX_ARRAY_LENGTH: i64 = i64::MAX / WORD_SIZE as i64 - ARRAY_DATA_LAYOUT_SIZE as i64;
+// Same for the arrays of bytes, whose elements take a single byte.
+const MAX_BYTE_ARRAY_LENGTH: i64 = i64::MAX - (ARRAY_DATA_LAYOUT_SIZE * WORD_SIZE) as i64;
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const SC_PAGESIZE: c_int = 30;
 const WORD_SIZE: usize = 8;
 
+#[repr(C)]
+struct Timespec {
+    seconds: i64,
+    nanoseconds: i64,
+}
+
+extern "C" {
+    fn clock_gettime(clock_id: c_int, time: *mut Timespec) -> c_int;
+    fn mmap(address: *mut c_void, length: usize, protection: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(address: *mut c_void, length: usize) -> c_int;
+    fn sysconf(name: c_int) -> c_long;
+}
+
 /*extern {
     fn main();
 }*/
 
+/// Run the Tiger code deferred until the runtime is entered at a safepoint.
+fn safepoint() {
+    signal::dispatch();
+    collector::run_finalizers();
+    collector::clear_stack();
+}
+
 #[no_mangle]
 extern fn ord(string: *const c_char) -> i64 {
     let cstring = unsafe { CStr::from_ptr(string_offset(string)) };
@@ -57,6 +104,7 @@ extern fn ord(string: *const c_char) -> i64 {
 
 #[no_mangle]
 extern fn chr(num: i64) -> *const c_char {
+    safepoint();
     let char = num as u8;
     let ptr = GARBAGE_COLLECTOR.with(|collector| {
         collector.borrow_mut().allocate(Layout::String(1))
@@ -73,6 +121,7 @@ extern fn chr(num: i64) -> *const c_char {
 
 #[no_mangle]
 extern fn getchar() -> *const c_char {
+    safepoint();
     let stdin = stdin();
     let char = stdin.bytes().next().expect("next char").expect("read stdin") as char;
 
@@ -96,21 +145,7 @@ extern fn concat(string1: *const c_char, string2: *const c_char) -> *const c_cha
     let mut string1 = cstring1.to_str().expect("to_str").to_string();
     let string2 = cstring2.to_str().expect("to_str").to_string();
     string1.push_str(&string2);
-
-    let length = string1.len();
-    let ptr = GARBAGE_COLLECTOR.with(|collector| {
-        collector.borrow_mut().allocate(Layout::String(length))
-    });
-    let string = ptr as *mut c_char;
-    unsafe {
-        let mut string_ptr = string_offset(string) as *mut c_char;
-        for byte in string1.as_bytes() {
-            *string_ptr = *byte as c_char;
-            string_ptr = string_ptr.offset(1);
-        }
-        *string_ptr = 0;
-    }
-    string
+    allocate_string(&string1)
 }
 
 #[no_mangle]
@@ -120,24 +155,127 @@ extern fn stringEqual(string1: *const c_char, string2: *const c_char) -> i64 {
     (cstring1 == cstring2) as i64
 }
 
+/// Sign of the lexicographic comparison of the strings.
+#[no_mangle]
+extern "C" fn stringCompare(string1: *const c_char, string2: *const c_char) -> i64 {
+    let cstring1 = unsafe { CStr::from_ptr(string_offset(string1)) };
+    let cstring2 = unsafe { CStr::from_ptr(string_offset(string2)) };
+    cstring1.cmp(cstring2) as i64
+}
+
+/// Index of the string in the sorted table of string literals of a case expression, or -1 if it is
+/// not there.
+#[no_mangle]
+extern "C" fn stringSwitch(table: *const *const c_char, count: i64, string: *const c_char) -> i64 {
+    let string = unsafe { CStr::from_ptr(string_offset(string)) };
+    let labels = unsafe { slice::from_raw_parts(table, count as usize) };
+    labels.binary_search_by(|&label| unsafe { CStr::from_ptr(string_offset(label)) }.cmp(string))
+        .map_or(-1, |index| index as i64)
+}
+
+/// Stop the program if one of its object files was compiled for other data layouts or tables than
+/// those of this runtime, and record the frame of main for the conservative collector. Called first
+/// by the main function.
+#[no_mangle]
+extern "C" fn checkVersions() {
+    for module in collector::modules() {
+        if module.version != VERSION {
+            eprintln!("object file compiled for runtime version {:x}, but this runtime is version {:x}: \
+                link the program with the libruntime.a of the compiler that built it", module.version, VERSION);
+            process::exit(1);
+        }
+    }
+    collector::record_stack_base();
+}
+
+#[no_mangle]
+extern fn allocClass(data_layout: *const c_char, position: *const c_char) -> i64 {
+    safepoint();
+    let position = unsafe { CStr::from_ptr(string_offset(position)) };
+    GARBAGE_COLLECTOR.with(|collector| {
+        collector.borrow_mut().allocate_at(Layout::Class(data_layout), position)
+    })
+}
+
+#[no_mangle]
+extern "C" fn divisionOverflow(position: *const c_char) {
+    let position = unsafe { CStr::from_ptr(string_offset(position)) };
+    runtime_error(&format!("{}: division overflow", position.to_string_lossy()));
+}
+
+#[no_mangle]
+extern fn allocRecord(data_layout: *const c_char, position: *const c_char) -> i64 {
+    safepoint();
+    let position = unsafe { CStr::from_ptr(string_offset(position)) };
+    GARBAGE_COLLECTOR.with(|collector| {
+        collector.borrow_mut().allocate_at(Layout::Record(data_layout), position)
+    })
+}
+
 #[no_mangle]
-extern fn allocClass(data_layout: *const c_char) -> i64 {
+extern fn initArray(length: i64, is_pointer: i64, position: *const c_char) -> i64 {
+    safepoint();
+    let position = unsafe { CStr::from_ptr(string_offset(position)) };
+    check_array_length(length, MAX_ARRAY_LENGTH, position);
     GARBAGE_COLLECTOR.with(|collector| {
-        collector.borrow_mut().allocate(Layout::Class(data_layout))
+        collector.borrow_mut().allocate_at(Layout::Array(length as usize, is_pointer != 0), position)
     })
 }
 
+/// Allocate a record whose fields are copied from `template`, a read-only table of their initial
+/// values.
+#[no_mangle]
+extern "C" fn cloneRecord(data_layout: *const c_char, template: *const u8, position: *const c_char) -> i64 {
+    let record = allocRecord(data_layout, position);
+    let field_count = unsafe { CStr::from_ptr(string_offset(data_layout)) }.to_bytes().len();
+    copy_template(record, RECORD_DATA_LAYOUT_SIZE, template, field_count);
+    record
+}
+
+/// Allocate an array whose elements are copied from `template`, which holds `length` words.
 #[no_mangle]
-extern fn allocRecord(data_layout: *const c_char) -> i64 {
+extern "C" fn cloneArray(length: i64, is_pointer: i64, template: *const u8, position: *const c_char) -> i64 {
+    let array = initArray(length, is_pointer, position);
+    copy_template(array, ARRAY_DATA_LAYOUT_SIZE, template, length as usize);
+    array
+}
+
+/// Copy the `count` words of `template` after the header of `object`, which can be unaligned.
+fn copy_template(object: i64, header_size: usize, template: *const u8, count: usize) {
+    unsafe {
+        let fields = (object as *mut u8).add(header_size * WORD_SIZE);
+        ptr::copy_nonoverlapping(template, fields, count * WORD_SIZE);
+    }
+}
+
+#[no_mangle]
+extern "C" fn initByteArray(length: i64, position: *const c_char) -> i64 {
+    safepoint();
+    let position = unsafe { CStr::from_ptr(string_offset(position)) };
+    check_array_length(length, MAX_BYTE_ARRAY_LENGTH, position);
+    GARBAGE_COLLECTOR.with(|collector| {
+        collector.borrow_mut().allocate_at(Layout::ByteArray(length as usize), position)
+    })
+}
+
+fn check_array_length(length: i64, max_length: i64, position: &CStr) {
+    if !(0..=max_length).contains(&length) {
+        let reason = if length < 0 { "negative" } else { "too big" };
+        runtime_error(&format!("{}: array size {} is {}", position.to_string_lossy(), length, reason));
+    }
+}
+
+#[no_mangle]
+extern "C" fn weak(pointer: i64) -> i64 {
     GARBAGE_COLLECTOR.with(|collector| {
-        collector.borrow_mut().allocate(Layout::Record(data_layout))
+        collector.borrow_mut().weak_reference(pointer as usize) as i64
     })
 }
 
 #[no_mangle]
-extern fn initArray(length: usize, is_pointer: i64) -> i64 {
+extern "C" fn get(reference: i64) -> i64 {
     GARBAGE_COLLECTOR.with(|collector| {
-        collector.borrow_mut().allocate(Layout::Array(length, is_pointer != 0))
+        collector.borrow().weak_target(reference as usize) as i64
     })
 }
 
@@ -152,9 +290,263 @@ extern fn print(string: *const c_char) {
 
 #[no_mangle]
 extern fn printi(num: i32) {
+    safepoint();
     println!("{}", num);
 }
 
+/// Print `num` in hexadecimal, without a newline.
+#[no_mangle]
+extern "C" fn print_hex(num: i64) {
+    print!("{}", format_int(num, 16));
+    let _ = stdout().flush();
+}
+
+/// Print `num` right-aligned in `width` columns, without a newline.
+#[no_mangle]
+extern "C" fn print_padded(num: i64, width: i64) {
+    print!("{:>width$}", num, width = width.max(0) as usize);
+    let _ = stdout().flush();
+}
+
+/// Digits of `num` in `base`, between 2 and 36, with a minus sign when it is negative.
+#[no_mangle]
+extern "C" fn int_to_string(num: i64, base: i64) -> *const c_char {
+    safepoint();
+    if !(2..=36).contains(&base) {
+        runtime_error(&format!("int_to_string: base {} is not between 2 and 36", base));
+    }
+    allocate_string(&format_int(num, base as u64))
+}
+
+fn format_int(num: i64, base: u64) -> String {
+    let mut magnitude = num.unsigned_abs();
+    let mut digits = vec![];
+    loop {
+        let digit = (magnitude % base) as u32;
+        digits.push(char::from_digit(digit, base as u32).expect("digit"));
+        magnitude /= base;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if num < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+#[no_mangle]
+extern fn on_signal(signal: i64, function: usize, static_link: usize) {
+    signal::register(signal, function, static_link);
+}
+
+/// Milliseconds elapsed on a monotonic clock since an arbitrary point in time.
+#[no_mangle]
+extern fn clock_ms() -> i64 {
+    safepoint();
+    let mut time = Timespec {
+        nanoseconds: 0,
+        seconds: 0,
+    };
+    if unsafe { clock_gettime(CLOCK_MONOTONIC, &mut time) } != 0 {
+        panic!("cannot read the monotonic clock");
+    }
+    time.seconds * 1000 + time.nanoseconds / 1_000_000
+}
+
+#[no_mangle]
+extern fn sleep_ms(milliseconds: i64) {
+    safepoint();
+    if milliseconds > 0 {
+        thread::sleep(Duration::from_millis(milliseconds as u64));
+    }
+    safepoint();
+}
+
+/// Copy the characters of an array of chars in a new string.
+#[no_mangle]
+extern "C" fn chars_to_string(array: i64) -> *const c_char {
+    safepoint();
+    // NOTE: the characters are copied before the allocation, which can move the array.
+    let chars = array_bytes(array).to_vec();
+    allocate_bytes(&chars)
+}
+
+/// Copy the characters of `string` at the start of an array of chars, and return how many fit.
+#[no_mangle]
+extern "C" fn string_to_chars(string: *const c_char, array: i64) -> i64 {
+    let string = unsafe { CStr::from_ptr(string_offset(string)) }.to_bytes();
+    let chars = array_bytes(array);
+    let count = string.len().min(chars.len());
+    chars[..count].copy_from_slice(&string[..count]);
+    count as i64
+}
+
+/// Map the file at `path` in an array of bytes without copying it: the header of the array is at
+/// the end of a page placed just before the mapping of the file. The array lives outside the heap,
+/// so the collector neither scans nor moves it, and it stays mapped until `munmap` is called.
+/// The writes to the array are not written back to the file.
+#[no_mangle]
+extern "C" fn mmap_file(path: *const c_char) -> i64 {
+    safepoint();
+    let path = unsafe { CStr::from_ptr(string_offset(path)) }.to_string_lossy().into_owned();
+    let fail = |error: &dyn Display| -> ! {
+        runtime_error(&format!("{}: cannot map file: {}", path, error))
+    };
+    let file = File::open(&path).unwrap_or_else(|error| fail(&error));
+    let length = file.metadata().unwrap_or_else(|error| fail(&error)).len() as usize;
+    let page_size = unsafe { sysconf(SC_PAGESIZE) } as usize;
+    unsafe {
+        let region = mmap(ptr::null_mut(), page_size + length, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+        if region == MAP_FAILED {
+            fail(&io::Error::last_os_error());
+        }
+        let elements = (region as usize + page_size) as *mut c_void;
+        // NOTE: an empty file cannot be mapped.
+        if length > 0 && mmap(elements, length, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_FIXED, file.as_raw_fd(), 0) == MAP_FAILED {
+            fail(&io::Error::last_os_error());
+        }
+        let array = elements as usize - ARRAY_DATA_LAYOUT_SIZE * WORD_SIZE;
+        Layout::ByteArray(length).write_repr(array as *mut usize);
+        array as i64
+    }
+}
+
+/// Unmap an array returned by `mmap_file`, which must not be used afterwards.
+#[no_mangle]
+extern "C" fn munmap_file(array: i64) {
+    let page_size = unsafe { sysconf(SC_PAGESIZE) } as usize;
+    let elements = array as usize + ARRAY_DATA_LAYOUT_SIZE * WORD_SIZE;
+    let in_heap = GARBAGE_COLLECTOR.with(|collector| collector.borrow().in_heap(array as usize));
+    if in_heap || elements & (page_size - 1) != 0 {
+        runtime_error("munmap: the array was not returned by mmap_file");
+    }
+    let length = array_bytes(array).len();
+    if unsafe { munmap((elements - page_size) as *mut c_void, page_size + length) } != 0 {
+        runtime_error(&format!("munmap: {}", io::Error::last_os_error()));
+    }
+}
+
+#[no_mangle]
+extern "C" fn bytes_length(array: i64) -> i64 {
+    array_bytes(array).len() as i64
+}
+
+/// Open a TCP connection to `host` on `port`. A socket is the file descriptor of the connection,
+/// which stays open until `sock_close` is called.
+#[no_mangle]
+extern "C" fn tcp_connect(host: *const c_char, port: i64) -> i64 {
+    safepoint();
+    let host = unsafe { CStr::from_ptr(string_offset(host)) }.to_string_lossy().into_owned();
+    if port < 0 || port > u16::MAX as i64 {
+        runtime_error(&format!("{}:{}: invalid port", host, port));
+    }
+    match TcpStream::connect((host.as_str(), port as u16)) {
+        Ok(stream) => stream.into_raw_fd() as i64,
+        Err(error) => runtime_error(&format!("{}:{}: cannot connect: {}", host, port, error)),
+    }
+}
+
+/// Send the whole `string` on the socket and return the number of bytes sent.
+#[no_mangle]
+extern "C" fn sock_send(socket: i64, string: *const c_char) -> i64 {
+    safepoint();
+    let bytes = unsafe { CStr::from_ptr(string_offset(string)) }.to_bytes();
+    if let Err(error) = socket_stream(socket, "sock_send").write_all(bytes) {
+        runtime_error(&format!("sock_send: {}", error));
+    }
+    bytes.len() as i64
+}
+
+/// Receive at most `max` bytes from the socket, blocking until some are available. The empty
+/// string is returned once the peer closed the connection.
+#[no_mangle]
+extern "C" fn sock_recv(socket: i64, max: i64) -> *const c_char {
+    safepoint();
+    if max <= 0 {
+        runtime_error(&format!("sock_recv: the maximum size must be positive, but is {}", max));
+    }
+    let mut buffer = vec![0; max as usize];
+    match socket_stream(socket, "sock_recv").read(&mut buffer) {
+        Ok(size) => allocate_bytes(&buffer[..size]),
+        Err(error) => runtime_error(&format!("sock_recv: {}", error)),
+    }
+}
+
+#[no_mangle]
+extern "C" fn sock_close(socket: i64) {
+    let stream = socket_stream(socket, "sock_close");
+    drop(ManuallyDrop::into_inner(stream));
+}
+
+/// Connection of a socket returned by `tcp_connect`, which is not closed when dropped.
+fn socket_stream(socket: i64, function: &str) -> ManuallyDrop<TcpStream> {
+    if socket < 0 || socket > c_int::MAX as i64 {
+        runtime_error(&format!("{}: invalid socket", function));
+    }
+    ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(socket as RawFd) })
+}
+
+/// Elements of an array of bytes, whose header holds their count.
+fn array_bytes(array: i64) -> &'static mut [u8] {
+    let ptr = array as *mut usize;
+    unsafe {
+        let length = ptr::read_unaligned(ptr.offset(1));
+        slice::from_raw_parts_mut(ptr.add(ARRAY_DATA_LAYOUT_SIZE) as *mut u8, length)
+    }
+}
+
+#[no_mangle]
+extern fn big_from_int(num: i64) -> i64 {
+    BigInt::from(num).allocate()
+}
+
+#[no_mangle]
+extern fn big_add(left: i64, right: i64) -> i64 {
+    (&BigInt::read(left) + &BigInt::read(right)).allocate()
+}
+
+#[no_mangle]
+extern fn big_sub(left: i64, right: i64) -> i64 {
+    (&BigInt::read(left) - &BigInt::read(right)).allocate()
+}
+
+#[no_mangle]
+extern fn big_mul(left: i64, right: i64) -> i64 {
+    (&BigInt::read(left) * &BigInt::read(right)).allocate()
+}
+
+#[no_mangle]
+extern fn big_cmp(left: i64, right: i64) -> i64 {
+    BigInt::read(left).cmp(&BigInt::read(right)) as i64
+}
+
+#[no_mangle]
+extern fn big_to_string(num: i64) -> *const c_char {
+    allocate_string(&BigInt::read(num).to_string())
+}
+
+/// Copy `string` in a new string managed by the garbage collector.
+fn allocate_string(string: &str) -> *const c_char {
+    allocate_bytes(string.as_bytes())
+}
+
+fn allocate_bytes(bytes: &[u8]) -> *const c_char {
+    let ptr = GARBAGE_COLLECTOR.with(|collector| {
+        collector.borrow_mut().allocate(Layout::String(bytes.len()))
+    });
+    let result = ptr as *mut c_char;
+    unsafe {
+        let mut string_ptr = string_offset(result) as *mut c_char;
+        for byte in bytes {
+            *string_ptr = *byte as c_char;
+            string_ptr = string_ptr.offset(1);
+        }
+        *string_ptr = 0;
+    }
+    result
+}
+
 // Get the pointer where the string starts, i.e. after the data layout.
 fn string_offset(ptr: *const c_char) -> *const c_char {
     let ptr = ptr as *const usize;
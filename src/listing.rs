@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use asm::{Instruction, Subroutine};
+use frame::Frame;
+
+/// Listing file interleaving the lines of the source file with the instructions generated for them.
+pub struct Listing {
+    current_line: Option<u32>,
+    file: BufWriter<File>,
+    source_lines: Vec<String>,
+}
+
+impl Listing {
+    pub fn new(source_path: &Path, listing_path: &Path) -> io::Result<Self> {
+        let source_lines = BufReader::new(File::open(source_path)?).lines()
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            current_line: None,
+            file: BufWriter::new(File::create(listing_path)?),
+            source_lines,
+        })
+    }
+
+    pub fn write_subroutine<F: Frame>(&mut self, subroutine: &Subroutine) -> io::Result<()> {
+        self.current_line = None;
+        writeln!(self.file, "{}", subroutine.prolog)?;
+        for instruction in &subroutine.body {
+            if let Instruction::Position { line } = *instruction {
+                // The instructions of a line are not always contiguous, so the line is shown again
+                // every time the code comes back to it.
+                if self.current_line != Some(line) {
+                    self.current_line = Some(line);
+                    let source_line = self.source_lines.get(line as usize - 1).map(String::as_str).unwrap_or("");
+                    writeln!(self.file, "; {:>4} | {}", line, source_line)?;
+                }
+            }
+            else {
+                let instruction = instruction.to_string::<F>();
+                if !instruction.is_empty() {
+                    writeln!(self.file, "    {}", instruction)?;
+                }
+            }
+        }
+        writeln!(self.file, "    {}", subroutine.epilog)?;
+        writeln!(self.file)
+    }
+}
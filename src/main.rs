@@ -36,231 +36,1672 @@
 #![deny(clippy::pattern_type_mismatch)]
 #![feature(box_patterns)]
 
+mod alias;
+mod annotation;
 mod asm;
 mod asm_gen;
 mod ast;
+mod ast_dump;
 mod canon;
+mod cli;
+mod const_eval;
 mod data_layout;
+mod debugger;
+mod dialect;
+mod effects;
 mod env;
 mod error;
 mod escape;
+mod feature_report;
 mod flow;
+mod fragments;
 mod frame;
 mod gen;
 mod graph;
+mod interpreter;
 mod ir;
 mod lexer;
+mod listing;
 mod liveness;
+mod minimizer;
+mod opt_level;
 mod parser;
 mod position;
+mod printer;
+mod project;
+mod purity;
 mod reg_alloc;
 mod rewriter;
 mod semant;
+mod stats;
 mod symbol;
+mod target;
 mod temp;
 mod terminal;
+mod timing;
 mod token;
+mod toml;
 mod types;
+mod watcher;
 
-use std::env::args;
-use std::fs::{File, read_dir};
-use std::io::{self, BufReader, Write};
-use std::path::PathBuf;
-use std::process::Command;
+use std::collections::HashSet;
+use std::env::{args, current_exe, temp_dir, var, var_os};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Write, stderr, stdin, stdout};
+use std::iter;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
 use std::rc::Rc;
+use std::slice;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::Instant;
 
+use alias::eliminate_redundant_loads;
+use asm::Instruction;
 use asm_gen::Gen;
-use canon::{basic_blocks, linearize, trace_schedule};
-use data_layout::{STRING_DATA_LAYOUT_SIZE, STRING_TYPE};
+use ast::Ast;
+use canon::{
+    BranchWeights,
+    basic_blocks,
+    block_lines,
+    combine_field_stores,
+    eliminate_jump_chains,
+    linearize,
+    optimize,
+    schedule_for_pressure,
+    thread_jumps,
+    trace_schedule,
+};
+use cli::Arguments;
+use data_layout::{CONSERVATIVE_GC, PRECISE_GC, STRING_DATA_LAYOUT_SIZE, STRING_TYPE, VERSION};
+use dialect::Dialect;
 use env::Env;
-use error::Error;
+use error::{Catalog, Error, ErrorFormat, STDIN_NAME, set_stdin_source, show_json_diagnostic};
 use escape::find_escapes;
+use feature_report::FeatureReport;
 use frame::{Fragment, Frame};
 use frame::x86_64::X86_64;
+use gen::NestedAccess;
+use ir::{Statement, _Statement};
 use lexer::Lexer;
+use listing::Listing;
+use opt_level::OptLevel;
 use parser::Parser;
-use reg_alloc::alloc;
+use project::{PROJECT_FILE, Project};
+use purity::{PURE_EXTERNAL_FUNCTIONS, eliminate_redundant_calls};
+use reg_alloc::{Location, PointerMap, alloc};
 use rewriter::Rewriter;
-use semant::SemanticAnalyzer;
-use symbol::{Strings, Symbols};
-use terminal::Terminal;
+use semant::{Export, SemanticAnalyzer};
+use stats::{CallSite, FunctionStats};
+use symbol::{Strings, Symbol, Symbols};
+use target::Target;
+use temp::Label;
+use terminal::{ColorChoice, Terminal};
+use timing::{Phase, Timings};
+use watcher::Watcher;
 
+const ASSEMBLER_VARIABLE: &str = "TIGER_NASM";
+const DEFAULT_ASSEMBLER: &str = "nasm";
+const DEFAULT_FREESTANDING_ENTRY: &str = "_start";
+const DEFAULT_LINKER: &str = "cc";
+const DEFAULT_MAX_FRAME_SIZE: i64 = 64 * 1024;
 const END_MARKER: &str = "__tiger_pointer_map_end";
+const FRAGMENTS_EXTENSION: &str = "frag";
+const FRAME_INFO_NAME: &str = "__tiger_frame_info";
+const FRAMES_SECTION: &str = "tiger_frames";
+const MODULES_SECTION: &str = "tiger_modules";
 const POINTER_MAP_NAME: &str = "__tiger_pointer_map";
+// Directory of a sysroot holding a directory named after each target with its runtime library.
+const RUNTIME_DIRECTORY: &str = "lib/tiger";
+const RUNTIME_LIBRARY: &str = "libruntime.a";
+const RUNTIME_VARIABLE: &str = "TIGER_RUNTIME";
+// Kinds of locations of the callee-saved registers in the frame descriptions.
+const SAVED_IN_FRAME: i64 = 1;
+const SAVED_IN_REGISTER: i64 = 0;
+const SOURCE_FILE_NAME: &str = "__tiger_source_file";
+const STATIC_ROOTS_NAME: &str = "__tiger_static_roots";
+// Name of the program read from the standard input on the command line.
+const STDIN_FILENAME: &str = "-";
+const SYS_EXIT: i64 = 60;
+const SYSTEM_SYSROOTS: [&str; 2] = ["/usr/local", "/usr"];
+// Stack of the threads compiling the files, as big as the one of the main thread since the passes
+// recurse over the AST.
+const THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+struct Options {
+    // Stop after writing the assembly, without assembling and linking it.
+    asm: bool,
+    // Comment each instruction with the source line, the IR statement and the registers it comes from.
+    annotate_asm: bool,
+    // Path of nasm, instead of the one given by TIGER_NASM or found in the PATH.
+    assembler: Option<String>,
+    // Flags passed to the assembler after the output format, like -g -F dwarf.
+    assembler_flags: Vec<String>,
+    // Static estimate of how likely the branches are taken, used to lay out the basic blocks.
+    branch_weights: BranchWeights,
+    // Only analyze the sources, to report their errors without compiling them.
+    check: bool,
+    // Whether the diagnostics are colored.
+    color: ColorChoice,
+    // Write no pointer maps and let the collector scan the stack for anything that looks like a
+    // pointer.
+    conservative_gc: bool,
+    // Run the program in the interpreter under the control of the debugger instead of compiling it.
+    debug: bool,
+    // Language features accepted by the compiler.
+    dialect: Option<Dialect>,
+    // Print the AST of each file after the rewriter.
+    dump_ast: bool,
+    // Print the pointers live at each call site and the size of the pointer maps.
+    dump_gc_maps: bool,
+    // Print the IR tree of each function after the semantic analysis, after linearize and after
+    // trace_schedule.
+    dump_ir: bool,
+    // Symbol where the execution of the program starts.
+    entry: Option<String>,
+    // Write the diagnostics as text or as JSON lines.
+    error_format: ErrorFormat,
+    // Print the language features used by the program instead of compiling it.
+    feature_report: bool,
+    // The program, followed by the libraries it calls.
+    filenames: Vec<String>,
+    // Write the fragments produced by the front end to a file instead of compiling them.
+    fragments: bool,
+    // Emit the entry point directly and link without the C runtime and the Tiger runtime.
+    freestanding: bool,
+    // Leave the assembly and object files which are not outputs instead of removing them.
+    keep_intermediates: bool,
+    // Compiler driver used to link the executable, which knows where the C runtime is installed.
+    linker: Option<String>,
+    // Flags passed to the linker after the runtime, like the libraries called by the program.
+    linker_flags: Vec<String>,
+    // Write a listing file interleaving the source and the assembly.
+    listing: bool,
+    // Functions with a bigger stack frame than this many bytes are reported.
+    max_frame_size: i64,
+    // TOML catalog translating the diagnostics.
+    messages: Option<String>,
+    // Script which succeeds when the program given as argument still shows a bug: reduce the
+    // program as long as it does, instead of compiling it.
+    minimize: Option<String>,
+    // How the functions access the variables of the functions they are nested in.
+    nested_access: NestedAccess,
+    // Assemble the program to an object file without linking it.
+    object: bool,
+    // Optimization passes run by the semantic analysis, the canonicalization and the register
+    // allocation.
+    opt_level: OptLevel,
+    // Path of the executable, next to which the intermediate files are written.
+    output: Option<String>,
+    // Enforce the rules of the Tiger reference manual and default to the tiger dialect.
+    pedantic: bool,
+    // Print the path of the runtime library instead of compiling.
+    print_runtime_path: bool,
+    // Arguments given after -- to the program run by --run.
+    program_args: Vec<String>,
+    // Run the executable once it is linked, with the exit status of the compiler being its own.
+    run: bool,
+    // Runtime library linked with the program, instead of the one searched for.
+    runtime: Option<String>,
+    // Print the source of the program after the rewriter instead of compiling it.
+    source: bool,
+    // Link a shared library of position-independent code instead of an executable.
+    shared: bool,
+    // Link the C library statically, so that the executable does not need a dynamic loader.
+    static_executable: bool,
+    // Print statistics about the code generated for each function.
+    stats: bool,
+    // Installation directory where the runtime library is searched for first.
+    sysroot: Option<String>,
+    // Operating system and architecture the program is compiled for.
+    target: Target,
+    // Directory of the assembly and object files which are not outputs.
+    temp_dir: Option<String>,
+    // Report the overflow of the smallest integer divided by -1 instead of letting the CPU fault.
+    trap_on_div_min: bool,
+    // Accept the Unicode letters and digits in identifiers.
+    unicode_idents: bool,
+    // Print the time spent in each phase of the compiler.
+    verbose: bool,
+    // Compile the sources again each time one of them is written.
+    watch: bool,
+}
+
+impl Options {
+    fn dialect(&self) -> Dialect {
+        self.dialect.unwrap_or(if self.pedantic { Dialect::Tiger } else { Dialect::Extended })
+    }
+
+    /// Path of the file with this extension written for the source file: next to the executable
+    /// when its path is given, or else next to the source file. The files of a library keep the
+    /// name of the library.
+    fn output_path(&self, filename: &str, extension: &str) -> PathBuf {
+        let mut path =
+            match self.output {
+                Some(ref output) if self.filenames.first().map(String::as_str) == Some(filename) =>
+                    PathBuf::from(output),
+                Some(ref output) =>
+                    Path::new(output).with_file_name(Path::new(filename).file_name().expect("library file name")),
+                None => PathBuf::from(filename),
+            };
+        path.set_extension(extension);
+        path
+    }
+
+    /// Directory of the assembly and object files which are not outputs: the one given by
+    /// --temp-dir, or else one for this compiler in the temporary directory of the system, unless
+    /// they are kept next to the outputs.
+    fn intermediates_directory(&self) -> Option<PathBuf> {
+        match self.temp_dir {
+            Some(ref directory) => Some(PathBuf::from(directory)),
+            None if self.keep_intermediates => None,
+            None => Some(temp_dir().join(format!("tiger-{}", process::id()))),
+        }
+    }
+
+    /// Path of the file with this extension written for the source file when it is not an output.
+    fn intermediate_path(&self, filename: &str, extension: &str) -> PathBuf {
+        let path = self.output_path(filename, extension);
+        match self.intermediates_directory() {
+            Some(directory) => directory.join(path.file_name().expect("intermediate file name")),
+            None => path,
+        }
+    }
+
+    /// Whether reports are written while compiling, which would be interleaved with the progress
+    /// line.
+    fn prints_reports(&self) -> bool {
+        self.dump_ast || self.dump_gc_maps || self.dump_ir || self.stats || self.verbose
+    }
+
+    /// Draw the progress line for the source file.
+    fn show_progress(&self, terminal: &Terminal, filename: &str, pass: &str) {
+        let index = self.filenames.iter().position(|name| name == filename).unwrap_or(0);
+        terminal.show_progress(index + 1, self.filenames.len(), filename, pass);
+    }
+
+    fn executable_path(&self, filename: &str) -> PathBuf {
+        match self.output {
+            Some(ref output) => PathBuf::from(output),
+            None if self.shared => self.output_path(filename, self.target.shared_library_extension()),
+            None => self.output_path(filename, self.target.executable_extension()),
+        }
+    }
+}
+
+/// Subcommands given as the first argument, with their description printed by --help.
+const SUBCOMMANDS: &[(&str, &str)] = &[
+    ("build", "Compile the sources listed in tiger.toml with its options"),
+    ("check", "Only analyze the sources, like --check"),
+    ("run", "Run the program once it is linked, like --run"),
+];
+
+/// Options, with the placeholder of their value and their description printed by --help.
+const OPTIONS: &[(&str, &str)] = &[
+    ("--annotate-asm", "Comment each instruction with the source line and the IR it comes from"),
+    ("--assembler PATH", "Assemble with this nasm instead of TIGER_NASM or the one in the PATH"),
+    ("--assembler-flags FLAGS", "Pass these flags to the assembler, like \"-g -F dwarf\""),
+    ("--branch-weights WEIGHTS", "Weigh the branches when laying out the blocks, like loop-edge=4,cold-call=-8"),
+    ("-c", "Assemble to an object file without linking it"),
+    ("--check", "Only analyze the sources, to report their errors"),
+    ("--color WHEN", "Color the diagnostics: always, never or auto"),
+    ("--debug", "Run the program in the debugger instead of compiling it"),
+    ("--dialect NAME", "Accept the features of tiger, object-tiger, extended or unsafe"),
+    ("--dump-ast", "Print the AST of each file after the rewriter"),
+    ("--dump-gc-maps", "Print the pointers live at each call site"),
+    ("--dump-ir", "Print the IR of each function after each phase"),
+    ("--emit KIND", "Stop after writing asm, listing, fragments or source"),
+    ("--emit-asm", "Stop after writing the assembly, like --emit asm"),
+    ("--entry SYMBOL", "Start the execution of the program at this symbol"),
+    ("--error-format FORMAT", "Write the diagnostics as human text or json lines"),
+    ("--feature-report", "Print the language features used by the program"),
+    ("--freestanding", "Link without the C runtime and the Tiger runtime"),
+    ("--gc KIND", "Collect the garbage with precise or conservative pointer maps"),
+    ("-h, --help", "Print this help"),
+    ("--keep-intermediates", "Leave the assembly and object files instead of removing them"),
+    ("--ld-args ARGS", "Pass these arguments to the linker after the runtime, like \"-lm -L lib\""),
+    ("--linker PROGRAM", "Link with this compiler driver instead of cc"),
+    ("--max-frame-size BYTES", "Report the functions with a bigger stack frame"),
+    ("--messages PATH", "Translate the diagnostics with this TOML catalog"),
+    ("--minimize SCRIPT", "Reduce the program while the script succeeds on it"),
+    ("--nested ACCESS", "Access the variables of outer functions by static-link or display"),
+    ("-o PATH", "Write the executable to this path"),
+    ("-O LEVEL", "Optimize at level 0, 1 or 2"),
+    ("--pedantic", "Enforce the rules of the Tiger reference manual"),
+    ("--print-runtime-path", "Print the path of the runtime library the programs are linked with"),
+    ("--run", "Run the program once it is linked, with the arguments after --"),
+    ("--runtime PATH", "Link with this runtime library"),
+    ("--shared", "Link a shared library instead of an executable"),
+    ("--static", "Link the C library statically, into an executable without a dynamic loader"),
+    ("--stats", "Print statistics about the code of each function"),
+    ("--sysroot DIRECTORY", "Find the runtime in DIRECTORY/lib/tiger/TARGET"),
+    ("--target TRIPLE", "Compile for this target, like x86_64-unknown-linux-gnu"),
+    ("--temp-dir DIRECTORY", "Write the assembly and object files to this directory"),
+    ("--trap-on-div-min", "Report the overflow of the smallest integer divided by -1"),
+    ("--unicode-idents", "Accept the Unicode letters and digits in identifiers"),
+    ("-V, --version", "Print the version of the compiler"),
+    ("--verbose", "Print the time spent in each phase"),
+    ("--watch", "Compile the sources again each time one of them is written"),
+];
+
+fn parse_args() -> Result<Options, Error> {
+    let mut options = Options {
+        asm: false,
+        annotate_asm: false,
+        assembler: None,
+        assembler_flags: vec![],
+        branch_weights: BranchWeights::default(),
+        check: false,
+        color: ColorChoice::default(),
+        conservative_gc: false,
+        debug: false,
+        dialect: None,
+        dump_ast: false,
+        dump_gc_maps: false,
+        dump_ir: false,
+        entry: None,
+        error_format: ErrorFormat::default(),
+        feature_report: false,
+        filenames: vec![],
+        fragments: false,
+        freestanding: false,
+        linker: None,
+        linker_flags: vec![],
+        keep_intermediates: false,
+        listing: false,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        messages: None,
+        minimize: None,
+        nested_access: NestedAccess::StaticLink,
+        object: false,
+        opt_level: OptLevel::default(),
+        output: None,
+        pedantic: false,
+        print_runtime_path: false,
+        program_args: vec![],
+        run: false,
+        runtime: None,
+        shared: false,
+        source: false,
+        static_executable: false,
+        stats: false,
+        sysroot: None,
+        target: Target::default(),
+        temp_dir: None,
+        trap_on_div_min: false,
+        unicode_idents: false,
+        verbose: false,
+        watch: false,
+    };
+    let mut args = Arguments::new(args().skip(1));
+    let subcommand = args.subcommand(SUBCOMMANDS);
+    // tiger build 从当前目录的 tiger.toml 读取选项，命令行上的选项覆盖它们。
+    let build = subcommand.as_deref() == Some("build");
+    options.check = subcommand.as_deref() == Some("check");
+    options.run = subcommand.as_deref() == Some("run");
+    if build {
+        let project = Project::load(PROJECT_FILE)?;
+        options.assembler = project.assembler;
+        options.assembler_flags = project.assembler_flags;
+        options.filenames = project.sources;
+        options.linker_flags = project.linker_flags;
+        options.opt_level = project.opt_level;
+        options.output = project.output;
+        options.runtime = project.runtime;
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--annotate-asm" => options.annotate_asm = true,
+            "--assembler" => {
+                options.assembler = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--assembler expects the path of nasm".to_string()))?);
+            },
+            "--assembler-flags" => {
+                let flags = args.value()
+                    .ok_or_else(|| Error::Msg("--assembler-flags expects flags like \"-g -F dwarf\"".to_string()))?;
+                options.assembler_flags.extend(flags.split_whitespace().map(ToString::to_string));
+            },
+            "--branch-weights" => {
+                options.branch_weights = args.value()
+                    .and_then(|spec| BranchWeights::parse(&spec))
+                    .ok_or_else(|| Error::Msg("--branch-weights expects weights like loop-edge=4,cold-call=-8,equal-constant=-2".to_string()))?;
+            },
+            "--check" => options.check = true,
+            "--color" => {
+                options.color = args.value()
+                    .and_then(|name| ColorChoice::from_name(&name))
+                    .ok_or_else(|| Error::Msg("--color expects always, never or auto".to_string()))?;
+            },
+            "--debug" => options.debug = true,
+            "--dialect" => {
+                options.dialect = Some(args.value()
+                    .and_then(|name| Dialect::from_name(&name))
+                    .ok_or_else(|| Error::Msg("--dialect expects tiger, object-tiger, extended or unsafe".to_string()))?);
+            },
+            "--dump-ast" => options.dump_ast = true,
+            "--dump-gc-maps" => options.dump_gc_maps = true,
+            "--dump-ir" => options.dump_ir = true,
+            "--entry" => {
+                options.entry = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--entry expects a symbol name".to_string()))?);
+            },
+            "--error-format" => {
+                options.error_format = args.value()
+                    .and_then(|name| ErrorFormat::from_name(&name))
+                    .ok_or_else(|| Error::Msg("--error-format expects human or json".to_string()))?;
+            },
+            "--emit" => {
+                match args.value().as_deref() {
+                    Some("asm") => options.asm = true,
+                    Some("fragments") => options.fragments = true,
+                    Some("listing") => options.listing = true,
+                    Some("source") => options.source = true,
+                    _ => return Err(Error::Msg("--emit expects asm, listing, fragments or source".to_string())),
+                }
+            },
+            "--emit-asm" => options.asm = true,
+            "--feature-report" => options.feature_report = true,
+            "--freestanding" => options.freestanding = true,
+            "--gc" => {
+                options.conservative_gc =
+                    match args.value().as_deref() {
+                        Some("conservative") => true,
+                        Some("precise") => false,
+                        _ => return Err(Error::Msg("--gc expects precise or conservative".to_string())),
+                    };
+            },
+            "--help" | "-h" => {
+                print!("{}", cli::usage(SUBCOMMANDS, OPTIONS));
+                process::exit(0);
+            },
+            "--keep-intermediates" => options.keep_intermediates = true,
+            "--ld-args" => {
+                let args = args.value()
+                    .ok_or_else(|| Error::Msg("--ld-args expects linker arguments like \"-lm -L lib\"".to_string()))?;
+                options.linker_flags.extend(args.split_whitespace().map(ToString::to_string));
+            },
+            "--linker" => {
+                options.linker = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--linker expects a program like cc or gcc".to_string()))?);
+            },
+            "--max-frame-size" => {
+                options.max_frame_size = args.value()
+                    .and_then(|size| size.parse().ok())
+                    .ok_or_else(|| Error::Msg("--max-frame-size expects a number of bytes".to_string()))?;
+            },
+            "--messages" => {
+                options.messages = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--messages expects the path of a TOML message catalog".to_string()))?);
+            },
+            "--minimize" => {
+                options.minimize = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--minimize expects the path of a predicate script".to_string()))?);
+            },
+            "--nested" => {
+                options.nested_access = args.value()
+                    .and_then(|name| NestedAccess::from_name(&name))
+                    .ok_or_else(|| Error::Msg("--nested expects static-link or display".to_string()))?;
+            },
+            "-c" => options.object = true,
+            "--" => options.program_args = args.rest(),
+            "-o" => {
+                options.output = Some(args.value()
+                    .ok_or_else(|| Error::Msg("-o expects an output path".to_string()))?);
+            },
+            "--pedantic" => options.pedantic = true,
+            "--print-runtime-path" => options.print_runtime_path = true,
+            "--run" => options.run = true,
+            "--runtime" => {
+                options.runtime = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--runtime expects the path of libruntime.a".to_string()))?);
+            },
+            "--shared" => options.shared = true,
+            "--static" => options.static_executable = true,
+            "--stats" => options.stats = true,
+            "--sysroot" => {
+                options.sysroot = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--sysroot expects a directory".to_string()))?);
+            },
+            "--target" => {
+                options.target = args.value()
+                    .and_then(|triple| Target::from_triple(&triple))
+                    .ok_or_else(|| Error::Msg("--target expects a triple like x86_64-unknown-linux-gnu".to_string()))?;
+            },
+            "--temp-dir" => {
+                options.temp_dir = Some(args.value()
+                    .ok_or_else(|| Error::Msg("--temp-dir expects a directory".to_string()))?);
+            },
+            "--trap-on-div-min" => options.trap_on_div_min = true,
+            "--unicode-idents" => options.unicode_idents = true,
+            "--verbose" => options.verbose = true,
+            "--version" | "-V" => {
+                println!("tiger {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            },
+            "--watch" => options.watch = true,
+            _ if arg.starts_with("-O") => {
+                options.opt_level = OptLevel::from_name(&arg[2..])
+                    .ok_or_else(|| Error::Msg("-O expects 0, 1 or 2".to_string()))?;
+            },
+            _ if arg.starts_with('-') && arg != STDIN_FILENAME =>
+                return Err(Error::Msg(format!("unknown option {}, see tiger --help", arg))),
+            _ if build =>
+                return Err(Error::Msg(format!("tiger build compiles the sources listed in {}, not {}", PROJECT_FILE,
+                    arg))),
+            _ => options.filenames.push(arg),
+        }
+        if let Some((option, value)) = args.unused_value() {
+            return Err(Error::Msg(format!("{} expects no value, found {}", option, value)));
+        }
+    }
+    // 打印运行时的路径不需要源文件。
+    if options.print_runtime_path {
+        println!("{}", runtime_path(&options)?.display());
+        process::exit(0);
+    }
+    if options.filenames.is_empty() {
+        return Err(Error::Msg("no input file, see tiger --help".to_string()));
+    }
+    if options.shared && options.freestanding {
+        return Err(Error::Msg("--shared cannot be used with --freestanding".to_string()));
+    }
+    if options.shared && options.static_executable {
+        return Err(Error::Msg("--shared cannot be used with --static".to_string()));
+    }
+    if options.static_executable && options.target.static_linker_flags().is_none() {
+        return Err(Error::Msg(format!("--static is not supported for the target {}", options.target)));
+    }
+    if options.run && (options.asm || options.check || options.fragments || options.object || options.shared) {
+        return Err(Error::Msg("--run expects an executable to be linked".to_string()));
+    }
+    // 监视时编译要一直进行下去，不能交给程序或调试器。
+    if options.watch && (options.debug || options.minimize.is_some() || options.run) {
+        return Err(Error::Msg("--watch cannot be used with --debug, --minimize or --run".to_string()));
+    }
+    if !options.run && !options.program_args.is_empty() {
+        return Err(Error::Msg("the arguments after -- are passed to the program by --run".to_string()));
+    }
+    if let Some(missing) = options.target.missing_support() {
+        return Err(Error::Msg(format!("cannot compile for {} yet: {}", options.target, missing)));
+    }
+    if options.check && options.filenames.iter().any(|filename| is_fragments_file(filename)) {
+        return Err(Error::Msg("--check expects Tiger source files".to_string()));
+    }
+    if options.filenames.iter().skip(1).any(|filename| filename == STDIN_FILENAME) {
+        return Err(Error::Msg("only the program can be read from the standard input".to_string()));
+    }
+    if options.filenames.first().map(String::as_str) == Some(STDIN_FILENAME) {
+        if options.listing {
+            return Err(Error::Msg("--emit listing expects a Tiger source file".to_string()));
+        }
+        if options.minimize.is_some() {
+            return Err(Error::Msg("--minimize expects a Tiger source file".to_string()));
+        }
+        // 调试器从标准输入读取命令。
+        if options.debug {
+            return Err(Error::Msg("--debug expects a Tiger source file".to_string()));
+        }
+        if options.watch {
+            return Err(Error::Msg("--watch expects Tiger source files".to_string()));
+        }
+        if options.output.is_none() && !options.asm && !options.check {
+            return Err(Error::Msg("compiling the standard input expects -o or --emit asm".to_string()));
+        }
+    }
+    Ok(options)
+}
 
 fn main() {
-    let strings = Rc::new(Strings::new());
-    let mut symbols = Symbols::new(Rc::clone(&strings));
-    if let Err(error) = drive(strings, &mut symbols) {
-        let terminal = Terminal::new();
-        if let Err(error) = error.show(&symbols, &terminal) {
-            eprintln!("Error printing errors: {}", error);
+    let strings = Arc::new(Strings::new());
+    let mut symbols = Symbols::new(Arc::clone(&strings));
+    let mut catalog = Catalog::english();
+    let mut color = ColorChoice::default();
+    let mut error_format = ErrorFormat::default();
+    #[allow(clippy::result_large_err)]
+    let result = parse_args().and_then(|options| {
+        color = options.color;
+        error_format = options.error_format;
+        if let Some(ref messages) = options.messages {
+            catalog = Catalog::load(messages)?;
+        }
+        if options.watch {
+            return Ok(watch(&options, Arc::clone(&strings), &catalog)?);
         }
+        drive(&options, Arc::clone(&strings), &mut symbols)
+    });
+    if let Err(error) = result {
+        match error_format {
+            ErrorFormat::Human => {
+                let terminal = Terminal::new(color);
+                if let Err(error) = error.show(&symbols, &terminal, &catalog) {
+                    eprintln!("Error printing errors: {}", error);
+                }
+            },
+            ErrorFormat::Json => error.show_json(&symbols, &catalog),
+        }
+        process::exit(1);
     }
 }
 
-fn drive(strings: Rc<Strings>, symbols: &mut Symbols<()>) -> Result<(), Error> {
-    let mut args = args();
-    args.next();
-    if let Some(filename) = args.next() {
-        let file = BufReader::new(File::open(&filename)?);
-        let file_symbol = symbols.symbol(&filename);
-        // 1. 词法分析
-        let lexer = Lexer::new(file, file_symbol);
-        let main_symbol = symbols.symbol("main");
-        let self_symbol = symbols.symbol("self");
-        let object_symbol = symbols.symbol("Object");
-        // 2. 语法分析
-        let mut parser = Parser::new(lexer, symbols);
-        let ast = parser.parse()?;
-        // 3. 实现了一些操作来对表达式（Expr）进行重写。它的目标是让垃圾回收（GC）更方便地收集不再需要的数据。
-        let mut rewriter = Rewriter::new(symbols);
-        let ast = rewriter.rewrite(ast);
-        // 4. 找出所有需要 "逃逸" 的变量
-        let escape_env = find_escapes(&ast, Rc::clone(&strings));
-        // 5. Env 结构体表示了一个环境，这个环境存储了与编译、类型检查、代码生成等任务相关的信息
-        let mut env = Env::<X86_64>::new(&strings, escape_env);
-        {
-            let semantic_analyzer = SemanticAnalyzer::new(&mut env, Rc::clone(&strings), self_symbol, object_symbol);
-            // Fragment 枚举用于表示计算机程序的一部分（例如，函数、字符串或者虚拟表）
-            let fragments = semantic_analyzer.analyze(main_symbol, ast)?;
-
-            let mut asm_output_path = PathBuf::from(&filename);
-            asm_output_path.set_extension("s");
-            let mut file = File::create(&asm_output_path)?;
-
-            writeln!(file, "global main")?;
-            writeln!(file, "global {}", POINTER_MAP_NAME)?;
-            writeln!(file, "global {}", END_MARKER)?;
-
-            for (function_name, _) in env::external_functions() {
-                writeln!(file, "extern {}", function_name)?;
-            }
-            writeln!(file)?;
-
-            writeln!(file, "section .data")?;
-            writeln!(file, "    align 2")?;
-
-            for fragment in &fragments {
-                match *fragment {
-                    Fragment::Function { .. } => (),
-                    Fragment::Str(ref label, ref string) => {
-                        // NOTE: creating a useless data layout here so that heap-allocated strings
-                        // are accessed the same way as static strings.
-                        write!(file, "    {}: ", label)?;
-                        writeln!(file, "dq {}", STRING_TYPE)?;
-                        for _ in 0..STRING_DATA_LAYOUT_SIZE - 1 {
-                            writeln!(file, "dq 0")?;
-                        }
-                        writeln!(file, "db {}, 0", to_nasm(string))?;
-                    },
-                    Fragment::VTable { ref class, ref methods } => {
-                        writeln!(file, "{}:", class)?;
-                        if !methods.is_empty() {
-                            let labels = methods.iter()
-                                .map(|label| label.to_string())
-                                .collect::<Vec<_>>()
-                                .join("\n    dq ");
-                            writeln!(file, "    dq {}", labels)?;
-                        }
-                    },
+/// Compile the sources given in the options, or do instead what the options ask for.
+fn drive(options: &Options, strings: Arc<Strings>, symbols: &mut Symbols<()>) -> Result<(), Error> {
+    let mut terminal = Terminal::new(options.color);
+    if !options.prints_reports() {
+        terminal.enable_progress();
+    }
+    // 第一个文件是程序，其余的文件是它调用的库。
+    let (filename, libraries) =
+        match options.filenames.split_first() {
+            Some((filename, libraries)) => (filename, libraries),
+            None => return Ok(()),
+        };
+
+    if options.feature_report {
+        for filename in &options.filenames {
+            // 接受所有方言的语法，报告中列出程序需要的方言。
+            let (file, file_symbol) = open_source(filename, symbols)?;
+            let mut lexer = Lexer::new(file, file_symbol);
+            lexer.set_unicode_idents(options.unicode_idents);
+            let mut parser = Parser::new(lexer, symbols);
+            parser.set_dialect(Dialect::Unsafe);
+            let ast = parser.parse()?;
+            FeatureReport::new(&ast, symbols).print();
+        }
+        return Ok(());
+    }
+
+    let mut timings = Timings::new();
+    if let Some(ref script) = options.minimize {
+        return minimize(filename, script, options, symbols, &mut timings);
+    }
+    if options.debug {
+        if !libraries.is_empty() || is_fragments_file(filename) {
+            return Err(Error::Msg("--debug expects a single Tiger source file".to_string()));
+        }
+        // 先做语义分析，报告程序中的错误，然后解释重写之前的 AST，这样单步执行与源代码一致。
+        let ast = parse_and_rewrite(filename, options, symbols, &mut timings)?;
+        frontend(ast, options, strings, symbols, &[], &mut timings)?;
+        let ast = parse(filename, options, symbols, &mut timings)?;
+        return Ok(debugger::debug(&ast, filename, symbols)?);
+    }
+    if options.source {
+        let ast = parse(filename, options, symbols, &mut timings)?;
+        let ast = Rewriter::new(symbols).rewrite(ast);
+        let source = printer::print(&ast, symbols);
+        match options.output {
+            Some(ref output) => fs::write(output, source)?,
+            None => print!("{}", source),
+        }
+        return Ok(());
+    }
+
+    for library in libraries {
+        if is_fragments_file(library) {
+            return Err(Error::Msg(format!("library {} must be a Tiger source file", library)));
+        }
+    }
+    if options.listing && is_fragments_file(filename) {
+        return Err(Error::Msg("--emit listing expects a Tiger source file".to_string()));
+    }
+    let intermediates =
+        if options.check || options.fragments {
+            None
+        }
+        else {
+            Some(Intermediates::new(options)?)
+        };
+
+    // 每个库可以调用前面的库导出的函数，程序可以调用所有库导出的函数：每个文件从通道收到前面的库导出的函数，
+    // 再把它们和自己导出的函数发给下一个文件。
+    let (first_exports, mut imports) = channel();
+    let _ = first_exports.send(vec![]);
+    let mut files = vec![];
+    for (name, program) in libraries.iter().map(|library| (library, false)).chain(iter::once((filename, true))) {
+        let (exports, next_imports) = channel();
+        files.push((name.as_str(), program, mem::replace(&mut imports, next_imports), exports));
+    }
+    // 每个文件在自己的线程中编译，只有语义分析要等前面的库。报告会交错，所以打印报告时依次编译。
+    let mut builds: Vec<_> =
+        if files.len() == 1 || options.prints_reports() {
+            files.into_iter()
+                .map(|(filename, program, imports, exports)|
+                    build(filename, program, options, Arc::clone(&strings), &terminal, imports, exports))
+                .collect()
+        }
+        else {
+            thread::scope(|scope| {
+                let threads = files.into_iter()
+                    .map(|(filename, program, imports, exports)| {
+                        let strings = Arc::clone(&strings);
+                        let terminal = &terminal;
+                        thread::Builder::new()
+                            .stack_size(THREAD_STACK_SIZE)
+                            .spawn_scoped(scope, move ||
+                                build(filename, program, options, strings, terminal, imports, exports))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                Ok::<_, Error>(threads.into_iter()
+                    .map(|thread| thread.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+                    .collect())
+            })?
+        };
+
+    // 诊断信息和汇编文件按命令行中文件的顺序排列：先是程序，然后是库。
+    builds.rotate_right(1);
+    let mut assembly_files = vec![];
+    let mut errors = vec![];
+    for build in builds {
+        match build {
+            Ok(Some(build)) => {
+                for warning in &build.warnings {
+                    warn(&terminal, options, warning);
                 }
-            }
+                timings.add_all(&build.timings);
+                assembly_files.extend(build.files);
+            },
+            // 前面的库有错误，这个文件没有被分析。
+            Ok(None) => (),
+            Err(error) => errors.push(error),
+        }
+    }
+    if !errors.is_empty() {
+        // 多个错误是倒序显示的。
+        errors.reverse();
+        return Err(if errors.len() == 1 { errors.remove(0) } else { Error::Multi(errors) });
+    }
 
-            let mut pointer_map = vec![];
+    if options.check || options.fragments {
+        if options.verbose {
+            timings.print();
+        }
+        return Ok(());
+    }
+    let objects = timings.time(Phase::Assembly, || assemble(assembly_files, options, &terminal));
+    if options.verbose {
+        timings.print();
+    }
+    // 汇编器已经显示了诊断信息，汇编失败时不链接，并以非零状态退出。
+    let objects = objects.into_iter().collect::<Option<Vec<_>>>()
+        .ok_or_else(|| Error::Msg("assembling failed".to_string()))?;
 
-            writeln!(file, "\nsection .text")?;
+    if options.asm || options.object {
+        return Ok(());
+    }
+    link(filename, &objects, options)?;
+    if options.run {
+        drop(intermediates);
+        return Ok(run(filename, options)?);
+    }
+    Ok(())
+}
 
-            for fragment in fragments {
-                match fragment {
-                    Fragment::Function { body, escaping_vars, frame, temp_map } => {
-                        let mut frame = frame.borrow_mut();
-                        let body = frame.proc_entry_exit1(body);
+/// Compile the sources each time one of them is written, showing the errors of each build
+/// followed by a line telling whether it succeeded, until the compiler is interrupted.
+fn watch(options: &Options, strings: Arc<Strings>, catalog: &Catalog) -> io::Result<()> {
+    let files: Vec<PathBuf> = options.filenames.iter().map(PathBuf::from).collect();
+    let mut watcher = Watcher::new(&files)?;
+    loop {
+        // 每次编译都从新的符号表开始。
+        let mut symbols = Symbols::new(Arc::clone(&strings));
+        let result = drive(options, Arc::clone(&strings), &mut symbols);
+        match options.error_format {
+            ErrorFormat::Human => {
+                let status =
+                    match result {
+                        Ok(()) => "compiled",
+                        Err(error) => {
+                            error.show(&symbols, &Terminal::new(options.color), catalog)?;
+                            "failed"
+                        },
+                    };
+                eprintln!("{}, waiting for changes", status);
+            },
+            ErrorFormat::Json => {
+                if let Err(error) = result {
+                    error.show_json(&symbols, catalog);
+                }
+            },
+        }
+        let changed = watcher.wait()?;
+        if options.error_format == ErrorFormat::Human {
+            let names: Vec<_> = changed.iter().map(|file| file.display().to_string()).collect();
+            eprintln!("{} changed, compiling again", names.join(", "));
+        }
+    }
+}
 
-                        // 将函数体body转换为一系列线性化的语句，这可能涉及到删除无用的跳转，排序语句等
-                        let statements = linearize(body);
-                        // 对得到的线性化语句进行基本块分析。基本块是一种在编译器中使用的程序结构，在基本块内部，控制流程是线性的
-                        let (basic_blocks, done_label) = basic_blocks(statements);
-                        // 对基本块进行跟踪调度，为了改善程序的运行时间
-                        let statements = trace_schedule(basic_blocks, done_label);
+/// What the compilation of a file of the build produced.
+struct Build {
+    // The assembly file and the object file to assemble it to, unless the assembly is the output.
+    files: Option<(PathBuf, PathBuf)>,
+    timings: Timings,
+    // Warnings of the backend, shown in the order of the files once they are all compiled.
+    warnings: Vec<String>,
+}
 
-                        // 使用Gen生成器，将语句转化为目标代码（这里是 X86_64 汇编的表示形式）
-                        let mut generator = Gen::<X86_64>::new();
-                        for statement in statements {
-                            generator.munch_statement(statement);
-                        }
-                        let instructions = generator.get_result();
-                        let instructions = frame.proc_entry_exit2(instructions, escaping_vars);
-
-                        // 调用alloc为使用的临时变量分配物理寄存器或内存空间
-                        let (instructions, temp_map) = alloc::<X86_64>(instructions, &mut *frame, temp_map);
-                        pointer_map.push(temp_map);
-
-                        let subroutine = frame.proc_entry_exit3(instructions);
-                        // 将生成的指令写入文件
-                        writeln!(file, "{}", subroutine.prolog)?;
-                        for instruction in subroutine.body {
-                            let instruction = instruction.to_string::<X86_64>();
-                            if !instruction.is_empty() {
-                                writeln!(file, "    {}", instruction)?;
-                            }
+/// Compile a file of the build, which is the program or a library. Its semantic analysis waits for
+/// `imports`, the functions exported by the previous libraries, then sends them to the next file
+/// through `exports` along with those of the library. Return None when a previous library failed,
+/// since the file cannot be analyzed without the functions it calls.
+fn build(filename: &str, program: bool, options: &Options, strings: Arc<Strings>, terminal: &Terminal,
+    imports: Receiver<Vec<Export>>, exports: Sender<Vec<Export>>) -> Result<Option<Build>, Error>
+{
+    let mut symbols = Symbols::new(Arc::clone(&strings));
+    let mut timings = Timings::new();
+    let ast =
+        if is_fragments_file(filename) {
+            None
+        }
+        else {
+            Some(parse_and_rewrite(filename, options, &mut symbols, &mut timings)?)
+        };
+    let mut imports =
+        match imports.recv() {
+            Ok(imports) => imports,
+            Err(_) => return Ok(None),
+        };
+    let function_names = |exports: &[Export], symbols: &Symbols<()>| -> Vec<String> {
+        exports.iter()
+            .map(|export| Label::with_name(&symbols.name(export.name)).to_string())
+            .collect()
+    };
+    let (fragments, library_functions) =
+        match ast {
+            Some(ast) => {
+                options.show_progress(terminal, filename, "semantic analysis");
+                if program {
+                    let fragments = frontend(ast, options, strings, &mut symbols, &imports, &mut timings)?;
+                    (fragments, function_names(&imports, &symbols))
+                }
+                else {
+                    let (fragments, library_exports) =
+                        library_frontend(ast, options, strings, &mut symbols, &imports, &mut timings)?;
+                    let library_functions = function_names(&library_exports, &symbols);
+                    imports.extend(library_exports);
+                    let _ = exports.send(imports);
+                    (fragments, library_functions)
+                }
+            },
+            None => (fragments::read(&fs::read_to_string(filename)?)?, function_names(&imports, &symbols)),
+        };
+
+    if options.check || options.fragments {
+        if options.fragments {
+            fragments::write(&mut File::create(options.output_path(filename, FRAGMENTS_EXTENSION))?, &fragments)?;
+        }
+        return Ok(Some(Build {
+            files: None,
+            timings,
+            warnings: vec![],
+        }));
+    }
+    Ok(Some(compile(filename, fragments, program, &library_functions, options, terminal, timings)?))
+}
+
+/// Write the assembly of the fragments of the source file, returning the paths of the assembly file
+/// and of the object file to assemble it to, unless only the assembly is emitted, along with the
+/// `timings` of the file and the warnings.
+/// `library_functions` are the functions exported by the libraries: those of this library if it is
+/// not the program, or else those of all the libraries.
+fn compile(filename: &str, fragments: Vec<Fragment<X86_64>>, program: bool, library_functions: &[String],
+    options: &Options, terminal: &Terminal, mut timings: Timings) -> Result<Build, Error>
+{
+    let mut warnings = vec![];
+    let asm_output_path =
+        if options.asm {
+            options.output_path(filename, "s")
+        }
+        else {
+            options.intermediate_path(filename, "s")
+        };
+    let object_output_path =
+        if options.object {
+            options.output_path(filename, "o")
+        }
+        else {
+            options.intermediate_path(filename, "o")
+        };
+    // 从标准输入编译且没有 -o 时，汇编代码写到标准输出。
+    let mut file: Box<dyn Write> =
+        if filename == STDIN_FILENAME && options.output.is_none() {
+            Box::new(stdout())
+        }
+        else {
+            Box::new(File::create(&asm_output_path)?)
+        };
+
+    let entry = options.entry.clone().unwrap_or_else(|| DEFAULT_FREESTANDING_ENTRY.to_string());
+    if program {
+        writeln!(file, "global main")?;
+        if options.freestanding {
+            writeln!(file, "global {}", entry)?;
+        }
+    }
+    // 库导出的函数在库中是全局符号，在程序中是外部符号。
+    for function_name in library_functions {
+        writeln!(file, "{} {}", if program { "extern" } else { "global" }, function_name)?;
+    }
+
+    for (function_name, _) in env::external_functions() {
+        writeln!(file, "extern {}", env::runtime_name(function_name))?;
+    }
+    writeln!(file, "extern {}", gen::HEAP_NEXT_NAME)?;
+    writeln!(file, "extern {}", gen::HEAP_LIMIT_NAME)?;
+    writeln!(file)?;
+    // 位置无关的代码通过 GOT 和 PLT 访问全局符号和外部符号，因为它们可能在其他模块中定义。
+    let global_symbols = Rc::new(iter::once("main")
+        .chain(library_functions.iter().map(String::as_str))
+        .chain(env::external_functions().keys().map(|&name| env::runtime_name(name)))
+        .chain([gen::HEAP_NEXT_NAME, gen::HEAP_LIMIT_NAME])
+        .map(ToString::to_string)
+        .collect::<HashSet<_>>());
+
+    let word = X86_64::word_directive();
+    writeln!(file, "section .data")?;
+    writeln!(file, "    align 2")?;
+
+    let mut static_roots = vec![];
+    // 纯函数以相同参数调用的结果可以复用。
+    let pure_functions: HashSet<Label> = fragments.iter()
+        .flat_map(|fragment| match *fragment {
+            Fragment::PureFunctions(ref functions) => functions.clone(),
+            _ => vec![],
+        })
+        .chain(PURE_EXTERNAL_FUNCTIONS.iter().map(|&name| Label::with_name(env::runtime_name(name))))
+        .collect();
+
+    for fragment in &fragments {
+        match *fragment {
+            Fragment::Constant(ref label, value) => {
+                writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+                writeln!(file, "{}:", label)?;
+                writeln!(file, "    {} {}", word, value)?;
+            },
+            Fragment::Display { ref name, size } => {
+                writeln!(file, "{}:", name)?;
+                writeln!(file, "    times {} {} 0", size, word)?;
+            },
+            Fragment::Function { .. } | Fragment::PureFunctions(_) => (),
+            Fragment::StaticFields { ref data_layout, ref name } => {
+                writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+                writeln!(file, "{}:", name)?;
+                writeln!(file, "    times {} {} 0", data_layout.len(), word)?;
+                for (index, kind) in data_layout.chars().enumerate() {
+                    if kind == 'p' {
+                        static_roots.push(format!("{} + {}", name, index as i64 * X86_64::WORD_SIZE));
+                    }
+                }
+            },
+            Fragment::Str(ref label, ref string) => {
+                // NOTE: creating a useless data layout here so that heap-allocated strings
+                // are accessed the same way as static strings.
+                write!(file, "    {}: ", label)?;
+                writeln!(file, "{} {}", word, STRING_TYPE)?;
+                for _ in 0..STRING_DATA_LAYOUT_SIZE - 1 {
+                    writeln!(file, "{} 0", word)?;
+                }
+                writeln!(file, "db {}, 0", to_nasm(string))?;
+            },
+            Fragment::StringTable { ref name, ref strings } => {
+                writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+                writeln!(file, "{}:", name)?;
+                for string in strings {
+                    writeln!(file, "    {} {}", word, string)?;
+                }
+            },
+            Fragment::Template { ref name, ref values } => {
+                // 模板只被复制，不会被写入，所以放在只读段中。
+                writeln!(file, "section .rodata")?;
+                writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+                writeln!(file, "{}:", name)?;
+                for value in values {
+                    writeln!(file, "    {} {}", word, value)?;
+                }
+                writeln!(file, "section .data")?;
+            },
+            Fragment::VTable { ref class, finalizer, ref methods } => {
+                // 虚表前面的两个字：终结方法在虚表中的索引加一（没有终结方法时为 0），以及保存其静态链的字段的索引。
+                let (finalizer_method, link_field) =
+                    match finalizer {
+                        Some(finalizer) => (finalizer.method + 1, finalizer.link_field),
+                        None => (0, 0),
+                    };
+                writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+                writeln!(file, "    {} {}, {}", word, finalizer_method, link_field)?;
+                writeln!(file, "{}:", class)?;
+                if !methods.is_empty() {
+                    let labels = methods.iter()
+                        .map(|label| label.to_string())
+                        .collect::<Vec<_>>()
+                        .join(&format!("\n    {} ", word));
+                    writeln!(file, "    {} {}", word, labels)?;
+                }
+            },
+        }
+    }
+
+    let mut pointer_map = vec![];
+    let mut call_sites = vec![];
+    let mut function_names = vec![];
+    let mut function_stats = vec![];
+    let mut frame_descriptions = vec![];
+    let mut gc_call_sites = vec![];
+
+    let mut listing =
+        if options.listing {
+            let listing_output_path = options.output_path(filename, "lst");
+            Some(Listing::new(filename.as_ref(), &listing_output_path)?)
+        }
+        else {
+            None
+        };
+
+    writeln!(file, "\nsection .text")?;
+
+    if program && options.freestanding {
+        // 没有 C 运行时的情况下，入口直接调用 main，然后通过 exit 系统调用退出
+        writeln!(file, "{}:", entry)?;
+        writeln!(file, "    mov rbp, 0")?;
+        writeln!(file, "    mov rdi, 0")?;
+        writeln!(file, "    call main")?;
+        writeln!(file, "    mov rdi, rax")?;
+        writeln!(file, "    mov rax, {}", SYS_EXIT)?;
+        writeln!(file, "    syscall")?;
+    }
+
+    let function_count = fragments.iter()
+        .filter(|fragment| matches!(**fragment, Fragment::Function { .. }))
+        .count();
+    let mut function_index = 0;
+    for fragment in fragments {
+        match fragment {
+            Fragment::Function { body, escaping_vars, frame, temp_map } => {
+                function_index += 1;
+                options.show_progress(terminal, filename, &format!("code generation, function {}/{}", function_index,
+                    function_count));
+                let mut frame = frame.borrow_mut();
+                if options.dump_ir {
+                    dump_ir(frame.name(), "semantic analysis", slice::from_ref(&body));
+                }
+                let body = frame.proc_entry_exit1(body);
+                let ir_nodes = body.node_count();
+
+                let start = Instant::now();
+                // 将函数体body转换为一系列线性化的语句，这可能涉及到删除无用的跳转，排序语句等
+                let statements = linearize(body);
+                // 按优化级别删除无用的赋值，折叠比较常量的条件跳转
+                let statements = optimize::<X86_64>(statements, options.opt_level);
+                if options.dump_ir {
+                    dump_ir(frame.name(), "linearize", &statements);
+                }
+                // 对得到的线性化语句进行基本块分析。基本块是一种在编译器中使用的程序结构，在基本块内部，控制流程是线性的
+                let (basic_blocks, done_label) = basic_blocks(statements);
+                let block_lines = block_lines(&basic_blocks);
+                // 将跳转到“只含相同条件跳转”的基本块的边直接连到已知的目标
+                let basic_blocks = thread_jumps(basic_blocks);
+                // 对基本块进行跟踪调度，为了改善程序的运行时间
+                let statements = trace_schedule(basic_blocks, done_label, &options.branch_weights);
+                if options.dump_ir {
+                    dump_ir(frame.name(), "trace_schedule", &statements);
+                }
+                // 消除跳转链和不可达的基本块
+                let statements = eliminate_jump_chains(statements);
+                // 在基本块内复用已经加载或存储到临时变量中的内存值，而不是重新加载
+                let statements =
+                    if options.opt_level >= OptLevel::O1 {
+                        eliminate_redundant_loads::<X86_64>(statements, &pure_functions)
+                    }
+                    else {
+                        statements
+                    };
+                // 在基本块内复用以相同参数调用纯函数的结果
+                let statements =
+                    if options.opt_level >= OptLevel::O2 {
+                        eliminate_redundant_calls::<X86_64>(statements, &pure_functions)
+                    }
+                    else {
+                        statements
+                    };
+                // 直接存储只被下一条存储语句读取的临时变量的值，让记录字段的连续存储使用立即数
+                let statements =
+                    if options.opt_level >= OptLevel::O1 {
+                        combine_field_stores::<X86_64>(statements)
+                    }
+                    else {
+                        statements
+                    };
+                // 把只被读取一次的临时变量的赋值移到读取它的语句之前，减少同时存活的临时变量
+                let statements =
+                    if options.opt_level >= OptLevel::O1 {
+                        schedule_for_pressure::<X86_64>(statements)
+                    }
+                    else {
+                        statements
+                    };
+                timings.add(Phase::Canonicalization, start.elapsed());
+
+                let start = Instant::now();
+                // 使用Gen生成器，将语句转化为目标代码（这里是 X86_64 汇编的表示形式）
+                let mut generator = Gen::<X86_64>::new();
+                if options.shared {
+                    generator.set_position_independent(Rc::clone(&global_symbols));
+                }
+                for statement in statements {
+                    if options.annotate_asm {
+                        match statement.statement {
+                            _Statement::Label(_) | _Statement::Position(_) => (),
+                            _ => generator.emit(Instruction::Node {
+                                temps: statement.temps().into_iter()
+                                    .filter(|&temp| X86_64::special_name(temp).is_none())
+                                    .map(|temp| (temp, None))
+                                    .collect(),
+                                text: fragments::statement_text(&statement, None),
+                            }),
                         }
-                        writeln!(file, "    {}", subroutine.epilog)?;
-                    },
-                    Fragment::Str(_, _) => (),
-                    Fragment::VTable { .. } => (),
+                    }
+                    generator.munch_statement(statement);
                 }
-            }
+                let instructions = generator.get_result();
+                let instructions = frame.proc_entry_exit2(instructions, escaping_vars);
+                timings.add(Phase::InstructionSelection, start.elapsed());
+                let instructions_before_alloc = stats::instruction_count(&instructions);
+                let temps = stats::temp_count(&instructions);
+
+                let start = Instant::now();
+                // 调用alloc为使用的临时变量分配物理寄存器或内存空间
+                let (instructions, temp_map, saved_registers, alloc_stats) =
+                    alloc::<X86_64>(instructions, &mut *frame, temp_map, options.opt_level);
+                timings.add(Phase::Liveness, alloc_stats.liveness_time);
+                timings.add(Phase::RegisterAllocation, start.elapsed() - alloc_stats.liveness_time);
+                pointer_map.push(temp_map);
 
-            writeln!(file)?;
+                let frame_size = frame.frame_size();
+                if options.stats {
+                    function_stats.push(FunctionStats {
+                        coalesced_moves: alloc_stats.coalesced_moves,
+                        estimated_cycles: stats::estimated_cycles(&instructions),
+                        frame_size,
+                        instructions_after_alloc: stats::instruction_count(&instructions),
+                        instructions_before_alloc,
+                        ir_nodes,
+                        name: frame.name().to_string(),
+                        spills: alloc_stats.spills,
+                        temps,
+                    });
+                }
+                if frame_size > options.max_frame_size {
+                    warnings.push(format!("stack frame of function `{}` is {} bytes (limit is {} bytes)",
+                        frame.name(), frame_size, options.max_frame_size));
+                }
 
-            writeln!(file, "{}:", POINTER_MAP_NAME)?;
-            for map in &pointer_map {
-                for &(ref label, ref pointer_temps) in map {
-                    writeln!(file, "    dq {}", label)?;
-                    for temp_label in pointer_temps {
-                        writeln!(file, "    dq {}", temp_label.to_label::<X86_64>())?;
+                let subroutine = frame.proc_entry_exit3(instructions);
+                // 记录每个调用点所在的函数和源代码行，运行时错误据此打印栈回溯。
+                // 基本块被重新排列过，所以每个基本块的开头恢复它原来所在的行。
+                let mut line = 0;
+                for instruction in &subroutine.body {
+                    match *instruction {
+                        Instruction::Call { ref return_label, .. } => {
+                            call_sites.push((return_label.clone(), function_names.len(), line));
+                            if options.dump_gc_maps {
+                                let pointers = pointer_map.last()
+                                    .and_then(|map: &PointerMap| map.iter().find(|entry| entry.0 == *return_label))
+                                    .map(|entry| entry.1.iter().map(ToString::to_string).collect());
+                                // 没有指针图项的调用点返回时，垃圾回收器找不到它的栈帧中的指针。
+                                if pointers.is_none() {
+                                    warnings.push(format!("call site {} in function `{}` has no pointer map entry",
+                                        return_label, frame.name()));
+                                }
+                                gc_call_sites.push(CallSite {
+                                    call: instruction.to_string::<X86_64>(),
+                                    function: frame.name().to_string(),
+                                    line,
+                                    pointers,
+                                    return_label: return_label.to_string(),
+                                });
+                            }
+                        },
+                        Instruction::Label { ref label, .. } => {
+                            if let Some(&block_line) = block_lines.get(label) {
+                                line = block_line;
+                            }
+                        },
+                        Instruction::Position { line: position } => line = position,
+                        _ => (),
                     }
-                    writeln!(file, "    dq {}", END_MARKER)?;
                 }
+                function_names.push(frame.name().to_name());
+                if let Some(ref mut listing) = listing {
+                    listing.write_subroutine::<X86_64>(&subroutine)?;
+                }
+                // 将生成的指令写入文件
+                if options.annotate_asm {
+                    annotation::write_subroutine::<X86_64, _>(&mut file, &subroutine)?;
+                }
+                else {
+                    writeln!(file, "{}", subroutine.prolog)?;
+                    for instruction in subroutine.body {
+                        let instruction = instruction.to_string::<X86_64>();
+                        if !instruction.is_empty() {
+                            writeln!(file, "    {}", instruction)?;
+                        }
+                    }
+                    writeln!(file, "    {}", subroutine.epilog)?;
+                }
+                let end_label = Label::new();
+                writeln!(file, "{}:", end_label)?;
+                frame_descriptions.push((frame.name(), end_label, frame_size, saved_registers));
+            },
+            Fragment::Constant(_, _) | Fragment::Display { .. } | Fragment::PureFunctions(_) | Fragment::StaticFields { .. }
+                | Fragment::Str(_, _) | Fragment::StringTable { .. } | Fragment::Template { .. } | Fragment::VTable { .. } =>
+                (),
+        }
+    }
+
+    writeln!(file)?;
+
+    if options.stats {
+        stats::print(&function_stats);
+    }
+
+    // 下面的表含有地址，放在数据段中，使共享库的代码段不需要重定位。
+    writeln!(file, "section .data")?;
+    // 静态字段中的指针也是垃圾回收的根。
+    writeln!(file, "    align {}", X86_64::WORD_SIZE)?;
+    writeln!(file, "{}:", STATIC_ROOTS_NAME)?;
+    for root in &static_roots {
+        writeln!(file, "    {} {}", word, root)?;
+    }
+    writeln!(file, "    {} {}", word, END_MARKER)?;
+
+    writeln!(file, "{}:", POINTER_MAP_NAME)?;
+    // 保守的垃圾回收器扫描整个栈，不需要指针图。
+    let pointer_map = if options.conservative_gc { &[][..] } else { &pointer_map[..] };
+    for map in pointer_map {
+        for &(ref label, ref pointer_temps) in map {
+            writeln!(file, "    {} {}", word, label)?;
+            for pointer in pointer_temps {
+                writeln!(file, "    {} {}", word, pointer)?;
             }
-            writeln!(file, "    dq {}", END_MARKER)?;
-            writeln!(file, "{}:", END_MARKER)?;
-
-            // 这段代码使用了 Rust 的 Command 类来启动一个新的进程执行 nasm 命令。nasm 是一个通用的 x86 汇编器，将汇编源文件转换为机器语言的可执行文件或目标文件。
-            let status = Command::new("nasm")
-                .args(&["-f", "elf64", asm_output_path.to_str().expect("asm output path")])
-                .status();
-
-            match status {
-                Ok(return_code) => {
-                    if return_code.success() {
-                        let mut object_output_path = PathBuf::from(&filename);
-                        object_output_path.set_extension("o");
-                        let mut executable_output_path = PathBuf::from(&filename);
-                        executable_output_path.set_extension("");
-                        Command::new("ld")
-                            .args(&[
-                                "-dynamic-linker", "/lib64/ld-linux-x86-64.so.2", "-o",
-                                executable_output_path.to_str().expect("executable output path"),
-                                "/usr/lib/Scrt1.o", "/usr/lib/crti.o", &format!("-L{}", get_gcc_lib_dir()?),
-                                "-L/usr/lib64/",
-                                object_output_path.to_str().expect("object output path"),
-                                "target/debug/libruntime.a", "-lpthread", "-ldl", "--no-as-needed", "-lc", "-lgcc", "--as-needed",
-                                "-lgcc_s", "--no-as-needed", "/usr/lib/crtn.o"
-                            ])
-                            .status()
-                            .expect("link");
+            writeln!(file, "    {} {}", word, END_MARKER)?;
+        }
+    }
+    writeln!(file, "    {} {}", word, END_MARKER)?;
+    if options.dump_gc_maps {
+        // 每项：返回地址、指针和结束标记，最后还有一个结束标记。
+        let map_words = pointer_map.iter()
+            .flatten()
+            .map(|entry| entry.1.len() as i64 + 2)
+            .sum::<i64>() + 1;
+        stats::print_gc_maps(&gc_call_sites, map_words * X86_64::WORD_SIZE);
+    }
+
+    // 每个调用点一项：返回地址、所在函数的名字和源代码行。
+    writeln!(file, "{}:", FRAME_INFO_NAME)?;
+    for &(ref return_label, function, line) in &call_sites {
+        writeln!(file, "    {} {}, __tiger_function_name_{}, {}", word, return_label, function, line)?;
+    }
+    writeln!(file, "    {} {}", word, END_MARKER)?;
+    writeln!(file, "{}:", END_MARKER)?;
+    let source_name = if filename == STDIN_FILENAME { STDIN_NAME } else { filename };
+    writeln!(file, "{}: db {}, 0", SOURCE_FILE_NAME, to_nasm(source_name))?;
+    for (index, name) in function_names.iter().enumerate() {
+        writeln!(file, "__tiger_function_name_{}: db {}, 0", index, to_nasm(name))?;
+    }
+
+    // 每个函数在这个段中放一项，供运行时展开栈：代码的起止地址、栈帧大小、返回地址相对于 rbp 的偏移，
+    // 以及被调用者保存的寄存器保存在哪里（寄存器或者相对于 rbp 的栈槽）。寄存器使用 DWARF 编号。
+    writeln!(file, "\nsection {} progbits alloc noexec write align={}", FRAMES_SECTION, X86_64::WORD_SIZE)?;
+    for &(ref name, ref end_label, frame_size, ref saved_registers) in &frame_descriptions {
+        writeln!(file, "    {} {}, {}, {}, {}, {}", word, name, end_label, frame_size, X86_64::WORD_SIZE,
+            saved_registers.len())?;
+        for &(register, location) in saved_registers {
+            let register = X86_64::dwarf_number(register).expect("callee-saved register number");
+            let (kind, value) =
+                match location {
+                    Location::Register(saved_in) =>
+                        (SAVED_IN_REGISTER, X86_64::dwarf_number(saved_in).expect("register number") as i64),
+                    Location::Stack(offset) => (SAVED_IN_FRAME, offset),
+                };
+            writeln!(file, "    {} {}, {}, {}", word, register, kind, value)?;
+        }
+    }
+
+    // 每个目标文件在这个段中放一项，指向它的表，运行时通过链接器定义的段的起止符号找到所有的表。
+    writeln!(file, "\nsection {} progbits alloc noexec write align={}", MODULES_SECTION, X86_64::WORD_SIZE)?;
+    let gc = if options.conservative_gc { CONSERVATIVE_GC } else { PRECISE_GC };
+    writeln!(file, "    {} {:#x}, {}, {}, {}, {}, {}, {}", word, VERSION, POINTER_MAP_NAME, END_MARKER, STATIC_ROOTS_NAME,
+        FRAME_INFO_NAME, SOURCE_FILE_NAME, gc)?;
+
+    let files =
+        if options.asm {
+            None
+        }
+        else {
+            Some((asm_output_path, object_output_path))
+        };
+    Ok(Build {
+        files,
+        timings,
+        warnings,
+    })
+}
+
+/// Assemble the pairs of assembly and object files with concurrent assembler processes, returning
+/// the object files in the same order, or None for those the assembler failed on. The diagnostics
+/// of each process are captured and shown in the order of the files.
+fn assemble(files: Vec<(PathBuf, PathBuf)>, options: &Options, terminal: &Terminal) -> Vec<Option<PathBuf>> {
+    // 这段代码使用了 Rust 的 Command 类来启动一个新的进程执行 nasm 命令。nasm 是一个通用的 x86 汇编器，将汇编源文件转换为机器语言的可执行文件或目标文件。
+    // nasm 的路径依次取自 --assembler、TIGER_NASM 和 PATH。
+    let assembler = options.assembler.clone()
+        .or_else(|| var(ASSEMBLER_VARIABLE).ok())
+        .unwrap_or_else(|| DEFAULT_ASSEMBLER.to_string());
+    // 汇编器的诊断信息不能接在进度行的后面。
+    terminal.clear_progress();
+    // 先启动所有的进程，再按文件的顺序等待它们，这样诊断信息的顺序是确定的。
+    let children: Vec<_> = files.iter()
+        .map(|paths| {
+            let (ref asm_path, ref object_path) = *paths;
+            Command::new(&assembler)
+                .args(["-f", options.target.object_format()])
+                .args(&options.assembler_flags)
+                .arg("-o")
+                .arg(object_path)
+                .arg(asm_path)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+        })
+        .collect();
+
+    files.into_iter().zip(children)
+        .map(|((_, object_path), child)| {
+            match child.and_then(|child| child.wait_with_output()) {
+                Ok(output) => {
+                    let _ = stdout().write_all(&output.stdout);
+                    let _ = stderr().write_all(&output.stderr);
+                    if output.status.success() {
+                        return Some(object_path);
                     }
                 },
-                Err(error) => eprintln!("Error running nasm: {}", error),
+                Err(error) => eprintln!("Error running {}: {}", assembler, error),
+            }
+            None
+        })
+        .collect()
+}
+
+/// Assembly and object files which are not outputs, removed once the program is linked or the
+/// build failed, unless --keep-intermediates is given.
+struct Intermediates<'a> {
+    options: &'a Options,
+}
+
+impl<'a> Intermediates<'a> {
+    fn new(options: &'a Options) -> io::Result<Self> {
+        if let Some(directory) = options.intermediates_directory() {
+            fs::create_dir_all(directory)?;
+        }
+        Ok(Intermediates {
+            options,
+        })
+    }
+}
+
+impl<'a> Drop for Intermediates<'a> {
+    fn drop(&mut self) {
+        if self.options.keep_intermediates {
+            return;
+        }
+        // 作为输出的文件不在这个目录中，不会被删除。
+        for filename in &self.options.filenames {
+            for extension in ["s", "o"] {
+                let _ = fs::remove_file(self.options.intermediate_path(filename, extension));
+            }
+        }
+        if self.options.temp_dir.is_none() {
+            if let Some(directory) = self.options.intermediates_directory() {
+                let _ = fs::remove_dir(directory);
             }
         }
-        env.end_scope(); // TODO: move after the semantic analysis?
+    }
+}
+
+/// Link the object files of the program and of its libraries into the executable, or into a shared
+/// library with --shared.
+fn link(filename: &str, objects: &[PathBuf], options: &Options) -> Result<(), Error> {
+    let executable_output_path = options.executable_path(filename);
+    let executable_output_path = executable_output_path.to_str().expect("executable output path");
+    let objects = objects.iter()
+        .map(|object| object.to_str().expect("object output path"));
+    let linker_name = options.linker.as_deref().unwrap_or(DEFAULT_LINKER);
+    // 由编译器驱动程序（cc）找到 C 运行时的启动文件和库的路径。
+    let mut linker = Command::new(linker_name);
+    linker.args(options.target.linker_flags(options.shared));
+    // 静态链接 C 库，生成的可执行文件不依赖动态链接器的路径。
+    if options.static_executable && !options.freestanding {
+        linker.args(options.target.static_linker_flags().unwrap_or_default());
+    }
+    linker.args(["-o", executable_output_path]);
+    if options.freestanding {
+        let entry = options.entry.as_deref().unwrap_or(DEFAULT_FREESTANDING_ENTRY);
+        linker.args(["-static", "-nostdlib", &format!("-Wl,-e,{}", entry)]);
+        linker.args(objects);
+    }
+    else {
+        linker.args(objects);
+        linker.arg(runtime_path(options)?);
+        linker.args(options.target.system_libraries());
+        if let Some(ref entry) = options.entry {
+            linker.arg(format!("-Wl,-e,{}", entry));
+        }
+    }
+    linker.args(&options.linker_flags);
+    match linker.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(Error::Msg(format!("linking with `{}` failed", linker_name))),
+        Err(error) => Err(Error::Msg(format!("cannot run the linker `{}`: {}", linker_name, error))),
+    }
+}
+
+/// Replace the compiler by the executable it linked, so that the exit status is the one of the
+/// program. Only returns if the executable cannot be run.
+fn run(filename: &str, options: &Options) -> io::Result<()> {
+    let mut path = options.executable_path(filename);
+    // 没有目录的路径会在 PATH 中查找。
+    if path.is_relative() {
+        path = Path::new(".").join(path);
+    }
+    stdout().flush()?;
+    let error = Command::new(&path).args(&options.program_args).exec();
+    Err(io::Error::other(format!("cannot run {}: {}", path.display(), error)))
+}
+
+/// Path of the runtime library: the one given by --runtime or TIGER_RUNTIME, or else the one of
+/// the target in the sysroot given by --sysroot, or else the first one found in the sysroot the
+/// compiler is installed in, next to the compiler or in the system sysroots.
+fn runtime_path(options: &Options) -> Result<PathBuf, Error> {
+    if let Some(ref runtime) = options.runtime {
+        return Ok(PathBuf::from(runtime));
+    }
+    if let Some(runtime) = var_os(RUNTIME_VARIABLE) {
+        return Ok(PathBuf::from(runtime));
+    }
+    let runtime = Path::new(RUNTIME_DIRECTORY).join(options.target.to_string()).join(RUNTIME_LIBRARY);
+    if let Some(ref sysroot) = options.sysroot {
+        let path = Path::new(sysroot).join(runtime);
+        if !path.is_file() {
+            return Err(Error::Msg(format!("cannot find the runtime {} in the sysroot {}", path.display(), sysroot)));
+        }
+        return Ok(path);
+    }
+    // 安装的编译器在 <sysroot>/bin 中；没有安装时，运行时和编译器都在 cargo 的输出目录中。
+    let compiler = current_exe()?;
+    let installed = compiler.parent()
+        .and_then(Path::parent)
+        .map(|sysroot| sysroot.join(&runtime));
+    let build_directory = compiler.with_file_name(RUNTIME_LIBRARY);
+    let system_sysroots = SYSTEM_SYSROOTS.iter()
+        .map(|sysroot| Path::new(sysroot).join(&runtime));
+    installed.into_iter().chain(iter::once(build_directory)).chain(system_sysroots)
+        .find(|path| path.is_file())
+        .ok_or_else(|| Error::Msg(format!("cannot find the runtime {}: use --runtime, --sysroot or {}",
+            RUNTIME_LIBRARY, RUNTIME_VARIABLE)))
+}
+
+/// Print the statements of a function after a phase of the compiler.
+fn dump_ir(function: Label, phase: &str, statements: &[Statement]) {
+    println!(";; {} after {}", function, phase);
+    for statement in statements {
+        println!("{}", fragments::statement_text(statement, Some(0)));
+    }
+}
+
+/// Reduce the program while the script succeeds on it, leave the smallest one in a `.min.tig` file
+/// and print it.
+fn minimize(filename: &str, script: &str, options: &Options, symbols: &mut Symbols<()>, timings: &mut Timings)
+    -> Result<(), Error>
+{
+    let ast = parse(filename, options, symbols, timings)?;
+    let candidate_path = options.output_path(filename, "min.tig");
+    let mut run_count = 0;
+    let mut interesting = |source: &str| {
+        fs::write(&candidate_path, source)?;
+        run_count += 1;
+        let status = Command::new(script)
+            .arg(&candidate_path)
+            .status()
+            .map_err(|error| io::Error::other(format!("cannot run the predicate {}: {}", script, error)))?;
+        Ok(status.success())
+    };
+    if !interesting(&printer::print(&ast, symbols))? {
+        return Err(Error::Msg(format!("the predicate {} fails on {}: there is nothing to reduce", script,
+            filename)));
+    }
+    let ast = minimizer::minimize(ast, symbols, &mut interesting)?;
+    let source = printer::print(&ast, symbols);
+    fs::write(&candidate_path, &source)?;
+    print!("{}", source);
+    if options.verbose {
+        eprintln!("{} runs of the predicate, smallest program in {}", run_count, candidate_path.display());
     }
     Ok(())
 }
 
+/// Open the source file, or read the standard input for `-`, and return it with the symbol of its
+/// name in the positions.
+fn open_source(filename: &str, symbols: &mut Symbols<()>) -> io::Result<(Box<dyn Read>, Symbol)> {
+    if filename == STDIN_FILENAME {
+        let mut source = vec![];
+        stdin().read_to_end(&mut source)?;
+        // 标准输入不能再读一次，所以保存下来，用于在诊断信息中显示源代码行。
+        set_stdin_source(source.clone());
+        Ok((Box::new(Cursor::new(source)), symbols.symbol(STDIN_NAME)))
+    }
+    else {
+        Ok((Box::new(BufReader::new(File::open(filename)?)), symbols.symbol(filename)))
+    }
+}
+
+fn is_fragments_file(filename: &str) -> bool {
+    Path::new(filename).extension().is_some_and(|extension| extension == FRAGMENTS_EXTENSION)
+}
+
+/// Analyze the rewritten AST of the program and translate it to fragments.
+fn frontend(ast: Ast, options: &Options, strings: Arc<Strings>, symbols: &mut Symbols<()>, imports: &[Export],
+    timings: &mut Timings) -> Result<Vec<Fragment<X86_64>>, Error>
+{
+    let main_symbol = symbols.symbol("main");
+    analyze(ast, options, strings, symbols, timings, |mut semantic_analyzer, ast| {
+        semantic_analyzer.import(imports);
+        semantic_analyzer.analyze(main_symbol, ast)
+    })
+}
+
+/// Analyze the rewritten AST of the library and translate it to fragments, with the functions it
+/// exports.
+fn library_frontend(ast: Ast, options: &Options, strings: Arc<Strings>, symbols: &mut Symbols<()>,
+    previous_exports: &[Export], timings: &mut Timings) -> Result<(Vec<Fragment<X86_64>>, Vec<Export>), Error>
+{
+    analyze(ast, options, strings, symbols, timings, |semantic_analyzer, ast| {
+        semantic_analyzer.analyze_library(ast, previous_exports)
+    })
+}
+
+/// Parse the source file.
+fn parse(filename: &str, options: &Options, symbols: &mut Symbols<()>, timings: &mut Timings)
+    -> Result<Ast, Error>
+{
+    let (file, file_symbol) = open_source(filename, symbols)?;
+    // 1. 词法分析
+    let mut lexer = Lexer::new(file, file_symbol);
+    lexer.set_unicode_idents(options.unicode_idents);
+    // 2. 语法分析
+    let mut parser = Parser::new(lexer, symbols);
+    parser.set_dialect(options.dialect());
+    let start = Instant::now();
+    let ast = parser.parse()?;
+    // 词法分析在语法分析的过程中进行，所以从语法分析的时间中减去。
+    timings.add(Phase::Lexing, parser.lexing_time());
+    timings.add(Phase::Parsing, start.elapsed() - parser.lexing_time());
+    Ok(ast)
+}
+
+/// Parse the source file and rewrite its AST for the semantic analysis.
+fn parse_and_rewrite(filename: &str, options: &Options, symbols: &mut Symbols<()>, timings: &mut Timings)
+    -> Result<Ast, Error>
+{
+    let ast = parse(filename, options, symbols, timings)?;
+    // 3. 实现了一些操作来对表达式（Expr）进行重写。它的目标是让垃圾回收（GC）更方便地收集不再需要的数据。
+    let mut rewriter = Rewriter::new(symbols);
+    let ast = rewriter.rewrite(ast);
+    if options.dump_ast {
+        print!("{}", ast_dump::dump(&ast, symbols));
+    }
+    Ok(ast)
+}
+
+fn analyze<T, A>(ast: Ast, options: &Options, strings: Arc<Strings>, symbols: &mut Symbols<()>,
+    timings: &mut Timings, analyze: A) -> Result<T, Error>
+where A: FnOnce(SemanticAnalyzer<X86_64>, Ast) -> Result<T, Error>
+{
+    let self_symbol = symbols.symbol("self");
+    let object_symbol = symbols.symbol("Object");
+    // 4. 找出所有需要 "逃逸" 的变量
+    let escape_env = timings.time(Phase::EscapeAnalysis, || find_escapes(&ast.arena, ast.root, Arc::clone(&strings)));
+    // 5. Env 结构体表示了一个环境，这个环境存储了与编译、类型检查、代码生成等任务相关的信息
+    let mut env = Env::<X86_64>::new(&strings, escape_env);
+    // Fragment 枚举用于表示计算机程序的一部分（例如，函数、字符串或者虚拟表）
+    let start = Instant::now();
+    let result = {
+        let mut semantic_analyzer = SemanticAnalyzer::new(&mut env, Arc::clone(&strings), self_symbol, object_symbol);
+        semantic_analyzer.set_dialect(options.dialect());
+        semantic_analyzer.set_freestanding(options.freestanding);
+        semantic_analyzer.set_nested_access(options.nested_access);
+        semantic_analyzer.set_pedantic(options.pedantic);
+        semantic_analyzer.set_trap_on_div_min(options.trap_on_div_min);
+        semantic_analyzer.set_opt_level(options.opt_level);
+        analyze(semantic_analyzer, ast)?
+    };
+    timings.add(Phase::SemanticAnalysis, start.elapsed());
+    env.end_scope(); // TODO: move after the semantic analysis?
+    Ok(result)
+}
+
+fn warn(terminal: &Terminal, options: &Options, message: &str) {
+    terminal.clear_progress();
+    match options.error_format {
+        ErrorFormat::Human =>
+            eprintln!("{}{}warning: {}{}{}", terminal.bold(), terminal.yellow(), terminal.reset_color(), message,
+                terminal.end_bold()),
+        ErrorFormat::Json => show_json_diagnostic("warning", message, None),
+    }
+}
+
 fn to_nasm(string: &str) -> String {
     let mut result = "'".to_string();
     for char in string.chars() {
@@ -275,16 +1716,3 @@ fn to_nasm(string: &str) -> String {
     result
 }
 
-fn get_gcc_lib_dir() -> io::Result<String> {
-    let directory = "/usr/lib64/gcc/x86_64-pc-linux-gnu/";
-    let files = read_dir(directory)?;
-    for file in files {
-        let file = file?;
-        if file.metadata()?.is_dir() {
-            return file.file_name().to_str()
-                .map(|str| format!("{}{}", directory, str))
-                .ok_or_else(|| io::ErrorKind::InvalidData.into());
-        }
-    }
-    Err(io::ErrorKind::NotFound.into())
-}
@@ -0,0 +1,303 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Reduction of a program to a smaller one which still shows a bug, for `--minimize`.
+//!
+//! The candidates are the program with one edit of its AST: the removal of a chunk of
+//! declarations, of expressions of a sequence or of arms of a case, like in delta debugging, the
+//! replacement of an expression by one of its operands or branches, or by a literal. Each edit
+//! makes the program smaller, so the reduction ends. The edits are tried in the order of the AST,
+//! the biggest first, and the ones kept by the predicate are applied until none is.
+
+use std::io;
+
+use ast::{
+    Arena,
+    Ast,
+    Declaration,
+    DeclarationWithPos,
+    Expr,
+    ExprId,
+    ExprWithPos,
+};
+use position::WithPos;
+use printer;
+use symbol::Symbols;
+
+/// Reduce the program while `interesting` returns true for the source of the reduced program.
+pub fn minimize<F>(ast: Ast, symbols: &Symbols<()>, mut interesting: F) -> io::Result<Ast>
+where F: FnMut(&str) -> io::Result<bool>
+{
+    let mut program = ast;
+    loop {
+        let mut reduced = false;
+        let mut index = 0;
+        while let Some(candidate) = Reducer::new(&program.arena, index).edit(program.root) {
+            if interesting(&printer::print(&candidate, symbols))? {
+                // NOTE: the next edit now has the same index.
+                program = candidate;
+                reduced = true;
+            }
+            else {
+                index += 1;
+            }
+        }
+        if !reduced {
+            return Ok(program);
+        }
+    }
+}
+
+/// Finder of the edit at an index in the order of the AST.
+struct Reducer<'a> {
+    arena: &'a Arena,
+    // The expression to replace and its replacement, once found.
+    edit: Option<(ExprId, ExprWithPos)>,
+    remaining: usize,
+}
+
+impl<'a> Reducer<'a> {
+    fn new(arena: &'a Arena, index: usize) -> Self {
+        Self {
+            arena,
+            edit: None,
+            remaining: index,
+        }
+    }
+
+    /// The AST with the edit applied, or None if there are fewer edits.
+    fn edit(mut self, root: ExprId) -> Option<Ast> {
+        self.expr(root);
+        let (id, expr) = self.edit?;
+        let mut arena = self.arena.clone();
+        arena[id] = expr;
+        Some(Ast {
+            arena,
+            root,
+        })
+    }
+
+    fn declaration(&mut self, declaration: &DeclarationWithPos) {
+        match declaration.node {
+            Declaration::ClassDeclaration { ref members, .. } => {
+                for member in members {
+                    self.declaration(&member.declaration);
+                }
+            },
+            Declaration::Function(ref functions) => {
+                for function in functions {
+                    self.expr(function.node.body);
+                }
+            },
+            Declaration::Type(_) => (),
+            Declaration::VariableDeclaration { init, .. } => self.expr(init),
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        if self.edit.is_some() {
+            return;
+        }
+        let arena = self.arena;
+        let mut alternatives = alternatives(arena, expr);
+        if self.remaining < alternatives.len() {
+            self.edit = Some((expr, alternatives.swap_remove(self.remaining)));
+            return;
+        }
+        self.remaining -= alternatives.len();
+
+        // NOTE: the variables, fields and receivers are not edited, since replacing them by a
+        // literal would not give a valid program.
+        match arena[expr].node {
+            Expr::AddressOf { .. } | Expr::Break | Expr::Deref { .. } | Expr::Field { .. } | Expr::Int { .. }
+                | Expr::New { .. } | Expr::Nil | Expr::Str { .. } | Expr::Subscript { .. } | Expr::Variable(_) => (),
+            Expr::Array { init, size, .. } => {
+                self.expr(init);
+                self.expr(size);
+            },
+            Expr::Assign { expr, .. } => self.expr(expr),
+            Expr::Call { ref args, .. } | Expr::MethodCall { ref args, .. } => self.exprs(args),
+            Expr::Case { ref arms, else_, subject } => {
+                self.expr(subject);
+                for arm in arms {
+                    self.expr(arm.node.body);
+                }
+                if let Some(else_) = else_ {
+                    self.expr(else_);
+                }
+            },
+            Expr::If { else_, test, then } => {
+                self.expr(test);
+                self.expr(then);
+                if let Some(else_) = else_ {
+                    self.expr(else_);
+                }
+            },
+            Expr::Let { body, ref declarations } => {
+                for declaration in declarations {
+                    self.declaration(declaration);
+                }
+                self.expr(body);
+            },
+            Expr::Oper { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            },
+            Expr::Record { ref fields, .. } => {
+                for field in fields {
+                    self.expr(field.node.expr);
+                }
+            },
+            Expr::Sequence(ref exprs) => self.exprs(exprs),
+            Expr::While { body, test } => {
+                self.expr(test);
+                self.expr(body);
+            },
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[ExprId]) {
+        for &expr in exprs {
+            self.expr(expr);
+        }
+    }
+}
+
+/// The smaller expressions which can replace `expr`.
+fn alternatives(arena: &Arena, expr: ExprId) -> Vec<ExprWithPos> {
+    let pos = arena[expr].pos;
+    let mut alternatives = vec![];
+    match arena[expr].node {
+        Expr::Case { ref arms, else_, subject } => {
+            if let Some(else_) = else_ {
+                alternatives.push(arena[else_].clone());
+                alternatives.push(WithPos::new(Expr::Case {
+                    arms: arms.clone(),
+                    else_: None,
+                    subject,
+                }, pos));
+            }
+            for arms in without_chunks(arms) {
+                alternatives.push(WithPos::new(Expr::Case {
+                    arms,
+                    else_,
+                    subject,
+                }, pos));
+            }
+        },
+        Expr::If { else_, test, then } => {
+            alternatives.push(arena[then].clone());
+            if let Some(else_) = else_ {
+                alternatives.push(arena[else_].clone());
+                alternatives.push(WithPos::new(Expr::If {
+                    else_: None,
+                    test,
+                    then,
+                }, pos));
+            }
+        },
+        Expr::Let { body, ref declarations } => {
+            alternatives.push(arena[body].clone());
+            let new_let = |declarations| WithPos::new(Expr::Let {
+                body,
+                declarations,
+            }, pos);
+            for declarations in without_chunks(declarations) {
+                // NOTE: removing all the declarations is the same as keeping only the body.
+                if !declarations.is_empty() {
+                    alternatives.push(new_let(declarations));
+                }
+            }
+            // The functions of a group and the members of a class.
+            for (index, declaration) in declarations.iter().enumerate() {
+                let mut replace = |new_declaration| {
+                    let mut declarations = declarations.clone();
+                    declarations[index] = WithPos::new(new_declaration, declaration.pos);
+                    alternatives.push(new_let(declarations));
+                };
+                match declaration.node {
+                    Declaration::ClassDeclaration { ref members, ref name, ref parent_class } => {
+                        for members in without_chunks(members) {
+                            replace(Declaration::ClassDeclaration {
+                                members,
+                                name: name.clone(),
+                                parent_class: parent_class.clone(),
+                            });
+                        }
+                    },
+                    Declaration::Function(ref functions) if functions.len() > 1 => {
+                        for functions in without_chunks(functions) {
+                            if !functions.is_empty() {
+                                replace(Declaration::Function(functions));
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        },
+        Expr::Oper { left, right, .. } => {
+            alternatives.push(arena[left].clone());
+            alternatives.push(arena[right].clone());
+        },
+        Expr::Sequence(ref exprs) => {
+            if exprs.len() == 1 {
+                alternatives.push(arena[exprs[0]].clone());
+            }
+            for exprs in without_chunks(exprs) {
+                alternatives.push(WithPos::new(Expr::Sequence(exprs), pos));
+            }
+        },
+        Expr::While { body, .. } => alternatives.push(arena[body].clone()),
+        _ => (),
+    }
+    if !is_leaf(&arena[expr]) {
+        for literal in [Expr::Int { value: 0 }, Expr::Str { value: String::new() }, Expr::Nil, Expr::Sequence(vec![])] {
+            alternatives.push(WithPos::new(literal, pos));
+        }
+    }
+    alternatives
+}
+
+fn is_leaf(expr: &ExprWithPos) -> bool {
+    match expr.node {
+        Expr::Break | Expr::Int { .. } | Expr::New { .. } | Expr::Nil | Expr::Str { .. } | Expr::Variable(_) => true,
+        Expr::Sequence(ref exprs) => exprs.is_empty(),
+        _ => false,
+    }
+}
+
+/// The list without each of its chunks, from the whole list to single elements, whose size is
+/// halved each time.
+fn without_chunks<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+    let mut lists = vec![];
+    let mut size = items.len();
+    while size > 0 {
+        for start in (0..items.len()).step_by(size) {
+            let end = (start + size).min(items.len());
+            let mut list = items[..start].to_vec();
+            list.extend_from_slice(&items[end..]);
+            lists.push(list);
+        }
+        size /= 2;
+    }
+    lists
+}
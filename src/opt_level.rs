@@ -0,0 +1,50 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Optimization levels selected by `-O`, each one running the passes of the lower levels too.
+
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    /// Direct translation, with only the simplifications done while canonicalizing the IR.
+    #[default]
+    O0,
+    /// Remove the dead moves from the canonical IR, reuse the values loaded or stored in a basic
+    /// block when the stores in between cannot alias them, move the temporaries read once next to
+    /// their use to shorten their lifetimes and give the same register to both temporaries of a
+    /// move when they do not interfere, so that the move disappears.
+    O1,
+    /// Translate the conditionals testing a constant to the branch they take, evaluate the calls
+    /// to pure functions with constant arguments and fold the conditional jumps comparing
+    /// constants, removing the code they skip.
+    O2,
+}
+
+impl OptLevel {
+    /// Parse the level written after `-O`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
@@ -20,7 +20,7 @@
  */
 
 /*
- * Operator precedence:
+ * Operator precedence, from the tightest to the loosest (see binary_operator()):
  * -
  * * /
  * + -
@@ -30,14 +30,21 @@
  */
 
 use std::io::Read;
+use std::mem;
 use std::result;
+use std::time::{Duration, Instant};
 
 use ast::{
+    Arena,
+    Ast,
+    CaseArm,
+    ClassMember,
     Declaration,
     Declaration::ClassDeclaration,
     Declaration::VariableDeclaration,
     DeclarationWithPos,
     Expr,
+    ExprId,
     ExprWithPos,
     Field,
     FieldWithPos,
@@ -50,8 +57,10 @@ use ast::{
     TypeDec,
     TypeDecWithPos,
     TyWithPos,
+    Visibility,
     dummy_var_expr,
 };
+use dialect::Dialect;
 use error::Error;
 use error::Error::UnexpectedToken;
 use lexer::Lexer;
@@ -111,7 +120,11 @@ macro_rules! fields {
 pub type Result<T> = result::Result<T, Error>;
 
 pub struct Parser<'a, R: Read> {
+    arena: Arena,
+    dialect: Dialect,
     lexer: Lexer<R>,
+    // Time spent in the lexer, which is called while parsing.
+    lexing_time: Duration,
     lookahead: Option<Result<Token>>,
     symbols: &'a mut Symbols<()>,
 }
@@ -119,36 +132,28 @@ pub struct Parser<'a, R: Read> {
 impl<'a, R: Read> Parser<'a, R> {
     pub fn new(lexer: Lexer<R>, symbols: &'a mut Symbols<()>) -> Self {
         Parser {
+            arena: Arena::new(),
+            dialect: Dialect::Extended,
             lexer,
+            lexing_time: Duration::default(),
             lookahead: None,
             symbols,
         }
     }
 
-    fn additive_expr(&mut self) -> Result<ExprWithPos> {
-        let mut expr = self.multiplicative_expr()?;
-        loop {
-            let oper =
-                match self.peek_token() {
-                    Ok(&Minus) => WithPos::new(Operator::Minus, eat!(self, Minus)),
-                    Ok(&Plus) => WithPos::new(Operator::Plus, eat!(self, Plus)),
-                    _ => break,
-                };
-            let right = Box::new(self.multiplicative_expr()?);
-            let pos = expr.pos.grow(right.pos);
-            expr = WithPos::new(Expr::Oper {
-                left: Box::new(expr),
-                oper,
-                right,
-            }, pos);
-        }
-        Ok(expr)
+    pub fn lexing_time(&self) -> Duration {
+        self.lexing_time
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
     }
 
-    fn array(&mut self, size: Box<ExprWithPos>, typ: SymbolWithPos, pos: Pos) -> Result<ExprWithPos> {
+    fn array(&mut self, size: ExprId, typ: SymbolWithPos, pos: Pos) -> Result<ExprWithPos> {
         eat!(self, Of);
-        let init = Box::new(self.expr()?);
+        let init = self.expr()?;
         let pos = pos.grow(init.pos);
+        let init = self.arena.alloc(init);
 
         Ok(WithPos::new(Expr::Array {
             init,
@@ -168,12 +173,35 @@ impl<'a, R: Read> Parser<'a, R> {
         }, pos))
     }
 
+    /// Parse an expression whose binary operators have a precedence of at least `min_precedence`.
+    fn binary_expr(&mut self, min_precedence: u8) -> Result<ExprWithPos> {
+        let mut expr = self.unary_expr()?;
+        loop {
+            let (operator, precedence) =
+                match self.peek_token().ok().and_then(binary_operator) {
+                    Some((operator, precedence)) if precedence >= min_precedence => (operator, precedence),
+                    _ => break,
+                };
+            let oper = WithPos::new(operator, self.token()?.pos);
+            // The operators are left-associative, so the right operand only contains the ones
+            // binding tighter.
+            let right = self.binary_expr(precedence + 1)?;
+            let pos = expr.pos.grow(right.pos);
+            expr = WithPos::new(Expr::Oper {
+                left: self.arena.alloc(expr),
+                oper,
+                right: self.arena.alloc(right),
+            }, pos);
+        }
+        Ok(expr)
+    }
+
     fn break_(&mut self) -> Result<ExprWithPos> {
         let pos = eat!(self, Break);
         Ok(WithPos::new(Expr::Break, pos))
     }
 
-    fn call_args(&mut self) -> Result<(Vec<ExprWithPos>, Pos)> {
+    fn call_args(&mut self) -> Result<(Vec<ExprId>, Pos)> {
         eat!(self, OpenParen);
         let mut args = vec![];
         loop {
@@ -181,7 +209,7 @@ impl<'a, R: Read> Parser<'a, R> {
                 break;
             }
             let arg = self.expr()?;
-            args.push(arg);
+            args.push(self.arena.alloc(arg));
             match self.peek()?.token {
                 Comma => { self.token()?; },
                 _ => break,
@@ -213,8 +241,46 @@ impl<'a, R: Read> Parser<'a, R> {
         }
     }
 
+    /// Parse a case expression, whose arms are string literals followed by `=>` and their body.
+    /// A subject ending with a subscript must be parenthesized, since it would be parsed as an
+    /// array creation.
+    fn case_expr(&mut self) -> Result<ExprWithPos> {
+        let pos = eat!(self, Case);
+        self.require_dialect(Dialect::Extended, "Case expression", pos)?;
+        let subject = self.expr()?;
+        let subject = self.arena.alloc(subject);
+        eat!(self, Of);
+        let mut arms = vec![];
+        while let Str(_) = self.peek()?.token {
+            let label;
+            let label_pos = eat!(self, Str, label);
+            eat!(self, Arrow);
+            let body = self.expr()?;
+            arms.push(WithPos::new(CaseArm {
+                body: self.arena.alloc(body),
+                label,
+            }, label_pos));
+        }
+        let else_ =
+            if let Else = self.peek()?.token {
+                eat!(self, Else);
+                let else_ = self.expr()?;
+                Some(self.arena.alloc(else_))
+            }
+            else {
+                None
+            };
+        let end_pos = eat!(self, End, "else, end or string literal".to_string());
+        Ok(WithPos::new(Expr::Case {
+            arms,
+            else_,
+            subject,
+        }, pos.grow(end_pos)))
+    }
+
     fn class_dec(&mut self) -> Result<DeclarationWithPos> {
         let pos = eat!(self, Class);
+        self.require_dialect(Dialect::ObjectTiger, "Class declaration", pos)?;
         let name;
         let ident_pos = eat!(self, Ident, name);
         let name = WithPos::new(self.symbols.symbol(&name), ident_pos);
@@ -224,19 +290,56 @@ impl<'a, R: Read> Parser<'a, R> {
         let parent_class = WithPos::new(self.symbols.symbol(&parent_class), ident_pos);
         eat!(self, OpenCurly);
 
-        let mut declarations = vec![];
-        while let Method | Var = self.peek()?.token {
-            declarations.push(self.dec()?);
+        let mut members = vec![];
+        while let Ident(_) | Method | Var = self.peek()?.token {
+            members.push(self.class_member()?);
         }
 
         let end_pos = eat!(self, CloseCurly);
         Ok(WithPos::new(ClassDeclaration {
-            declarations,
+            members,
             name,
             parent_class,
         }, pos.grow(end_pos)))
     }
 
+    /// Parse an attribute or a method, optionally preceded by `private` or `public` and by
+    /// `static`, which are only keywords in a class body.
+    fn class_member(&mut self) -> Result<ClassMember> {
+        let visibility =
+            match self.peek()?.token {
+                Ident(ref ident) if ident == "private" => Some(Visibility::Private),
+                Ident(ref ident) if ident == "public" => Some(Visibility::Public),
+                _ => None,
+            };
+        if visibility.is_some() {
+            self.token()?;
+        }
+        let is_static =
+            match self.peek()?.token {
+                Ident(ref ident) => ident == "static",
+                _ => false,
+            };
+        if is_static {
+            self.token()?;
+        }
+        let declaration =
+            match self.peek()?.token {
+                Method => {
+                    let method = self.fun_dec(Method)?;
+                    let pos = method.pos;
+                    WithPos::new(Declaration::Function(vec![method]), pos)
+                },
+                Var => self.var_dec()?,
+                _ => return Err(self.unexpected_token("method, var, private, public or static")?),
+            };
+        Ok(ClassMember {
+            declaration,
+            is_static,
+            visibility: visibility.unwrap_or(Visibility::Public),
+        })
+    }
+
     fn dec(&mut self) -> Result<DeclarationWithPos> {
         match self.peek()?.token {
             Class => self.class_dec(),
@@ -248,8 +351,33 @@ impl<'a, R: Read> Parser<'a, R> {
         }
     }
 
+    fn deref(&mut self, pointer: ExprWithPos) -> Result<ExprWithPos> {
+        let end_pos = eat!(self, Caret);
+        self.require_dialect(Dialect::Unsafe, "Pointer dereference", end_pos)?;
+        let pos = pointer.pos.grow(end_pos);
+        let var = WithPos::new(Expr::Deref {
+            pointer: self.arena.alloc(pointer),
+        }, pos);
+        self.lvalue(var)
+    }
+
     fn expr(&mut self) -> Result<ExprWithPos> {
-        self.logical_or_expr()
+        self.binary_expr(0)
+    }
+
+    /// Parse the expressions separated by semicolons up to `end`, which is not eaten.
+    fn exprs(&mut self, end: Tok) -> Result<Vec<ExprId>> {
+        let mut exprs = vec![];
+        if self.peek()?.token != end {
+            let expr = self.expr()?;
+            exprs.push(self.arena.alloc(expr));
+            while let Semicolon = self.peek()?.token {
+                eat!(self, Semicolon);
+                let expr = self.expr()?;
+                exprs.push(self.arena.alloc(expr));
+            }
+        }
+        Ok(exprs)
     }
 
     fn field_dec(&mut self) -> Result<FieldWithPos> {
@@ -273,12 +401,13 @@ impl<'a, R: Read> Parser<'a, R> {
         let pos = eat!(self, Ident, name);
         let name = WithPos::new(self.symbols.symbol(&name), pos);
         if self.peek()?.token == OpenParen {
+            self.require_dialect(Dialect::ObjectTiger, "Method call", pos)?;
             let (args, end_pos) = self.call_args()?;
             let call_pos = var.pos.grow(end_pos);
             let method_call = WithPos::new(Expr::MethodCall {
                 args,
                 method: name,
-                this: Box::new(var),
+                this: self.arena.alloc(var),
             }, call_pos);
             self.lvalue(method_call)
         }
@@ -286,7 +415,7 @@ impl<'a, R: Read> Parser<'a, R> {
             let var_pos = var.pos.grow(pos);
             let var = WithPos::new(Expr::Field {
                 ident: name,
-                this: Box::new(var),
+                this: self.arena.alloc(var),
             }, var_pos);
             self.lvalue(var)
         }
@@ -309,7 +438,7 @@ impl<'a, R: Read> Parser<'a, R> {
         eat!(self, Equal);
         let expr = self.expr()?;
         Ok(WithPos::new(RecordField {
-            expr,
+            expr: self.arena.alloc(expr),
             ident,
         }, pos))
     }
@@ -332,58 +461,69 @@ impl<'a, R: Read> Parser<'a, R> {
         let declarations = vec![
             WithPos::dummy(VariableDeclaration {
                 escape: false,
-                init: start,
+                init: self.arena.alloc(start),
+                loop_variable: true,
                 name: start_symbol,
                 typ: None,
             }),
             WithPos::dummy(VariableDeclaration {
                 escape: false,
-                init: end,
+                init: self.arena.alloc(end),
+                loop_variable: false,
                 name: end_symbol,
                 typ: None,
             }),
         ];
-        let body =
-            Expr::If {
-                else_: None,
-                test: Box::new(
-                    WithPos::dummy(Expr::Oper {
-                        left: Box::new(iter_variable.clone()),
-                        oper: WithPos::dummy(Operator::Le),
-                        right: Box::new(dummy_var_expr(end_symbol)),
-                    })
-                ),
-                then:
-                    Box::new(WithPos::dummy(Expr::While {
-                        body: Box::new(WithPos::dummy(Expr::Sequence(vec![
-                            body,
-                            WithPos::dummy(Expr::If {
-                                else_: Some(Box::new(WithPos::dummy(Expr::Break))),
-                                test:
-                                    Box::new(WithPos::dummy(Expr::Oper {
-                                        left: Box::new(iter_variable.clone()),
-                                        oper: WithPos::dummy(Operator::Lt),
-                                        right: Box::new(dummy_var_expr(end_symbol)),
-                                    })),
-                                then:
-                                    Box::new(WithPos::dummy(Expr::Assign {
-                                        expr: Box::new(WithPos::dummy(Expr::Oper {
-                                            left: Box::new(iter_variable.clone()),
-                                            oper: WithPos::dummy(Operator::Plus),
-                                            right: Box::new(WithPos::dummy(Expr::Int { value: 1 })),
-                                        })),
-                                        var: Box::new(iter_variable),
-                                    })),
-                            }),
-                        ]))),
-                        test: Box::new(WithPos::dummy(Expr::Int {
-                            value: 1,
-                        })),
-                    })),
-            };
+        let arena = &mut self.arena;
+        let variable = arena.alloc(iter_variable.clone());
+        let limit = dummy_var_expr(arena, end_symbol);
+        let test = arena.alloc(WithPos::dummy(Expr::Oper {
+            left: variable,
+            oper: WithPos::dummy(Operator::Le),
+            right: limit,
+        }));
+        let variable = arena.alloc(iter_variable.clone());
+        let limit = dummy_var_expr(arena, end_symbol);
+        let next_test = arena.alloc(WithPos::dummy(Expr::Oper {
+            left: variable,
+            oper: WithPos::dummy(Operator::Lt),
+            right: limit,
+        }));
+        let variable = arena.alloc(iter_variable.clone());
+        let one = arena.alloc(WithPos::dummy(Expr::Int { value: 1 }));
+        let increment = arena.alloc(WithPos::dummy(Expr::Oper {
+            left: variable,
+            oper: WithPos::dummy(Operator::Plus),
+            right: one,
+        }));
+        let variable = arena.alloc(iter_variable);
+        let assign = arena.alloc(WithPos::dummy(Expr::Assign {
+            expr: increment,
+            var: variable,
+        }));
+        let break_ = arena.alloc(WithPos::dummy(Expr::Break));
+        let next = arena.alloc(WithPos::dummy(Expr::If {
+            else_: Some(break_),
+            test: next_test,
+            then: assign,
+        }));
+        let body = arena.alloc(body);
+        let loop_body = arena.alloc(WithPos::dummy(Expr::Sequence(vec![body, next])));
+        let loop_test = arena.alloc(WithPos::dummy(Expr::Int {
+            value: 1,
+        }));
+        let while_loop = arena.alloc(WithPos::dummy(Expr::While {
+            body: loop_body,
+            test: loop_test,
+        }));
+        let body = arena.alloc(WithPos::dummy(Expr::If {
+            else_: None,
+            test,
+            then: while_loop,
+        }));
 
         Ok(WithPos::new(Expr::Let {
-            body: Box::new(WithPos::dummy(body)),
+            body,
             declarations,
         }, pos))
     }
@@ -412,7 +552,7 @@ impl<'a, R: Read> Parser<'a, R> {
         eat!(self, Equal);
         let body = self.expr()?;
         Ok(WithPos::new(FuncDeclaration {
-            body,
+            body: self.arena.alloc(body),
             name,
             params,
             result,
@@ -421,18 +561,21 @@ impl<'a, R: Read> Parser<'a, R> {
 
     fn if_then_else(&mut self) -> Result<ExprWithPos> {
         let pos = eat!(self, If);
-        let test = Box::new(self.expr()?);
+        let test = self.expr()?;
+        let test = self.arena.alloc(test);
         eat!(self, Then);
-        let then = Box::new(self.expr()?);
+        let then = self.expr()?;
+        let then_pos = then.pos;
+        let then = self.arena.alloc(then);
         let (else_, end_pos) =
             if let Else = self.peek()?.token {
                 eat!(self, Else);
                 let expr = self.expr()?;
                 let end_pos = expr.pos;
-                (Some(Box::new(expr)), end_pos)
+                (Some(self.arena.alloc(expr)), end_pos)
             }
             else {
-                (None, then.pos)
+                (None, then_pos)
             };
         Ok(WithPos::new(Expr::If {
             else_,
@@ -455,53 +598,20 @@ impl<'a, R: Read> Parser<'a, R> {
         while let Class | Function | Type | Var = self.peek()?.token {
             declarations.push(self.dec()?);
         }
-        eat!(self, In, "class, function, in, type, var".to_string());
-        let expr = self.expr()?;
-        let mut exprs = vec![expr];
-        while let Semicolon = self.peek()?.token {
-            eat!(self, Semicolon);
-            exprs.push(self.expr()?);
-        }
+        let in_pos = eat!(self, In, "class, function, in, type, var".to_string());
+        // The body can be empty, like in the libraries which only declare functions.
+        let exprs = self.exprs(End)?;
         eat!(self, End);
-        let body_pos = exprs[0].pos;
+        let body_pos = exprs.first().map_or(in_pos, |&expr| self.arena[expr].pos);
         Ok(WithPos::new(Expr::Let {
-            body: Box::new(WithPos::new(Expr::Sequence(exprs), body_pos)),
+            body: self.arena.alloc(WithPos::new(Expr::Sequence(exprs), body_pos)),
             declarations,
         }, pos))
     }
 
-    fn logical_and_expr(&mut self) -> Result<ExprWithPos> {
-        let mut expr = self.relational_expr()?;
-        while let Ok(&Ampersand) = self.peek_token() {
-            let oper_pos = eat!(self, Ampersand);
-            let right = Box::new(self.relational_expr()?);
-            let pos = expr.pos.grow(right.pos);
-            expr = WithPos::new(Expr::Oper {
-                left: Box::new(expr),
-                oper: WithPos::new(Operator::And, oper_pos),
-                right,
-            }, pos);
-        }
-        Ok(expr)
-    }
-
-    fn logical_or_expr(&mut self) -> Result<ExprWithPos> {
-        let mut expr = self.logical_and_expr()?;
-        while let Ok(&Pipe) = self.peek_token() {
-            let oper_pos = eat!(self, Pipe);
-            let right = Box::new(self.logical_and_expr()?);
-            let pos = expr.pos.grow(right.pos);
-            expr = WithPos::new(Expr::Oper {
-                left: Box::new(expr),
-                oper: WithPos::new(Operator::Or, oper_pos),
-                right,
-            }, pos);
-        }
-        Ok(expr)
-    }
-
     fn lvalue(&mut self, var: ExprWithPos) -> Result<ExprWithPos> {
         match self.peek()?.token {
+            Caret => self.deref(var),
             OpenSquare => self.subscript(var),
             Dot => self.field_exp_or_method_call(var),
             _ => Ok(var),
@@ -514,15 +624,17 @@ impl<'a, R: Read> Parser<'a, R> {
             if let Of = self.peek()?.token {
                 match var.node {
                     Expr::Subscript { expr, this } => {
-                        let pos = this.pos;
-                        if let Expr::Variable(ident) = this.node {
-                            return self.array(expr, WithPos::new(ident.node, pos), pos);
+                        let pos = self.arena[this].pos;
+                        if let Expr::Variable(ref ident) = self.arena[this].node {
+                            let ident = ident.node;
+                            return self.array(expr, WithPos::new(ident, pos), pos);
                         }
                         else {
                             return Err(self.unexpected_token("neither dot nor subscript")?);
                         }
                     },
-                    _ => return Err(self.unexpected_token(":= or (nothing)")?),
+                    // NOTE: the subject of a case expression.
+                    _ => var,
                 }
             }
             else {
@@ -530,11 +642,11 @@ impl<'a, R: Read> Parser<'a, R> {
             };
         if let ColonEqual = self.peek()?.token {
             eat!(self, ColonEqual);
-            let expr = Box::new(self.expr()?);
+            let expr = self.expr()?;
             let pos = value.pos.grow(expr.pos);
             Ok(WithPos::new(Expr::Assign {
-                expr,
-                var: Box::new(value),
+                expr: self.arena.alloc(expr),
+                var: self.arena.alloc(value),
             }, pos))
         }
         else {
@@ -542,28 +654,9 @@ impl<'a, R: Read> Parser<'a, R> {
         }
     }
 
-    fn multiplicative_expr(&mut self) -> Result<ExprWithPos> {
-        let mut expr = self.unary_expr()?;
-        loop {
-            let oper =
-                match self.peek_token() {
-                    Ok(&Slash) => WithPos::new(Operator::Divide, eat!(self, Slash)),
-                    Ok(&Star) => WithPos::new(Operator::Times, eat!(self, Star)),
-                    _ => break,
-                };
-            let right = Box::new(self.unary_expr()?);
-            let pos = expr.pos.grow(right.pos);
-            expr = WithPos::new(Expr::Oper {
-                left: Box::new(expr),
-                oper,
-                right,
-            }, pos);
-        }
-        Ok(expr)
-    }
-
     fn new_object(&mut self) -> Result<ExprWithPos> {
         let pos = eat!(self, New);
+        self.require_dialect(Dialect::ObjectTiger, "Object creation", pos)?;
         let class_name;
         let end_pos = eat!(self, Ident, class_name);
         let class_name = WithPos::new(self.symbols.symbol(&class_name), end_pos);
@@ -572,6 +665,13 @@ impl<'a, R: Read> Parser<'a, R> {
         }, pos.grow(end_pos)))
     }
 
+    fn next_token(&mut self) -> Result<Token> {
+        let start = Instant::now();
+        let token = self.lexer.token();
+        self.lexing_time += start.elapsed();
+        token
+    }
+
     fn nil(&mut self) -> Result<ExprWithPos> {
         let pos = eat!(self, Nil);
         Ok(WithPos::new(Expr::Nil, pos))
@@ -592,6 +692,7 @@ impl<'a, R: Read> Parser<'a, R> {
     fn primary_expr(&mut self) -> Result<ExprWithPos> {
         match self.peek()?.token {
             Break => self.break_(),
+            Case => self.case_expr(),
             For => self.for_loop(),
             If => self.if_then_else(),
             Ident(_) => self.call_expr_or_other(),
@@ -602,7 +703,7 @@ impl<'a, R: Read> Parser<'a, R> {
             OpenParen => self.seq_exp(),
             Str(_) => self.string_lit(),
             While => self.while_loop(),
-            _ => Err(self.unexpected_token("break, for, if, identifier, integer literal, let, nil, (, string literal, while")?),
+            _ => Err(self.unexpected_token("break, case, for, if, identifier, integer literal, let, nil, (, string literal, while")?),
         }
     }
 
@@ -632,39 +733,23 @@ impl<'a, R: Read> Parser<'a, R> {
         }, pos))
     }
 
-    fn relational_expr(&mut self) -> Result<ExprWithPos> {
-        let mut expr = self.additive_expr()?;
-        loop {
-            let oper =
-                match self.peek_token() {
-                    Ok(&Equal) => WithPos::new(Operator::Equal, eat!(self, Equal)),
-                    Ok(&Greater) => WithPos::new(Operator::Gt, eat!(self, Greater)),
-                    Ok(&GreaterOrEqual) => WithPos::new(Operator::Ge, eat!(self, GreaterOrEqual)),
-                    Ok(&Lesser) => WithPos::new(Operator::Lt, eat!(self, Lesser)),
-                    Ok(&LesserOrEqual) => WithPos::new(Operator::Le, eat!(self, LesserOrEqual)),
-                    Ok(&NotEqual) => WithPos::new(Operator::Neq, eat!(self, NotEqual)),
-                    _ => break,
-                };
-            let right = Box::new(self.additive_expr()?);
-            let pos = expr.pos.grow(right.pos);
-            expr = WithPos::new(Expr::Oper {
-                left: Box::new(expr),
-                oper,
-                right,
-            }, pos);
+    fn require_dialect(&self, dialect: Dialect, feature: &str, pos: Pos) -> Result<()> {
+        if self.dialect < dialect {
+            return Err(Error::NonStandard {
+                dialect,
+                feature: feature.to_string(),
+                pos,
+            });
         }
-        Ok(expr)
+        Ok(())
     }
 
     fn seq_exp(&mut self) -> Result<ExprWithPos> {
-        eat!(self, OpenParen);
-        let mut exprs = vec![self.expr()?];
-        while let Semicolon = self.peek()?.token {
-            eat!(self, Semicolon);
-            exprs.push(self.expr()?);
-        }
+        let open_pos = eat!(self, OpenParen);
+        // () is the empty sequence, which produces no value.
+        let exprs = self.exprs(CloseParen)?;
         eat!(self, CloseParen);
-        let pos = exprs[0].pos;
+        let pos = exprs.first().map_or(open_pos, |&expr| self.arena[expr].pos);
         Ok(WithPos::new(Expr::Sequence(exprs), pos))
     }
 
@@ -678,12 +763,12 @@ impl<'a, R: Read> Parser<'a, R> {
 
     fn subscript(&mut self, var: ExprWithPos) -> Result<ExprWithPos> {
         eat!(self, OpenSquare);
-        let expr = Box::new(self.expr()?);
+        let expr = self.expr()?;
         let end_pos = eat!(self, CloseSquare);
         let pos = var.pos.grow(end_pos);
         let var = WithPos::new(Expr::Subscript {
-            expr,
-            this: Box::new(var),
+            expr: self.arena.alloc(expr),
+            this: self.arena.alloc(var),
         }, pos);
         self.lvalue(var)
     }
@@ -695,6 +780,29 @@ impl<'a, R: Read> Parser<'a, R> {
             Ident(_) => {
                 let type_name;
                 let pos = eat!(self, Ident, type_name);
+                // NOTE: weak is only a keyword when followed by a type name.
+                if type_name == "weak" {
+                    if let Ident(_) = self.peek()?.token {
+                        let type_name;
+                        let ident_pos = eat!(self, Ident, type_name);
+                        let ident = self.symbols.symbol(&type_name);
+                        return Ok(WithPos::new(Ty::Weak {
+                            ident: WithPos::new(ident, ident_pos),
+                        }, pos.grow(ident_pos)));
+                    }
+                }
+                // NOTE: so is pointer.
+                if type_name == "pointer" {
+                    if let Ident(_) = self.peek()?.token {
+                        self.require_dialect(Dialect::Unsafe, "Pointer type", pos)?;
+                        let type_name;
+                        let ident_pos = eat!(self, Ident, type_name);
+                        let ident = self.symbols.symbol(&type_name);
+                        return Ok(WithPos::new(Ty::Pointer {
+                            ident: WithPos::new(ident, ident_pos),
+                        }, pos.grow(ident_pos)));
+                    }
+                }
                 let ident = self.symbols.symbol(&type_name);
                 Ok(WithPos::new(Ty::Name {
                     ident: WithPos::new(ident, pos),
@@ -729,16 +837,28 @@ impl<'a, R: Read> Parser<'a, R> {
 
     fn unary_expr(&mut self) -> Result<ExprWithPos> {
         match self.peek()?.token {
+            Ampersand => {
+                let pos = eat!(self, Ampersand);
+                self.require_dialect(Dialect::Unsafe, "Address-of", pos)?;
+                let name;
+                let var_pos = eat!(self, Ident, name);
+                let var = WithPos::new(Expr::Variable(WithPos::new(self.symbols.symbol(&name), var_pos)), var_pos);
+                let var = self.lvalue(var)?;
+                let pos = pos.grow(var.pos);
+                Ok(WithPos::new(Expr::AddressOf {
+                    var: self.arena.alloc(var),
+                }, pos))
+            },
             Minus => {
                 let pos = eat!(self, Minus);
                 let expr = self.unary_expr()?;
                 let pos = pos.grow(expr.pos);
                 Ok(WithPos::new(Expr::Oper {
-                    left: Box::new(WithPos::new(Expr::Int {
+                    left: self.arena.alloc(WithPos::new(Expr::Int {
                         value: 0,
                     }, pos)),
                     oper: WithPos::new(Operator::Minus, pos),
-                    right: Box::new(expr),
+                    right: self.arena.alloc(expr),
                 }, pos))
             },
             _ => self.primary_expr(),
@@ -755,7 +875,8 @@ impl<'a, R: Read> Parser<'a, R> {
         let init = self.expr()?;
         Ok(WithPos::new(VariableDeclaration {
             escape: false,
-            init,
+            init: self.arena.alloc(init),
+            loop_variable: false,
             name,
             typ,
         }, pos))
@@ -763,26 +884,48 @@ impl<'a, R: Read> Parser<'a, R> {
 
     fn while_loop(&mut self) -> Result<ExprWithPos> {
         let pos = eat!(self, While);
-        let test = Box::new(self.expr()?);
+        let test = self.expr()?;
+        let test = self.arena.alloc(test);
         eat!(self, Do);
-        let body = Box::new(self.expr()?);
+        let body = self.expr()?;
+        let body = self.arena.alloc(body);
         Ok(WithPos::new(Expr::While {
             body,
             test,
         }, pos))
     }
 
-    pub fn parse(&mut self) -> Result<ExprWithPos> {
+    /// Parse the program, reporting the comments and strings left open with the error which
+    /// stopped the parsing, if any.
+    pub fn parse(&mut self) -> Result<Ast> {
+        let result = self.program();
+        let mut errors = self.lexer.take_errors();
+        if errors.is_empty() {
+            let root = result?;
+            return Ok(Ast {
+                arena: mem::take(&mut self.arena),
+                root,
+            });
+        }
+        match result {
+            // The end of the file is reached too soon because of the comment or string left open.
+            Ok(_) | Err(UnexpectedToken { unexpected: EndOfFile, .. }) => (),
+            Err(error) => errors.push(error),
+        }
+        Err(Error::Multi(errors))
+    }
+
+    fn program(&mut self) -> Result<ExprId> {
         let main_expression = self.expr()?;
         match self.token() {
-            Ok(Token { token: EndOfFile, .. }) | Err(Error::Eof) => Ok(main_expression),
+            Ok(Token { token: EndOfFile, .. }) | Err(Error::Eof) => Ok(self.arena.alloc(main_expression)),
             _ => Err(self.unexpected_token("end of file")?),
         }
     }
 
     fn peek(&mut self) -> result::Result<&Token, &Error> {
         if self.lookahead.is_none() {
-            self.lookahead = Some(self.lexer.token());
+            self.lookahead = Some(self.next_token());
         }
         // NOTE: lookahead always contain a value, hence unwrap.
         self.lookahead.as_ref().unwrap()
@@ -798,7 +941,7 @@ impl<'a, R: Read> Parser<'a, R> {
         if let Some(token) = self.lookahead.take() {
             return token;
         }
-        self.lexer.token()
+        self.next_token()
     }
 
     fn unexpected_token(&mut self, expected: &str) -> Result<Error> {
@@ -810,3 +953,71 @@ impl<'a, R: Read> Parser<'a, R> {
         })
     }
 }
+
+/// The operator of a binary operator token, with its precedence: an operator binds tighter than
+/// the ones with a lower precedence.
+fn binary_operator(token: &Tok) -> Option<(Operator, u8)> {
+    let operator =
+        match *token {
+            Pipe => (Operator::Or, 1),
+            Ampersand => (Operator::And, 2),
+            Equal => (Operator::Equal, 3),
+            Greater => (Operator::Gt, 3),
+            GreaterOrEqual => (Operator::Ge, 3),
+            Lesser => (Operator::Lt, 3),
+            LesserOrEqual => (Operator::Le, 3),
+            NotEqual => (Operator::Neq, 3),
+            Minus => (Operator::Minus, 4),
+            Plus => (Operator::Plus, 4),
+            Slash => (Operator::Divide, 5),
+            Star => (Operator::Times, 5),
+            _ => return None,
+        };
+    Some(operator)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ast::{Arena, Expr, ExprId};
+    use lexer::Lexer;
+    use parser::Parser;
+    use symbol::{Strings, Symbols};
+
+    /// The operators of the expression in prefix notation.
+    fn prefix(arena: &Arena, expr: ExprId) -> String {
+        match arena[expr].node {
+            Expr::Int { value } => value.to_string(),
+            Expr::Oper { left, ref oper, right } =>
+                format!("({:?} {} {})", oper.node, prefix(arena, left), prefix(arena, right)),
+            ref expr => panic!("unexpected expression {:?}", expr),
+        }
+    }
+
+    fn parse(source: &str) -> String {
+        let mut symbols = Symbols::new(Arc::new(Strings::new()));
+        let file_symbol = symbols.symbol("no_file");
+        // The lexer expects a character after the last token.
+        let source = format!("{}\n", source);
+        let lexer = Lexer::new(source.as_bytes(), file_symbol);
+        let mut parser = Parser::new(lexer, &mut symbols);
+        let ast = parser.parse().expect("parse");
+        prefix(&ast.arena, ast.root)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(Plus 1 (Times 2 3))");
+        assert_eq!(parse("1 * 2 + 3 / 4"), "(Plus (Times 1 2) (Divide 3 4))");
+        assert_eq!(parse("1 = 2 | 3 < 4 & 5 >= 6"), "(Or (Equal 1 2) (And (Lt 3 4) (Ge 5 6)))");
+        assert_eq!(parse("-1 * 2 <> 3"), "(Neq (Times (Minus 0 1) 2) 3)");
+    }
+
+    #[test]
+    fn test_associativity() {
+        assert_eq!(parse("1 - 2 - 3"), "(Minus (Minus 1 2) 3)");
+        assert_eq!(parse("8 / 4 / 2"), "(Divide (Divide 8 4) 2)");
+        assert_eq!(parse("1 | 2 | 3"), "(Or (Or 1 2) 3)");
+    }
+}
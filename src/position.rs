@@ -49,6 +49,10 @@ impl Pos {
         Self::new(u32::MAX, u32::MAX, u64::MAX, 0, 0)
     }
 
+    pub fn is_dummy(&self) -> bool {
+        self.byte == u64::MAX
+    }
+
     pub fn grow(&self, pos: Pos) -> Self {
         Pos {
             byte: self.byte,
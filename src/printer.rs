@@ -0,0 +1,582 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Tiger source of an AST, printed by `--emit source`.
+//!
+//! The parentheses of the source are kept as sequences of one expression, and the printer only adds
+//! those needed to parse the same tree again: around the operands binding less tightly than their
+//! operator and around the expressions which would swallow what follows them, like an `if`
+//! without `else`. Printing the source parsed from the output thus gives the same output.
+
+use ast::{
+    Arena,
+    Ast,
+    Declaration,
+    DeclarationWithPos,
+    Expr,
+    ExprId,
+    FieldWithPos,
+    Operator,
+    Ty,
+    TyWithPos,
+    Visibility,
+};
+use symbol::{Symbol, Symbols};
+
+const INDENT: &str = "    ";
+
+pub fn print(ast: &Ast, symbols: &Symbols<()>) -> String {
+    let mut printer = Printer {
+        arena: &ast.arena,
+        hoisted: vec![],
+        indent: 0,
+        output: String::new(),
+        symbols,
+    };
+    printer.expr(ast.root);
+    printer.output.push('\n');
+    printer.output
+}
+
+struct Printer<'a> {
+    arena: &'a Arena,
+    // Lets whose declarations are printed by an enclosing let, so that only their body is printed.
+    hoisted: Vec<ExprId>,
+    indent: usize,
+    output: String,
+    symbols: &'a Symbols<()>,
+}
+
+impl<'a> Printer<'a> {
+    fn declaration(&mut self, declaration: &DeclarationWithPos, in_class: bool) {
+        match declaration.node {
+            Declaration::ClassDeclaration { ref members, ref name, ref parent_class } => {
+                self.write("class ");
+                self.symbol(name.node);
+                self.write(" extends ");
+                self.symbol(parent_class.node);
+                self.write(" {");
+                self.indent += 1;
+                for member in members {
+                    self.newline();
+                    if member.visibility == Visibility::Private {
+                        self.write("private ");
+                    }
+                    if member.is_static {
+                        self.write("static ");
+                    }
+                    self.declaration(&member.declaration, true);
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            },
+            Declaration::Function(ref functions) => {
+                for (index, function) in functions.iter().enumerate() {
+                    if index > 0 {
+                        self.newline();
+                    }
+                    self.write(if in_class { "method " } else { "function " });
+                    self.symbol(function.node.name.node);
+                    self.write("(");
+                    self.fields(&function.node.params);
+                    self.write(")");
+                    if let Some(ref result) = function.node.result {
+                        self.write(": ");
+                        self.symbol(result.node);
+                    }
+                    self.write(" =");
+                    self.indent += 1;
+                    self.newline();
+                    self.expr(function.node.body);
+                    self.indent -= 1;
+                }
+            },
+            Declaration::Type(ref types) => {
+                for (index, declaration) in types.iter().enumerate() {
+                    if index > 0 {
+                        self.newline();
+                    }
+                    self.write("type ");
+                    self.symbol(declaration.node.name.node);
+                    self.write(" = ");
+                    self.ty(&declaration.node.ty);
+                }
+            },
+            Declaration::VariableDeclaration { init, name, ref typ, .. } => {
+                self.write("var ");
+                self.symbol(name);
+                if let Some(ref typ) = *typ {
+                    self.write(": ");
+                    self.symbol(typ.node);
+                }
+                self.write(" := ");
+                self.expr(init);
+            },
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let arena = self.arena;
+        if let Some(hoisted) = hoist_let(arena, expr, &self.hoisted) {
+            if let Expr::Let { ref declarations, .. } = arena[hoisted].node {
+                self.hoisted.push(hoisted);
+                self.let_expr(declarations, expr);
+                self.hoisted.pop();
+            }
+            return;
+        }
+        match arena[expr].node {
+            Expr::AddressOf { var } => {
+                self.write("&");
+                self.expr(var);
+            },
+            Expr::Array { init, size, ref typ } => {
+                self.symbol(typ.node);
+                self.write(" [");
+                self.expr(size);
+                self.write("] of ");
+                self.expr(init);
+            },
+            Expr::Assign { expr, var } => {
+                self.expr(var);
+                self.write(" := ");
+                self.expr(expr);
+            },
+            Expr::Break => self.write("break"),
+            Expr::Call { ref args, function } => {
+                self.symbol(function);
+                self.args(args);
+            },
+            Expr::Case { ref arms, else_, subject } => {
+                self.write("case ");
+                // NOTE: a subject ending with a subscript would be parsed as an array creation.
+                if let Expr::Subscript { .. } = arena[skip_empty_lets(arena, subject)].node {
+                    self.parenthesized(subject);
+                }
+                else {
+                    self.closed(subject);
+                }
+                self.write(" of");
+                self.indent += 1;
+                for arm in arms {
+                    self.newline();
+                    self.string(&arm.node.label);
+                    self.write(" => ");
+                    self.closed(arm.node.body);
+                }
+                if let Some(else_) = else_ {
+                    self.newline();
+                    self.write("else ");
+                    self.closed(else_);
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("end");
+            },
+            Expr::Deref { pointer } => {
+                self.expr(pointer);
+                self.write("^");
+            },
+            Expr::Field { ref ident, this } => {
+                self.expr(this);
+                self.write(".");
+                self.symbol(ident.node);
+            },
+            Expr::If { else_, test, then } => {
+                self.write("if ");
+                self.expr(test);
+                self.write(" then ");
+                match else_ {
+                    Some(else_) => {
+                        // NOTE: the else would belong to an if ending the then branch.
+                        self.closed(then);
+                        self.write(" else ");
+                        self.expr(else_);
+                    },
+                    None => self.expr(then),
+                }
+            },
+            Expr::Int { value } => self.write(&value.to_string()),
+            Expr::Let { body, ref declarations } => {
+                // NOTE: a let needs a declaration, like the ones the rewriter adds for the parameters.
+                if declarations.is_empty() || self.hoisted.contains(&expr) {
+                    self.expr(body);
+                }
+                else {
+                    self.let_expr(declarations, body);
+                }
+            },
+            Expr::MethodCall { ref args, ref method, this } => {
+                self.expr(this);
+                self.write(".");
+                self.symbol(method.node);
+                self.args(args);
+            },
+            Expr::New { ref class_name } => {
+                self.write("new ");
+                self.symbol(class_name.node);
+            },
+            Expr::Nil => self.write("nil"),
+            Expr::Oper { left, ref oper, right } => {
+                let precedence = precedence(oper.node);
+                self.operand(left, precedence);
+                self.write(" ");
+                self.write(operator(oper.node));
+                self.write(" ");
+                // NOTE: the operators are left-associative.
+                self.operand(right, precedence + 1);
+            },
+            Expr::Record { ref fields, ref typ } => {
+                self.symbol(typ.node);
+                self.write(" {");
+                for (index, field) in fields.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    self.symbol(field.node.ident);
+                    self.write(" = ");
+                    self.expr(field.node.expr);
+                }
+                self.write("}");
+            },
+            Expr::Sequence(ref exprs) => {
+                match exprs.len() {
+                    0 => self.write("()"),
+                    1 => self.parenthesized(exprs[0]),
+                    _ => {
+                        self.write("(");
+                        self.indent += 1;
+                        for (index, &expr) in exprs.iter().enumerate() {
+                            if index > 0 {
+                                self.write(";");
+                            }
+                            self.newline();
+                            self.expr(expr);
+                        }
+                        self.indent -= 1;
+                        self.newline();
+                        self.write(")");
+                    },
+                }
+            },
+            Expr::Str { ref value } => self.string(value),
+            Expr::Subscript { expr, this } => {
+                self.expr(this);
+                self.write("[");
+                self.expr(expr);
+                self.write("]");
+            },
+            Expr::Variable(ref var) => self.symbol(var.node),
+            Expr::While { body, test } => {
+                self.write("while ");
+                self.expr(test);
+                self.write(" do ");
+                self.expr(body);
+            },
+        }
+    }
+
+    fn args(&mut self, args: &[ExprId]) {
+        self.write("(");
+        for (index, &arg) in args.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.expr(arg);
+        }
+        self.write(")");
+    }
+
+    /// Print the expression, in parentheses if it would swallow what follows it.
+    fn closed(&mut self, expr: ExprId) {
+        if is_open(self.arena, expr) {
+            self.parenthesized(expr);
+        }
+        else {
+            self.expr(expr);
+        }
+    }
+
+    fn fields(&mut self, fields: &[FieldWithPos]) {
+        for (index, field) in fields.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.symbol(field.node.name);
+            self.write(": ");
+            self.symbol(field.node.typ.node);
+        }
+    }
+
+    /// Print a let declaring `declarations` with `body`.
+    fn let_expr(&mut self, declarations: &[DeclarationWithPos], body: ExprId) {
+        self.write("let");
+        self.indent += 1;
+        for declaration in declarations {
+            self.newline();
+            self.declaration(declaration, false);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.write("in");
+        self.indent += 1;
+        match self.arena[body].node {
+            Expr::Sequence(ref exprs) => {
+                for (index, &expr) in exprs.iter().enumerate() {
+                    if index > 0 {
+                        self.write(";");
+                    }
+                    self.newline();
+                    self.expr(expr);
+                }
+            },
+            _ => {
+                self.newline();
+                self.expr(body);
+            },
+        }
+        self.indent -= 1;
+        self.newline();
+        self.write("end");
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    /// Print the operand of an operator, in parentheses if it binds less tightly than
+    /// `min_precedence`.
+    fn operand(&mut self, expr: ExprId, min_precedence: u8) {
+        match self.arena[skip_empty_lets(self.arena, expr)].node {
+            Expr::Oper { ref oper, .. } if precedence(oper.node) < min_precedence => self.parenthesized(expr),
+            _ => self.closed(expr),
+        }
+    }
+
+    fn parenthesized(&mut self, expr: ExprId) {
+        self.write("(");
+        self.expr(expr);
+        self.write(")");
+    }
+
+    fn string(&mut self, string: &str) {
+        self.output.push('"');
+        for char in string.chars() {
+            match char {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\t' => self.output.push_str("\\t"),
+                char if char.is_ascii_control() => self.output.push_str(&format!("\\{:03}", char as u32)),
+                char => self.output.push(char),
+            }
+        }
+        self.output.push('"');
+    }
+
+    fn symbol(&mut self, symbol: Symbol) {
+        let name = self.symbols.name(symbol);
+        self.output.push_str(&name);
+    }
+
+    fn ty(&mut self, ty: &TyWithPos) {
+        match ty.node {
+            Ty::Array { ref ident } => {
+                self.write("array of ");
+                self.symbol(ident.node);
+            },
+            Ty::Name { ref ident } => self.symbol(ident.node),
+            Ty::Pointer { ref ident } => {
+                self.write("pointer ");
+                self.symbol(ident.node);
+            },
+            Ty::Record { ref fields } => {
+                self.write("{");
+                self.fields(fields);
+                self.write("}");
+            },
+            Ty::Weak { ref ident } => {
+                self.write("weak ");
+                self.symbol(ident.node);
+            },
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+}
+
+/// The let starting the chain of field accesses, subscripts and method calls of `expr`, or of the
+/// variable it assigns, whose declarations are printed around `expr`, with the let printed as its
+/// body, since `(let ... end).field` cannot be written. The receivers are evaluated first, so this
+/// does not change the order of evaluation. The lets of `hoisted` are already printed as their
+/// body.
+fn hoist_let(arena: &Arena, expr: ExprId, hoisted: &[ExprId]) -> Option<ExprId> {
+    match arena[expr].node {
+        Expr::Assign { var, .. } => hoist_receiver_let(arena, var, hoisted),
+        // NOTE: a let alone is printed as is.
+        Expr::Let { .. } => None,
+        _ => hoist_receiver_let(arena, expr, hoisted),
+    }
+}
+
+fn hoist_receiver_let(arena: &Arena, expr: ExprId, hoisted: &[ExprId]) -> Option<ExprId> {
+    match arena[expr].node {
+        Expr::Deref { pointer: this } | Expr::Field { this, .. } | Expr::MethodCall { this, .. }
+            | Expr::Subscript { this, .. } => hoist_receiver_let(arena, this, hoisted),
+        Expr::Let { body, ref declarations } if !declarations.is_empty() => {
+            if hoisted.contains(&expr) {
+                hoist_receiver_let(arena, body, hoisted)
+            }
+            else {
+                Some(expr)
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Whether the expression ends with an expression, which would extend over an operator or an
+/// `else` following it.
+fn is_open(arena: &Arena, expr: ExprId) -> bool {
+    match arena[skip_empty_lets(arena, expr)].node {
+        Expr::Array { .. } | Expr::Assign { .. } | Expr::If { .. } | Expr::While { .. } => true,
+        _ => false,
+    }
+}
+
+pub fn operator(operator: Operator) -> &'static str {
+    match operator {
+        Operator::And => "&",
+        Operator::Divide => "/",
+        Operator::Equal => "=",
+        Operator::Ge => ">=",
+        Operator::Gt => ">",
+        Operator::Le => "<=",
+        Operator::Lt => "<",
+        Operator::Minus => "-",
+        Operator::Neq => "<>",
+        Operator::Or => "|",
+        Operator::Plus => "+",
+        Operator::Times => "*",
+    }
+}
+
+/// The precedence of the operator in the parser: an operator binds tighter than the ones with a
+/// lower precedence.
+fn precedence(operator: Operator) -> u8 {
+    match operator {
+        Operator::Or => 1,
+        Operator::And => 2,
+        Operator::Equal | Operator::Ge | Operator::Gt | Operator::Le | Operator::Lt | Operator::Neq => 3,
+        Operator::Minus | Operator::Plus => 4,
+        Operator::Divide | Operator::Times => 5,
+    }
+}
+
+/// The expression printed for `expr`, which is its body when it is a let without declarations.
+fn skip_empty_lets(arena: &Arena, mut expr: ExprId) -> ExprId {
+    while let Expr::Let { body, ref declarations } = arena[expr].node {
+        if !declarations.is_empty() {
+            break;
+        }
+        expr = body;
+    }
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use ast::Ast;
+    use dialect::Dialect;
+    use lexer::Lexer;
+    use parser::Parser;
+    use rewriter::Rewriter;
+    use symbol::{Strings, Symbols};
+    use super::print;
+
+    fn parse(source: &str, symbols: &mut Symbols<()>) -> Ast {
+        let file_symbol = symbols.symbol("no_file");
+        // The lexer expects a character after the last token.
+        let source = format!("{}\n", source);
+        let mut lexer = Lexer::new(source.as_bytes(), file_symbol);
+        lexer.set_unicode_idents(true);
+        let mut parser = Parser::new(lexer, symbols);
+        parser.set_dialect(Dialect::Unsafe);
+        parser.parse().expect("parse")
+    }
+
+    /// Print the tree and check that parsing the result gives the same tree.
+    fn check_reparse(ast: &Ast, symbols: &mut Symbols<()>) -> String {
+        let printed = print(ast, symbols);
+        let reparsed = parse(&printed, symbols);
+        assert_eq!(print(&reparsed, symbols), printed);
+        printed
+    }
+
+    fn reprint(source: &str) -> String {
+        let mut symbols = Symbols::new(Arc::new(Strings::new()));
+        let ast = parse(source, &mut symbols);
+        check_reparse(&ast, &mut symbols)
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(reprint("1 - (2 - 3) * 4"), "1 - (2 - 3) * 4\n");
+        assert_eq!(reprint("1 + 2 * 3 = 7 | 1 < 2 & 0"), "1 + 2 * 3 = 7 | 1 < 2 & 0\n");
+        assert_eq!(reprint("-(1 + 2)"), "0 - (1 + 2)\n");
+        assert_eq!(reprint("a := 1 + (if 1 then 2 else 3) * 4"), "a := 1 + (if 1 then 2 else 3) * 4\n");
+    }
+
+    #[test]
+    fn test_dangling_else() {
+        assert_eq!(reprint("if 1 then (if 2 then f()) else g()"), "if 1 then (if 2 then f()) else g()\n");
+        assert_eq!(reprint("case s of \"a\" => (if 1 then f()) else g() end"),
+            "case s of\n    \"a\" => (if 1 then f())\n    else g()\nend\n");
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(reprint("\"a\\\"b\\\\c\\n\\t\\001\""), "\"a\\\"b\\\\c\\n\\t\\001\"\n");
+    }
+
+    /// The rewritten test programs, printed by `--emit source`, parse again.
+    #[test]
+    fn test_rewritten_programs() {
+        for entry in fs::read_dir("tests").expect("read tests") {
+            let path = entry.expect("test entry").path();
+            if path.extension().is_some_and(|extension| extension == "tig") {
+                let mut symbols = Symbols::new(Arc::new(Strings::new()));
+                let ast = parse(&fs::read_to_string(&path).expect("read test"), &mut symbols);
+                let ast = Rewriter::new(&mut symbols).rewrite(ast);
+                check_reparse(&ast, &mut symbols);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::fs;
+
+use error::Error;
+use opt_level::OptLevel;
+use toml::{parse_digits, parse_string, parse_string_array};
+
+/// Configuration file read by `tiger build` in the current directory.
+pub const PROJECT_FILE: &str = "tiger.toml";
+
+/// Options of a project, which are given on the command line otherwise.
+pub struct Project {
+    // Path of nasm.
+    pub assembler: Option<String>,
+    // Flags passed to the assembler, like -g -F dwarf.
+    pub assembler_flags: Vec<String>,
+    // Flags passed to the linker after the runtime, like the libraries called by the program.
+    pub linker_flags: Vec<String>,
+    // Level of the optimizations.
+    pub opt_level: OptLevel,
+    // Path of the executable.
+    pub output: Option<String>,
+    // Runtime library linked with the program.
+    pub runtime: Option<String>,
+    // The program, followed by the libraries it calls.
+    pub sources: Vec<String>,
+}
+
+impl Project {
+    /// Load the project from a TOML file of `key = value` lines, which can be under a `[project]`
+    /// table.
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let content = fs::read_to_string(filename)
+            .map_err(|error| Error::Msg(format!("cannot read {}: {}", filename, error)))?;
+        let mut project = Self {
+            assembler: None,
+            assembler_flags: vec![],
+            linker_flags: vec![],
+            opt_level: OptLevel::default(),
+            output: None,
+            runtime: None,
+            sources: vec![],
+        };
+        for (index, line) in content.lines().enumerate() {
+            let error = |message: String| Error::Msg(format!("{}:{}: {}", filename, index + 1, message));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line == "[project]" {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| error("expecting key = value".to_string()))?;
+            let key = key.trim().trim_matches('"');
+            let value = value.trim();
+            let string = || parse_string(value)
+                .ok_or_else(|| error(format!("expecting a string for `{}`", key)));
+            let strings = || parse_string_array(value)
+                .ok_or_else(|| error(format!("expecting an array of strings for `{}`", key)));
+            match key {
+                "assembler" => project.assembler = Some(string()?),
+                "assembler-flags" => project.assembler_flags = strings()?,
+                "linker-flags" => project.linker_flags = strings()?,
+                "opt-level" => {
+                    project.opt_level = parse_digits(value)
+                        .and_then(OptLevel::from_name)
+                        .ok_or_else(|| error("expecting 0, 1 or 2 for `opt-level`".to_string()))?;
+                },
+                "output" => project.output = Some(string()?),
+                "runtime" => project.runtime = Some(string()?),
+                "sources" => project.sources = strings()?,
+                _ => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        if project.sources.is_empty() {
+            return Err(Error::Msg(format!("{}: `sources` should list the program, followed by the libraries it calls",
+                filename)));
+        }
+        Ok(project)
+    }
+}
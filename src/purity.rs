@@ -0,0 +1,211 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Functions without side effects, whose calls with the same arguments give the same result.
+//!
+//! A function is pure when its body only reads and assigns its parameters and its local
+//! variables and calls pure functions: it reads no memory, allocates nothing and does no input or
+//! output. The semantic analysis finds them at -O2, and the calls to those returning a value the
+//! collector does not track are reused within a basic block of the canonical statements.
+
+use std::collections::{HashMap, HashSet};
+
+use ast::{Arena, Declaration, Expr, ExprId};
+use frame::Frame;
+use ir::{Exp, Statement, _Statement};
+use symbol::Symbol;
+use temp::{Label, Temp};
+
+/// Functions of the runtime which compute their result from their arguments only, without
+/// allocating.
+pub const PURE_EXTERNAL_FUNCTIONS: [&str; 5] = ["not", "ord", "size", "stringCompare", "stringEqual"];
+
+/// Names of the functions called by the body of a function with these parameters, if the body is
+/// pure provided that these functions are.
+pub fn pure_calls(arena: &Arena, params: &[Symbol], body: ExprId) -> Option<Vec<Symbol>> {
+    let mut calls = vec![];
+    let mut variables = params.to_vec();
+    if is_pure(arena, body, &mut variables, &mut calls) {
+        Some(calls)
+    }
+    else {
+        None
+    }
+}
+
+fn is_pure(arena: &Arena, expr: ExprId, variables: &mut Vec<Symbol>, calls: &mut Vec<Symbol>) -> bool {
+    match arena[expr].node {
+        Expr::Assign { expr, var } =>
+            matches!(arena[var].node, Expr::Variable(ref name) if variables.contains(&name.node)) &&
+                is_pure(arena, expr, variables, calls),
+        Expr::Break | Expr::Int { .. } | Expr::Nil | Expr::Str { .. } => true,
+        Expr::Call { ref args, function } => {
+            calls.push(function);
+            args.iter().all(|&arg| is_pure(arena, arg, variables, calls))
+        },
+        Expr::Case { ref arms, else_, subject } =>
+            is_pure(arena, subject, variables, calls) &&
+                arms.iter().all(|arm| is_pure(arena, arm.node.body, variables, calls)) &&
+                else_.is_none_or(|else_| is_pure(arena, else_, variables, calls)),
+        Expr::If { else_, test, then } =>
+            is_pure(arena, test, variables, calls) && is_pure(arena, then, variables, calls) &&
+                else_.is_none_or(|else_| is_pure(arena, else_, variables, calls)),
+        Expr::Let { body, ref declarations } => {
+            let scope = variables.len();
+            let mut pure = true;
+            for declaration in declarations {
+                match declaration.node {
+                    Declaration::Type(_) => (),
+                    Declaration::VariableDeclaration { init, name, .. } if is_pure(arena, init, variables, calls) =>
+                        variables.push(name),
+                    _ => {
+                        pure = false;
+                        break;
+                    },
+                }
+            }
+            pure = pure && is_pure(arena, body, variables, calls);
+            variables.truncate(scope);
+            pure
+        },
+        Expr::Oper { left, right, .. } =>
+            is_pure(arena, left, variables, calls) && is_pure(arena, right, variables, calls),
+        Expr::Sequence(ref exprs) => exprs.iter().all(|&expr| is_pure(arena, expr, variables, calls)),
+        // Only the local variables: the others can be assigned by other functions.
+        Expr::Variable(ref name) => variables.contains(&name.node),
+        Expr::While { body, test } => is_pure(arena, test, variables, calls) && is_pure(arena, body, variables, calls),
+        Expr::AddressOf { .. } | Expr::Array { .. } | Expr::Deref { .. } | Expr::Field { .. } | Expr::MethodCall { .. }
+            | Expr::New { .. } | Expr::Record { .. } | Expr::Subscript { .. } => false,
+    }
+}
+
+/// Replace the calls to pure functions whose result is already in a temporary: the same function
+/// was called with the same arguments by a previous statement of the same basic block, and none of
+/// these temporaries was assigned in between. The arguments are compared by the arithmetic which
+/// computed them from the temporaries, so that a copy or a sum computed again compares as the
+/// original. Only the calls whose result is not tracked by the collector are replaced.
+pub fn eliminate_redundant_calls<F: Frame>(statements: Vec<Statement>, pure_functions: &HashSet<Label>)
+    -> Vec<Statement>
+{
+    let registers = F::temp_map();
+    // The instructions can write the machine registers without a move in the IR.
+    let reusable = |temp: Temp| temp == F::fp().temp() || !registers.contains_key(&temp);
+    // Calls whose result is in a temporary, with their arguments.
+    let mut available: Vec<(Label, Vec<Exp>, Temp)> = vec![];
+    // Values of the temporaries, in terms of the temporaries they were computed from.
+    let mut values: HashMap<Temp, Exp> = HashMap::new();
+    let mut result = Vec::with_capacity(statements.len());
+    for statement in statements {
+        let stack_var = statement.stack_var;
+        let statement =
+            match statement.statement {
+                _Statement::Move(Exp::Temp(temp), source) => {
+                    let call = pure_call(&source, pure_functions)
+                        .and_then(|(function, arguments)| {
+                            let arguments = arguments.iter()
+                                .map(|argument| value(argument, &values, &reusable))
+                                .collect::<Option<Vec<_>>>()?;
+                            Some((function.clone(), arguments))
+                        });
+                    let previous = call.as_ref()
+                        .and_then(|call|
+                            available.iter()
+                                .find(|previous| previous.0 == call.0 && previous.1 == call.1))
+                        .map(|&(_, _, result)| result);
+                    let source =
+                        match previous {
+                            Some(previous) if stack_var.is_none() => Exp::Temp(previous),
+                            _ => source,
+                        };
+
+                    let source_value = value(&source, &values, &reusable);
+                    available.retain(|&(_, ref arguments, result)|
+                        result != temp && !arguments.iter().any(|argument| uses(argument, temp)));
+                    values.retain(|&computed, value| computed != temp && !uses(value, temp));
+                    if reusable(temp) {
+                        match source_value {
+                            Some(value) if !uses(&value, temp) => {
+                                values.insert(temp, value);
+                            },
+                            _ => {
+                                if let Some((function, arguments)) = call {
+                                    if previous.is_none() && !arguments.iter().any(|argument| uses(argument, temp)) {
+                                        available.push((function, arguments, temp));
+                                    }
+                                }
+                            },
+                        }
+                    }
+                    _Statement::Move(Exp::Temp(temp), source)
+                },
+                // Other blocks can jump to a label, with other values in the temporaries.
+                statement @ (_Statement::Label(_) | _Statement::Sequence(_, _)) => {
+                    available.clear();
+                    values.clear();
+                    statement
+                },
+                statement => statement,
+            };
+        result.push(Statement {
+            stack_var,
+            statement,
+        });
+    }
+    result
+}
+
+/// Value of the expression in terms of the temporaries it is computed from, if it only does
+/// arithmetic on constants, labels and temporaries which the instructions cannot write implicitly.
+fn value(expr: &Exp, values: &HashMap<Temp, Exp>, reusable: &impl Fn(Temp) -> bool) -> Option<Exp> {
+    match *expr {
+        Exp::BinOp { ref op, ref left, ref right } =>
+            Some(Exp::BinOp {
+                op: op.clone(),
+                left: Box::new(value(left, values, reusable)?),
+                right: Box::new(value(right, values, reusable)?),
+            }),
+        Exp::Const(_) | Exp::Name(_) => Some(expr.clone()),
+        Exp::Temp(temp) if reusable(temp) => Some(values.get(&temp).cloned().unwrap_or(Exp::Temp(temp))),
+        _ => None,
+    }
+}
+
+/// Whether the value uses the temporary.
+fn uses(value: &Exp, temp: Temp) -> bool {
+    match *value {
+        Exp::BinOp { ref left, ref right, .. } => uses(left, temp) || uses(right, temp),
+        Exp::Temp(used) => used == temp,
+        _ => false,
+    }
+}
+
+/// Function and arguments of the call, if it calls a pure function returning a value which is not
+/// tracked by the collector.
+fn pure_call<'a>(expr: &'a Exp, pure_functions: &HashSet<Label>) -> Option<(&'a Label, &'a [Exp])> {
+    match *expr {
+        Exp::Call { ref arguments, collectable_return_type: false, ref function_expr, .. } =>
+            match **function_expr {
+                Exp::Name(ref function) if pure_functions.contains(function) => Some((function, arguments)),
+                _ => None,
+            },
+        _ => None,
+    }
+}
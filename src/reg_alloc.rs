@@ -107,48 +107,97 @@
 
 use std::collections::{
     BinaryHeap,
+    BTreeMap,
     BTreeSet,
     HashMap,
     HashSet,
 };
+use std::fmt::{self, Display, Formatter};
 use std::mem;
+use std::time::{Duration, Instant};
 
-use asm::Instruction;
+use asm::{Instruction, Operand};
 use asm_gen::Gen;
 use flow::instructions_to_graph;
-use frame::Frame;
+use frame::{Frame, Memory, Register};
 use ir::{Exp, _Statement};
 use liveness::{Interval, StackLocation, live_intervals};
+use opt_level::OptLevel;
 use temp::{Label, Temp, TempMap};
 
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Pointer(i64);
 
-impl Pointer {
-    pub fn to_label<F: Frame>(&self) -> String {
-        self.0.to_string()
+impl Display for Pointer {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
     }
 }
 
-pub fn alloc<F: Frame>(instructions: Vec<Instruction>, frame: &mut F, temp_map: TempMap) -> (Vec<Instruction>, Vec<(Label, Vec<Pointer>)>) {
+/// Pointers live at each return address of a function, used by the garbage collector.
+pub type PointerMap = Vec<(Label, Vec<Pointer>)>;
+
+/// Where a temporary is kept for the whole function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Location {
+    Register(Register),
+    // Offset from the frame pointer of the stack slot the temporary was spilled to.
+    Stack(i64),
+}
+
+/// Callee-saved registers, each with the location where the function saved it.
+pub type SavedRegisters = Vec<(Register, Location)>;
+
+/// Statistics about the register allocation of a function.
+pub struct AllocStats {
+    /// Number of moves removed because their source and destination were allocated the same register.
+    pub coalesced_moves: usize,
+    /// Time spent computing the live intervals of the temporaries.
+    pub liveness_time: Duration,
+    /// Number of temporaries spilled to the stack.
+    pub spills: usize,
+}
+
+pub fn alloc<F: Frame>(instructions: Vec<Instruction>, frame: &mut F, temp_map: TempMap, opt_level: OptLevel)
+    -> (Vec<Instruction>, PointerMap, SavedRegisters, AllocStats)
+{
     let mut allocator = Allocator::new::<F>(instructions, temp_map);
+    if opt_level >= OptLevel::O1 {
+        allocator.find_move_partners::<F>();
+    }
     //allocator.spill_weight_calculation();
+    let start = Instant::now();
     let (intervals, _, temp_pointers) = allocator.live_interval_analysis::<F>();
+    let liveness_time = start.elapsed();
     allocator.create_priority_queue(intervals);
     allocator.register_assignment();
+    let spills = allocator.spill_temps.len();
     allocator.spill(frame);
-    allocator.replace_allocation();
+    let coalesced_moves = allocator.replace_allocation();
     let temp_pointers = allocator.replace_temp_map(temp_pointers);
-    (allocator.instructions, temp_pointers)
+    let saved_registers = frame.saved_registers().iter()
+        .filter_map(|&(register, temp)| allocator.location(temp).map(|location| (register, location)))
+        .collect();
+    let stats = AllocStats {
+        coalesced_moves,
+        liveness_time,
+        spills,
+    };
+    (allocator.instructions, temp_pointers, saved_registers, stats)
 }
 
 struct Allocator {
     instructions: Vec<Instruction>,
     memory_location: HashMap<Temp, i64>,
+    // Temporaries moved to or from each temporary, whose register it should get to remove the move.
+    move_partners: HashMap<Temp, Vec<Temp>>,
     priority_queue: BinaryHeap<Interval>,
-    registers: Vec<Register>,
-    register_map: HashMap<Temp, Temp>,
-    spill_temps: HashMap<Temp, Interval>,
+    registers: Vec<RegisterUsage>,
+    register_map: HashMap<Temp, Register>,
+    // Stack slot of each spilled temporary.
+    spill_slots: HashMap<Temp, i64>,
+    // Ordered so that the spilled temporaries get the same stack slots at each compilation.
+    spill_temps: BTreeMap<Temp, Interval>,
     spill_to_split: HashMap<Temp, HashSet<Temp>>,
     split_to_spill: HashMap<Temp, Temp>,
     temp_map: TempMap,
@@ -158,17 +207,19 @@ impl Allocator {
     fn new<F: Frame>(instructions: Vec<Instruction>, temp_map: TempMap) -> Self {
         let mut registers: Vec<_> = F::temp_map()
             .into_iter()
-            .map(|(temp, _)| Register::new(temp, Interval::empty(temp)))
+            .map(|(register, _)| RegisterUsage::new(register, Interval::empty(register.temp())))
             .collect();
-        registers.sort_by_key(|register| register.temp);
+        registers.sort_by_key(|usage| usage.register);
 
         Self {
             instructions,
             memory_location: HashMap::new(),
+            move_partners: HashMap::new(),
             priority_queue: BinaryHeap::new(),
             registers,
             register_map: HashMap::new(),
-            spill_temps: HashMap::new(),
+            spill_slots: HashMap::new(),
+            spill_temps: BTreeMap::new(),
             spill_to_split: HashMap::new(),
             split_to_spill: HashMap::new(),
             temp_map,
@@ -176,16 +227,48 @@ impl Allocator {
     }
 
     fn assign_to_register(&mut self, interval: &Interval) -> bool {
-        for register in &mut self.registers {
-            if !register.conflict(interval) {
-                self.register_map.insert(interval.temp, register.temp);
-                register.assign(interval);
+        // Try the registers of the move partners first, so that the moves between them disappear.
+        let preferred = self.preferred_registers(interval.temp);
+        let mut order: Vec<_> = (0..self.registers.len())
+            .filter(|&index| preferred.contains(&self.registers[index].register))
+            .collect();
+        order.extend(0..self.registers.len());
+        for index in order {
+            let usage = &mut self.registers[index];
+            if !usage.conflict(interval) {
+                self.register_map.insert(interval.temp, usage.register);
+                usage.assign(interval);
                 return true;
             }
         }
         false
     }
 
+    /// Record the temporaries copied to one another by a register move. The frame pointer is
+    /// never given to a temporary.
+    fn find_move_partners<F: Frame>(&mut self) {
+        for instruction in &self.instructions {
+            if let Instruction::Move { ref assembly, ref destination, ref source, .. } = *instruction {
+                if assembly == "mov 'd0, 's0" && destination[0].temp() != F::fp().temp() && source[0].temp() != F::fp().temp() {
+                    let (destination, source) = (destination[0].temp(), source[0].temp());
+                    self.move_partners.entry(destination).or_default().push(source);
+                    self.move_partners.entry(source).or_default().push(destination);
+                }
+            }
+        }
+    }
+
+    /// Registers already holding the move partners of the temporary.
+    fn preferred_registers(&self, temp: Temp) -> Vec<Register> {
+        self.move_partners.get(&temp).into_iter()
+            .flatten()
+            .filter_map(|partner| {
+                self.register_map.get(partner).copied()
+                    .or_else(|| self.registers.iter().map(|usage| usage.register).find(|register| register.temp() == *partner))
+            })
+            .collect()
+    }
+
     // TODO: change from Vec<(Temp, Interval)> to Vec<Interval>?
     fn create_priority_queue(&mut self, live_intervals: Vec<(Temp, Interval)>) {
         self.priority_queue.extend(live_intervals.into_iter().map(|(_, interval)| interval));
@@ -206,9 +289,9 @@ impl Allocator {
         let flow_graph = instructions_to_graph(&self.instructions);
         let (intervals, precolored_intervals, _, temp_pointers) = live_intervals::<F>(flow_graph, &self.temp_map, true);
 
-        for register in &mut self.registers {
-            if let Some(ref interval) = precolored_intervals.get(&register.temp) {
-                register.assign(interval);
+        for usage in &mut self.registers {
+            if let Some(ref interval) = precolored_intervals.get(&usage.register.temp()) {
+                usage.assign(interval);
             }
         }
         (intervals, precolored_intervals, temp_pointers)
@@ -224,28 +307,35 @@ impl Allocator {
         //allocator.split();
     }
 
-    fn replace_allocation(&mut self) {
+    /// Replace the temporaries by their register and return the number of moves removed.
+    fn replace_allocation(&mut self) -> usize {
         for instruction in &mut self.instructions {
             match *instruction {
-                Instruction::Label { .. } => (),
+                Instruction::Label { .. } | Instruction::Position { .. } => (),
+                Instruction::Node { ref mut temps, .. } => {
+                    for &mut (temp, ref mut register) in temps {
+                        *register = self.register_map.get(&temp).map(Register::temp);
+                    }
+                },
                 Instruction::Call { ref mut destination, ref mut source, .. } |
                     Instruction::Move { ref mut destination, ref mut source, .. } |
                     Instruction::Operation { ref mut destination, ref mut source, .. } =>
                     {
                         for destination in destination {
-                            if let Some(allocation) = self.register_map.get(destination) {
-                                *destination = *allocation;
+                            if let Some(&allocation) = self.register_map.get(&destination.temp()) {
+                                *destination = Operand::Register(allocation);
                             }
                         }
                         for source in source {
-                            if let Some(allocation) = self.register_map.get(source) {
-                                *source = *allocation;
+                            if let Some(&allocation) = self.register_map.get(&source.temp()) {
+                                *source = Operand::Register(allocation);
                             }
                         }
                     },
             }
         }
 
+        let instruction_count = self.instructions.len();
         self.instructions.retain(|instruction| {
             match *instruction {
                 Instruction::Move { ref assembly, ref destination, ref source, .. } =>
@@ -253,6 +343,7 @@ impl Allocator {
                 _ => true,
             }
         });
+        instruction_count - self.instructions.len()
     }
 
     fn spill<F: Frame>(&mut self, frame: &mut F) {
@@ -262,7 +353,10 @@ impl Allocator {
         let mut new_intervals = vec![];
         for (temp, spill) in &self.spill_temps {
             let local = frame.alloc_local(true);
-            let exp = frame.exp(local, Exp::Temp(F::fp()));
+            if let Some(offset) = local.as_stack() {
+                self.spill_slots.insert(*temp, offset);
+            }
+            let exp = frame.exp(local, Exp::Temp(F::fp().temp()));
             memory.insert(temp, exp);
             intervals.insert(temp, spill);
         }
@@ -279,32 +373,34 @@ impl Allocator {
                     {
                         let mut source_temps = HashMap::new();
                         for source in source {
-                            if self.spill_temps.contains_key(source) {
+                            let spill = source.temp();
+                            if self.spill_temps.contains_key(&spill) {
                                 let temp = Temp::new();
-                                source_temps.insert(*source, temp);
-                                self.spill_to_split.entry(*source)
+                                source_temps.insert(spill, temp);
+                                self.spill_to_split.entry(spill)
                                     .or_default()
                                     .insert(temp);
-                                self.split_to_spill.insert(temp, *source);
-                                *source = temp;
+                                self.split_to_spill.insert(temp, spill);
+                                *source = Operand::Temp(temp);
                             }
                         }
                         for destination in destination {
-                            if self.spill_temps.contains_key(destination) {
+                            let spill = destination.temp();
+                            if self.spill_temps.contains_key(&spill) {
                                 let temp =
-                                    match source_temps.get(destination) {
+                                    match source_temps.get(&spill) {
                                         Some(temp) => *temp,
                                         None => Temp::new(),
                                     };
-                                self.spill_to_split.entry(*destination)
+                                self.spill_to_split.entry(spill)
                                     .or_default()
                                     .insert(temp);
-                                self.split_to_spill.insert(temp, *destination);
-                                *destination = temp;
+                                self.split_to_spill.insert(temp, spill);
+                                *destination = Operand::Temp(temp);
                             }
                         }
                     },
-                    Instruction::Label { .. } => (),
+                    Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } => (),
             }
         }
 
@@ -313,25 +409,25 @@ impl Allocator {
                 Instruction::Call { ref destination, ref source, .. } | Instruction::Move { ref destination, ref source, .. } |
                     Instruction::Operation { ref destination, ref source, .. } =>
                     {
-                        for (source_index, source) in source.iter().enumerate() {
-                            if self.spill_temps.contains_key(self.split_to_spill.get(source).unwrap_or(source)) {
-                                let original_spill = self.split_to_spill[source];
+                        for (source_index, source) in source.iter().map(Operand::temp).enumerate() {
+                            if self.spill_temps.contains_key(self.split_to_spill.get(&source).unwrap_or(&source)) {
+                                let original_spill = self.split_to_spill[&source];
                                 // Reload before use.
-                                let temp = gen.munch_expression(memory[&original_spill].clone());
-                                gen.munch_statement(_Statement::Move(Exp::Temp(*source), Exp::Temp(temp)).into());
+                                let temp = gen.munch_expression(memory[&original_spill].clone()).temp();
+                                gen.munch_statement(_Statement::Move(Exp::Temp(source), Exp::Temp(temp)).into());
                                 let mut interval = intervals[&original_spill].clone();
                                 interval.split_for_reload(index, source_index + 1);
                                 interval.temp = temp;
                                 new_intervals.push((temp, interval.clone()));
 
-                                interval.temp = *source;
-                                new_intervals.push((*source, interval));
+                                interval.temp = source;
+                                new_intervals.push((source, interval));
                             }
                         }
                         let destination = destination.clone(); // TODO: remove this clone?
                         gen.emit(instruction);
-                        for (destination_index, destination) in destination.iter().enumerate() {
-                            if self.spill_temps.contains_key(self.split_to_spill.get(destination).unwrap_or(destination)) {
+                        for (destination_index, destination) in destination.iter().map(Operand::temp).enumerate() {
+                            if self.spill_temps.contains_key(self.split_to_spill.get(&destination).unwrap_or(&destination)) {
                                 let original_spill = self.split_to_spill[&destination];
                                 // Spill after def.
                                 let mut offset = None;
@@ -342,16 +438,17 @@ impl Allocator {
                                         }
                                     }
                                 }
-                                debug_assert!(self.memory_location.insert(*destination, offset.expect("offset")).is_none());
-                                gen.munch_statement(_Statement::Move(memory[&original_spill].clone(), Exp::Temp(*destination)).into());
+                                debug_assert!(self.memory_location.insert(destination, offset.expect("offset")).is_none());
+                                gen.munch_statement(_Statement::Move(memory[&original_spill].clone(), Exp::Temp(destination)).into());
                                 let mut interval = intervals[&original_spill].clone();
                                 interval.split_for_spill(index, destination_index);
-                                interval.temp = *destination;
-                                new_intervals.push((*destination, interval));
+                                interval.temp = destination;
+                                new_intervals.push((destination, interval));
                             }
                         }
                     },
-                    Instruction::Label { .. } => gen.emit(instruction),
+                    Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } =>
+                        gen.emit(instruction),
             }
         }
 
@@ -369,7 +466,15 @@ impl Allocator {
         //correctly.
     }
 
-    fn replace_temp_map(&self, temp_map: Vec<(Label, BTreeSet<StackLocation>)>) -> Vec<(Label, Vec<Pointer>)> {
+    /// Location of a temporary of the original instructions.
+    fn location(&self, temp: Temp) -> Option<Location> {
+        match self.register_map.get(&temp) {
+            Some(&register) => Some(Location::Register(register)),
+            None => self.spill_slots.get(&temp).map(|&offset| Location::Stack(offset)),
+        }
+    }
+
+    fn replace_temp_map(&self, temp_map: Vec<(Label, BTreeSet<StackLocation>)>) -> PointerMap {
         let mut pointer_temps = vec![];
         for (label, locations) in temp_map {
             let new_temps = locations.iter().map(|location| {
@@ -385,16 +490,17 @@ impl Allocator {
     }
 }
 
+/// The intervals during which a machine register is already used.
 #[derive(Debug)]
-struct Register {
-    temp: Temp,
+struct RegisterUsage {
+    register: Register,
     used_interval: Interval,
 }
 
-impl Register {
-    fn new(temp: Temp, used_interval: Interval) -> Self {
+impl RegisterUsage {
+    fn new(register: Register, used_interval: Interval) -> Self {
         Self {
-            temp,
+            register,
             used_interval,
         }
     }
@@ -440,7 +546,7 @@ impl Register {
     }
 
     fn conflict(&self, interval: &Interval) -> bool {
-        if self.temp == interval.temp {
+        if self.register.temp() == interval.temp {
             // TODO: is that logical to do so?
             return false;
         }
@@ -486,26 +592,26 @@ mod tests {
     use std::collections::HashMap;
     use std::fs::File;
     use std::io::BufReader;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     use asm_gen::Gen;
-    use canon::{basic_blocks, linearize, trace_schedule};
+    use canon::{BranchWeights, basic_blocks, eliminate_jump_chains, linearize, trace_schedule};
     use env::Env;
     use escape::find_escapes;
-    use frame::{Fragment, Frame};
+    use frame::{Fragment, Frame, Register};
     use frame::x86_64::X86_64;
     use lexer::Lexer;
     use liveness::Interval;
     use parser::Parser;
     use semant::SemanticAnalyzer;
-    use super::{Allocator, Register};
+    use super::{Allocator, RegisterUsage};
     use symbol::{Strings, Symbols};
     use temp::Temp;
 
     fn get_intervals(filename: &str) -> (Vec<(Temp, Interval)>, HashMap<Temp, Interval>) {
-        let strings = Rc::new(Strings::new());
+        let strings = Arc::new(Strings::new());
         let file = BufReader::new(File::open(&filename).expect("file open"));
-        let mut symbols = Symbols::new(Rc::clone(&strings));
+        let mut symbols = Symbols::new(Arc::clone(&strings));
         let file_symbol = symbols.symbol("no_file");
         let lexer = Lexer::new(file, file_symbol);
         let main_symbol = symbols.symbol("main");
@@ -513,10 +619,10 @@ mod tests {
         let object_symbol = symbols.symbol("Object");
         let mut parser = Parser::new(lexer, &mut symbols);
         let ast = parser.parse().expect("parse");
-        let escape_env = find_escapes(&ast, Rc::clone(&strings));
+        let escape_env = find_escapes(&ast.arena, ast.root, Arc::clone(&strings));
         let mut env = Env::<X86_64>::new(&strings, escape_env);
         {
-            let semantic_analyzer = SemanticAnalyzer::new(&mut env, Rc::clone(&strings), self_symbol, object_symbol);
+            let semantic_analyzer = SemanticAnalyzer::new(&mut env, Arc::clone(&strings), self_symbol, object_symbol);
             let fragments = semantic_analyzer.analyze(main_symbol, ast).expect("semantic analyze");
 
             for fragment in fragments {
@@ -527,7 +633,8 @@ mod tests {
 
                         let statements = linearize(body);
                         let (basic_blocks, done_label) = basic_blocks(statements);
-                        let statements = trace_schedule(basic_blocks, done_label);
+                        let statements = trace_schedule(basic_blocks, done_label, &BranchWeights::default());
+                        let statements = eliminate_jump_chains(statements);
 
                         let mut generator = Gen::<X86_64>::new();
                         for statement in statements {
@@ -541,8 +648,9 @@ mod tests {
 
                         return (intervals, precolored_intervals);
                     },
-                    Fragment::Str(_, _) => (),
-                    Fragment::VTable { .. } => (),
+                    Fragment::Constant(_, _) | Fragment::Display { .. } | Fragment::PureFunctions(_)
+                        | Fragment::StaticFields { .. } | Fragment::Str(_, _) | Fragment::StringTable { .. }
+                        | Fragment::Template { .. } | Fragment::VTable { .. } => (),
                 }
             }
         }
@@ -562,21 +670,21 @@ mod tests {
         let mut expected_precolored_intervals = HashMap::new();
 
         let mut intervals = HashMap::new();
-        intervals.insert(29, vec![(12, 12), (23, usize::max_value())]);
-        intervals.insert(30, vec![(8, 9), (23, usize::max_value())]);
-        intervals.insert(31, vec![(13, 14), (23, usize::max_value())]);
+        intervals.insert(30, vec![(15, 15), (25, usize::max_value())]);
+        intervals.insert(31, vec![(11, 12), (25, usize::max_value())]);
+        intervals.insert(32, vec![(17, 18), (25, usize::max_value())]);
         expected_intervals.insert("tests/hello.tig", intervals);
         let mut intervals = HashMap::new();
         intervals.insert(2, vec![(0, usize::max_value())]);
         expected_precolored_intervals.insert("tests/hello.tig", intervals);
 
         let mut intervals = HashMap::new();
-        intervals.insert(66, vec![(20, 21), (83, usize::max_value())]);
-        intervals.insert(65, vec![(21, 23), (83, usize::max_value())]);
+        intervals.insert(67, vec![(25, 26), (88, usize::max_value())]);
+        intervals.insert(66, vec![(26, 28), (88, usize::max_value())]);
         expected_intervals.insert("tests/integers.tig", intervals);
 
         let mut intervals = HashMap::new();
-        intervals.insert(106, vec![(19, 20), (23, 26), (203, usize::max_value())]);
+        intervals.insert(105, vec![(33, 35), (119, 120), (155, usize::max_value())]);
         expected_intervals.insert("tests/conditions.tig", intervals);
         let mut intervals = HashMap::new();
         intervals.insert(2, vec![(0, usize::max_value())]);
@@ -604,7 +712,7 @@ mod tests {
     fn register() {
         let mut interval = Interval::empty(Temp::from_num(6));
         interval.ranges = vec![(0, 0), (27, 27)];
-        let mut register = Register::new(Temp::from_num(6), interval);
+        let mut register = RegisterUsage::new(Register::from_num(6), interval);
         let mut interval = Interval::empty(Temp::from_num(22));
         interval.ranges = vec![(9, 26)];
         register.assign(&interval);
@@ -612,7 +720,7 @@ mod tests {
 
         let mut interval = Interval::empty(Temp::from_num(6));
         interval.ranges = vec![(0, 0), (27, 27), (42, usize::max_value())];
-        let mut register = Register::new(Temp::from_num(6), interval);
+        let mut register = RegisterUsage::new(Register::from_num(6), interval);
         let mut interval = Interval::empty(Temp::from_num(22));
         interval.ranges = vec![(9, 26), (42, usize::max_value())];
         register.assign(&interval);
@@ -620,7 +728,7 @@ mod tests {
 
         let mut interval = Interval::empty(Temp::from_num(6));
         interval.ranges = vec![(0, usize::max_value())];
-        let register = Register::new(Temp::from_num(1), interval);
+        let register = RegisterUsage::new(Register::from_num(1), interval);
         let mut interval = Interval::empty(Temp::from_num(2));
         interval.ranges = vec![(0, 0), (3, 3), (8, 8), (14, 14), (20, 21), (21, 21), (27, 27), (35, usize::max_value())];
         assert!(register.conflict(&interval));
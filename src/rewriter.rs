@@ -1,17 +1,27 @@
 /// Extract the values collectable by the GC as variables.
 /// They need to be on the stack in order to be accessible by the GC.
 
+use std::mem;
+
 use ast::{
+    Arena,
+    Ast,
+    ClassMember,
     Declaration,
     DeclarationWithPos,
     Expr,
+    ExprId,
     ExprWithPos,
+    Operator,
     RecordField,
 };
+use env::ON_SIGNAL;
 use position::{Pos, WithPos};
 use symbol::{Symbol, Symbols};
 
 pub struct Rewriter<'a> {
+    // Arena of the AST being rewritten, where each expression is replaced by its rewritten version.
+    arena: Arena,
     index: usize,
     symbols: &'a mut Symbols<()>,
 }
@@ -19,52 +29,84 @@ pub struct Rewriter<'a> {
 impl<'a> Rewriter<'a> {
     pub fn new(symbols: &'a mut Symbols<()>) -> Self {
         Self {
+            arena: Arena::new(),
             index: 0,
             symbols,
         }
     }
 
-    fn extract(&mut self, expr: ExprWithPos) -> (Symbol, Declaration) {
-        let name = self.symbols.symbol(&format!("__var_{}", self.index));
-        self.index += 1;
+    fn extract(&mut self, expr: ExprId) -> (Symbol, Declaration) {
+        // NOTE: skip the names used by the program, like those of the source printed by --emit source.
+        let name =
+            loop {
+                let name = format!("__var_{}", self.index);
+                self.index += 1;
+                if !self.symbols.contains_name(&name) {
+                    break self.symbols.symbol(&name);
+                }
+            };
         (name, Declaration::VariableDeclaration {
             escape: false,
-            init: self.rewrite(expr),
+            init: self.rewrite_expr(expr),
+            loop_variable: false,
             name,
             typ: None,
         })
     }
 
-    pub fn rewrite(&mut self, expr: ExprWithPos) -> ExprWithPos {
+    pub fn rewrite(&mut self, ast: Ast) -> Ast {
+        self.arena = ast.arena;
+        let root = self.rewrite_expr(ast.root);
+        Ast {
+            arena: mem::take(&mut self.arena),
+            root,
+        }
+    }
+
+    /// Rewrite the expression in place, returning its handle.
+    fn rewrite_expr(&mut self, id: ExprId) -> ExprId {
+        let expr = self.arena.take(id);
+        self.arena[id] = self.rewrite_node(expr);
+        id
+    }
+
+    fn rewrite_node(&mut self, expr: ExprWithPos) -> ExprWithPos {
         let pos = expr.pos;
         match expr.node {
+            // NOTE: do not rewrite the variable since its address is taken.
+            Expr::AddressOf { var } => WithPos::new(Expr::AddressOf { var }, pos),
             Expr::Array { init, size, typ } => {
                 // NOTE: do not rewrite the initial element of the array because it's immediately assigned a memory location in the array.
-                let init = self.rewrite(*init);
+                let init = self.rewrite_expr(init);
                 WithPos::new(Expr::Array {
-                    init: Box::new(init),
-                    size: Box::new(self.rewrite(*size)),
+                    init,
+                    size: self.rewrite_expr(size),
                     typ,
                 }, pos)
             },
             Expr::Assign { expr, var } => {
                 WithPos::new(Expr::Assign {
-                    expr: Box::new(self.rewrite(*expr)),
-                    var: Box::new(self.rewrite(*var)),
+                    expr: self.rewrite_expr(expr),
+                    var: self.rewrite_expr(var),
                 }, pos)
             },
             Expr::Break => WithPos::new(Expr::Break, pos),
             Expr::Call { args, function } => {
                 let mut new_args = vec![];
                 let mut declarations = vec![];
-                for arg in args {
-                    if can_extract(&arg) {
+                // The handler of on_signal names a function, which is not a value.
+                let is_on_signal = self.symbols.name(function) == ON_SIGNAL;
+                for (index, arg) in args.into_iter().enumerate() {
+                    if is_on_signal && index == 1 {
+                        new_args.push(arg);
+                    }
+                    else if can_extract(&self.arena, arg) {
                         let (name, declaration) = self.extract(arg);
                         declarations.push(WithPos::new(declaration, pos));
-                        new_args.push(variable(name, pos));
+                        new_args.push(self.variable(name, pos));
                     }
                     else {
-                        new_args.push(self.rewrite(arg));
+                        new_args.push(self.rewrite_expr(arg));
                     }
                 }
                 let call = WithPos::new(Expr::Call {
@@ -76,38 +118,58 @@ impl<'a> Rewriter<'a> {
                     call
                 }
                 else {
-                    add_declarations(call, declarations, pos)
+                    self.add_declarations(call, declarations, pos)
                 }
             },
+            Expr::Case { arms, else_, subject } => {
+                // NOTE: the subject is not extracted because looking it up does not allocate.
+                let arms = arms.into_iter()
+                    .map(|mut arm| {
+                        arm.node.body = self.rewrite_expr(arm.node.body);
+                        arm
+                    })
+                    .collect();
+                WithPos::new(Expr::Case {
+                    arms,
+                    else_: else_.map(|else_| self.rewrite_expr(else_)),
+                    subject: self.rewrite_expr(subject),
+                }, pos)
+            },
+            Expr::Deref { pointer } => {
+                WithPos::new(Expr::Deref {
+                    pointer: self.rewrite_expr(pointer),
+                }, pos)
+            },
             Expr::Field { ident, this } => {
                 WithPos::new(Expr::Field {
                     ident,
-                    this: Box::new(self.rewrite(*this)),
+                    this: self.rewrite_expr(this),
                 }, pos)
             },
             Expr::If { else_, test, then } => {
                 // TODO: extract then and else?
                 let mut declarations = vec![];
+                // Comparisons are kept in the test so that the code generator can branch on them.
                 let test =
-                    if can_extract(&test) {
-                        let (name, declaration) = self.extract(*test);
+                    if can_extract(&self.arena, test) && !is_comparison(&self.arena[test]) {
+                        let (name, declaration) = self.extract(test);
                         declarations.push(WithPos::new(declaration, pos));
-                        variable(name, pos)
+                        self.variable(name, pos)
                     }
                     else {
-                        self.rewrite(*test)
+                        self.rewrite_expr(test)
                     };
-                let else_ = else_.map(|else_| Box::new(self.rewrite(*else_)));
+                let else_ = else_.map(|else_| self.rewrite_expr(else_));
                 let cond = WithPos::new(Expr::If {
                     else_,
-                    test: Box::new(test),
-                    then: Box::new(self.rewrite(*then)),
+                    test,
+                    then: self.rewrite_expr(then),
                 }, pos);
                 if declarations.is_empty() {
                     cond
                 }
                 else {
-                    add_declarations(cond, declarations, pos)
+                    self.add_declarations(cond, declarations, pos)
                 }
             },
             Expr::Int { value } => WithPos::new(Expr::Int { value }, pos),
@@ -117,7 +179,7 @@ impl<'a> Rewriter<'a> {
                     new_declarations.push(self.rewrite_dec(declaration));
                 }
                 WithPos::new(Expr::Let {
-                    body: Box::new(self.rewrite(*body)),
+                    body: self.rewrite_expr(body),
                     declarations: new_declarations,
                 }, pos)
             },
@@ -125,26 +187,38 @@ impl<'a> Rewriter<'a> {
                 let mut new_args = vec![];
                 let mut declarations = vec![];
                 for arg in args {
-                    if can_extract(&arg) {
+                    if can_extract(&self.arena, arg) {
                         let (name, declaration) = self.extract(arg);
                         declarations.push(WithPos::new(declaration, pos));
-                        new_args.push(variable(name, pos));
+                        new_args.push(self.variable(name, pos));
                     }
                     else {
-                        new_args.push(self.rewrite(arg));
+                        new_args.push(self.rewrite_expr(arg));
                     }
                 }
+                // NOTE: the receiver is evaluated before the arguments, so it must be extracted
+                // as well when they are.
+                let this =
+                    match self.arena[this].node {
+                        Expr::Variable(_) => self.rewrite_expr(this),
+                        _ if !declarations.is_empty() => {
+                            let (name, declaration) = self.extract(this);
+                            declarations.insert(0, WithPos::new(declaration, pos));
+                            self.variable(name, pos)
+                        },
+                        _ => self.rewrite_expr(this),
+                    };
                 let call = WithPos::new(Expr::MethodCall {
                     args: new_args,
                     method,
-                    this: Box::new(self.rewrite(*this)),
+                    this,
                 }, pos);
 
                 if declarations.is_empty() {
                     call
                 }
                 else {
-                    add_declarations(call, declarations, pos)
+                    self.add_declarations(call, declarations, pos)
                 }
             },
             Expr::New { class_name } => WithPos::new(Expr::New { class_name }, pos),
@@ -152,50 +226,50 @@ impl<'a> Rewriter<'a> {
             Expr::Oper { left, right, oper } => {
                 let mut declarations = vec![];
                 let left =
-                    if can_extract(&left) {
-                        let (name, declaration) = self.extract(*left);
+                    if can_extract(&self.arena, left) {
+                        let (name, declaration) = self.extract(left);
                         declarations.push(WithPos::new(declaration, pos));
-                        variable(name, pos)
+                        self.variable(name, pos)
                     }
                     else {
-                        self.rewrite(*left)
+                        self.rewrite_expr(left)
                     };
                 let right =
-                    if can_extract(&right) {
-                        let (name, declaration) = self.extract(*right);
+                    if can_extract(&self.arena, right) {
+                        let (name, declaration) = self.extract(right);
                         declarations.push(WithPos::new(declaration, pos));
-                        variable(name, pos)
+                        self.variable(name, pos)
                     }
                     else {
-                        self.rewrite(*right)
+                        self.rewrite_expr(right)
                     };
                 let oper = WithPos::new(Expr::Oper {
-                    left: Box::new(left),
-                    right: Box::new(right),
+                    left,
+                    right,
                     oper,
                 }, pos);
                 if declarations.is_empty() {
                     oper
                 }
                 else {
-                    add_declarations(oper, declarations, pos)
+                    self.add_declarations(oper, declarations, pos)
                 }
             },
             Expr::Record { fields, typ } => {
                 let mut new_fields = vec![];
                 let mut declarations = vec![];
                 for mut field in fields {
-                    if can_extract(&field.node.expr) {
+                    if can_extract(&self.arena, field.node.expr) {
                         let (name, declaration) = self.extract(field.node.expr);
                         declarations.push(WithPos::new(declaration, pos));
                         field.node = RecordField {
-                            expr: variable(name, pos),
+                            expr: self.variable(name, pos),
                             ident: field.node.ident,
                         };
                         new_fields.push(field);
                     }
                     else {
-                        field.node.expr = self.rewrite(field.node.expr);
+                        field.node.expr = self.rewrite_expr(field.node.expr);
                         new_fields.push(field);
                     }
                 }
@@ -208,30 +282,30 @@ impl<'a> Rewriter<'a> {
                     record
                 }
                 else {
-                    add_declarations(record, declarations, pos)
+                    self.add_declarations(record, declarations, pos)
                 }
             },
             Expr::Sequence(exprs) => {
                 // TODO: extract.
                 let mut new_exprs = vec![];
                 for expr in exprs {
-                    new_exprs.push(self.rewrite(expr));
+                    new_exprs.push(self.rewrite_expr(expr));
                 }
                 WithPos::new(Expr::Sequence(new_exprs), pos)
             },
             Expr::Str { value } => WithPos::new(Expr::Str { value }, pos),
             Expr::Subscript { expr, this } => {
                 WithPos::new(Expr::Subscript {
-                    expr: Box::new(self.rewrite(*expr)),
-                    this: Box::new(self.rewrite(*this)),
+                    expr: self.rewrite_expr(expr),
+                    this: self.rewrite_expr(this),
                 }, pos)
             },
             Expr::Variable(var) => WithPos::new(Expr::Variable(var), pos),
             Expr::While { body, test } => {
                 // TODO: extract.
                 WithPos::new(Expr::While {
-                    body: Box::new(self.rewrite(*body)),
-                    test: Box::new(self.rewrite(*test)),
+                    body: self.rewrite_expr(body),
+                    test: self.rewrite_expr(test),
                 }, pos)
             },
         }
@@ -240,14 +314,18 @@ impl<'a> Rewriter<'a> {
     fn rewrite_dec(&mut self, mut declaration: DeclarationWithPos) -> DeclarationWithPos {
         declaration.node =
             match declaration.node {
-                Declaration::ClassDeclaration { declarations, name, parent_class } => {
-                    let declarations =
-                        declarations
+                Declaration::ClassDeclaration { members, name, parent_class } => {
+                    let members =
+                        members
                             .into_iter()
-                            .map(|declaration| self.rewrite_dec(declaration))
+                            .map(|member| ClassMember {
+                                declaration: self.rewrite_dec(member.declaration),
+                                is_static: member.is_static,
+                                visibility: member.visibility,
+                            })
                             .collect();
                     Declaration::ClassDeclaration {
-                        declarations,
+                        members,
                         name,
                         parent_class,
                     }
@@ -255,29 +333,34 @@ impl<'a> Rewriter<'a> {
                 Declaration::Function(functions) => {
                     let mut new_functions = vec![];
                     for mut function in functions {
-                        function.node.body = self.rewrite(function.node.body);
+                        let body = self.rewrite_expr(function.node.body);
                         let mut declarations = vec![];
                         for param in &function.node.params {
+                            let init =
+                                self.arena.alloc(WithPos::new(Expr::Variable(WithPos::new(param.node.name, param.pos)),
+                                    param.pos));
                             declarations.push(WithPos::new(Declaration::VariableDeclaration {
                                 escape: false,
-                                init: WithPos::new(Expr::Variable(WithPos::new(param.node.name, param.pos)), param.pos),
+                                init,
+                                loop_variable: false,
                                 name: param.node.name,
                                 typ: None,
                             }, param.pos));
                         }
-                        function.node.body = WithPos::new(Expr::Let {
-                            body: Box::new(function.node.body),
+                        function.node.body = self.arena.alloc(WithPos::new(Expr::Let {
+                            body,
                             declarations,
-                        }, function.pos);
+                        }, function.pos));
                         new_functions.push(function);
                     }
                     Declaration::Function(new_functions)
                 },
                 Declaration::Type(types) => Declaration::Type(types),
-                Declaration::VariableDeclaration { escape, init, name, typ } => {
+                Declaration::VariableDeclaration { escape, init, loop_variable, name, typ } => {
                     Declaration::VariableDeclaration {
                         escape,
-                        init: self.rewrite(init),
+                        init: self.rewrite_expr(init),
+                        loop_variable,
                         name,
                         typ,
                     }
@@ -285,22 +368,41 @@ impl<'a> Rewriter<'a> {
             };
         declaration
     }
-}
 
-fn add_declarations(body: ExprWithPos, declarations: Vec<DeclarationWithPos>, pos: Pos) -> ExprWithPos {
-    WithPos::new(Expr::Let {
-        body: Box::new(body),
-        declarations,
-    }, pos)
+    fn add_declarations(&mut self, body: ExprWithPos, declarations: Vec<DeclarationWithPos>, pos: Pos) -> ExprWithPos {
+        WithPos::new(Expr::Let {
+            body: self.arena.alloc(body),
+            declarations,
+        }, pos)
+    }
+
+    fn variable(&mut self, name: Symbol, pos: Pos) -> ExprId {
+        self.arena.alloc(WithPos::new(Expr::Variable(WithPos::new(name, pos)), pos))
+    }
 }
 
-fn can_extract(expr: &ExprWithPos) -> bool {
-    match expr.node {
-        Expr::Nil => false,
+fn can_extract(arena: &Arena, expr: ExprId) -> bool {
+    match arena[expr].node {
+        // Integer literals are never collected and are kept as is so that they can be checked and
+        // folded as constants.
+        Expr::Int { .. } | Expr::Nil => false,
+        // So is arithmetic on them, like a negative number.
+        Expr::Oper { left, ref oper, right } =>
+            match oper.node {
+                Operator::Minus | Operator::Plus | Operator::Times => can_extract(arena, left) || can_extract(arena, right),
+                _ => true,
+            },
         _ => true,
     }
 }
 
-fn variable(name: Symbol, pos: Pos) -> ExprWithPos {
-    WithPos::new(Expr::Variable(WithPos::new(name, pos)), pos)
+fn is_comparison(expr: &ExprWithPos) -> bool {
+    match expr.node {
+        Expr::Oper { ref oper, .. } =>
+            match oper.node {
+                Operator::Equal | Operator::Ge | Operator::Gt | Operator::Le | Operator::Lt | Operator::Neq => true,
+                _ => false,
+            },
+        _ => false,
+    }
 }
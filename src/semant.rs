@@ -21,32 +21,60 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::iter;
 use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use ast::{
+    Arena,
+    Ast,
+    CaseArmWithPos,
     Declaration,
     DeclarationWithPos,
     Expr,
-    ExprWithPos,
+    ExprId,
     FieldWithPos,
     FuncDeclaration,
+    FuncDeclarationWithPos,
     Operator,
     RecordFieldWithPos,
     Ty,
     TypeDec,
     TypeDecWithPos,
     TyWithPos,
+    Visibility,
+};
+use canon::constant_value;
+use const_eval::{EvaluableFunctions, evaluable_calls};
+use dialect::Dialect;
+use env::{
+    CHARS_TO_STRING,
+    CHECK_VERSIONS,
+    Env,
+    Entry,
+    ON_SIGNAL,
+    STANDARD_FUNCTIONS,
+    STRING_TO_CHARS,
+    WEAK,
+    WEAK_GET,
+    bytes_type,
+    external_functions,
+    runtime_name,
 };
-use env::{Env, Entry};
 use error::{Error, Result};
-use frame::{Fragment, Frame, Memory};
+use frame::{Finalizer, Fragment, Frame, Memory};
 use gen;
 use gen::{
     Gen,
     Level,
+    NestedAccess,
+    array_clone,
     array_subscript,
     binary_oper,
+    byte_array_subscript,
+    case_expression,
+    checked_division,
     class_create,
     field_access,
     function_call,
@@ -55,20 +83,26 @@ use gen::{
     init_array,
     method_call,
     num,
+    record_clone,
     record_create,
     relational_oper,
     simple_var,
-    string_equality,
+    static_field,
+    static_fields_init,
+    static_link,
+    string_comparison,
     unit,
     var_dec,
     var_decs,
     while_loop,
 };
 use ir::{Exp, Statement, _Statement};
+use opt_level::OptLevel;
+use purity::{PURE_EXTERNAL_FUNCTIONS, pure_calls};
 use position::{Pos, WithPos};
 use self::AddError::*;
 use symbol::{Strings, Symbol, SymbolWithPos};
-use temp::{Label, TempMap};
+use temp::{DataLabel, Label, TempMap};
 use types::{
     ClassField,
     ClassMethod,
@@ -77,9 +111,6 @@ use types::{
     Unique,
 };
 
-// Offset 2, because offset 0 is the object type (class) and offset 1 is the data layout.
-pub const VTABLE_OFFSET: usize = 2;
-
 #[derive(PartialEq)]
 enum AddError {
     AddError,
@@ -92,6 +123,14 @@ pub struct ExpTy {
     pub ty: Type,
 }
 
+/// Function declared at the top level of a library, which the program can call.
+pub struct Export {
+    pub file: String,
+    pub name: Symbol,
+    pub parameters: Vec<Type>,
+    pub result: Type,
+}
+
 pub enum FieldType {
     Class,
     Record,
@@ -104,52 +143,148 @@ const EXP_TYPE_ERROR: ExpTy =
     };
 
 pub struct SemanticAnalyzer<'a, F: Clone + Frame + 'a> {
+    // Expressions of the file being analyzed, shared with the functions translating them.
+    arena: Rc<Arena>,
+    // Class being declared, whose private members are accessible.
+    current_class: Option<Symbol>,
+    // Functions whose name is taken in the executable, with where they are declared.
+    declared_functions: HashMap<Symbol, String>,
+    dialect: Dialect,
     env: &'a mut Env<F>,
     errors: Vec<Error>,
     escaping_vars: Vec<i64>,
+    // Functions whose calls with constant arguments are evaluated at -O2, with their levels at the
+    // same indices.
+    evaluable_function_levels: Vec<Level<F>>,
+    evaluable_functions: EvaluableFunctions,
+    finalize_symbol: Symbol,
+    // Hidden field of the classes declaring a finalizer, holding its static link.
+    finalizer_link_symbol: Symbol,
+    // Linked without the runtime, which provides checkVersions.
+    freestanding: bool,
     gen: Gen<F>,
     in_loop: bool,
+    // Translating a static method, which has no `self`.
+    in_static_method: bool,
     methods_level: HashMap<(Symbol, Symbol), Level<F>>,
+    nested_access: NestedAccess,
+    opt_level: OptLevel,
+    // Enforce the rules of the Tiger reference manual.
+    pedantic: bool,
+    // Functions without side effects, found at -O2.
+    pure_function_levels: Vec<Level<F>>,
     self_symbol: Symbol,
-    strings: Rc<Strings>,
+    strings: Arc<Strings>,
     temp_map: TempMap,
+    // Report the overflow of the smallest integer divided by -1 instead of letting the CPU fault.
+    trap_on_div_min: bool,
 }
 
 impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
-    pub fn new(env: &'a mut Env<F>, strings: Rc<Strings>, self_symbol: Symbol, object_symbol: Symbol) -> Self {
+    pub fn new(env: &'a mut Env<F>, strings: Arc<Strings>, self_symbol: Symbol, object_symbol: Symbol) -> Self {
         let object_class = Type::Class {
             data_layout: String::new(),
             fields: vec![],
             methods: vec![],
             name: object_symbol,
             parent_class: None,
+            static_data: DataLabel::new(),
+            static_fields: vec![],
+            static_methods: vec![],
             unique: Unique::new(),
-            vtable_name: Label::with_name("__vtable_Object"),
+            vtable_name: DataLabel::with_name("__vtable_Object"),
         };
         env.enter_type(object_symbol, object_class);
+        let finalize_symbol = env.type_symbol("finalize");
+        let finalizer_link_symbol = env.type_symbol("__finalizer_link");
         SemanticAnalyzer {
+            arena: Rc::new(Arena::new()),
+            current_class: None,
+            declared_functions: HashMap::new(),
+            dialect: Dialect::Extended,
             env,
             errors: vec![],
             escaping_vars: vec![],
+            evaluable_function_levels: vec![],
+            evaluable_functions: EvaluableFunctions::new(),
+            finalize_symbol,
+            finalizer_link_symbol,
+            freestanding: false,
             gen: Gen::new(),
             in_loop: false,
+            in_static_method: false,
             methods_level: HashMap::new(),
+            nested_access: NestedAccess::StaticLink,
+            opt_level: OptLevel::default(),
+            pedantic: false,
+            pure_function_levels: vec![],
             self_symbol,
             strings,
             temp_map: TempMap::new(),
+            trap_on_div_min: false,
         }
     }
 
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn set_freestanding(&mut self, freestanding: bool) {
+        self.freestanding = freestanding;
+    }
+
+    pub fn set_nested_access(&mut self, nested_access: NestedAccess) {
+        self.nested_access = nested_access;
+    }
+
+    pub fn set_opt_level(&mut self, opt_level: OptLevel) {
+        self.opt_level = opt_level;
+    }
+
+    pub fn set_pedantic(&mut self, pedantic: bool) {
+        self.pedantic = pedantic;
+    }
+
+    pub fn set_trap_on_div_min(&mut self, trap_on_div_min: bool) {
+        self.trap_on_div_min = trap_on_div_min;
+    }
+
     fn add_error(&mut self, error: Error) {
         self.errors.push(error);
     }
 
-    pub fn analyze(mut self, main_symbol: Symbol, expr: ExprWithPos) -> Result<Vec<Fragment<F>>> {
-        let pos = expr.pos;
-        let body = WithPos::new(
-            Expr::Sequence(vec![expr, WithPos::new(Expr::Int { value: 0 }, pos)]),
-            pos,
-        );
+    fn require_dialect(&mut self, dialect: Dialect, feature: &str, pos: Pos) {
+        if self.dialect < dialect {
+            self.add_error(Error::NonStandard {
+                dialect,
+                feature: feature.to_string(),
+                pos,
+            });
+        }
+    }
+
+    /// String literal of the position, used by the runtime to identify allocation sites.
+    fn position_literal(&mut self, pos: Pos) -> Exp {
+        let position = format!("{}:{}:{}", self.env.var_name(pos.file), pos.line, pos.column);
+        self.gen.string_literal(position)
+    }
+
+    pub fn analyze(mut self, main_symbol: Symbol, ast: Ast) -> Result<Vec<Fragment<F>>> {
+        let mut arena = ast.arena;
+        let pos = arena[ast.root].pos;
+        let mut body = vec![];
+        // The program first checks that it is linked with the runtime it was compiled for, unless
+        // it is linked without a runtime.
+        if !self.freestanding {
+            body.push(arena.alloc(WithPos::dummy(Expr::Call {
+                args: vec![],
+                function: self.env.var_symbol(CHECK_VERSIONS),
+            })));
+        }
+        body.push(ast.root);
+        body.push(arena.alloc(WithPos::new(Expr::Int { value: 0 }, pos)));
+        let body = arena.alloc(WithPos::new(Expr::Sequence(body), pos));
+        self.arena = Rc::new(arena);
         let result = Some(WithPos::new(self.env.type_symbol("int"), pos));
         self.trans_dec(&WithPos::new(Declaration::Function(vec![
             WithPos::new(FuncDeclaration {
@@ -158,7 +293,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 params: vec![],
                 result,
             }, pos)
-        ]), pos), &gen::outermost(), None);
+        ]), pos), &gen::outermost(self.nested_access), None);
         if self.errors.is_empty() {
             Ok(self.gen.get_result())
         }
@@ -167,25 +302,133 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         }
     }
 
+    /// Make the functions exported by the libraries callable from the program.
+    pub fn import(&mut self, exports: &[Export]) {
+        for export in exports {
+            self.env.import_function(export.name, export.parameters.clone(), export.result.clone());
+            self.declared_functions.insert(export.name, format!("`{}`", export.file));
+        }
+    }
+
+    /// Analyze a library, which is a `let` declaring types and functions. Its functions are not
+    /// nested in a main function, so that the other files can call them: they are exported,
+    /// unless their name is already taken by the runtime or by the previous libraries.
+    pub fn analyze_library(mut self, ast: Ast, previous_exports: &[Export])
+        -> Result<(Vec<Fragment<F>>, Vec<Export>)>
+    {
+        for (name, _) in external_functions() {
+            let symbol = self.env.type_symbol(runtime_name(name));
+            self.declared_functions.insert(symbol, "the runtime".to_string());
+        }
+        for export in previous_exports {
+            self.declared_functions.insert(export.name, format!("`{}`", export.file));
+        }
+        let main_symbol = self.env.type_symbol("main");
+        self.declared_functions.insert(main_symbol, "the program".to_string());
+
+        self.arena = Rc::new(ast.arena);
+        let arena = Rc::clone(&self.arena);
+        let outermost = gen::outermost(self.nested_access);
+        let mut exports = vec![];
+        if let Expr::Let { ref declarations, .. } = arena[ast.root].node {
+            for declaration in declarations {
+                match declaration.node {
+                    Declaration::Function(ref functions) => {
+                        self.trans_dec(declaration, &outermost, None);
+                        for function in functions {
+                            if let Some(export) = self.export(&function.node.name) {
+                                exports.push(export);
+                            }
+                        }
+                    },
+                    Declaration::Type(_) => {
+                        self.trans_dec(declaration, &outermost, None);
+                    },
+                    Declaration::ClassDeclaration { .. } | Declaration::VariableDeclaration { .. } =>
+                        self.add_error(Error::LibraryDeclaration { pos: declaration.pos }),
+                }
+            }
+        }
+        else {
+            self.add_error(Error::LibraryDeclaration { pos: arena[ast.root].pos });
+        }
+
+        if self.errors.is_empty() {
+            Ok((self.gen.get_result(), exports))
+        }
+        else {
+            Err(Error::Multi(self.errors))
+        }
+    }
+
+    fn export(&mut self, name: &SymbolWithPos) -> Option<Export> {
+        let (parameters, result) =
+            match self.env.look_var(name.node).cloned() {
+                Some(Entry::Fun { parameters, result, .. }) => (parameters, result),
+                _ => return None,
+            };
+        // NOTE: the types declared in a file are unknown to the other files.
+        let mut valid = true;
+        for typ in parameters.iter().chain(Some(&result)) {
+            let typ = self.actual_ty(typ);
+            if !is_predefined(&typ) {
+                self.add_error(Error::ExportedType {
+                    pos: name.pos,
+                    typ,
+                });
+                valid = false;
+            }
+        }
+        if !valid {
+            return None;
+        }
+        Some(Export {
+            file: self.env.var_name(name.pos.file),
+            name: name.node,
+            parameters: parameters.iter().map(|typ| self.actual_ty(typ)).collect(),
+            result: self.actual_ty(&result),
+        })
+    }
+
     fn actual_ty(&mut self, typ: &Type) -> Type {
         match *typ {
             Type::Name(_, Some(ref typ)) => *typ.clone(),
             Type::Name(ref symbol, None) => self.get_type(symbol, DontAddError),
+            // NOTE: the types in the signatures of the methods were resolved while the class was
+            // being declared, so they miss its members.
+            Type::Class { name, ref unique, .. } => {
+                if let Some(class) = self.env.look_type(name) {
+                    if let Type::Class { unique: ref class_unique, .. } = *class {
+                        if class_unique == unique {
+                            return class.clone();
+                        }
+                    }
+                }
+                typ.clone()
+            },
             ref typ => typ.clone(),
         }
     }
 
-    fn check_binary_op(&mut self, oper: Operator, left: &ExprWithPos, right: &ExprWithPos, level: &Level<F>,
+    fn check_binary_op(&mut self, oper: Operator, left: ExprId, right: ExprId, level: &Level<F>,
                        done_label: Option<Label>) -> ExpTy
     {
-        let left_pos = left.pos;
+        let left_pos = self.arena[left].pos;
         let left = self.trans_exp(left, level, done_label.clone(), true);
         self.check_int(&left, left_pos);
-        let right_pos = right.pos;
+        let right_pos = self.arena[right].pos;
         let right = self.trans_exp(right, level, done_label, true);
         self.check_int(&right, right_pos);
+        let exp =
+            if oper == Operator::Divide && self.trap_on_div_min {
+                let position = self.position_literal(left_pos);
+                checked_division::<F>(left.exp, right.exp, position)
+            }
+            else {
+                binary_oper(oper, left.exp, right.exp)
+            };
         ExpTy {
-            exp: binary_oper(oper, left.exp, right.exp),
+            exp,
             ty: Type::Int,
         }
     }
@@ -206,7 +449,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
     }
 
     fn check_int(&mut self, expr: &ExpTy, pos: Pos) {
-        if expr.ty != Type::Int && expr.ty != Type::Error {
+        if expr.ty != Type::Int && !expr.ty.is_error() {
             self.add_error(Error::Type {
                 expected: Type::Int,
                 pos,
@@ -216,7 +459,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
     }
 
     fn check_function_types(&mut self, expected: &FunctionType, unexpected: &FunctionType, pos: Pos) {
-        if expected != unexpected {
+        if expected != unexpected && !expected.is_error() && !unexpected.is_error() {
             self.add_error(Error::FunctionType {
                 expected: expected.clone(),
                 pos,
@@ -225,10 +468,37 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         }
     }
 
+    /// Raw pointers cannot point to the values managed by the collector, since it would not know
+    /// about these references.
+    fn check_pointer_target(&mut self, typ: &Type, pos: Pos) -> bool {
+        match self.actual_ty(typ) {
+            Type::Error | Type::Int | Type::Pointer(_) => true,
+            typ => {
+                self.add_error(Error::PointerToCollected {
+                    pos,
+                    typ,
+                });
+                false
+            },
+        }
+    }
+
     fn check_types(&mut self, expected: &Type, unexpected: &Type, pos: Pos) {
         let expected = self.actual_ty(expected);
         let unexpected = self.actual_ty(unexpected);
-        if expected != unexpected && expected != Type::Error && unexpected != Type::Error {
+        if expected != unexpected && !expected.is_error() && !unexpected.is_error() {
+            if let Type::Weak(ref expected) = expected {
+                if let Type::Weak(ref unexpected) = unexpected {
+                    // NOTE: weak references are not declared with a unique type, so they are
+                    // compatible when their targets are.
+                    return self.check_types(expected, unexpected, pos);
+                }
+            }
+            if let Type::Pointer(ref expected) = expected {
+                if let Type::Pointer(ref unexpected) = unexpected {
+                    return self.check_types(expected, unexpected, pos);
+                }
+            }
             if let Type::Class { .. } | Type::Record { .. } = expected {
                 if unexpected == Type::Nil {
                     return;
@@ -265,8 +535,22 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
     }
 
     fn get_type(&mut self, symbol: &SymbolWithPos, add: AddError) -> Type {
-        if let Some(typ) = self.env.look_type(symbol.node) {
-            return typ.clone();
+        if let Some(typ) = self.env.look_type(symbol.node).cloned() {
+            if add == AddError {
+                let extension =
+                    match typ {
+                        Type::BigInt => Some("bigint"),
+                        Type::Byte => Some("byte"),
+                        Type::Char => Some("char"),
+                        Type::Array(..) if typ == bytes_type() => Some("bytes"),
+                        Type::Opaque(name) => Some(name),
+                        _ => None,
+                    };
+                if let Some(extension) = extension {
+                    self.require_dialect(Dialect::Extended, &format!("Type `{}`", extension), symbol.pos);
+                }
+            }
+            return typ;
         }
         if add == AddError {
             self.undefined_type(symbol)
@@ -276,32 +560,285 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         }
     }
 
+    /// Only the variables, which live in the frame since they escape, have an address: the fields
+    /// and the array elements are moved by the collector.
+    fn trans_address_of(&mut self, var: ExprId, level: &Level<F>) -> ExpTy {
+        let var = &Rc::clone(&self.arena)[var];
+        if let Expr::Variable(ref ident) = var.node {
+            match self.env.look_var(ident.node).cloned() {
+                Some(Entry::Var { access, ref typ, .. }) => {
+                    let typ = self.actual_ty(typ);
+                    if !self.check_pointer_target(&typ, var.pos) {
+                        return EXP_TYPE_ERROR;
+                    }
+                    if let Exp::Mem(box address) = simple_var(access, level) {
+                        return ExpTy {
+                            exp: address,
+                            ty: Type::Pointer(Box::new(typ)),
+                        };
+                    }
+                },
+                None => return self.undefined_variable(ident.node, ident.pos),
+                _ => (),
+            }
+        }
+        self.add_error(Error::AddressOf {
+            pos: var.pos,
+        });
+        EXP_TYPE_ERROR
+    }
+
+    /// Conversion written like a call to a type, `intp(address)` or `int(pointer)`, which only
+    /// converts an integer to a pointer or a pointer to an integer, without changing the value.
+    fn trans_conversion(&mut self, typ: &Type, args: &[ExprId], level: &Level<F>, done_label: Option<Label>,
+        pos: Pos) -> ExpTy
+    {
+        self.require_dialect(Dialect::Unsafe, "Pointer conversion", pos);
+        if args.len() != 1 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 1,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let value = self.trans_exp(args[0], level, done_label, true);
+        let to = self.actual_ty(typ);
+        match (self.actual_ty(&value.ty), &to) {
+            (Type::Int, &Type::Pointer(_)) | (Type::Pointer(_), &Type::Int) => ExpTy {
+                exp: value.exp,
+                ty: to,
+            },
+            (from, _) => {
+                self.add_error(Error::Conversion {
+                    from,
+                    pos: self.arena[args[0]].pos,
+                    to,
+                });
+                EXP_TYPE_ERROR
+            },
+        }
+    }
+
+    fn trans_case(&mut self, subject: ExprId, arms: &[CaseArmWithPos], else_: Option<ExprId>,
+                  level: &Level<F>, done_label: Option<Label>) -> ExpTy
+    {
+        let subject_expr = self.trans_exp(subject, level, done_label.clone(), true);
+        self.check_types(&Type::String, &subject_expr.ty, self.arena[subject].pos);
+
+        // The labels are numbered by their rank in the table, which is sorted so that the runtime
+        // can find the subject with a binary search.
+        let mut labels = vec![];
+        for arm in arms {
+            if labels.contains(&arm.node.label) {
+                self.add_error(Error::DuplicateCase {
+                    label: arm.node.label.clone(),
+                    pos: arm.pos,
+                });
+            }
+            else {
+                labels.push(arm.node.label.clone());
+            }
+        }
+        labels.sort();
+
+        let mut ty = None;
+        let mut arm_exprs = vec![];
+        for arm in arms {
+            let body = self.trans_exp(arm.node.body, level, done_label.clone(), true);
+            match ty {
+                Some(ref ty) => self.check_types(ty, &body.ty, self.arena[arm.node.body].pos),
+                None => ty = Some(body.ty),
+            }
+            if let Ok(index) = labels.binary_search(&arm.node.label) {
+                arm_exprs.push((index, body.exp));
+            }
+        }
+        let else_expr =
+            match else_ {
+                Some(else_) => {
+                    let else_expr = self.trans_exp(else_, level, done_label, true);
+                    match ty {
+                        Some(ref ty) => self.check_types(ty, &else_expr.ty, self.arena[else_].pos),
+                        None => ty = Some(else_expr.ty),
+                    }
+                    Some(else_expr.exp)
+                },
+                None => {
+                    // NOTE: like an if without else, the arms cannot produce a value.
+                    if let Some(ref ty) = ty {
+                        self.check_types(&Type::Unit, ty, self.arena[arms[0].node.body].pos);
+                    }
+                    ty = Some(Type::Unit);
+                    None
+                },
+            };
+
+        let label_count = labels.len();
+        let table = self.gen.string_table(labels);
+        ExpTy {
+            exp: case_expression(subject_expr.exp, table, label_count, arm_exprs, else_expr, level),
+            ty: ty.unwrap_or(Type::Unit),
+        }
+    }
+
+    /// Record the functions of the group which can be evaluated at compile time and those which are
+    /// pure.
+    fn analyze_functions(&mut self, declarations: &[FuncDeclarationWithPos], levels: &[Level<F>]) {
+        let mut evaluable = vec![];
+        let mut pure = vec![];
+        for declaration in declarations {
+            let FuncDeclaration { body, ref params, ref result, .. } = declaration.node;
+            let integers =
+                match *result {
+                    Some(ref result) => {
+                        let mut types = iter::once(result).chain(params.iter().map(|param| &param.node.typ));
+                        types.all(|typ| {
+                            let typ = self.get_type(typ, DontAddError);
+                            self.actual_ty(&typ) == Type::Int
+                        })
+                    },
+                    None => false,
+                };
+            let param_names: Vec<_> = params.iter().map(|param| param.node.name).collect();
+            evaluable.push(evaluable_calls(&self.arena, &param_names, body)
+                .filter(|_| integers)
+                .map(|calls| (param_names.clone(), calls)));
+            pure.push(pure_calls(&self.arena, &param_names, body).map(|calls| ((), calls)));
+        }
+
+        self.keep_closed_candidates(&mut evaluable, levels,
+            |this, level| this.evaluable_function(level).is_some());
+        let first_index = self.evaluable_functions.count();
+        let remaining: Vec<_> = evaluable.iter().map(Option::is_some).collect();
+        for ((declaration, level), candidate) in declarations.iter().zip(levels).zip(evaluable) {
+            if let Some((params, calls)) = candidate {
+                let callees = calls.into_iter()
+                    .map(|name| {
+                        let callee = self.evaluable_callee(name, levels, &remaining, first_index);
+                        (name, callee.expect("evaluable callee"))
+                    })
+                    .collect();
+                self.evaluable_functions.add(params, declaration.node.body, callees);
+                self.evaluable_function_levels.push(level.clone());
+            }
+        }
+
+        self.keep_closed_candidates(&mut pure, levels,
+            |this, level| group_position(&this.pure_function_levels, level).is_some());
+        let mut labels = vec![];
+        for (level, candidate) in levels.iter().zip(pure) {
+            if candidate.is_some() {
+                labels.push(level.current.borrow().name());
+                self.pure_function_levels.push(level.clone());
+            }
+        }
+        if !labels.is_empty() {
+            self.gen.pure_functions(labels);
+        }
+    }
+
+    /// Remove the candidates of the group calling a function which is neither recorded, as told by
+    /// `recorded`, nor a candidate left, until those left only call each other or recorded
+    /// functions. The pure functions of the runtime are recorded.
+    fn keep_closed_candidates<T>(&self, candidates: &mut [Option<(T, Vec<Symbol>)>], group: &[Level<F>],
+        recorded: impl Fn(&Self, &Level<F>) -> bool)
+    {
+        loop {
+            let remaining: Vec<_> = candidates.iter().map(Option::is_some).collect();
+            let mut changed = false;
+            for candidate in candidates.iter_mut() {
+                if let Some((_, ref calls)) = *candidate {
+                    let closed = calls.iter().all(|&name|
+                        match self.env.look_var(name) {
+                            Some(&Entry::Fun { external: true, ref label, .. }) =>
+                                PURE_EXTERNAL_FUNCTIONS.iter().any(|&function| runtime_name(function) == label.to_name()),
+                            Some(&Entry::Fun { external: false, ref level, .. }) =>
+                                recorded(self, level) || group_position(group, level).is_some_and(|position| remaining[position]),
+                            _ => false,
+                        });
+                    if !closed {
+                        *candidate = None;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    /// Index of the function which can be evaluated called by this name, either recorded before or
+    /// in the group of functions being declared, where only those marked as `remaining` are, from
+    /// `first_index`.
+    fn evaluable_callee(&self, name: Symbol, group: &[Level<F>], remaining: &[bool], first_index: usize)
+        -> Option<usize>
+    {
+        match self.env.look_var(name) {
+            Some(&Entry::Fun { external: false, ref level, .. }) => {
+                if let Some(index) = self.evaluable_function(level) {
+                    return Some(index);
+                }
+                let position = group_position(group, level)?;
+                if remaining[position] {
+                    Some(first_index + remaining[..position].iter().filter(|&&remaining| remaining).count())
+                }
+                else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn evaluable_function(&self, level: &Level<F>) -> Option<usize> {
+        group_position(&self.evaluable_function_levels, level)
+    }
+
+    /// Value of the call of the function with these arguments, if it can be evaluated and they are
+    /// constants.
+    fn evaluate_call(&self, level: &Level<F>, args: &[Exp]) -> Option<i64> {
+        let function = self.evaluable_function(level)?;
+        let args = args.iter()
+            .map(constant_value)
+            .collect::<Option<Vec<_>>>()?;
+        self.evaluable_functions.evaluate(&self.arena, function, args)
+    }
+
     fn trans_dec(&mut self, declaration: &DeclarationWithPos, parent_level: &Level<F>, done_label: Option<Label>)
         -> Option<Statement>
     {
         match declaration.node {
-            Declaration::ClassDeclaration { ref declarations, ref name, ref parent_class } => {
+            Declaration::ClassDeclaration { ref members, ref name, ref parent_class } => {
                 struct Method<F> {
-                    body: ExprWithPos,
+                    body: ExprId,
+                    is_static: bool,
                     level: Level<F>,
                     param_names: Vec<Symbol>,
                     param_types: Vec<Type>,
                     return_type: Type,
                 }
 
+                let unique = Unique::new();
                 let empty_class_type = Type::Class {
                     data_layout: String::new(),
                     fields: vec![],
                     methods: vec![],
                     name: name.node,
                     parent_class: Some(parent_class.clone()),
-                    unique: Unique::new(),
-                    vtable_name: Label::new(),
+                    static_data: DataLabel::new(),
+                    static_fields: vec![],
+                    static_methods: vec![],
+                    unique: unique.clone(),
+                    vtable_name: DataLabel::new(),
                 };
                 self.env.enter_type(name.node, empty_class_type);
 
-                let old_escaping_vars = mem::replace(&mut self.escaping_vars, vec![]);
-                let old_temp_map = mem::replace(&mut self.temp_map, TempMap::new());
+                let mut old_escaping_vars = mem::replace(&mut self.escaping_vars, vec![]);
+                let mut old_temp_map = mem::replace(&mut self.temp_map, TempMap::new());
+                let old_current_class = self.current_class.replace(name.node);
 
                 let mut pending_methods = vec![];
                 let parent_type = self.get_type(parent_class, AddError);
@@ -316,8 +853,14 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 }
                 let (mut fields, mut data_layout, parent_methods) = self.parent_members(parent_class);
                 let mut methods = vec![];
-                for declaration in declarations {
-                    match declaration.node {
+                let class_name = self.strings.get(name.node).expect("string get");
+                let static_data = DataLabel::with_name(&format!("__static_{}", class_name));
+                let mut static_fields = vec![];
+                let mut static_data_layout = String::new();
+                let mut static_inits = vec![];
+                let mut static_methods = vec![];
+                for member in members {
+                    match member.declaration.node {
                         Declaration::Function(ref functions) => {
                             for function in functions {
                                 let params = &function.node.params;
@@ -342,12 +885,31 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                 let mut formals: Vec<_> = params.iter()
                                     .map(|param| self.env.look_escape(param.node.name))
                                     .collect();
-                                formals.insert(0, true); // NOTE: self implicit parameter.
+                                if !member.is_static {
+                                    formals.insert(0, true); // NOTE: self implicit parameter.
+                                }
                                 let func_name = function.node.name.node;
+                                if func_name == self.finalize_symbol {
+                                    if member.is_static || !params.is_empty() || return_type != Type::Unit {
+                                        self.add_error(Error::Finalizer {
+                                            pos: function.node.name.pos,
+                                        });
+                                    }
+                                    // NOTE: the collector calls the finalizer, so its static link is
+                                    // saved in the object when it is created.
+                                    data_layout.push('n');
+                                    fields.push(ClassField {
+                                        class_name: name.node,
+                                        name: self.finalizer_link_symbol,
+                                        typ: Type::Int,
+                                        value: None,
+                                        visibility: Visibility::Private,
+                                    });
+                                }
                                 let label = self.method_label(name.node, func_name);
                                 let level = Level::new(parent_level, label.clone(), formals);
                                 self.methods_level.insert((name.node, func_name), level.clone());
-                                methods.push(ClassMethod {
+                                let method = ClassMethod {
                                     class_name: name.node,
                                     label,
                                     name: function.node.name.clone(),
@@ -355,10 +917,18 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                         param_types: param_types.clone(),
                                         return_type: return_type.clone(),
                                     },
-                                });
+                                    visibility: member.visibility,
+                                };
+                                if member.is_static {
+                                    static_methods.push(method);
+                                }
+                                else {
+                                    methods.push(method);
+                                }
 
                                 pending_methods.push(Method {
-                                    body: function.node.body.clone(),
+                                    body: function.node.body,
+                                    is_static: member.is_static,
                                     level,
                                     param_names,
                                     param_types,
@@ -366,40 +936,67 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                 });
                             }
                         },
-                        Declaration::VariableDeclaration { ref init, name, ref typ, .. } => {
-                            let exp = self.trans_exp(init, parent_level, done_label.clone(), true);
+                        Declaration::VariableDeclaration { init, name: field_name, ref typ, .. } => {
+                            let exp =
+                                if member.is_static {
+                                    // NOTE: the initializer of a static field runs in the enclosing function.
+                                    mem::swap(&mut self.temp_map, &mut old_temp_map);
+                                    mem::swap(&mut self.escaping_vars, &mut old_escaping_vars);
+                                    let exp = self.trans_exp(init, parent_level, done_label.clone(), true);
+                                    mem::swap(&mut self.temp_map, &mut old_temp_map);
+                                    mem::swap(&mut self.escaping_vars, &mut old_escaping_vars);
+                                    exp
+                                }
+                                else {
+                                    self.trans_exp(init, parent_level, done_label.clone(), true)
+                                };
                             let is_pointer =
                                 match exp.ty {
-                                    Type::Name(ref symbol, None) if symbol.node == name =>
+                                    Type::Name(ref symbol, None) if symbol.node == field_name =>
                                         true,
                                     _ => self.actual_ty(&exp.ty).is_pointer(),
                                 };
                             let typ =
                                 if let Some(ref typ) = *typ {
                                     let typ = self.get_type(typ, AddError);
-                                    self.check_types(&typ, &exp.ty, init.pos);
+                                    self.check_types(&typ, &exp.ty, self.arena[init].pos);
                                     typ
                                 }
                                 else {
                                     exp.ty
                                 };
+                            let layout =
+                                if member.is_static {
+                                    static_inits.push(exp.exp);
+                                    &mut static_data_layout
+                                }
+                                else {
+                                    &mut data_layout
+                                };
                             if is_pointer {
-                                data_layout.push('p')
+                                layout.push('p')
                             }
                             else {
-                                data_layout.push('n')
+                                layout.push('n')
                             }
-                            fields.push(ClassField {
-                                name,
+                            let field = ClassField {
+                                class_name: name.node,
+                                name: field_name,
                                 typ,
-                                value: init.clone(),
-                            });
+                                value: Some(init),
+                                visibility: member.visibility,
+                            };
+                            if member.is_static {
+                                static_fields.push(field);
+                            }
+                            else {
+                                fields.push(field);
+                            }
                         },
                         _ => unreachable!("cannot get that kind of declaration in a class"),
                     }
                 }
-                let class_name = self.strings.get(name.node).expect("string get");
-                let vtable_name = Label::with_name(&format!("__vtable_{}", class_name));
+                let vtable_name = DataLabel::with_name(&format!("__vtable_{}", class_name));
                 let methods = self.inherit_methods(parent_methods, &methods);
                 let class_type = Type::Class {
                     data_layout,
@@ -407,47 +1004,59 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     methods: methods.clone(),
                     name: name.node,
                     parent_class: Some(parent_class.clone()),
-                    unique: Unique::new(),
+                    static_data: static_data.clone(),
+                    static_fields,
+                    static_methods,
+                    unique,
                     vtable_name: vtable_name.clone(),
                 };
                 self.env.replace_type(name.node, class_type.clone());
 
+                let old_in_static_method = self.in_static_method;
                 for method in pending_methods {
-                    let body = &method.body;
+                    let body = method.body;
                     self.env.begin_scope();
+                    self.in_static_method = method.is_static;
                     let mut formals = method.level.formals().into_iter();
-                    self.env.enter_var(self.self_symbol, Entry::Var {
-                        access: formals.next().expect("self parameter").clone(),
-                        typ: class_type.clone(),
-                    });
-                    let fields =
+                    if !method.is_static {
+                        self.env.enter_var(self.self_symbol, Entry::Var {
+                            access: formals.next().expect("self parameter").clone(),
+                            loop_variable: false,
+                            typ: class_type.clone(),
+                        });
+                    }
+                    let (fields, static_fields) =
                         match class_type {
-                            Type::Class { ref fields, .. } => fields,
+                            Type::Class { ref fields, ref static_fields, .. } => (fields, static_fields),
                             _ => unreachable!(),
                         };
-                    for field in fields {
+                    for field in fields.iter().chain(static_fields) {
                         self.env.enter_var(field.name, Entry::ClassField { class: class_type.clone() });
                     }
                     for ((param, name), access) in method.param_types.into_iter().zip(method.param_names).zip(formals) {
-                        self.env.enter_var(name, Entry::Var { access, typ: param });
+                        self.env.enter_var(name, Entry::Var { access, loop_variable: false, typ: param });
                     }
                     let exp = self.trans_exp(body, &method.level, done_label.clone(), true);
-                    self.check_types(&method.return_type, &exp.ty, body.pos);
+                    self.check_types(&method.return_type, &exp.ty, self.arena[body].pos);
                     let current_temp_map = mem::replace(&mut self.temp_map, TempMap::new());
                     let escaping_vars = mem::replace(&mut self.escaping_vars, vec![]);
                     self.gen.proc_entry_exit(&method.level, exp.exp, current_temp_map, escaping_vars);
                     self.env.end_scope();
                 }
+                self.in_static_method = old_in_static_method;
 
                 let method_labels: Vec<_> = methods.iter()
                     .map(|method| method.label.clone())
                     .collect();
-                self.gen.vtable(vtable_name, method_labels);
+                let finalizer = self.finalizer(&class_type);
+                self.gen.vtable(vtable_name, method_labels, finalizer);
+                self.gen.static_fields(static_data.clone(), static_data_layout);
 
                 self.escaping_vars = old_escaping_vars;
                 self.temp_map = old_temp_map;
+                self.current_class = old_current_class;
 
-                None
+                static_fields_init::<F>(&static_data, static_inits)
             },
             Declaration::Function(ref declarations) => {
                 let old_temp_map = mem::replace(&mut self.temp_map, TempMap::new());
@@ -479,6 +1088,15 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                             self.duplicate_param(param);
                         }
                     }
+                    // NOTE: the label of a function is its name, so it must not be taken by
+                    // another file.
+                    if let Some(file) = self.declared_functions.get(&func_name).cloned() {
+                        self.add_error(Error::DuplicateFunction {
+                            file,
+                            ident: self.env.var_name(func_name),
+                            pos: name.pos,
+                        });
+                    }
                     levels.push(level.clone());
                     self.env.enter_var(func_name, Entry::Fun {
                         external: false,
@@ -488,9 +1106,12 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         result: result_type.clone(),
                     });
                 }
+                if self.opt_level >= OptLevel::O2 {
+                    self.analyze_functions(declarations, &levels);
+                }
 
                 // 收集局部变量到 env 中
-                for (&WithPos { node: FuncDeclaration { ref params, ref body, ref result, .. }, .. }, ref level) in
+                for (&WithPos { node: FuncDeclaration { ref params, body, ref result, .. }, .. }, ref level) in
                     declarations.iter().zip(&levels)
                 {
                     let result_type =
@@ -508,10 +1129,10 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     }
                     self.env.begin_scope();
                     for ((param, name), access) in parameters.into_iter().zip(param_names).zip(level.formals().into_iter()) {
-                        self.env.enter_var(name, Entry::Var { access, typ: param });
+                        self.env.enter_var(name, Entry::Var { access, loop_variable: false, typ: param });
                     }
                     let exp = self.trans_exp(body, level, done_label.clone(), true);
-                    self.check_types(&result_type, &exp.ty, body.pos);
+                    self.check_types(&result_type, &exp.ty, self.arena[body].pos);
                     let current_temp_map = mem::replace(&mut self.temp_map, TempMap::new());
                     let escaping_vars = mem::replace(&mut self.escaping_vars, vec![]);
                     self.gen.proc_entry_exit(&level, exp.exp, current_temp_map, escaping_vars);
@@ -531,11 +1152,40 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     let new_type = self.trans_ty(name.node, ty);
                     self.env.replace_type(name.node, new_type);
                 }
+
+                // NOTE: the targets of the pointers are checked once all the types of the group
+                // are known.
+                for &WithPos { node: TypeDec { ref ty, .. }, .. } in type_declarations {
+                    if let Ty::Pointer { ref ident } = ty.node {
+                        let typ = self.get_type(ident, DontAddError);
+                        self.check_pointer_target(&typ, ident.pos);
+                    }
+                }
                 None
             },
-            Declaration::VariableDeclaration { ref init, name, ref typ, .. } => {
+            Declaration::VariableDeclaration { init, loop_variable, name, ref typ, .. } => {
                 let exp = self.trans_exp(init, parent_level, done_label, true);
-                let is_collectable = type_is_collectable(&exp.ty);
+                if self.pedantic && exp.ty == Type::Unit {
+                    self.add_error(Error::NoValue { pos: self.arena[init].pos });
+                }
+                // NOTE: the variable has the declared type, even when the initial value does not
+                // match it, so that its uses are checked against it.
+                let typ =
+                    match *typ {
+                        Some(ref ident) => {
+                            let typ = self.get_type(ident, AddError);
+                            self.check_types(&typ, &exp.ty, ident.pos);
+                            typ
+                        },
+                        None => {
+                            if exp.ty == Type::Nil {
+                                self.add_error(Error::RecordType { pos: declaration.pos });
+                                return None;
+                            }
+                            exp.ty
+                        },
+                    };
+                let is_collectable = type_is_collectable(&self.actual_ty(&typ));
                 let escape = self.env.look_escape(name);
                 let access = gen::alloc_local(parent_level, escape || is_collectable); // TODO: check if this is necessary.
                 if escape {
@@ -546,24 +1196,19 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 if is_collectable {
                     self.temp_map.insert::<F>(&access.1);
                 }
-                if let Some(ref ident) = *typ {
-                    let typ = self.get_type(ident, AddError);
-                    self.check_types(&typ, &exp.ty, ident.pos);
-                } else if exp.ty == Type::Nil {
-                    self.add_error(Error::RecordType { pos: declaration.pos });
-                    return None;
-                }
                 let var = var_dec(&access, exp.exp);
-                self.env.enter_var(name, Entry::Var { access, typ: exp.ty });
+                self.env.enter_var(name, Entry::Var { access, loop_variable, typ });
                 Some(var)
             },
         }
     }
 
-    pub fn trans_exp(&mut self, expr: &ExprWithPos, level: &Level<F>, done_label: Option<Label>, outer_array: bool) -> ExpTy {
-        let pos = expr.pos;
-        match expr.node {
-            Expr::Array { ref init, ref size, ref typ } => {
+    pub fn trans_exp(&mut self, expr: ExprId, level: &Level<F>, done_label: Option<Label>, outer_array: bool) -> ExpTy {
+        let arena = Rc::clone(&self.arena);
+        let pos = arena[expr].pos;
+        match arena[expr].node {
+            Expr::AddressOf { var } => self.trans_address_of(var, level),
+            Expr::Array { init, size, ref typ } => {
                 // NOTE: Since an array can contains heap-allocated values, which could make the
                 // heap grow and thus moving the newly allocated array, we should put this array
                 // on the stack immediately, because the initialization happens before the array
@@ -582,7 +1227,17 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     };
 
                 let size_expr = self.trans_exp(size, level, done_label.clone(), true);
-                self.check_int(&size_expr, size.pos);
+                self.check_int(&size_expr, arena[size].pos);
+                if let Some(size_value) = constant_value(&size_expr.exp) {
+                    if size_value < 0 {
+                        self.add_error(Error::NegativeArraySize {
+                            pos: arena[size].pos,
+                            size: size_value,
+                        });
+                    }
+                }
+                // The runtime reports the position of the array when its size is invalid.
+                let position = self.position_literal(arena[expr].pos);
                 let ty = self.get_type(typ, AddError);
                 let inner_type =
                     match ty {
@@ -590,26 +1245,54 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         Type::Error => &Type::Error,
                         _ => unreachable!(),
                     };
+                let packed = self.is_packed(inner_type);
                 let init_expr = self.trans_exp(init, level, done_label, false);
-                self.check_types(inner_type, &init_expr.ty, init.pos);
+                if packed {
+                    self.check_int(&init_expr, arena[init].pos);
+                }
+                else {
+                    self.check_types(inner_type, &init_expr.ty, arena[init].pos);
+                }
                 let is_pointer = self.array_contains_pointer(&ty);
-                let is_pointer = num(is_pointer as i64);
-                let exp = init_array::<F>(var, size_expr.exp, is_pointer, init_expr.exp, level);
+                let is_pointer = num::<F>(is_pointer as i64);
+                let template =
+                    if packed {
+                        None
+                    }
+                    else {
+                        self.gen.array_template(&size_expr.exp, &init_expr.exp)
+                    };
+                let exp =
+                    match template {
+                        Some(template) => array_clone::<F>(var, size_expr.exp, is_pointer, position, template),
+                        None => init_array::<F>(var, size_expr.exp, is_pointer, packed, position, init_expr.exp),
+                    };
                 ExpTy {
                     exp,
                     ty,
                 }
             },
-            Expr::Assign { ref expr, ref var } => {
-                match var.node {
-                    Expr::Field { .. } | Expr::Subscript { .. } | Expr::Variable(_) => (),
+            Expr::Assign { expr, var } => {
+                match arena[var].node {
+                    Expr::Deref { .. } | Expr::Field { .. } | Expr::Subscript { .. } | Expr::Variable(_) => (),
                     _ => self.add_error(Error::Assign {
-                        pos: var.pos,
+                        pos: arena[var].pos,
                     }),
                 }
+                // NOTE: the increment of the loop variable generated by the parser has a dummy position.
+                if let Expr::Variable(ref ident) = arena[var].node {
+                    if let Some(&Entry::Var { loop_variable: true, .. }) = self.env.look_var(ident.node) {
+                        if self.pedantic && !arena[expr].pos.is_dummy() {
+                            self.add_error(Error::LoopVariableAssign {
+                                ident: self.env.var_name(ident.node),
+                                pos: arena[var].pos,
+                            });
+                        }
+                    }
+                }
                 let var = self.trans_exp(var, level, done_label.clone(), true);
                 let expr_expr = self.trans_exp(expr, level, done_label, true);
-                self.check_types(&var.ty, &expr_expr.ty, expr.pos);
+                self.check_types(&var.ty, &expr_expr.ty, arena[expr].pos);
                 ExpTy {
                     exp: Exp::ExpSequence(Box::new(_Statement::Move(var.exp, expr_expr.exp).into()), Box::new(unit())),
                     ty: Type::Unit,
@@ -618,7 +1301,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
             Expr::Break => {
                 if !self.in_loop {
                     self.add_error(Error::BreakOutsideLoop {
-                        pos: expr.pos,
+                        pos: arena[expr].pos,
                     });
                     return EXP_TYPE_ERROR;
                 }
@@ -629,7 +1312,24 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
             },
             Expr::Call { ref args, function } => {
                 if let Some(entry@Entry::Fun { .. }) = self.env.look_var(function).cloned() { // TODO: remove this clone.
+                    if let Entry::Fun { external: true, .. } = entry {
+                        let name = self.env.var_name(function);
+                        // NOTE: the calls generated by the compiler have a dummy position.
+                        if !STANDARD_FUNCTIONS.contains(&name.as_str()) && !pos.is_dummy() {
+                            self.require_dialect(Dialect::Extended, &format!("Function `{}`", name), pos);
+                        }
+                    }
                     return match entry {
+                        Entry::Fun { external: true, ref label, .. } if label.to_name() == ON_SIGNAL =>
+                            self.trans_on_signal(args, level, done_label, pos),
+                        Entry::Fun { external: true, ref label, .. } if label.to_name() == WEAK =>
+                            self.trans_weak(args, level, done_label, pos),
+                        Entry::Fun { external: true, ref label, .. } if label.to_name() == WEAK_GET =>
+                            self.trans_weak_get(args, level, done_label, pos),
+                        Entry::Fun { external: true, ref label, .. } if label.to_name() == CHARS_TO_STRING =>
+                            self.trans_chars_to_string(args, level, done_label, pos),
+                        Entry::Fun { external: true, ref label, .. } if label.to_name() == STRING_TO_CHARS =>
+                            self.trans_string_to_chars(args, level, done_label, pos),
                         Entry::Fun { external, ref label, ref parameters, ref result, level: ref current_level } => {
                             let mut expr_args = vec![];
                             if parameters.len() != args.len() {
@@ -639,9 +1339,9 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                     pos,
                                 });
                             }
-                            for (arg, param) in args.iter().zip(parameters) {
+                            for (&arg, param) in args.iter().zip(parameters) {
                                 let exp = self.trans_exp(arg, level, done_label.clone(), true);
-                                self.check_types(param, &exp.ty, arg.pos);
+                                self.check_types(param, &exp.ty, arena[arg].pos);
                                 expr_args.push(exp.exp);
                             }
                             let collectable_return_type = type_is_collectable(result);
@@ -649,6 +1349,9 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                 if external {
                                     F::external_call(&label.to_name(), expr_args, collectable_return_type)
                                 }
+                                else if let Some(value) = self.evaluate_call(current_level, &expr_args) {
+                                    num::<F>(value)
+                                }
                                 else {
                                     function_call(label, expr_args, level, current_level, collectable_return_type)
                                 };
@@ -660,17 +1363,43 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         _ => unreachable!(),
                     };
                 }
-                self.undefined_function(function, expr.pos)
+                if let Some(typ) = self.env.look_type(function).cloned() {
+                    return self.trans_conversion(&typ, args, level, done_label, pos);
+                }
+                self.undefined_function(function, arena[expr].pos)
+            },
+            Expr::Case { ref arms, else_, subject } =>
+                self.trans_case(subject, arms, else_, level, done_label),
+            Expr::Deref { pointer } => {
+                let pointer_expr = self.trans_exp(pointer, level, done_label, true);
+                match self.actual_ty(&pointer_expr.ty) {
+                    Type::Pointer(typ) => ExpTy {
+                        exp: Exp::Mem(Box::new(pointer_expr.exp)),
+                        ty: self.actual_ty(&typ),
+                    },
+                    Type::Error => EXP_TYPE_ERROR,
+                    typ => {
+                        self.add_error(Error::CannotDeref {
+                            pos: arena[pointer].pos,
+                            typ,
+                        });
+                        EXP_TYPE_ERROR
+                    },
+                }
             },
-            Expr::Field { ref ident, ref this } => {
+            Expr::Field { ref ident, this } => {
+                if let Some(class) = self.static_receiver(this) {
+                    return self.static_field_access(&class, ident);
+                }
                 let var = self.trans_exp(this, level, done_label, true);
                 match var.ty {
                     Type::Class { name: class_type, ref fields, .. } => {
                         for (index, class_field) in fields.iter().enumerate() {
                             if class_field.name == ident.node {
+                                self.check_visibility(class_field.class_name, class_field.visibility, ident, "Field");
                                 return ExpTy {
                                     exp: field_access::<F>(var.exp, index, FieldType::Class),
-                                    ty: class_field.typ.clone(),
+                                    ty: self.actual_ty(&class_field.typ),
                                 };
                             }
                         }
@@ -687,49 +1416,63 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         }
                         self.unexpected_field(ident, ident.pos, record_type)
                     },
+                    Type::Error => EXP_TYPE_ERROR,
                     typ => {
                         self.add_error(Error::NotARecordOrClass {
-                            pos: this.pos,
+                            pos: arena[this].pos,
                             typ,
                         });
                         EXP_TYPE_ERROR
                     },
                 }
             },
-            Expr::If { ref else_, ref test, ref then } => {
+            Expr::If { else_, test, then } => {
                 let test_expr = self.trans_exp(test, level, done_label.clone(), true);
-                self.check_int(&test_expr, then.pos);
+                self.check_int(&test_expr, arena[then].pos);
                 let if_expr = self.trans_exp(then, level, done_label.clone(), true);
                 let (else_expr, ty) =
-                    match *else_ {
-                        Some(ref else_) => {
-                            let else_expr = self.trans_exp(&else_, level, done_label, true);
-                            self.check_types(&if_expr.ty, &else_expr.ty, else_.pos);
+                    match else_ {
+                        Some(else_) => {
+                            let else_expr = self.trans_exp(else_, level, done_label, true);
+                            self.check_types(&if_expr.ty, &else_expr.ty, arena[else_].pos);
                             (Some(else_expr), if_expr.ty)
                         },
                         None => {
-                            self.check_types(&Type::Unit, &if_expr.ty, then.pos);
+                            self.check_types(&Type::Unit, &if_expr.ty, arena[then].pos);
                             (None, Type::Unit)
                         },
                     };
+                let else_expr = else_expr.map(|expr| expr.exp);
+                let exp =
+                    match constant_value(&test_expr.exp) {
+                        // Only the branch taken is translated to code.
+                        Some(value) if self.opt_level >= OptLevel::O2 =>
+                            if value != 0 {
+                                if_expr.exp
+                            }
+                            else {
+                                else_expr.unwrap_or_else(unit)
+                            },
+                        _ => if_expression(test_expr.exp, if_expr.exp, else_expr, level),
+                    };
                 ExpTy {
-                    exp: if_expression(test_expr.exp, if_expr.exp, else_expr.map(|expr| expr.exp), level),
+                    exp,
                     ty,
                 }
             },
             Expr::Int { value } =>
                 ExpTy {
-                    exp: num(value),
+                    exp: num::<F>(value),
                     ty: Type::Int,
                 },
-            Expr::Let { ref body, ref declarations } => {
+            Expr::Let { body, ref declarations } => {
                 let old_in_loop = self.in_loop;
                 self.in_loop = false;
                 self.env.begin_scope();
                 let mut vars = vec![];
                 for declaration in declarations {
                     if let Some(statement) = self.trans_dec(declaration, level, done_label.clone()) {
-                        vars.push(statement);
+                        vars.push(position_marker(declaration.pos, statement));
                     }
                 }
                 self.in_loop = old_in_loop;
@@ -740,18 +1483,28 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     ty: result.ty,
                 }
             },
-            Expr::MethodCall { ref args, ref method, ref this } => {
+            Expr::MethodCall { ref args, ref method, this } => {
+                if let Some(class) = self.static_receiver(this) {
+                    return self.static_method_call(&class, method, args, level, done_label, pos);
+                }
                 let this = self.trans_exp(this, level, done_label.clone(), true);
                 let methods =
                     match this.ty {
                         Type::Class { ref methods, .. } => {
                             methods
                         },
+                        Type::Error => {
+                            for &arg in args {
+                                self.trans_exp(arg, level, done_label.clone(), true);
+                            }
+                            return EXP_TYPE_ERROR;
+                        },
                         _ => return self.undefined_method(method.node, method.pos),
                     };
 
                 for (index, class_method) in methods.iter().enumerate() {
                     if method.node == class_method.name.node {
+                        self.check_visibility(class_method.class_name, class_method.visibility, method, "Method");
                         let mut expr_args = vec![this.exp];
                         let method_type = &class_method.typ;
                         if method_type.param_types.len() != args.len() {
@@ -761,9 +1514,9 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                 pos,
                             });
                         }
-                        for (arg, param) in args.iter().zip(method_type.param_types.iter()) {
+                        for (&arg, param) in args.iter().zip(method_type.param_types.iter()) {
                             let exp = self.trans_exp(arg, level, done_label.clone(), true);
-                            self.check_types(param, &exp.ty, arg.pos);
+                            self.check_types(param, &exp.ty, arena[arg].pos);
                             expr_args.push(exp.exp);
                         }
                         let result = &method_type.return_type;
@@ -787,7 +1540,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         Type::Class { ref data_layout, ref fields, ref vtable_name, .. } => {
                             (self.gen.string_literal(data_layout.clone()), fields.clone(), vtable_name.clone())
                         },
-                        Type::Error => (Exp::Error, vec![], Label::new()),
+                        Type::Error => (Exp::Error, vec![], DataLabel::new()),
                         _ => {
                             self.add_error(Error::UnexpectedType {
                                 kind: "record".to_string(),
@@ -805,9 +1558,19 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 }
                 let mut field_exprs = vec![];
                 for field in &fields {
-                    field_exprs.push(self.trans_exp(&field.value, level, done_label.clone(), false).exp);
+                    let exp =
+                        match field.value {
+                            Some(value) => self.trans_exp(value, level, done_label.clone(), false).exp,
+                            // NOTE: the hidden field holding the static link of the finalizer.
+                            None => {
+                                let finalizer_level = &self.methods_level[&(field.class_name, self.finalize_symbol)];
+                                static_link(level, finalizer_level)
+                            },
+                        };
+                    field_exprs.push(exp);
                 }
-                let exp = class_create::<F>(access, data_layout, field_exprs, vtable_name);
+                let position = self.position_literal(arena[expr].pos);
+                let exp = class_create::<F>(access, data_layout, position, field_exprs, vtable_name);
                 ExpTy {
                     exp,
                     ty: class,
@@ -815,32 +1578,32 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
             },
             Expr::Nil =>
                 ExpTy {
-                    exp: num(0),
+                    exp: num::<F>(0),
                     ty: Type::Nil,
                 },
-            Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Plus, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Minus, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Times, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::And, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Or, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Divide, .. }, ref right } =>
+            Expr::Oper { left, oper: WithPos { node: oper@Operator::Plus, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Minus, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Times, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::And, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Or, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Divide, .. }, right } =>
                 self.check_binary_op(oper, left, right, level, done_label),
-            Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Equal, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Neq, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Lt, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Gt, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Ge, .. }, ref right }
-            | Expr::Oper { ref left, oper: WithPos { node: oper@Operator::Le, .. }, ref right } => {
+            Expr::Oper { left, oper: WithPos { node: oper@Operator::Equal, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Neq, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Lt, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Gt, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Ge, .. }, right }
+            | Expr::Oper { left, oper: WithPos { node: oper@Operator::Le, .. }, right } => {
                 let left = self.trans_exp(left, level, done_label.clone(), true);
-                let right_pos = right.pos;
+                let right_pos = arena[right].pos;
                 let right = self.trans_exp(right, level, done_label, true);
                 self.check_types(&left.ty, &right.ty, right_pos);
                 let exp =
                     if left.ty == Type::String && right.ty == Type::String {
-                        string_equality::<F>(oper, left.exp, right.exp) // FIXME: strings work with <, <=, > and >= ?
+                        string_comparison::<F>(oper, left.exp, right.exp)
                     }
                     else {
-                        relational_oper(oper, left.exp, right.exp, level)
+                        relational_oper(oper, left.exp, right.exp)
                     };
                 ExpTy {
                     exp,
@@ -858,8 +1621,8 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                                 for field in fields {
                                     if type_field_name == field.node.ident {
                                         found = true;
-                                        let field_expr = self.trans_exp(&field.node.expr, level, done_label.clone(), true);
-                                        self.check_types(&type_field, &field_expr.ty, field.node.expr.pos);
+                                        let field_expr = self.trans_exp(field.node.expr, level, done_label.clone(), true);
+                                        self.check_types(&type_field, &field_expr.ty, arena[field.node.expr].pos);
                                         field_exprs.push(field_expr.exp);
                                     }
                                 }
@@ -887,26 +1650,36 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                             return EXP_TYPE_ERROR;
                         },
                     };
-                let exp = record_create::<F>(data_layout, field_exprs);
+                let position = self.position_literal(arena[expr].pos);
+                let exp =
+                    match self.gen.record_template(&field_exprs) {
+                        Some(template) => record_clone::<F>(data_layout, position, template),
+                        None => record_create::<F>(data_layout, position, field_exprs),
+                    };
                 ExpTy {
                     exp,
                     ty,
                 }
             },
             Expr::Sequence(ref exprs) => {
-                if let Some((last_expr, exprs)) = exprs.split_last() {
+                if let Some((&last_expr, exprs)) = exprs.split_last() {
                     let mut new_exprs = vec![];
-                    for expr in exprs {
-                        new_exprs.push(self.trans_exp(expr, level, done_label.clone(), true));
+                    for &expr in exprs {
+                        let exp = self.trans_exp(expr, level, done_label.clone(), true);
+                        new_exprs.push(position_marker(arena[expr].pos, _Statement::Exp(exp.exp).into()));
                     }
+                    let last_expr_pos = arena[last_expr].pos;
                     let last_expr = self.trans_exp(last_expr, level, done_label, true);
                     if new_exprs.is_empty() {
                         last_expr
                     }
                     else {
-                        let mut exprs = _Statement::Exp(new_exprs.pop().expect("pop").exp).into();
+                        if !last_expr_pos.is_dummy() {
+                            new_exprs.push(_Statement::Position(last_expr_pos.line).into());
+                        }
+                        let mut exprs = new_exprs.pop().expect("pop");
                         for expr in new_exprs.into_iter().rev() {
-                            exprs = _Statement::Sequence(Box::new(_Statement::Exp(expr.exp).into()), Box::new(exprs)).into();
+                            exprs = _Statement::Sequence(Box::new(expr), Box::new(exprs)).into();
                         }
                         ExpTy {
                             exp: Exp::ExpSequence(Box::new(exprs), Box::new(last_expr.exp)),
@@ -915,7 +1688,10 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     }
                 }
                 else {
-                    panic!("Unexpected empty sequence.");
+                    ExpTy {
+                        exp: unit(),
+                        ty: Type::Unit,
+                    }
                 }
             },
             Expr::Str { ref value } =>
@@ -923,11 +1699,15 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     exp: self.gen.string_literal(value.clone()),
                     ty: Type::String,
                 },
-            Expr::Subscript { ref expr, ref this } => {
+            Expr::Subscript { expr, this } => {
                 let var = self.trans_exp(this, level, done_label.clone(), true);
                 let subscript_expr = self.trans_exp(expr, level, done_label, true);
-                self.check_int(&subscript_expr, expr.pos);
+                self.check_int(&subscript_expr, arena[expr].pos);
                 match var.ty {
+                    Type::Array(ref typ, _) if self.is_packed(typ) => ExpTy {
+                        exp: byte_array_subscript::<F>(var.exp, subscript_expr.exp),
+                        ty: Type::Int,
+                    },
                     Type::Array(typ, _) => ExpTy {
                         exp: array_subscript::<F>(var.exp, subscript_expr.exp),
                         ty: self.actual_ty(&typ),
@@ -938,45 +1718,17 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                     },
                     typ => {
                         self.add_error(Error::CannotIndex {
-                            pos: this.pos,
+                            pos: arena[this].pos,
                             typ,
                         });
                         EXP_TYPE_ERROR
                     },
                 }
             },
-            Expr::Variable(ref ident) => {
-                match self.env.look_var(ident.node).cloned() { // TODO: remove this clone.
-                    Some(Entry::Var { ref access, ref typ, }) => {
-                        ExpTy {
-                            exp: simple_var(access.clone(), level),
-                            ty: self.actual_ty(typ),
-                        }
-                    },
-                    Some(Entry::ClassField { class }) => {
-                        let fields =
-                            match class {
-                                Type::Class { ref fields, .. } => fields,
-                                _ => unreachable!(),
-                            };
-                        for (index, class_field) in fields.iter().enumerate() {
-                            if class_field.name == ident.node {
-                                let this = self.trans_exp(&WithPos::dummy(Expr::Variable(WithPos::dummy(self.self_symbol))),
-                                    level, done_label, true);
-                                return ExpTy {
-                                    exp: field_access::<F>(this.exp, index, FieldType::Class),
-                                    ty: class_field.typ.clone(),
-                                };
-                            }
-                        }
-                        unreachable!();
-                    },
-                    _ => self.undefined_variable(ident.node, ident.pos),
-                }
-            },
-            Expr::While { ref body, ref test } => {
+            Expr::Variable(ref ident) => self.trans_var(ident, level),
+            Expr::While { body, test } => {
                 let test_expr = self.trans_exp(test, level, done_label, true);
-                self.check_int(&test_expr, test.pos);
+                self.check_int(&test_expr, arena[test].pos);
                 let old_in_loop = self.in_loop;
                 self.in_loop = true;
                 let while_done_label = Label::new();
@@ -984,10 +1736,211 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 self.in_loop = old_in_loop;
                 ExpTy {
                     exp: while_loop(&while_done_label, test_expr.exp, result.exp),
-                    ty: result.ty,
+                    ty: Type::Unit,
+                }
+            },
+        }
+    }
+
+    fn trans_var(&mut self, ident: &SymbolWithPos, level: &Level<F>) -> ExpTy {
+        if ident.node == self.self_symbol && self.in_static_method {
+            self.add_error(Error::StaticSelf {
+                pos: ident.pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+        match self.env.look_var(ident.node).cloned() { // TODO: remove this clone.
+            Some(Entry::Var { ref access, ref typ, .. }) => {
+                ExpTy {
+                    exp: simple_var(access.clone(), level),
+                    ty: self.actual_ty(typ),
                 }
             },
+            Some(Entry::ClassField { class }) => {
+                let (fields, static_fields) =
+                    match class {
+                        Type::Class { ref fields, ref static_fields, .. } => (fields, static_fields),
+                        _ => unreachable!(),
+                    };
+                if static_fields.iter().any(|field| field.name == ident.node) {
+                    return self.static_field_access(&class, ident);
+                }
+                if self.in_static_method {
+                    self.add_error(Error::StaticSelf {
+                        pos: ident.pos,
+                    });
+                    return EXP_TYPE_ERROR;
+                }
+                for (index, class_field) in fields.iter().enumerate() {
+                    if class_field.name == ident.node {
+                        self.check_visibility(class_field.class_name, class_field.visibility, ident, "Field");
+                        let this = self.trans_var(&WithPos::dummy(self.self_symbol), level);
+                        return ExpTy {
+                            exp: field_access::<F>(this.exp, index, FieldType::Class),
+                            ty: self.actual_ty(&class_field.typ),
+                        };
+                    }
+                }
+                unreachable!();
+            },
+            _ => self.undefined_variable(ident.node, ident.pos),
+        }
+    }
+
+    /// `weak` and `get` work with any type of record, class or array.
+    fn trans_weak(&mut self, args: &[ExprId], level: &Level<F>, done_label: Option<Label>, pos: Pos) -> ExpTy {
+        if args.len() != 1 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 1,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let target = self.trans_exp(args[0], level, done_label, true);
+        match self.actual_ty(&target.ty) {
+            typ@Type::Array(_, _) | typ@Type::Class { .. } | typ@Type::Record { .. } => ExpTy {
+                exp: F::external_call(WEAK, vec![target.exp], true),
+                ty: Type::Weak(Box::new(typ)),
+            },
+            Type::Error => EXP_TYPE_ERROR,
+            _ => {
+                self.add_error(Error::UnexpectedType {
+                    kind: "record, class or array".to_string(),
+                    pos: self.arena[args[0]].pos,
+                });
+                EXP_TYPE_ERROR
+            },
+        }
+    }
+
+    fn trans_weak_get(&mut self, args: &[ExprId], level: &Level<F>, done_label: Option<Label>, pos: Pos) -> ExpTy {
+        if args.len() != 1 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 1,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let reference = self.trans_exp(args[0], level, done_label, true);
+        match self.actual_ty(&reference.ty) {
+            Type::Weak(typ) => ExpTy {
+                exp: F::external_call(WEAK_GET, vec![reference.exp], true),
+                ty: self.actual_ty(&typ),
+            },
+            Type::Error => EXP_TYPE_ERROR,
+            _ => {
+                self.add_error(Error::UnexpectedType {
+                    kind: "weak".to_string(),
+                    pos: self.arena[args[0]].pos,
+                });
+                EXP_TYPE_ERROR
+            },
+        }
+    }
+
+    /// `chars_to_string` and `string_to_chars` work with any type of array of chars.
+    fn trans_chars_to_string(&mut self, args: &[ExprId], level: &Level<F>, done_label: Option<Label>, pos: Pos) -> ExpTy {
+        if args.len() != 1 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 1,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let chars = self.trans_chars(args[0], level, done_label);
+        ExpTy {
+            exp: F::external_call(CHARS_TO_STRING, vec![chars.exp], true),
+            ty: Type::String,
+        }
+    }
+
+    fn trans_string_to_chars(&mut self, args: &[ExprId], level: &Level<F>, done_label: Option<Label>, pos: Pos) -> ExpTy {
+        if args.len() != 2 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 2,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let string = self.trans_exp(args[0], level, done_label.clone(), true);
+        self.check_types(&Type::String, &string.ty, self.arena[args[0]].pos);
+        let chars = self.trans_chars(args[1], level, done_label);
+        ExpTy {
+            exp: F::external_call(STRING_TO_CHARS, vec![string.exp, chars.exp], false),
+            ty: Type::Int,
+        }
+    }
+
+    fn trans_chars(&mut self, chars: ExprId, level: &Level<F>, done_label: Option<Label>) -> ExpTy {
+        let expr = self.trans_exp(chars, level, done_label, true);
+        if let Type::Array(ref typ, _) = self.actual_ty(&expr.ty) {
+            if self.actual_ty(typ) == Type::Char {
+                return expr;
+            }
         }
+        if !expr.ty.is_error() {
+            self.add_error(Error::UnexpectedType {
+                kind: "array of char".to_string(),
+                pos: self.arena[chars].pos,
+            });
+        }
+        EXP_TYPE_ERROR
+    }
+
+    /// The handler given to `on_signal` is not a value, so it must name a function of type
+    /// `(int) -> unit` whose address and static link are passed to the runtime.
+    fn trans_on_signal(&mut self, args: &[ExprId], level: &Level<F>, done_label: Option<Label>, pos: Pos) -> ExpTy {
+        if args.len() != 2 {
+            self.add_error(Error::InvalidNumberOfParams {
+                actual: args.len(),
+                expected: 2,
+                pos,
+            });
+            return EXP_TYPE_ERROR;
+        }
+
+        let signal = self.trans_exp(args[0], level, done_label, true);
+        self.check_int(&signal, self.arena[args[0]].pos);
+
+        let handler = args[1];
+        if let Expr::Variable(ref ident) = Rc::clone(&self.arena)[handler].node {
+            if let Some(Entry::Fun { external: false, label, parameters, result, level: handler_level }) =
+                self.env.look_var(ident.node).cloned()
+            {
+                let handler_type = FunctionType {
+                    param_types: parameters.iter().map(|typ| self.actual_ty(typ)).collect(),
+                    return_type: self.actual_ty(&result),
+                };
+                if handler_type.param_types == [Type::Int] && handler_type.return_type == Type::Unit {
+                    // NOTE: the handler can run after the function declaring it returned.
+                    if !handler_level.is_top_level() {
+                        self.add_error(Error::SignalHandlerLevel {
+                            ident: self.env.var_name(ident.node),
+                            pos: self.arena[handler].pos,
+                        });
+                        return EXP_TYPE_ERROR;
+                    }
+                    let arguments = vec![signal.exp, Exp::Name(label), static_link(level, &handler_level)];
+                    return ExpTy {
+                        exp: F::external_call(ON_SIGNAL, arguments, false),
+                        ty: Type::Unit,
+                    };
+                }
+            }
+        }
+
+        self.add_error(Error::SignalHandler {
+            pos: self.arena[handler].pos,
+        });
+        EXP_TYPE_ERROR
     }
 
     fn trans_ty(&mut self, name: Symbol, ty: &TyWithPos) -> Type {
@@ -997,6 +1950,14 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                 Type::Array(Box::new(ty), Unique::new())
             },
             Ty::Name { ref ident } => self.get_type(ident, AddError),
+            Ty::Pointer { ref ident } => {
+                let ty = self.get_type(ident, AddError);
+                Type::Pointer(Box::new(ty))
+            },
+            Ty::Weak { ref ident } => {
+                let ty = self.get_type(ident, AddError);
+                Type::Weak(Box::new(ty))
+            },
             Ty::Record { ref fields } => {
                 let mut types = vec![];
                 let mut data_layout = String::new();
@@ -1035,6 +1996,14 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         }
     }
 
+    /// The bytes and the chars are packed in the arrays, and read and written as ints.
+    fn is_packed(&mut self, typ: &Type) -> bool {
+        match self.actual_ty(typ) {
+            Type::Byte | Type::Char => true,
+            _ => false,
+        }
+    }
+
     fn method_label(&self, class: Symbol, method: Symbol) -> Label {
         Label::with_name(&format!("{}_{}",
             self.strings.get(class).expect("strings get"),
@@ -1061,6 +2030,7 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
                         label: method.label.clone(),
                         name: method.name.clone(),
                         typ: method.typ.clone(),
+                        visibility: method.visibility,
                     });
                 }
             }
@@ -1081,6 +2051,19 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         (fields, data_layout, parent_methods)
     }
 
+    fn check_visibility(&mut self, class_name: Symbol, visibility: Visibility, ident: &SymbolWithPos, item: &str) {
+        if visibility == Visibility::Private && self.current_class != Some(class_name) {
+            let class_name = self.env.type_name(class_name);
+            let name = self.env.var_name(ident.node);
+            self.add_error(Error::Private {
+                class_name,
+                ident: name,
+                item: item.to_string(),
+                pos: ident.pos,
+            });
+        }
+    }
+
     fn duplicate_param(&mut self, param: &FieldWithPos) {
         let ident = self.env.var_name(param.node.name);
         self.add_error(Error::DuplicateParam {
@@ -1135,6 +2118,88 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
         EXP_TYPE_ERROR
     }
 
+    /// The `finalize` method of the class and the hidden field holding its static link.
+    fn finalizer(&self, class: &Type) -> Option<Finalizer> {
+        let (fields, methods) =
+            match *class {
+                Type::Class { ref fields, ref methods, .. } => (fields, methods),
+                _ => unreachable!("finalizer of a class"),
+            };
+        let method = methods.iter().position(|method| method.name.node == self.finalize_symbol)?;
+        let class_name = methods[method].class_name;
+        let link_field = fields.iter()
+            .position(|field| field.name == self.finalizer_link_symbol && field.class_name == class_name)?;
+        Some(Finalizer {
+            link_field,
+            method,
+        })
+    }
+
+    /// The class named by `this` when it is the receiver of a static member, like in `Point.origin`.
+    fn static_receiver(&self, this: ExprId) -> Option<Type> {
+        if let Expr::Variable(ref ident) = self.arena[this].node {
+            if self.env.look_var(ident.node).is_none() {
+                if let Some(class @ &Type::Class { .. }) = self.env.look_type(ident.node) {
+                    return Some(class.clone());
+                }
+            }
+        }
+        None
+    }
+
+    fn static_field_access(&mut self, class: &Type, ident: &SymbolWithPos) -> ExpTy {
+        if let Type::Class { name, ref static_data, ref static_fields, .. } = *class {
+            for (index, field) in static_fields.iter().enumerate() {
+                if field.name == ident.node {
+                    self.check_visibility(field.class_name, field.visibility, ident, "Field");
+                    return ExpTy {
+                        exp: static_field::<F>(static_data, index),
+                        ty: self.actual_ty(&field.typ),
+                    };
+                }
+            }
+            return self.unexpected_field(ident, ident.pos, name);
+        }
+        unreachable!("static receiver should be a class");
+    }
+
+    fn static_method_call(&mut self, class: &Type, method: &SymbolWithPos, args: &[ExprId], level: &Level<F>,
+        done_label: Option<Label>, pos: Pos) -> ExpTy
+    {
+        let static_methods =
+            match *class {
+                Type::Class { ref static_methods, .. } => static_methods,
+                _ => unreachable!("static receiver should be a class"),
+            };
+        for class_method in static_methods {
+            if method.node == class_method.name.node {
+                self.check_visibility(class_method.class_name, class_method.visibility, method, "Method");
+                let method_type = &class_method.typ;
+                if method_type.param_types.len() != args.len() {
+                    self.add_error(Error::InvalidNumberOfParams {
+                        actual: args.len(),
+                        expected: method_type.param_types.len(),
+                        pos,
+                    });
+                }
+                let mut expr_args = vec![];
+                for (&arg, param) in args.iter().zip(method_type.param_types.iter()) {
+                    let exp = self.trans_exp(arg, level, done_label.clone(), true);
+                    self.check_types(param, &exp.ty, self.arena[arg].pos);
+                    expr_args.push(exp.exp);
+                }
+                let result = &method_type.return_type;
+                let collectable_return_type = type_is_collectable(result);
+                let current_level = self.methods_level.get(&(class_method.class_name, method.node)).expect("level");
+                return ExpTy {
+                    exp: function_call(&class_method.label, expr_args, level, current_level, collectable_return_type),
+                    ty: self.actual_ty(result),
+                };
+            }
+        }
+        self.undefined_method(method.node, method.pos)
+    }
+
     fn undefined_method(&mut self, ident: Symbol, pos: Pos) -> ExpTy {
         let ident = self.env.var_name(ident);
         self.add_error(Error::Undefined {
@@ -1177,9 +2242,245 @@ impl<'a, F: Clone + Debug + Frame + PartialEq> SemanticAnalyzer<'a, F> {
     }
 }
 
+/// Position of the function of this level in the group.
+fn group_position<F>(group: &[Level<F>], level: &Level<F>) -> Option<usize> {
+    group.iter().position(|group_level| Rc::ptr_eq(&group_level.current, &level.current))
+}
+
+/// Mark the statement with the source line of its position, used to map the instructions to the source.
+fn position_marker(pos: Pos, statement: Statement) -> Statement {
+    if pos.is_dummy() {
+        return statement;
+    }
+    _Statement::Sequence(Box::new(_Statement::Position(pos.line).into()), Box::new(statement)).into()
+}
+
+/// Whether the type is known to all the files, without being declared.
+fn is_predefined(typ: &Type) -> bool {
+    match *typ {
+        Type::BigInt | Type::Int | Type::Opaque(_) | Type::String | Type::Unit => true,
+        Type::Array(..) => *typ == bytes_type(),
+        _ => false,
+    }
+}
+
 fn type_is_collectable(typ: &Type) -> bool {
     match *typ {
-        Type::Array { .. } | Type::Class { .. } | Type::Record { .. } | Type::String => true,
+        Type::Array { .. } | Type::BigInt | Type::Class { .. } | Type::Record { .. } | Type::String | Type::Weak(_) =>
+            true,
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use env::Env;
+    use escape::find_escapes;
+    use frame::x86_64::X86_64;
+    use lexer::Lexer;
+    use parser::Parser;
+    use rewriter::Rewriter;
+    use super::SemanticAnalyzer;
+    use symbol::{Strings, Symbols};
+    use self::Kind::{Int, Str, Unit};
+
+    /// Kind of the value of an expression, used to fill the holes of the productions.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Kind {
+        Int,
+        Str,
+        Unit,
+    }
+
+    impl Kind {
+        fn hole(self) -> &'static str {
+            match self {
+                Int => "$int",
+                Str => "$string",
+                Unit => "$unit",
+            }
+        }
+
+        /// Program checking that the expression is of this kind.
+        fn check(self, expr: &str) -> String {
+            match self {
+                Int => format!("let var checked: int := {} in () end", expr),
+                Str => format!("let var checked: string := {} in () end", expr),
+                Unit => format!("if 1 then {}", expr),
+            }
+        }
+    }
+
+    const KINDS: [Kind; 3] = [Int, Str, Unit];
+
+    /// Hole accepting an expression of any kind.
+    const ANY: &str = "$any";
+
+    /// The productions of the expressions of the grammar with a minimal program deriving each of
+    /// them and the kind of the program. The holes `$int`, `$string` and `$unit` stand for an
+    /// expression of this kind and `$any` for any expression, like the bodies of the loops and the
+    /// expressions of a sequence except the last, whose value is discarded.
+    const PRODUCTIONS: [(&str, &str, Kind); 33] = [
+        ("and", "$int & $int", Int),
+        ("array", "let type a = array of int var v := a [1] of $int in v[0] end", Int),
+        ("assign", "let var x := 0 in x := $int end", Unit),
+        ("break", "while $int do break", Unit),
+        ("call", "ord($string)", Int),
+        ("compare", "$int < $int", Int),
+        ("compare_strings", "$string >= $string", Int),
+        ("divide", "$int / $int", Int),
+        ("equal", "$int = $int", Int),
+        ("equal_strings", "$string <> $string", Int),
+        ("field", "let type r = {a: int} var v := r {a = $int} in v.a end", Int),
+        ("for", "for i := $int to $int do $any", Unit),
+        ("function", "let function f(x: int): int = x + $int in f($int) end", Int),
+        ("if", "if $int then $unit", Unit),
+        ("if_else", "if $int then $string else $string", Str),
+        ("int", "42", Int),
+        ("let", "let var x := $any in $int end", Int),
+        ("let_typed", "let var s: string := $string in s end", Str),
+        ("minus", "$int - $int", Int),
+        ("negate", "-$int", Int),
+        ("nil", "let type r = {a: int} var v: r := nil in v = nil end", Int),
+        ("or", "$int | $int", Int),
+        ("plus", "$int + $int", Int),
+        ("procedure", "let function p(s: string) = print(s) in p($string) end", Unit),
+        ("recursive_functions", "let function even(n: int): int = if n = 0 then 1 else odd(n - 1) \
+            function odd(n: int): int = if n = 0 then 0 else even(n - 1) in even($int) end", Int),
+        ("recursive_types", "let type list = {head: int, tail: list} \
+            var l := list {head = $int, tail = nil} in l.head end", Int),
+        ("sequence", "($any; $string)", Str),
+        ("string", "\"tiger\"", Str),
+        ("subscript_assign", "let type a = array of string var v := a [1] of \"\" in v[$int] := $string end", Unit),
+        ("times", "$int * $int", Int),
+        ("type_alias", "let type t = int var x: t := $int in x end", Int),
+        ("unit", "()", Unit),
+        ("while", "while $int do $any", Unit),
+    ];
+
+    /// Minimal programs breaking a rule of the language, with the key of the error they report.
+    const ERRORS: [(&str, &str); 20] = [
+        ("break_outside_loop", "break"),
+        ("break_outside_loop", "while 1 do let function f() = break in f() end"),
+        ("cannot_index", "let var x := 1 in x[0] end"),
+        ("cycle", "let type a = b type b = a in 0 end"),
+        ("duplicate_param", "let function f(a: int, a: int) = () in f(1, 2) end"),
+        ("extra_field", "let type r = {a: int} var v := r {a = 1, b = 2} in v.a end"),
+        ("invalid_escape", "\"\\q\""),
+        ("invalid_number_of_params", "ord(\"a\", \"b\")"),
+        ("missing_field", "let type r = {a: int, b: int} var v := r {a = 1} in v.a end"),
+        ("negative_array_size", "let type a = array of int var v := a [-1] of 0 in v[0] end"),
+        ("not_a_record_or_class", "let var x := 1 in x.a end"),
+        ("record_type", "let var v := nil in 0 end"),
+        ("type", "if 1 then 2"),
+        ("type", "let type r = {a: int} type s = {a: int} var v: r := s {a = 1} in 0 end"),
+        ("unclosed", "\"tiger"),
+        ("unclosed", "/* tiger"),
+        ("undefined", "x"),
+        ("undefined", "let var x: t := 1 in x end"),
+        ("unexpected_token", "1 +"),
+        ("unknown_token", "1 # 2"),
+    ];
+
+    /// The smallest expression of this kind, which has no hole.
+    fn leaf(kind: Kind) -> &'static str {
+        PRODUCTIONS.iter()
+            .find(|&&(_, template, production_kind)| production_kind == kind && !template.contains('$'))
+            .map(|&(_, template, _)| template)
+            .expect("leaf")
+    }
+
+    /// Fill the holes of the template with the smallest expressions.
+    fn fill_leaves(template: &str) -> String {
+        KINDS.iter()
+            .fold(template.replace(ANY, leaf(Unit)), |program, &kind| program.replace(kind.hole(), leaf(kind)))
+    }
+
+    /// Programs derived from the productions, checking their kind, with the key of the error
+    /// expected: each production is nested in each hole of the other productions, which is an
+    /// error when the hole expects another kind.
+    fn generate() -> Vec<(String, Option<&'static str>)> {
+        let mut programs = vec![];
+        for &(_, template, template_kind) in &PRODUCTIONS {
+            programs.push((template_kind.check(&fill_leaves(template)), None));
+            for (index, _) in template.match_indices('$') {
+                let hole = &template[index..];
+                let (hole_length, hole_kind) =
+                    match KINDS.iter().find(|kind| hole.starts_with(kind.hole())) {
+                        Some(&kind) => (kind.hole().len(), Some(kind)),
+                        None if hole.starts_with(ANY) => (ANY.len(), None),
+                        None => panic!("unknown hole {}", hole),
+                    };
+                let (before, after) = (&template[..index], &template[index + hole_length..]);
+                for &(_, nested, kind) in &PRODUCTIONS {
+                    let program = format!("{}({}){}", fill_leaves(before), fill_leaves(nested), fill_leaves(after));
+                    let expected = if hole_kind.is_none_or(|hole_kind| hole_kind == kind) { None } else { Some("type") };
+                    programs.push((template_kind.check(&program), expected));
+                }
+            }
+        }
+        programs
+    }
+
+    /// Keys of the errors reported by the parser or the semantic analyzer for this program.
+    fn analyze(program: &str) -> Vec<&'static str> {
+        let strings = Arc::new(Strings::new());
+        let mut symbols = Symbols::new(Arc::clone(&strings));
+        let file_symbol = symbols.symbol("no_file");
+        let main_symbol = symbols.symbol("main");
+        let self_symbol = symbols.symbol("self");
+        let object_symbol = symbols.symbol("Object");
+        // The lexer expects a character after the last token.
+        let source = format!("{}\n", program);
+        let lexer = Lexer::new(source.as_bytes(), file_symbol);
+        let mut parser = Parser::new(lexer, &mut symbols);
+        let ast =
+            match parser.parse() {
+                Ok(ast) => ast,
+                Err(error) => return error.keys(),
+            };
+        let ast = Rewriter::new(&mut symbols).rewrite(ast);
+        let escape_env = find_escapes(&ast.arena, ast.root, Arc::clone(&strings));
+        let mut env = Env::<X86_64>::new(&strings, escape_env);
+        let semantic_analyzer = SemanticAnalyzer::new(&mut env, Arc::clone(&strings), self_symbol, object_symbol);
+        match semantic_analyzer.analyze(main_symbol, ast) {
+            Ok(_) => vec![],
+            Err(error) => error.keys(),
+        }
+    }
+
+    #[test]
+    fn test_productions() {
+        let mut failures = vec![];
+        for (program, expected) in generate() {
+            let keys = analyze(&program);
+            let conforms =
+                match expected {
+                    Some(key) => keys.first() == Some(&key),
+                    None => keys.is_empty(),
+                };
+            if !conforms {
+                failures.push(format!("{}\n    expected {:?}, found {:?}", program, expected, keys));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let failures: Vec<_> = ERRORS.iter()
+            .filter_map(|&(key, program)| {
+                let keys = analyze(program);
+                if keys.first() == Some(&key) {
+                    None
+                }
+                else {
+                    Some(format!("{}\n    expected {}, found {:?}", program, key, keys))
+                }
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Signal handlers written in Tiger.
+//!
+//! A Tiger function cannot run from the signal handler itself since the signal can interrupt the
+//! program anywhere, for instance while the collector is running or at a point where the pointer
+//! map does not describe the stack. The signal is thus only recorded and the Tiger handler is
+//! called on the next entry in the runtime that is a safepoint, i.e. a call from Tiger code that
+//! does not hold heap pointers in Rust. Records and arrays allocated in the free space at the end
+//! of the heap do not enter the runtime, so a loop that only allocates those can delay the handler
+//! until the heap is full.
+//!
+//! Since the handler runs at a safepoint, it can allocate and collect like any other Tiger code:
+//! the collector walks the frame pointers through the frames of the runtime, so it finds the roots
+//! of the handler and those of the Tiger frames it interrupted. Only the handlers are not
+//! reentered: a signal received while a handler runs is dispatched once it returns.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::mem;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use stack_trace::runtime_error;
+
+const MAX_SIGNAL: i64 = 64;
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+// Bit `n - 1` is set when the signal `n` was received and its handler was not yet called.
+static PENDING_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy)]
+struct Handler {
+    function: usize,
+    static_link: usize,
+}
+
+thread_local! {
+    static HANDLERS: RefCell<BTreeMap<i64, Handler>> = const { RefCell::new(BTreeMap::new()) };
+    static IN_HANDLER: Cell<bool> = const { Cell::new(false) };
+}
+
+extern "C" fn record_signal(signal: c_int) {
+    PENDING_SIGNALS.fetch_or(1 << (signal - 1), Ordering::SeqCst);
+}
+
+pub fn register(signum: i64, function: usize, static_link: usize) {
+    if !(1..=MAX_SIGNAL).contains(&signum) {
+        runtime_error(&format!("on_signal: signal {} is not between 1 and {}", signum, MAX_SIGNAL));
+    }
+    HANDLERS.with(|handlers| {
+        handlers.borrow_mut().insert(signum, Handler {
+            function,
+            static_link,
+        });
+    });
+    unsafe {
+        signal(signum as c_int, record_signal);
+    }
+}
+
+/// Call the Tiger handlers of the signals received since the last dispatch.
+/// Signals received while a handler runs are deferred until it returns.
+pub fn dispatch() {
+    if IN_HANDLER.with(|in_handler| in_handler.get()) {
+        return;
+    }
+
+    IN_HANDLER.with(|in_handler| in_handler.set(true));
+    loop {
+        let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+        if pending == 0 {
+            break;
+        }
+        for signal in 1..=MAX_SIGNAL {
+            if pending & (1 << (signal - 1)) == 0 {
+                continue;
+            }
+            let handler = HANDLERS.with(|handlers| handlers.borrow().get(&signal).cloned());
+            if let Some(handler) = handler {
+                // Tiger functions take their static link as their last parameter.
+                let function: extern "C" fn(i64, usize) = unsafe { mem::transmute(handler.function) };
+                function(signal, handler.static_link);
+            }
+        }
+    }
+    IN_HANDLER.with(|in_handler| in_handler.set(false));
+}
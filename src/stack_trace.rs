@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) 2019 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Stack traces of the runtime errors.
+//!
+//! The compiler writes in each object file a table with an entry for each call site: its return
+//! address, the name of the function containing it and its source line. The runtime unwinds the
+//! stack and shows the frames whose return address is in this table, skipping the frames of the
+//! runtime itself.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write, stdout};
+use std::os::raw::c_char;
+use std::process;
+
+use collector;
+use unwind::Unwinder;
+
+// Name of the function of the main program, which is the outermost Tiger frame.
+const MAIN: &str = "main";
+
+struct CallSite {
+    file: Cow<'static, str>,
+    function: &'static str,
+    line: usize,
+}
+
+/// Print the message of a runtime error followed by the Tiger frames active when it happened and
+/// exit.
+pub fn runtime_error(message: &str) -> ! {
+    // NOTE: the output of the program is flushed first so that it appears before the error.
+    let _ = stdout().flush();
+    // The whole report is buffered so that it is written at once.
+    let mut report = format!("{}\n", message);
+    let frames = stack_trace();
+    if !frames.is_empty() {
+        report.push_str("stack trace:\n");
+        for frame in frames {
+            let _ = writeln!(report, "    {}", frame);
+        }
+    }
+    let _ = io::stderr().write_all(report.as_bytes());
+    process::exit(1);
+}
+
+/// The Tiger frames of the stack from the innermost, as function names with the line of the call
+/// each is executing.
+fn stack_trace() -> Vec<String> {
+    let call_sites = fetch_call_sites();
+    let mut frames = vec![];
+    let mut unwinder = Unwinder::new();
+    while let Some(return_address) = unwinder.step() {
+        if let Some(call_site) = call_sites.get(&return_address) {
+            frames.push(format!("{} ({}:{})", call_site.function, call_site.file, call_site.line));
+            if call_site.function == MAIN {
+                break;
+            }
+        }
+    }
+    frames
+}
+
+fn fetch_call_sites() -> HashMap<usize, CallSite> {
+    let mut call_sites = HashMap::new();
+    for module in collector::modules() {
+        unsafe {
+            let source_file = CStr::from_ptr(module.source_file).to_string_lossy();
+            let mut pointer = module.frame_info;
+            while *pointer != module.end_marker {
+                let function = CStr::from_ptr(*pointer.offset(1) as *const c_char);
+                call_sites.insert(*pointer, CallSite {
+                    file: source_file.clone(),
+                    function: function.to_str().unwrap_or("?"),
+                    line: *pointer.offset(2),
+                });
+                pointer = pointer.offset(3);
+            }
+        }
+    }
+    call_sites
+}
+
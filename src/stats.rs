@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::collections::BTreeSet;
+
+use asm::{Instruction, Operand};
+
+/// Statistics about the code generated for a function, printed by `--stats`.
+pub struct FunctionStats {
+    pub coalesced_moves: usize,
+    pub estimated_cycles: usize,
+    pub frame_size: i64,
+    pub instructions_after_alloc: usize,
+    pub instructions_before_alloc: usize,
+    pub ir_nodes: usize,
+    pub name: String,
+    pub spills: usize,
+    pub temps: usize,
+}
+
+/// A call site and the pointers live in the frame across it, printed by `--dump-gc-maps`.
+pub struct CallSite {
+    pub call: String,
+    pub function: String,
+    pub line: u32,
+    // Offsets from the frame pointer of the pointers, or None when the call has no entry in the
+    // pointer map.
+    pub pointers: Option<Vec<String>>,
+    pub return_label: String,
+}
+
+/// Number of instructions that are emitted, i.e. not counting labels and markers.
+pub fn instruction_count(instructions: &[Instruction]) -> usize {
+    instructions.iter()
+        .filter(|instruction|
+            match **instruction {
+                Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } => false,
+                Instruction::Call { .. } => true,
+                Instruction::Move { ref assembly, .. } | Instruction::Operation { ref assembly, .. } =>
+                    !assembly.is_empty(),
+            }
+        )
+        .count()
+}
+
+/// Number of temporaries used by the instructions, not counting the machine registers.
+pub fn temp_count(instructions: &[Instruction]) -> usize {
+    let mut temps = BTreeSet::new();
+    for instruction in instructions {
+        match *instruction {
+            Instruction::Call { ref destination, ref source, .. } | Instruction::Move { ref destination, ref source, .. }
+                | Instruction::Operation { ref destination, ref source, .. } =>
+                temps.extend(destination.iter().chain(source).filter_map(|operand| match *operand {
+                    Operand::Register(_) => None,
+                    Operand::Temp(temp) => Some(temp),
+                })),
+            Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } => (),
+        }
+    }
+    temps.len()
+}
+
+/// Rough static estimate of the number of cycles to execute each instruction once.
+pub fn estimated_cycles(instructions: &[Instruction]) -> usize {
+    instructions.iter()
+        .map(instruction_cycles)
+        .sum()
+}
+
+fn instruction_cycles(instruction: &Instruction) -> usize {
+    match *instruction {
+        Instruction::Label { .. } | Instruction::Node { .. } | Instruction::Position { .. } => 0,
+        Instruction::Call { .. } => 5,
+        Instruction::Move { ref assembly, .. } | Instruction::Operation { ref assembly, .. } => {
+            if assembly.is_empty() {
+                return 0;
+            }
+            let mut cycles =
+                if assembly.starts_with("idiv") {
+                    20
+                }
+                else if assembly.starts_with("imul") {
+                    3
+                }
+                else {
+                    1
+                };
+            // Memory accesses are slower than register accesses.
+            if assembly.contains('[') {
+                cycles += 3;
+            }
+            cycles
+        },
+    }
+}
+
+pub fn print(stats: &[FunctionStats]) {
+    println!("{:<24} {:>8} {:>8} {:>6} {:>8} {:>6} {:>9} {:>6} {:>8}", "function", "ir nodes", "before", "temps",
+        "after", "spills", "coalesced", "frame", "cycles");
+    for function in stats {
+        println!("{:<24} {:>8} {:>8} {:>6} {:>8} {:>6} {:>9} {:>6} {:>8}", function.name, function.ir_nodes,
+            function.instructions_before_alloc, function.temps, function.instructions_after_alloc, function.spills,
+            function.coalesced_moves, function.frame_size, function.estimated_cycles);
+    }
+}
+
+pub fn print_gc_maps(call_sites: &[CallSite], map_bytes: i64) {
+    println!("{:<24} {:<12} {:>6} {:<24} pointers", "function", "return", "line", "call");
+    for call_site in call_sites {
+        let pointers =
+            match call_site.pointers {
+                Some(ref pointers) if pointers.is_empty() => "-".to_string(),
+                Some(ref pointers) => pointers.join(" "),
+                None => "no entry".to_string(),
+            };
+        println!("{:<24} {:<12} {:>6} {:<24} {}", call_site.function, call_site.return_label, call_site.line,
+            call_site.call, pointers);
+    }
+    let entries = call_sites.iter()
+        .filter(|call_site| call_site.pointers.is_some())
+        .count();
+    println!("{} call sites, {} map entries, {} map bytes", call_sites.len(), entries, map_bytes);
+}
@@ -19,31 +19,35 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 use position::WithPos;
 
 pub type Symbol = i64;
 pub type SymbolWithPos = WithPos<Symbol>;
 
+/// Strings interned by the symbol tables, shared by the threads compiling the files of a build so
+/// that a symbol names the same string in all of them.
 #[derive(Debug)]
 pub struct Strings {
-    next_symbol: RefCell<Symbol>,
-    strings: RefCell<HashMap<Symbol, String>>,
+    next_symbol: Mutex<Symbol>,
+    strings: Mutex<HashMap<Symbol, String>>,
+    // Reverse of strings, to intern a string without going through all of them.
+    symbols: Mutex<HashMap<String, Symbol>>,
 }
 
 impl Strings {
     pub fn new() -> Self {
         Self {
-            next_symbol: RefCell::new(0),
-            strings: RefCell::new(HashMap::new()),
+            next_symbol: Mutex::new(0),
+            strings: Mutex::new(HashMap::new()),
+            symbols: Mutex::new(HashMap::new()),
         }
     }
 
     pub fn get(&self, symbol: Symbol) -> Option<String> {
-        let strings = self.strings.borrow();
+        let strings = self.strings.lock().expect("lock strings");
         strings.get(&symbol).map(Clone::clone)
     }
 }
@@ -51,12 +55,12 @@ impl Strings {
 #[derive(Debug)]
 pub struct Symbols<T> {
     stack: Vec<Vec<Symbol>>,
-    strings: Rc<Strings>,
+    strings: Arc<Strings>,
     table: HashMap<Symbol, Vec<T>>,
 }
 
 impl<T> Symbols<T> {
-    pub fn new(strings: Rc<Strings>) -> Self {
+    pub fn new(strings: Arc<Strings>) -> Self {
         let mut symbols = Self {
             stack: vec![],
             strings,
@@ -96,7 +100,7 @@ impl<T> Symbols<T> {
     }
 
     pub fn name(&self, symbol: Symbol) -> String {
-        self.strings.strings.borrow()[&symbol].to_string()
+        self.strings.strings.lock().expect("lock strings")[&symbol].to_string()
     }
 
     pub fn replace(&mut self, symbol: Symbol, data: T) {
@@ -105,14 +109,24 @@ impl<T> Symbols<T> {
         bindings.push(data);
     }
 
+    /// Whether a symbol was created for the string.
+    pub fn contains_name(&self, string: &str) -> bool {
+        self.strings.symbols.lock().expect("lock symbols").contains_key(string)
+    }
+
     pub fn symbol(&mut self, string: &str) -> Symbol {
-        if let Some((&key, _)) = self.strings.strings.borrow().iter().find(|&(_, value)| value == string) {
-            return key;
+        // The reverse table stays locked until the string is interned, so that two threads interning
+        // the same string get the same symbol.
+        let mut symbols = self.strings.symbols.lock().expect("lock symbols");
+        if let Some(&symbol) = symbols.get(string) {
+            return symbol;
         }
 
-        let symbol = *self.strings.next_symbol.borrow();
-        self.strings.strings.borrow_mut().insert(symbol, string.to_string());
-        *self.strings.next_symbol.borrow_mut() += 1;
+        let mut next_symbol = self.strings.next_symbol.lock().expect("lock next symbol");
+        let symbol = *next_symbol;
+        self.strings.strings.lock().expect("lock strings").insert(symbol, string.to_string());
+        symbols.insert(string.to_string(), symbol);
+        *next_symbol += 1;
         symbol
     }
 }
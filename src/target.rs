@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Targets selected by `--target`: the architecture selects the frame and the instruction
+//! selection, the operating system the format of the object files and how they are linked.
+
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Arch {
+    /// Compiled with the `X86_64` frame, following the System V calling convention.
+    #[default]
+    X86_64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OperatingSystem {
+    #[default]
+    Linux,
+    MacOs,
+    Windows,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Target {
+    pub arch: Arch,
+    pub os: OperatingSystem,
+}
+
+impl Target {
+    /// Parse a triple like `x86_64-unknown-linux-gnu`, where the vendor and the environment are
+    /// optional.
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let mut parts = triple.split('-');
+        let arch =
+            match parts.next()? {
+                "amd64" | "x86_64" => Arch::X86_64,
+                _ => return None,
+            };
+        let os = parts.find_map(|part| match part {
+            "darwin" | "macos" => Some(OperatingSystem::MacOs),
+            "linux" => Some(OperatingSystem::Linux),
+            "windows" => Some(OperatingSystem::Windows),
+            _ => None,
+        })?;
+        Some(Self {
+            arch,
+            os,
+        })
+    }
+
+    /// Format of the object files, as given to nasm with `-f`.
+    pub fn object_format(&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Linux => "elf64",
+            OperatingSystem::MacOs => "macho64",
+            OperatingSystem::Windows => "win64",
+        }
+    }
+
+    pub fn executable_extension(&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Linux | OperatingSystem::MacOs => "",
+            OperatingSystem::Windows => "exe",
+        }
+    }
+
+    pub fn shared_library_extension(&self) -> &'static str {
+        match self.os {
+            OperatingSystem::Linux => "so",
+            OperatingSystem::MacOs => "dylib",
+            OperatingSystem::Windows => "dll",
+        }
+    }
+
+    /// Flags of the linker producing an executable, or a shared library with `shared`.
+    pub fn linker_flags(&self, shared: bool) -> &'static [&'static str] {
+        match (self.os, shared) {
+            (OperatingSystem::Linux, false) => &["-no-pie", "-Wl,-z,noexecstack"],
+            (OperatingSystem::Linux, true) => &["-shared", "-Wl,-z,noexecstack"],
+            (OperatingSystem::MacOs, false) => &["-Wl,-no_pie"],
+            (OperatingSystem::MacOs, true) => &["-dynamiclib"],
+            (OperatingSystem::Windows, false) => &[],
+            (OperatingSystem::Windows, true) => &["-shared"],
+        }
+    }
+
+    /// Flags of the linker producing an executable with the C library and the other system
+    /// libraries linked in, which needs no dynamic loader, if the system has static libraries.
+    pub fn static_linker_flags(&self) -> Option<&'static [&'static str]> {
+        match self.os {
+            OperatingSystem::Linux => Some(&["-static"]),
+            OperatingSystem::MacOs | OperatingSystem::Windows => None,
+        }
+    }
+
+    /// Libraries of the system the runtime needs, after the runtime on the command line of the
+    /// linker.
+    pub fn system_libraries(&self) -> &'static [&'static str] {
+        match self.os {
+            OperatingSystem::Linux => &["-lpthread", "-ldl"],
+            OperatingSystem::MacOs | OperatingSystem::Windows => &[],
+        }
+    }
+
+    /// What is missing to compile for this target, if it cannot be compiled for yet.
+    pub fn missing_support(&self) -> Option<&'static str> {
+        match self.os {
+            OperatingSystem::Linux => None,
+            OperatingSystem::MacOs => Some("the runtime and the sections of the garbage collector metadata are only written for ELF"),
+            OperatingSystem::Windows => Some("there is no frame for the Windows x64 calling convention"),
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let arch =
+            match self.arch {
+                Arch::X86_64 => "x86_64",
+            };
+        let (vendor, os) =
+            match self.os {
+                OperatingSystem::Linux => ("unknown", "linux-gnu"),
+                OperatingSystem::MacOs => ("apple", "darwin"),
+                OperatingSystem::Windows => ("pc", "windows"),
+            };
+        write!(formatter, "{}-{}-{}", arch, vendor, os)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arch, OperatingSystem, Target};
+
+    #[test]
+    fn test_from_triple() {
+        let linux = Target { arch: Arch::X86_64, os: OperatingSystem::Linux };
+        assert_eq!(Target::from_triple("x86_64-unknown-linux-gnu"), Some(linux));
+        assert_eq!(Target::from_triple("x86_64-linux"), Some(linux));
+        assert_eq!(Target::from_triple("amd64-apple-darwin").map(|target| target.os), Some(OperatingSystem::MacOs));
+        assert_eq!(Target::from_triple("x86_64-pc-windows-msvc").map(|target| target.os), Some(OperatingSystem::Windows));
+        assert_eq!(Target::from_triple("x86_64"), None);
+        assert_eq!(Target::from_triple("riscv64-unknown-linux-gnu"), None);
+        assert_eq!(linux.to_string(), "x86_64-unknown-linux-gnu");
+    }
+}
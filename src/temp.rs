@@ -19,8 +19,10 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::cell::Cell;
 use std::collections::BTreeSet;
 use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use frame::{Frame, Memory};
 use self::Label::{Named, Num};
@@ -32,12 +34,11 @@ pub struct Temp {
 
 impl Temp {
     pub fn new() -> Self {
-        static mut COUNTER: u32 = 0;
-        unsafe {
-            COUNTER += 1;
-            Self {
-                num: COUNTER,
-            }
+        // Shared by the threads compiling the files of a build, since the registers are temps
+        // created by the first of them.
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        Self {
+            num: COUNTER.fetch_add(1, Ordering::Relaxed) + 1,
         }
     }
 
@@ -55,6 +56,7 @@ impl Temp {
     }
 }
 
+/// A label in the code section: a function, a jump target or a return address.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Label {
     Named(String),
@@ -63,11 +65,15 @@ pub enum Label {
 
 impl Label {
     pub fn new() -> Self {
-        static mut COUNTER: u32 = 0;
-        unsafe {
-            COUNTER += 1;
-            Num(COUNTER)
+        // Numbered by each thread, so that the assembly of a file does not depend on the files
+        // compiled with it: the numbered labels are local to the assembly file.
+        thread_local! {
+            static COUNTER: Cell<u32> = const { Cell::new(0) };
         }
+        COUNTER.with(|counter| {
+            counter.set(counter.get() + 1);
+            Num(counter.get())
+        })
     }
 
     pub fn to_name(&self) -> String {
@@ -85,12 +91,54 @@ impl Label {
 impl Display for Label {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         match *self {
-            Named(ref name) => write!(formatter, "{}", name),
+            // The assemblers only accept ASCII symbols, so the other characters are replaced by
+            // their code point.
+            Named(ref name) => {
+                for char in name.chars() {
+                    if char.is_ascii() {
+                        write!(formatter, "{}", char)?;
+                    }
+                    else {
+                        write!(formatter, "__u{:x}_", char as u32)?;
+                    }
+                }
+                Ok(())
+            },
             Num(num) => write!(formatter, "l{}", num),
         }
     }
 }
 
+/// A label in the data section: a string literal or a vtable.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DataLabel(Label);
+
+impl DataLabel {
+    pub fn new() -> Self {
+        DataLabel(Label::new())
+    }
+
+    pub fn with_name(name: &str) -> Self {
+        DataLabel(Label::with_name(name))
+    }
+
+    /// Use a label read back from a fragment file as a data label.
+    pub fn from_label(label: Label) -> Self {
+        DataLabel(label)
+    }
+
+    /// Get the label to use when taking the address of this data.
+    pub fn to_label(&self) -> Label {
+        self.0.clone()
+    }
+}
+
+impl Display for DataLabel {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        self.0.fmt(formatter)
+    }
+}
+
 #[derive(Debug)]
 pub struct TempMap {
     stack_vars: BTreeSet<i64>,
@@ -111,13 +159,29 @@ impl TempMap {
 
     pub fn insert<F: Frame>(&mut self, access: &F::Access) {
         if let Some(temp) = access.as_temp() {
-            self.temps.insert(*temp);
+            self.insert_temp(*temp);
         }
         else if let Some(stack_location) = access.as_stack() {
-            self.stack_vars.insert(stack_location);
+            self.insert_var(stack_location);
         }
         else {
             unreachable!();
         }
     }
+
+    pub fn insert_temp(&mut self, temp: Temp) {
+        self.temps.insert(temp);
+    }
+
+    pub fn insert_var(&mut self, stack_var: i64) {
+        self.stack_vars.insert(stack_var);
+    }
+
+    pub fn stack_vars(&self) -> &BTreeSet<i64> {
+        &self.stack_vars
+    }
+
+    pub fn temps(&self) -> &BTreeSet<Temp> {
+        &self.temps
+    }
 }
@@ -19,30 +19,102 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::io::stderr;
+use std::env::{var, var_os};
+use std::io::{Write, stderr};
 use std::os::raw::c_int;
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 const BOLD: &str = "\x1b[1m";
+/// Erase from the cursor to the end of the line.
+const CLEAR_LINE: &str = "\x1b[K";
 const BLUE: &str = "\x1b[34m";
 const END_BOLD: &str = "\x1b[22m";
 const RED: &str = "\x1b[31m";
 const RESET_COLOR: &str = "\x1b[39;49m";
+const YELLOW: &str = "\x1b[33m";
+/// Width of the terminal when COLUMNS does not give it.
+const DEFAULT_WIDTH: usize = 80;
+/// Variable disabling the colors when they are not explicitly requested, see https://no-color.org.
+const NO_COLOR_VARIABLE: &str = "NO_COLOR";
+
+/// Whether the diagnostics are colored, selected by `--color`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    Always,
+    /// Color the diagnostics written to a terminal, unless NO_COLOR is set.
+    #[default]
+    Auto,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "always" => Some(ColorChoice::Always),
+            "auto" => Some(ColorChoice::Auto),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
 
 pub struct Terminal {
-    is_a_tty: bool,
+    colored: bool,
+    // Whether the progress line is drawn, which must be erased before writing anything else.
+    drawn_progress: AtomicBool,
+    progress: bool,
 }
 
 impl Terminal {
-    pub fn new() -> Self {
+    pub fn new(color: ColorChoice) -> Self {
+        let colored =
+            match color {
+                ColorChoice::Always => true,
+                ColorChoice::Auto =>
+                    var_os(NO_COLOR_VARIABLE).is_none_or(|value| value.is_empty()) && stderr_is_a_tty(),
+                ColorChoice::Never => false,
+            };
         Self {
-            is_a_tty: stderr_is_a_tty(),
+            colored,
+            drawn_progress: AtomicBool::new(false),
+            progress: false,
+        }
+    }
+
+    /// Show the progress line when the standard error is a terminal, so that the logs and the
+    /// pipes only get the diagnostics.
+    pub fn enable_progress(&mut self) {
+        self.progress = stderr_is_a_tty();
+    }
+
+    /// Draw the progress line in place of the previous one: the pass the file number `index` of
+    /// `count` is in.
+    pub fn show_progress(&self, index: usize, count: usize, filename: &str, pass: &str) {
+        if !self.progress {
+            return;
+        }
+        let width = var("COLUMNS").ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_WIDTH);
+        let line = format!("[{}/{}] {}: {}", index, count, filename, pass);
+        // The line must not wrap, since only the last row would be redrawn.
+        let line: String = line.chars().take(width.saturating_sub(1)).collect();
+        eprint!("\r{}{}", line, CLEAR_LINE);
+        let _ = stderr().flush();
+        self.drawn_progress.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear_progress(&self) {
+        if self.drawn_progress.swap(false, Ordering::Relaxed) {
+            eprint!("\r{}", CLEAR_LINE);
+            let _ = stderr().flush();
         }
     }
 
     pub fn bold(&self) -> &str {
-        if self.is_a_tty {
+        if self.colored {
             BOLD
         }
         else {
@@ -51,7 +123,7 @@ impl Terminal {
     }
 
     pub fn blue(&self) -> &str {
-        if self.is_a_tty {
+        if self.colored {
             BLUE
         }
         else {
@@ -60,7 +132,7 @@ impl Terminal {
     }
 
     pub fn end_bold(&self) -> &str {
-        if self.is_a_tty {
+        if self.colored {
             END_BOLD
         }
         else {
@@ -69,7 +141,7 @@ impl Terminal {
     }
 
     pub fn red(&self) -> &str {
-        if self.is_a_tty {
+        if self.colored {
             RED
         }
         else {
@@ -78,13 +150,28 @@ impl Terminal {
     }
 
     pub fn reset_color(&self) -> &str {
-        if self.is_a_tty {
+        if self.colored {
             RESET_COLOR
         }
         else {
             ""
         }
     }
+
+    pub fn yellow(&self) -> &str {
+        if self.colored {
+            YELLOW
+        }
+        else {
+            ""
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.clear_progress();
+    }
 }
 
 #[cfg(unix)]
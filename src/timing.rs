@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Time spent in each phase of the compiler, printed by `--verbose` to find the hotspots when
+//! compiling big programs.
+
+use std::time::{Duration, Instant};
+
+/// Phases of the compiler whose duration is reported by `--verbose`.
+#[derive(Clone, Copy)]
+pub enum Phase {
+    Lexing,
+    Parsing,
+    EscapeAnalysis,
+    SemanticAnalysis,
+    Canonicalization,
+    InstructionSelection,
+    Liveness,
+    RegisterAllocation,
+    Assembly,
+}
+
+const PHASES: [(Phase, &str); 9] = [
+    (Phase::Lexing, "lexing"),
+    (Phase::Parsing, "parsing"),
+    (Phase::EscapeAnalysis, "escape analysis"),
+    (Phase::SemanticAnalysis, "semantic analysis"),
+    (Phase::Canonicalization, "canonicalization"),
+    (Phase::InstructionSelection, "instruction selection"),
+    (Phase::Liveness, "liveness"),
+    (Phase::RegisterAllocation, "register allocation"),
+    (Phase::Assembly, "assembly"),
+];
+
+/// Wall-clock time spent in each phase, summed over the functions and the files.
+#[derive(Default)]
+pub struct Timings {
+    durations: [Duration; PHASES.len()],
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, phase: Phase, duration: Duration) {
+        self.durations[phase as usize] += duration;
+    }
+
+    /// Add the durations of another file of the build, compiled on its own thread.
+    pub fn add_all(&mut self, timings: &Timings) {
+        for (duration, &other) in self.durations.iter_mut().zip(&timings.durations) {
+            *duration += other;
+        }
+    }
+
+    /// Run `function`, counting its duration in the phase.
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: Phase, function: F) -> T {
+        let start = Instant::now();
+        let result = function();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    pub fn print(&self) {
+        eprintln!("{:<24} {:>10}", "phase", "time (ms)");
+        for &(phase, name) in &PHASES {
+            eprintln!("{:<24} {:>10.3}", name, milliseconds(self.durations[phase as usize]));
+        }
+        let total = self.durations.iter().sum();
+        eprintln!("{:<24} {:>10.3}", "total", milliseconds(total));
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -28,7 +28,10 @@ use self::Tok::*;
 pub enum Tok {
     Ampersand,
     Array,
+    Arrow,
     Break,
+    Caret,
+    Case,
     Class,
     CloseCurly,
     CloseParen,
@@ -88,7 +91,10 @@ impl Display for Tok {
             let string = match *self {
                 Ampersand => "&",
                 Array => "array",
+                Arrow => "=>",
                 Break => "break",
+                Caret => "^",
+                Case => "case",
                 Class => "class",
                 CloseCurly => "}",
                 CloseParen => ")",
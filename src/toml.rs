@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Values of the TOML files read by the compiler, the message catalog and the project file, which
+//! only use strings and arrays of strings written on a single line.
+
+/// Value of a TOML basic or literal string, followed by an optional comment.
+pub fn parse_string(value: &str) -> Option<String> {
+    let (string, rest) = parse_string_prefix(value)?;
+    if is_end(rest) {
+        Some(string)
+    }
+    else {
+        None
+    }
+}
+
+/// Values of a TOML array of strings written on a single line, followed by an optional comment.
+pub fn parse_string_array(value: &str) -> Option<Vec<String>> {
+    let mut rest = value.strip_prefix('[')?.trim_start();
+    let mut strings = vec![];
+    loop {
+        if let Some(end) = rest.strip_prefix(']') {
+            return is_end(end).then_some(strings);
+        }
+        let (string, after) = parse_string_prefix(rest)?;
+        strings.push(string);
+        let after = after.trim_start();
+        rest =
+            match after.strip_prefix(',') {
+                Some(after) => after.trim_start(),
+                None if after.starts_with(']') => after,
+                None => return None,
+            };
+    }
+}
+
+/// Digits of a TOML integer, followed by an optional comment.
+pub fn parse_digits(value: &str) -> Option<&str> {
+    let end = value.find(|char: char| !char.is_ascii_digit()).unwrap_or(value.len());
+    let (digits, rest) = value.split_at(end);
+    (!digits.is_empty() && is_end(rest)).then_some(digits)
+}
+
+/// Value of the TOML string at the start of the text, and the text after it.
+fn parse_string_prefix(value: &str) -> Option<(String, &str)> {
+    let mut chars = value.chars();
+    let quote = chars.next().filter(|&char| char == '"' || char == '\'')?;
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            char if char == quote => break,
+            '\\' if quote == '"' => {
+                let escaped =
+                    match chars.next()? {
+                        'n' => '\n',
+                        't' => '\t',
+                        '"' => '"',
+                        '\\' => '\\',
+                        _ => return None,
+                    };
+                string.push(escaped);
+            },
+            char => string.push(char),
+        }
+    }
+    Some((string, chars.as_str()))
+}
+
+/// Whether only a comment follows a value.
+fn is_end(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
@@ -19,17 +19,23 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use ast::ExprWithPos;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ast::{ExprId, Visibility};
 use ir::Exp;
 use self::Type::*;
 use symbol::{Symbol, Symbols, SymbolWithPos};
-use temp::Label;
+use temp::{DataLabel, Label};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ClassField {
+    // Class declaring the field.
+    pub class_name: Symbol,
     pub name: Symbol,
     pub typ: Type,
-    pub value: ExprWithPos,
+    // Initial value, which the hidden fields do not have.
+    pub value: Option<ExprId>,
+    pub visibility: Visibility,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -39,6 +45,10 @@ pub struct FunctionType {
 }
 
 impl FunctionType {
+    pub fn is_error(&self) -> bool {
+        self.return_type.is_error() || self.param_types.iter().any(Type::is_error)
+    }
+
     pub fn show(&self, symbols: &Symbols<()>) -> std::string::String {
         let param_types = self.param_types.iter()
             .map(|typ| typ.show(symbols))
@@ -54,18 +64,29 @@ pub struct ClassMethod {
     pub label: Label,
     pub name: SymbolWithPos,
     pub typ: FunctionType,
+    pub visibility: Visibility,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Type {
+    // Arbitrary-precision integer managed by the runtime.
+    BigInt,
+    // Unsigned byte, only stored packed in the arrays and read as an int.
+    Byte,
+    // Character, stored like a byte, whose arrays convert to and from strings.
+    Char,
     Class {
         data_layout: std::string::String,
         fields: Vec<ClassField>,
         methods: Vec<ClassMethod>,
         name: Symbol,
         parent_class: Option<SymbolWithPos>,
+        // Storage of the static fields, shared by all the instances.
+        static_data: DataLabel,
+        static_fields: Vec<ClassField>,
+        static_methods: Vec<ClassMethod>,
         unique: Unique,
-        vtable_name: Label,
+        vtable_name: DataLabel,
     },
     Int,
     String,
@@ -79,13 +100,30 @@ pub enum Type {
     Nil,
     Unit,
     Name(SymbolWithPos, Option<Box<Type>>),
+    // Resource owned by the runtime, like a socket, which is an integer handle at runtime.
+    Opaque(&'static str),
+    // Reference to a value that the collector can reclaim, which is an object of the heap holding
+    // the address of the value at runtime.
+    Weak(Box<Type>),
+    // Raw pointer to a value that the collector does not manage, only in the unsafe dialect.
+    Pointer(Box<Type>),
     Error,
 }
 
 impl Type {
+    /// Whether an error was already reported for this type: it is then compatible with any other
+    /// type so that a single mistake does not cause a cascade of errors.
+    pub fn is_error(&self) -> bool {
+        match *self {
+            Error => true,
+            Name(_, Some(ref typ)) | Pointer(ref typ) | Weak(ref typ) => typ.is_error(),
+            _ => false,
+        }
+    }
+
     pub fn is_pointer(&self) -> bool {
         match *self {
-            Array { .. } | Class { .. } | Record { .. } | String  => true,
+            Array { .. } | BigInt | Class { .. } | Record { .. } | String | Weak(_) => true,
             Name(_, ref typ) => {
                 if let Some(typ) = typ.as_ref() {
                     typ.is_pointer()
@@ -103,6 +141,9 @@ impl Type {
             Array(ref typ, _) => {
                 format!("[{}]", typ.show(symbols))
             },
+            BigInt => "bigint".to_string(),
+            Byte => "byte".to_string(),
+            Char => "char".to_string(),
             Class { name, .. } => format!("class {}", symbols.name(name)),
             Int => "int".to_string(),
             Name(_, ref typ) => {
@@ -114,23 +155,30 @@ impl Type {
                 }
             },
             Nil => "nil".to_string(),
+            Opaque(name) => name.to_string(),
+            Pointer(ref typ) => format!("pointer {}", typ.show(symbols)),
             Record { name, .. } => format!("struct {}", symbols.name(name)),
             String => "string".to_string(),
             Unit => "()".to_string(),
+            Weak(ref typ) => format!("weak {}", typ.show(symbols)),
             Error => "type error".to_string(),
         }
     }
 }
 
-static mut UNIQUE_COUNT: u64 = 0;
+static UNIQUE_COUNT: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Unique(u64);
 
 impl Unique {
     pub fn new() -> Self {
-        let value = unsafe { UNIQUE_COUNT };
-        unsafe { UNIQUE_COUNT += 1 };
-        Unique(value)
+        Unique(UNIQUE_COUNT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Identity of the `index`th type predefined by the compiler, which does not depend on the
+    /// declarations of the program.
+    pub fn predefined(index: u64) -> Self {
+        Unique(u64::MAX - index)
     }
 }
@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Unwinding of the stack through the Tiger frames.
+//!
+//! The compiler describes each function in the `tiger_frames` section as a sequence of words:
+//!
+//! ```text
+//! START END FRAME-SIZE RETURN-ADDRESS-OFFSET SAVED-COUNT (REGISTER KIND LOCATION)...
+//! ```
+//!
+//! `START` and `END` delimit the code of the function, the return address is at
+//! `RETURN-ADDRESS-OFFSET` from the frame pointer and each callee-saved register the caller will
+//! find again is saved in the register `LOCATION` when `KIND` is 0, or in the frame at offset
+//! `LOCATION` from the frame pointer when `KIND` is 1. The registers use the DWARF numbering.
+//! The frames of the runtime and of the C library have no description: they are assumed to keep
+//! the frame pointer and the return address at the usual places.
+
+use std::arch::asm;
+use std::slice;
+
+// DWARF number of rbp.
+const FRAME_POINTER: usize = 6;
+const REGISTER_COUNT: usize = 16;
+const SAVED_IN_FRAME: usize = 1;
+const SAVED_IN_REGISTER: usize = 0;
+const WORD_SIZE: usize = 8;
+
+extern "C" {
+    static __start_tiger_frames: usize;
+    static __stop_tiger_frames: usize;
+}
+
+/// Description of the frame of a Tiger function.
+struct Function {
+    end: usize,
+    return_address_offset: usize,
+    saved_registers: &'static [SavedRegister],
+    start: usize,
+}
+
+#[repr(C)]
+struct SavedRegister {
+    register: usize,
+    kind: usize,
+    location: isize,
+}
+
+/// Walks the frames from the innermost one, restoring the callee-saved registers of each caller.
+pub struct Unwinder {
+    functions: Vec<Function>,
+    // Address of the code executing in the current frame, unknown for the innermost one.
+    pc: Option<usize>,
+    // Value of the registers in the current frame, 0 when unknown.
+    registers: [usize; REGISTER_COUNT],
+}
+
+impl Unwinder {
+    /// Start from the frame of the caller.
+    #[inline(never)]
+    pub fn new() -> Self {
+        let frame_pointer: usize;
+        unsafe {
+            asm!("mov {0}, rbp", out(reg) frame_pointer);
+        }
+        let mut registers = [0; REGISTER_COUNT];
+        // NOTE: this frame is gone when the caller steps, so start from the frame it points to.
+        registers[FRAME_POINTER] = unsafe { *(frame_pointer as *const usize) };
+        Self {
+            functions: functions(),
+            pc: None,
+            registers,
+        }
+    }
+
+    /// Go to the caller of the current frame and return the address at which it continues, or
+    /// `None` at the outermost frame.
+    pub fn step(&mut self) -> Option<usize> {
+        let frame_pointer = self.registers[FRAME_POINTER];
+        if frame_pointer == 0 {
+            return None;
+        }
+        let function = self.pc.and_then(|pc| self.functions.iter().find(|function| (function.start..function.end).contains(&pc)));
+        let return_address_offset = function.map_or(WORD_SIZE, |function| function.return_address_offset);
+        let mut caller_registers = self.registers;
+        unsafe {
+            if let Some(function) = function {
+                for saved in function.saved_registers {
+                    caller_registers[saved.register] =
+                        match saved.kind {
+                            SAVED_IN_REGISTER => self.registers[saved.location as usize],
+                            SAVED_IN_FRAME => *((frame_pointer as isize + saved.location) as *const usize),
+                            _ => unreachable!("saved register kind"),
+                        };
+                }
+            }
+            caller_registers[FRAME_POINTER] = *(frame_pointer as *const usize);
+            let return_address = *((frame_pointer + return_address_offset) as *const usize);
+            // NOTE: the frames of the C library calling main do not always keep a frame pointer,
+            // so stop at the first one which does not point to an outer frame.
+            if caller_registers[FRAME_POINTER] <= frame_pointer {
+                caller_registers[FRAME_POINTER] = 0;
+            }
+            self.registers = caller_registers;
+            self.pc = Some(return_address);
+            Some(return_address)
+        }
+    }
+}
+
+fn functions() -> Vec<Function> {
+    let mut functions = vec![];
+    unsafe {
+        let mut pointer = &__start_tiger_frames as *const usize;
+        let stop = &__stop_tiger_frames as *const usize;
+        while pointer < stop {
+            let saved_count = *pointer.add(4);
+            functions.push(Function {
+                end: *pointer.add(1),
+                return_address_offset: *pointer.add(3),
+                saved_registers: slice::from_raw_parts(pointer.add(5) as *const SavedRegister, saved_count),
+                start: *pointer,
+            });
+            pointer = pointer.add(5 + saved_count * 3);
+        }
+    }
+    functions
+}
@@ -0,0 +1,238 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Wait for the source files to change, for `--watch`.
+//!
+//! On Linux, inotify watches the directories of the files rather than the files themselves: the
+//! editors which save a file by renaming a new one over it would otherwise end the watch. Elsewhere,
+//! the modification times of the files are polled.
+
+use std::path::Path;
+
+/// Time during which the changes following the first one are gathered with it, since saving a
+/// file often takes several writes.
+const SETTLE_TIME_MS: u64 = 100;
+
+/// Directory watched for the file.
+fn directory(file: &Path) -> &Path {
+    match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::inotify::Watcher;
+#[cfg(not(target_os = "linux"))]
+pub use self::polling::Watcher;
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::convert::TryFrom;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::raw::{c_char, c_int, c_short, c_ulong, c_void};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    use super::{SETTLE_TIME_MS, directory};
+
+    const IN_CLOEXEC: c_int = 0o2000000;
+    const IN_CLOSE_WRITE: u32 = 0x8;
+    const IN_MOVED_TO: u32 = 0x80;
+    const IN_NONBLOCK: c_int = 0o4000;
+    const POLLIN: c_short = 1;
+    /// Size of an event without its name.
+    const EVENT_SIZE: usize = 16;
+
+    pub struct Watcher {
+        fd: c_int,
+        // The watch descriptor of the directory of each file.
+        files: Vec<(c_int, PathBuf)>,
+    }
+
+    impl Watcher {
+        pub fn new(files: &[PathBuf]) -> io::Result<Self> {
+            let fd = unsafe { inotify_init1(IN_CLOEXEC | IN_NONBLOCK) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut watcher = Watcher {
+                fd,
+                files: vec![],
+            };
+            for file in files {
+                let directory = CString::new(directory(file).as_os_str().as_bytes())?;
+                // The files in the same directory get the same watch descriptor.
+                let descriptor = unsafe { inotify_add_watch(fd, directory.as_ptr(), IN_CLOSE_WRITE | IN_MOVED_TO) };
+                if descriptor < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                watcher.files.push((descriptor, file.clone()));
+            }
+            Ok(watcher)
+        }
+
+        /// Block until some of the files are written and return them.
+        pub fn wait(&mut self) -> io::Result<Vec<PathBuf>> {
+            let mut changed = vec![];
+            let mut timeout = -1;
+            while self.poll(timeout)? {
+                self.read_events(&mut changed)?;
+                if !changed.is_empty() {
+                    timeout = SETTLE_TIME_MS as c_int;
+                }
+            }
+            Ok(changed)
+        }
+
+        /// Whether events can be read before the timeout, which is infinite when negative.
+        fn poll(&self, timeout: c_int) -> io::Result<bool> {
+            let mut poll_fd = PollFd {
+                fd: self.fd,
+                events: POLLIN,
+                revents: 0,
+            };
+            match unsafe { poll(&mut poll_fd, 1, timeout) } {
+                0 => Ok(false),
+                result if result > 0 => Ok(true),
+                _ => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        // The read finds no events and the poll is done again.
+                        Ok(true)
+                    }
+                    else {
+                        Err(error)
+                    }
+                },
+            }
+        }
+
+        fn read_events(&self, changed: &mut Vec<PathBuf>) -> io::Result<()> {
+            let mut buffer = [0; 4096];
+            let size = unsafe { read(self.fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) };
+            let size =
+                match usize::try_from(size) {
+                    Ok(size) => size,
+                    Err(_) => {
+                        let error = io::Error::last_os_error();
+                        return match error.kind() {
+                            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => Ok(()),
+                            _ => Err(error),
+                        };
+                    },
+                };
+            let word = |offset: usize| <[u8; 4]>::try_from(&buffer[offset..offset + 4]).expect("event word");
+            let mut offset = 0;
+            while offset + EVENT_SIZE <= size {
+                let descriptor = c_int::from_ne_bytes(word(offset));
+                let name_size = u32::from_ne_bytes(word(offset + 12)) as usize;
+                let name = &buffer[offset + EVENT_SIZE..offset + EVENT_SIZE + name_size];
+                // The name is padded with nul bytes.
+                let name = OsStr::from_bytes(name.split(|&byte| byte == 0).next().unwrap_or(name));
+                for &(watch, ref file) in &self.files {
+                    if watch == descriptor && file.file_name() == Some(name) && !changed.contains(file) {
+                        changed.push(file.clone());
+                    }
+                }
+                offset += EVENT_SIZE + name_size;
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            unsafe {
+                close(self.fd);
+            }
+        }
+    }
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    extern "C" {
+        fn close(fd: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+        fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod polling {
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    use super::SETTLE_TIME_MS;
+
+    const POLL_INTERVAL_MS: u64 = 250;
+
+    pub struct Watcher {
+        files: Vec<(PathBuf, Option<SystemTime>)>,
+    }
+
+    impl Watcher {
+        pub fn new(files: &[PathBuf]) -> io::Result<Self> {
+            Ok(Watcher {
+                files: files.iter()
+                    .map(|file| (file.clone(), modified(file)))
+                    .collect(),
+            })
+        }
+
+        /// Block until some of the files are written and return them.
+        pub fn wait(&mut self) -> io::Result<Vec<PathBuf>> {
+            loop {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                let mut changed = vec![];
+                for &mut (ref file, ref mut time) in &mut self.files {
+                    let new_time = modified(file);
+                    if new_time != *time {
+                        *time = new_time;
+                        changed.push(file.clone());
+                    }
+                }
+                if !changed.is_empty() {
+                    thread::sleep(Duration::from_millis(SETTLE_TIME_MS));
+                    for &mut (ref file, ref mut time) in &mut self.files {
+                        *time = modified(file);
+                    }
+                    return Ok(changed);
+                }
+            }
+        }
+    }
+
+    fn modified(file: &PathBuf) -> Option<SystemTime> {
+        fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+    }
+}
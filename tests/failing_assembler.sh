@@ -0,0 +1,8 @@
+#!/bin/sh
+# Assembler of the concurrent assembly test: fails on every file, slowly for the program.
+for source; do :; done
+case "$source" in
+    *use_library*) sleep 1;;
+esac
+echo "cannot assemble $source" >&2
+exit 1
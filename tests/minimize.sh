@@ -0,0 +1,3 @@
+#!/bin/sh
+# Predicate of the minimizer test: the program still prints "bug".
+grep -q 'print("bug")' "$1"
@@ -0,0 +1,9 @@
+#include <stdio.h>
+
+/* Function of tests/library.tig, whose last parameter is the unused static link. */
+long sum_to(long n, long static_link);
+
+int main(void) {
+    printf("%ld\n", sum_to(10, 0));
+    return 0;
+}
@@ -20,20 +20,33 @@
  */
 
 use std::fs::{self, remove_file};
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::thread;
 
 #[test]
 fn test_execution() {
     let files = [
+        "arithmetic",
         "array",
         "array_assignment",
+        "array_size",
+        "bigint",
+        "bytes",
+        "case",
+        "chain",
         "class",
+        "clock",
         "comments",
         "conditions",
+        "constants",
         "cycle",
         "escapes",
+        "evaluation_order",
+        "finalizer",
+        "format",
         "functions",
         "gc",
         "hello",
@@ -45,13 +58,21 @@ fn test_execution() {
         "lib",
         "loops",
         "merge",
+        "mmap",
         "nested",
         "prettyprint",
+        "private",
         "queens",
         "record",
+        "signal",
         "spill",
+        "static",
+        "static_links",
         "strings",
+        "syscall",
+        "templates",
         "vars",
+        "weak",
     ];
 
     for file in &files {
@@ -76,3 +97,1494 @@ fn test_execution() {
         assert_eq!(output, &*expected_output, "{}.tig", file);
     }
 }
+
+#[test]
+fn test_compile_errors() {
+    let files = [
+        ("case", "Duplicate case \"add\""),
+        ("chars", "Expecting array of char type"),
+        ("finalizer", "Method `finalize` should take no parameters, return no value and not be static"),
+        ("invalid_character", "Invalid character `→` (U+2192)"),
+        ("negative_array_size", "Array size cannot be negative, found -2"),
+        ("private_field", "Field `balance` is private to class `Account`"),
+        ("private_method", "Method `audit` is private to class `Account`"),
+        ("signal_nested", "Signal handler `handler` should be declared at the top level of the program, not in another function"),
+        ("static_self", "Cannot use `self` or instance fields in a static method"),
+        ("unicode_ident", "Character `é` (U+00E9) in identifier requires --unicode-idents"),
+        ("weak", "Expecting record, class or array type"),
+    ];
+
+    for &(file, message) in &files {
+        println!("{}", file);
+        let output = Command::new("./target/debug/tiger")
+            .arg(format!("tests/error/{}.tig", file))
+            .output()
+            .expect("compile");
+        assert!(!output.status.success(), "{}.tig", file);
+        let errors = String::from_utf8_lossy(&output.stderr);
+        assert!(errors.contains(message), "{}.tig: {}", file, errors);
+    }
+}
+
+#[test]
+fn test_unclosed_recovery() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--check", "tests/error/unclosed_recovery.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("Unclosed string\n   --> tests/error/unclosed_recovery.tig:2:21\n"), "{}", errors);
+    // The string ends at the end of its line, so that the rest of the file is parsed.
+    assert!(errors.contains("Unexpected token in, expecting"), "{}", errors);
+    assert!(errors.contains("--> tests/error/unclosed_recovery.tig:4:1\n"), "{}", errors);
+}
+
+#[test]
+fn test_cascading_errors() {
+    let output = Command::new("./target/debug/tiger")
+        .arg("tests/error/cascade.tig")
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    let messages: Vec<_> = errors.lines()
+        .filter(|line| line.contains("error:"))
+        .collect();
+    assert_eq!(messages.len(), 2, "{}", errors);
+    assert!(errors.contains("Undefined type `pointt`"), "{}", errors);
+    assert!(errors.contains("Undefined type `pointss`"), "{}", errors);
+}
+
+#[test]
+fn test_color() {
+    let errors = |args: &[&str], no_color: bool| {
+        let mut command = Command::new("./target/debug/tiger");
+        command.args(args).arg("tests/error/cascade.tig");
+        if no_color {
+            command.env("NO_COLOR", "1");
+        }
+        else {
+            command.env_remove("NO_COLOR");
+        }
+        let output = command.output().expect("compile");
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let red = "\x1b[31merror: ";
+    assert!(errors(&["--color=always"], false).contains(red));
+    assert!(errors(&["--color", "always"], false).contains(red));
+    // The flag overrides NO_COLOR.
+    assert!(errors(&["--color=always"], true).contains(red));
+    assert!(!errors(&["--color=never"], false).contains('\x1b'));
+    // The standard error of the test is not a terminal.
+    assert!(!errors(&["--color=auto"], false).contains('\x1b'));
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--color=blue", "tests/hello.tig"])
+        .output()
+        .expect("compile");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--color expects always, never or auto"));
+}
+
+#[test]
+fn test_progress() {
+    // The progress line is only drawn on a terminal.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--emit", "asm", "-o", "target/progress.s", "tests/queens.tig"])
+        .output()
+        .expect("compile");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_error_format_json() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--error-format=json", "--color=always", "tests/error/cascade.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+{\"severity\":\"error\",\"message\":\"Undefined type `pointss`\",\"file\":\"tests/error/cascade.tig\",\"line\":3,\"column\":19,\"span\":{\"byte\":84,\"length\":7}}
+{\"severity\":\"error\",\"message\":\"Undefined type `pointt`\",\"file\":\"tests/error/cascade.tig\",\"line\":2,\"column\":17,\"span\":{\"byte\":52,\"length\":6}}
+");
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--error-format", "json", "tests/missing.tig"])
+        .output()
+        .expect("compile");
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+        "{\"severity\":\"error\",\"message\":\"No such file or directory (os error 2)\",\"file\":null,\"line\":null,\"column\":null,\"span\":null}\n");
+}
+
+#[test]
+fn test_messages() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--messages", "tests/messages.toml", "tests/error/hello4.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("erreur: type `MyRecord` non défini"), "{}", errors);
+    assert!(errors.contains("erreur: Type int inattendu, string attendu"), "{}", errors);
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--messages", "tests/error/messages.toml", "tests/error/hello4.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("tests/error/messages.toml:2: message `undefined` has no argument `name`"), "{}", errors);
+}
+
+#[test]
+fn test_dialect_errors() {
+    let files: [(&str, &[&str], &str); 10] = [
+        ("class", &["--dialect", "tiger"], "Class declaration requires --dialect object-tiger"),
+        ("conversion", &[], "Pointer conversion requires --dialect unsafe"),
+        ("conversion", &["--dialect", "unsafe"], "Cannot convert a value of type `struct point` to `int`"),
+        ("pedantic_extension", &["--dialect", "object-tiger"], "Function `printi` requires --dialect extended"),
+        ("pedantic_extension", &["--pedantic"], "Function `printi` requires --dialect extended"),
+        ("pedantic_loop_variable", &["--pedantic"], "Cannot assign to loop variable `i`"),
+        ("pedantic_no_value", &["--pedantic", "--dialect", "extended"], "Expression produces no value"),
+        ("pointer_to_string", &[], "Pointer type requires --dialect unsafe"),
+        ("pointer_to_string", &["--dialect", "unsafe"], "Cannot point to type `string` managed by the garbage collector"),
+        ("socket", &["--dialect", "tiger"], "Type `socket` requires --dialect extended"),
+    ];
+
+    for &(file, options, message) in &files {
+        println!("{}", file);
+        let output = Command::new("./target/debug/tiger")
+            .args(options)
+            .arg(format!("tests/error/{}.tig", file))
+            .output()
+            .expect("compile");
+        assert!(!output.status.success(), "{}.tig", file);
+        let errors = String::from_utf8_lossy(&output.stderr);
+        assert!(errors.contains(message), "{}.tig: {}", file, errors);
+    }
+}
+
+#[test]
+fn test_unicode_idents() {
+    let _ = remove_file("./tests/unicode");
+    Command::new("./target/debug/tiger")
+        .args(["--unicode-idents", "tests/unicode.tig"])
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/unicode")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/unicode.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output);
+}
+
+#[test]
+fn test_nested_display() {
+    let files = [
+        "nested",
+        "queens",
+        "static_links",
+    ];
+
+    for file in &files {
+        println!("{}", file);
+        let _ = remove_file(format!("./tests/{}", file));
+        Command::new("./target/debug/tiger")
+            .args(["--nested", "display"])
+            .arg(format!("tests/{}.tig", file))
+            .status()
+            .expect("compile");
+        let output = Command::new(format!("./tests/{}", file))
+            .output()
+            .expect("run");
+        let expected_output = fs::read_to_string(format!("./tests/{}.stdout", file)).expect("read");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output, "{}.tig", file);
+    }
+}
+
+#[test]
+fn test_fragments() {
+    let files = [
+        "class",
+        "functions",
+        "nested",
+        "strings",
+    ];
+
+    for file in &files {
+        println!("{}", file);
+        let _ = remove_file(format!("./tests/{}", file));
+        Command::new("./target/debug/tiger")
+            .args(["--emit", "fragments"])
+            .arg(format!("tests/{}.tig", file))
+            .status()
+            .expect("emit fragments");
+        assert!(!Path::new(&format!("./tests/{}", file)).exists(), "{}.tig", file);
+        Command::new("./target/debug/tiger")
+            .arg(format!("tests/{}.frag", file))
+            .status()
+            .expect("compile fragments");
+        let mut child = Command::new(format!("./tests/{}", file))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn().expect("spawn");
+        if Path::new(&format!("./tests/{}.stdin", file)).exists() {
+            let input = fs::read(format!("./tests/{}.stdin", file)).expect("read");
+            child.stdin.take().expect("stdin").write_all(&input).expect("write stdin");
+        }
+        let output = child.wait_with_output().expect("run");
+        let expected_output = fs::read_to_string(format!("./tests/{}.stdout", file)).expect("read");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output, "{}.frag", file);
+    }
+}
+
+#[test]
+fn test_emit_source() {
+    let files = [
+        "case",
+        "chain",
+        "class",
+        "escapes",
+        "functions",
+        "merge",
+        "queens",
+        "strings",
+    ];
+
+    let _ = fs::remove_dir_all("./target/source");
+    fs::create_dir_all("./target/source").expect("create output directory");
+    for file in &files {
+        println!("{}", file);
+        let source_path = format!("target/source/{}.tig", file);
+        let status = Command::new("./target/debug/tiger")
+            .args(["--emit", "source", "-o", &source_path])
+            .arg(format!("tests/{}.tig", file))
+            .status()
+            .expect("emit source");
+        assert!(status.success(), "{}.tig", file);
+        // The printed source compiles to a program with the same output.
+        Command::new("./target/debug/tiger")
+            .arg(&source_path)
+            .status()
+            .expect("compile source");
+        let mut child = Command::new(format!("./target/source/{}", file))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn().expect("spawn");
+        if Path::new(&format!("./tests/{}.stdin", file)).exists() {
+            let input = fs::read(format!("./tests/{}.stdin", file)).expect("read");
+            child.stdin.take().expect("stdin").write_all(&input).expect("write stdin");
+        }
+        let output = child.wait_with_output().expect("run");
+        let expected_output = fs::read_to_string(format!("./tests/{}.stdout", file)).expect("read");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output, "{}.tig", file);
+    }
+}
+
+#[test]
+fn test_gc_log() {
+    let _ = remove_file("./tests/class");
+    Command::new("./target/debug/tiger")
+        .arg("tests/class.tig")
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/class")
+        .env("TIGER_GC_CAPACITY", "64")
+        .env("TIGER_GC_LOG", "1")
+        .output()
+        .expect("run");
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("gc event=collect reason=no_free_spot site=tests/class.tig:"), "{}", log);
+    for line in log.lines() {
+        assert!(line.starts_with("gc event="), "{}", line);
+        assert!(line.contains(" roots=") && line.contains(" pause_us="), "{}", line);
+    }
+}
+
+#[test]
+fn test_signal_gc() {
+    let _ = remove_file("./tests/signal_gc");
+    let status = Command::new("./target/debug/tiger")
+        .arg("tests/signal_gc.tig")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./tests/signal_gc")
+        .env("TIGER_GC_CAPACITY", "64")
+        .env("TIGER_GC_LOG", "1")
+        .output()
+        .expect("run");
+    // The handler collects while the interrupted program holds its list, which survives.
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("gc event=collect reason=no_free_spot site=tests/signal_gc.tig:10:"), "{}", log);
+    let expected_output = fs::read_to_string("./tests/signal_gc.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+    remove_file("./tests/signal_gc").expect("remove executable");
+}
+
+#[test]
+fn test_invalid_signal() {
+    let _ = remove_file("./tests/signal_invalid");
+    let status = Command::new("./target/debug/tiger")
+        .arg("tests/signal_invalid.tig")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./tests/signal_invalid")
+        .output()
+        .expect("run");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.starts_with("on_signal: signal 65 is not between 1 and 64\n"), "{}", errors);
+    remove_file("./tests/signal_invalid").expect("remove executable");
+}
+
+#[test]
+fn test_weak_references_freed() {
+    let _ = remove_file("./tests/weak_churn");
+    Command::new("./target/debug/tiger")
+        .arg("tests/weak_churn.tig")
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/weak_churn")
+        .env("TIGER_GC_LOG", "1")
+        .output()
+        .expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "20000\n1\n");
+    // The collector only keeps track of the weak references which are still reachable.
+    let log = String::from_utf8_lossy(&output.stderr);
+    let counts: Vec<usize> = log.lines()
+        .filter_map(|line| line.split(" weak_references=").nth(1))
+        .map(|rest| rest.split(' ').next().expect("count").parse().expect("number"))
+        .collect();
+    assert!(!counts.is_empty(), "{}", log);
+    assert!(counts.iter().all(|&count| count <= 3), "{}", log);
+}
+
+#[test]
+fn test_conservative_gc() {
+    let files = [
+        "class",
+        "finalizer",
+        "weak",
+    ];
+
+    for file in &files {
+        println!("{}", file);
+        let _ = remove_file(format!("./tests/{}", file));
+        Command::new("./target/debug/tiger")
+            .args(["--gc", "conservative"])
+            .arg(format!("tests/{}.tig", file))
+            .status()
+            .expect("compile");
+        let output = Command::new(format!("./tests/{}", file))
+            .env("TIGER_GC_CAPACITY", "64")
+            .output()
+            .expect("run");
+        let expected_output = fs::read_to_string(format!("./tests/{}.stdout", file)).expect("read");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output, "{}.tig", file);
+    }
+}
+
+#[test]
+fn test_annotate_asm() {
+    let _ = remove_file("./tests/array2d.s");
+    Command::new("./target/debug/tiger")
+        .args(["--annotate-asm", "--keep-intermediates", "tests/array2d.tig"])
+        .status()
+        .expect("compile");
+    let assembly = fs::read_to_string("./tests/array2d.s").expect("read assembly");
+    assert!(assembly.contains("; line 3 | (var -16 (move (mem (temp t"), "{}", assembly);
+    assert!(assembly.lines().any(|line| line.contains("; line 3 | (move (temp t") && line.contains("(name initArray)")), "{}", assembly);
+    assert!(assembly.lines().any(|line| line.contains("; line 5 | (move (temp t") && line.contains("=r")), "{}", assembly);
+}
+
+#[test]
+fn test_listing() {
+    let _ = remove_file("./tests/functions.lst");
+    Command::new("./target/debug/tiger")
+        .args(["--emit", "listing", "tests/functions.tig"])
+        .status()
+        .expect("compile");
+    let listing = fs::read_to_string("./tests/functions.lst").expect("read listing");
+    assert!(listing.contains(";    2 |         if a > b then\n"), "{}", listing);
+    assert!(listing.contains(";   14 |     printi(minimum(42, 24));\n    mov"), "{}", listing);
+}
+
+#[test]
+fn test_emit_asm() {
+    for options in [&["--emit", "asm"][..], &["--emit-asm"]] {
+        let _ = remove_file("./tests/hello1.s");
+        let _ = remove_file("./tests/hello1.o");
+        let status = Command::new("./target/debug/tiger")
+            .args(options)
+            .arg("tests/hello1.tig")
+            .status()
+            .expect("compile");
+        assert!(status.success());
+        let asm = fs::read_to_string("./tests/hello1.s").expect("read assembly");
+        assert!(asm.contains("global main\n"), "{}", asm);
+        assert!(!Path::new("./tests/hello1.o").exists());
+        remove_file("./tests/hello1.s").expect("remove assembly");
+    }
+}
+
+#[test]
+fn test_reproducible_asm() {
+    let mut assemblies = vec![];
+    for output in &["tests/spill_first", "tests/spill_second"] {
+        let status = Command::new("./target/debug/tiger")
+            .args(["--emit", "asm", "-o", output, "tests/spill.tig"])
+            .status()
+            .expect("compile");
+        assert!(status.success());
+        let path = format!("./{}.s", output);
+        assemblies.push(fs::read_to_string(&path).expect("read assembly"));
+        remove_file(&path).expect("remove assembly");
+    }
+    assert_eq!(assemblies[0], assemblies[1]);
+}
+
+#[test]
+fn test_object_file() {
+    let _ = remove_file("./tests/hello2.o");
+    let _ = remove_file("./tests/hello2");
+    let status = Command::new("./target/debug/tiger")
+        .args(["-c", "tests/hello2.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./tests/hello2.o").exists());
+    assert!(!Path::new("./tests/hello2").exists());
+}
+
+#[test]
+fn test_linker() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--linker", "./tests/missing-cc", "-o", "tests/linker", "tests/hello.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot run the linker `./tests/missing-cc`"));
+    assert!(!Path::new("./tests/linker").exists());
+    assert!(!Path::new("./tests/linker.s").exists());
+    assert!(!Path::new("./tests/linker.o").exists());
+
+    let _ = fs::remove_dir_all("./target/linker");
+    fs::create_dir_all("./target/linker").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--ld-args", "-lm -Wl,-Map,target/linker/hello.map", "-o", "target/linker/hello", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./target/linker/hello.map").exists());
+    let output = Command::new("./target/linker/hello").output().expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, World!\n");
+
+    let status = Command::new("./target/debug/tiger")
+        .args(["--ld-args", "-lmissing_tiger_library", "-o", "target/linker/missing", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(!status.success());
+    assert!(!Path::new("./target/linker/missing").exists());
+}
+
+#[test]
+fn test_shared_library() {
+    let _ = fs::remove_dir_all("./target/shared");
+    fs::create_dir_all("./target/shared").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--shared", "-o", "target/shared/libgreeting.so", "tests/use_library.tig", "tests/library.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let status = Command::new("cc")
+        .args(["-o", "target/shared/caller", "tests/shared.c", "target/shared/libgreeting.so"])
+        .status()
+        .expect("compile caller");
+    assert!(status.success());
+    let output = Command::new("./target/shared/caller")
+        .output()
+        .expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "55\n");
+}
+
+#[test]
+fn test_static_executable() {
+    let _ = fs::remove_dir_all("./target/static");
+    fs::create_dir_all("./target/static").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--static", "-o", "target/static/hello", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    // No program header of the ELF file is the path of a dynamic loader (PT_INTERP).
+    let executable = fs::read("./target/static/hello").expect("read executable");
+    let field = |offset: usize, size: usize| {
+        executable[offset..offset + size].iter().rev().fold(0, |value, &byte| value << 8 | byte as usize)
+    };
+    let (header_offset, header_size, header_count) = (field(0x20, 8), field(0x36, 2), field(0x38, 2));
+    assert!(header_count > 0);
+    assert!((0..header_count).all(|index| field(header_offset + index * header_size, 4) != 3));
+    let output = Command::new("./target/static/hello").output().expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, World!\n");
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--static", "--shared", "-o", "target/static/libhello.so", "tests/hello.tig"])
+        .output()
+        .expect("compile");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--shared cannot be used with --static"));
+}
+
+#[test]
+fn test_freestanding() {
+    let _ = fs::remove_dir_all("./target/freestanding");
+    fs::create_dir_all("./target/freestanding").expect("create output directory");
+    // Linked with -nostdlib: the program must not call the runtime, not even to check its version.
+    let status = Command::new("./target/debug/tiger")
+        .args(["--freestanding", "-o", "target/freestanding/exit", "tests/freestanding.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let status = Command::new("./target/freestanding/exit").status().expect("run");
+    assert_eq!(status.code(), Some(42));
+}
+
+#[test]
+fn test_freestanding_write() {
+    let _ = fs::remove_dir_all("./target/freestanding_write");
+    fs::create_dir_all("./target/freestanding_write").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--freestanding", "--dialect", "unsafe", "-o", "target/freestanding_write/write",
+            "tests/freestanding_write.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./target/freestanding_write/write").output().expect("run");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello\n");
+}
+
+#[test]
+fn test_entry() {
+    let _ = fs::remove_dir_all("./target/entry");
+    fs::create_dir_all("./target/entry").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--freestanding", "--entry", "tiger_start", "--dialect", "unsafe", "-o", "target/entry/write",
+            "tests/freestanding_write.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./target/entry/write").output().expect("run");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello\n");
+}
+
+#[test]
+fn test_runtime_path() {
+    let _ = fs::remove_dir_all("./target/sysroot");
+    let runtime_directory = "./target/sysroot/lib/tiger/x86_64-unknown-linux-gnu";
+    fs::create_dir_all(runtime_directory).expect("create sysroot");
+    fs::copy("./target/debug/libruntime.a", format!("{}/libruntime.a", runtime_directory)).expect("install runtime");
+    let output = Command::new("./target/debug/tiger")
+        .args(["--print-runtime-path", "--sysroot", "./target/sysroot"])
+        .env_remove("TIGER_RUNTIME")
+        .output()
+        .expect("print runtime path");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}/libruntime.a\n", runtime_directory));
+
+    // --runtime is used as is.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--print-runtime-path", "--sysroot", "./target/sysroot", "--runtime", "target/other.a"])
+        .output()
+        .expect("print runtime path");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "target/other.a\n");
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--print-runtime-path", "--sysroot", "./target/missing-sysroot"])
+        .env_remove("TIGER_RUNTIME")
+        .output()
+        .expect("print runtime path");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("cannot find the runtime ./target/missing-sysroot/lib/tiger/x86_64-unknown-linux-gnu/libruntime.a"),
+        "{}", errors);
+
+    // The sysroot is linked with.
+    let _ = remove_file("./target/sysroot/hello");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--sysroot", "./target/sysroot", "-o", "target/sysroot/hello", "tests/hello.tig"])
+        .env_remove("TIGER_RUNTIME")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./target/sysroot/hello").exists());
+}
+
+#[test]
+fn test_runtime() {
+    let status = Command::new("./target/debug/tiger")
+        .args(["-o", "tests/runtime", "tests/hello.tig"])
+        .env("TIGER_RUNTIME", "./target/debug/libruntime.a")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./tests/runtime")
+        .output()
+        .expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, World!\n");
+    remove_file("./tests/runtime").expect("remove executable");
+
+    let status = Command::new("./target/debug/tiger")
+        .args(["--runtime", "./tests/missing.a", "-o", "tests/runtime", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(!status.success());
+    assert!(!Path::new("./tests/runtime").exists());
+    assert!(!Path::new("./tests/runtime.s").exists());
+    assert!(!Path::new("./tests/runtime.o").exists());
+}
+
+#[test]
+fn test_assembler() {
+    let _ = fs::remove_dir_all("./target/assembler");
+    fs::create_dir_all("./target/assembler").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--assembler-flags", "-g -F dwarf", "-o", "target/assembler/hello", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./target/assembler/hello")
+        .output()
+        .expect("run");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, World!\n");
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["-o", "target/assembler/missing", "tests/hello.tig"])
+        .env("TIGER_NASM", "./tests/missing-nasm")
+        .output()
+        .expect("compile");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error running ./tests/missing-nasm"));
+    assert!(!Path::new("./target/assembler/missing").exists());
+}
+
+#[test]
+fn test_output_path() {
+    let _ = fs::remove_dir_all("./target/tests");
+    fs::create_dir_all("./target/tests").expect("create output directory");
+    let _ = remove_file("./tests/hello");
+    Command::new("./target/debug/tiger")
+        .args(["--keep-intermediates", "-o", "target/tests/greeting", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(Path::new("./target/tests/greeting.s").exists());
+    assert!(Path::new("./target/tests/greeting.o").exists());
+    assert!(!Path::new("./tests/hello").exists());
+    let output = Command::new("./target/tests/greeting")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/hello.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_intermediates() {
+    let _ = fs::remove_dir_all("./target/intermediates");
+    fs::create_dir_all("./target/intermediates/temp").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["-o", "target/intermediates/hello", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./target/intermediates/hello").exists());
+    assert!(!Path::new("./target/intermediates/hello.s").exists());
+    assert!(!Path::new("./target/intermediates/hello.o").exists());
+
+    let status = Command::new("./target/debug/tiger")
+        .args(["--temp-dir", "target/intermediates/temp", "--keep-intermediates", "-o", "target/intermediates/kept",
+            "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./target/intermediates/temp/kept.s").exists());
+    assert!(Path::new("./target/intermediates/temp/kept.o").exists());
+    assert!(!Path::new("./target/intermediates/kept.s").exists());
+
+    // The object file is the output of -c.
+    let status = Command::new("./target/debug/tiger")
+        .args(["--temp-dir", "target/intermediates/temp", "-c", "-o", "target/intermediates/object", "tests/hello.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./target/intermediates/object.o").exists());
+    assert!(!Path::new("./target/intermediates/temp/object.s").exists());
+}
+
+#[test]
+fn test_stdin() {
+    let compile_stdin = |args: &[&str]| {
+        let mut child = Command::new("./target/debug/tiger")
+            .args(args)
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn().expect("spawn");
+        let source = fs::read("./tests/hello.tig").expect("read");
+        // NOTE: the compiler does not read its input when the options are rejected.
+        let _ = child.stdin.take().expect("stdin").write_all(&source);
+        child.wait_with_output().expect("compile")
+    };
+
+    let output = compile_stdin(&["--emit", "asm"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("global main"));
+    assert!(!Path::new("./-.s").exists());
+
+    let _ = fs::remove_dir_all("./target/stdin");
+    fs::create_dir_all("./target/stdin").expect("create output directory");
+    compile_stdin(&["-o", "target/stdin/hello"]);
+    let output = Command::new("./target/stdin/hello")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/hello.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+
+    let output = compile_stdin(&[]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("compiling the standard input expects -o or --emit asm"));
+}
+
+#[test]
+fn test_project() {
+    let status = Command::new("../../target/debug/tiger")
+        .arg("build")
+        .current_dir("./tests/project")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./tests/project/greeting")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/use_library.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+    remove_file("./tests/project/greeting").expect("remove output");
+
+    // The -O given after build overrides the opt-level of tiger.toml.
+    let instruction_count = |options: &[&str]| -> usize {
+        let output = Command::new("../../target/debug/tiger")
+            .arg("build")
+            .args(options)
+            .args(["--stats", "--emit", "asm"])
+            .current_dir("./tests/project")
+            .output()
+            .expect("compile");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).lines()
+            .filter(|line| !line.starts_with("function"))
+            .map(|line| line.split_whitespace().nth(4).expect("after").parse::<usize>().expect("count"))
+            .sum()
+    };
+    assert!(instruction_count(&[]) < instruction_count(&["-O0"]));
+    assert_eq!(instruction_count(&[]), instruction_count(&["-O2"]));
+    remove_file("./tests/project/greeting.s").expect("remove assembly");
+    remove_file("./tests/project/library.s").expect("remove assembly");
+
+    let output = Command::new("../../target/debug/tiger")
+        .args(["build", "main.tig"])
+        .current_dir("./tests/project")
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tiger build compiles the sources listed in tiger.toml"));
+}
+
+#[test]
+fn test_feature_report() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--feature-report", "tests/feature_report.tig"])
+        .output()
+        .expect("compile");
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("for loops                        1  tiger         implemented\n"), "{}", report);
+    assert!(report.contains("function `substring`             2  tiger         missing from the runtime\n"), "{}", report);
+    assert!(report.contains("method calls                     1  object-tiger  implemented\n"), "{}", report);
+    assert!(report.contains("8 of 9 features implemented, requires --dialect object-tiger\nto do: function `substring`\n"), "{}", report);
+}
+
+#[test]
+fn test_stats() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--stats", "tests/functions.tig"])
+        .output()
+        .expect("compile");
+    let stats = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stats.lines();
+    let header = lines.next().expect("header");
+    assert!(header.starts_with("function") && header.ends_with("cycles"), "{}", stats);
+    let functions: Vec<_> = lines.map(|line| line.split_whitespace().next().expect("function name")).collect();
+    assert_eq!(functions, ["maximum", "minimum", "sum10", "main"]);
+}
+
+#[test]
+fn test_dump_gc_maps() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--dump-gc-maps", "--emit", "asm", "tests/record.tig"])
+        .output()
+        .expect("compile");
+    let _ = remove_file("./tests/record.s");
+    let maps = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = maps.lines().collect();
+    assert!(lines[0].starts_with("function") && lines[0].ends_with("pointers"), "{}", maps);
+    assert!(lines.iter().any(|line| line.contains("call allocRecord") && line.ends_with("-16")), "{}", maps);
+    assert_eq!(lines.last(), Some(&"9 call sites, 9 map entries, 208 map bytes"), "{}", maps);
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_dump_ast() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--dump-ast", "--emit", "asm", "tests/functions.tig"])
+        .output()
+        .expect("compile");
+    let _ = remove_file("./tests/functions.s");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let ast = String::from_utf8_lossy(&output.stdout);
+    assert!(ast.starts_with("Let 1:1\n"), "{}", ast);
+    assert!(ast.lines().any(|line| line.starts_with("    Function maximum(")), "{}", ast);
+    // The rewriter names the operands it moves to variables.
+    assert!(ast.contains("Var __var_0"), "{}", ast);
+}
+
+#[test]
+fn test_dump_ir() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--dump-ir", "--emit", "asm", "tests/functions.tig"])
+        .output()
+        .expect("compile");
+    let _ = remove_file("./tests/functions.s");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let ir = String::from_utf8_lossy(&output.stdout);
+    let headers: Vec<_> = ir.lines()
+        .filter(|line| line.starts_with(";;"))
+        .filter(|line| line.contains(" main "))
+        .collect();
+    assert_eq!(headers, [";; main after semantic analysis", ";; main after linearize", ";; main after trace_schedule"]);
+    // The canonical statements contain no sequences.
+    let canonical = ir.rsplit_once(";; main after linearize").expect("linearized main").1;
+    assert!(!canonical.contains("(seq") && !canonical.contains("(eseq"), "{}", canonical);
+}
+
+#[test]
+fn test_minimize() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--minimize", "tests/minimize.sh", "-o", "target/minimize", "tests/minimize.tig"])
+        .output()
+        .expect("minimize");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "print(\"bug\")\n");
+    assert_eq!(fs::read_to_string("target/minimize.min.tig").expect("read"), "print(\"bug\")\n");
+
+    // The program must satisfy the predicate.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--minimize", "tests/minimize.sh", "-o", "target/minimize", "tests/hello.tig"])
+        .output()
+        .expect("minimize");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("the predicate tests/minimize.sh fails on tests/hello.tig"), "{}", errors);
+}
+
+#[test]
+fn test_debug() {
+    let mut child = Command::new("./target/debug/tiger")
+        .args(["--debug", "tests/debug.tig"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn().expect("spawn");
+    // An empty line repeats the previous command.
+    let commands = "break 4\ncontinue\nbacktrace\nprint n\nfinish\nlocals\nprint corners[1].y\ndelete 4\nnext\n\n\
+        print total\nprint corners[2]\ncontinue\n";
+    child.stdin.take().expect("stdin").write_all(commands.as_bytes()).expect("write stdin");
+    let output = child.wait_with_output().expect("debug");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "\
+1\tlet type point = { x: int, y: int }
+(tiger) Breakpoint at line 4.
+(tiger) Breakpoint at line 4, in square.
+4\t        n * n
+(tiger) #0 square at line 4
+#1 main at line 9
+(tiger) n = 0
+(tiger) square returned 0.
+9\t        total := total + square(i);
+(tiger) i = 0
+corners = [point { x = 1, y = 2 }, point { x = 1, y = 2 }]
+total = 0
+(tiger) corners[1].y = 2
+(tiger) (tiger) 8\t    for i := 0 to 2 do
+(tiger) 9\t        total := total + square(i);
+(tiger) total = 0
+(tiger) Index 2 out of bounds of array of length 2.
+(tiger) 5
+Program exited with code 0.
+");
+}
+
+#[test]
+fn test_target() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--target", "x86_64-unknown-linux-gnu", "--emit", "asm", "-o", "target/target.s", "tests/hello.tig"])
+        .output()
+        .expect("compile");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let errors = |target: &str| {
+        let output = Command::new("./target/debug/tiger")
+            .args([&format!("--target={}", target), "tests/hello.tig"])
+            .output()
+            .expect("compile");
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    assert!(errors("x86_64-pc-windows-msvc").contains("cannot compile for x86_64-pc-windows yet: there is no frame"));
+    assert!(errors("x86_64-apple-darwin").contains("cannot compile for x86_64-apple-darwin yet"));
+    assert!(errors("mips").contains("--target expects a triple like x86_64-unknown-linux-gnu"));
+}
+
+#[test]
+fn test_check() {
+    let _ = remove_file("./target/check/library.s");
+    let output = Command::new("./target/debug/tiger")
+        .args(["--check", "-o", "target/check/use_library", "tests/use_library.tig", "tests/library.tig"])
+        .output()
+        .expect("check");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!Path::new("./target/check/use_library.s").exists());
+    assert!(!Path::new("./target/check/library.s").exists());
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--check", "tests/error/cascade.tig"])
+        .output()
+        .expect("check");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Undefined type `pointss`"));
+}
+
+#[test]
+fn test_run() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--run", "-o", "target/run/hello", "tests/hello.tig"])
+        .output()
+        .expect("run");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, World!\n");
+
+    let mut child = Command::new("./target/debug/tiger")
+        .args(["--run", "-o", "target/run/exit", "-", "--", "ignored"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    child.stdin.take().expect("stdin").write_all(b"(print(\"exiting\"); exit(3))\n").expect("write stdin");
+    let output = child.wait_with_output().expect("run");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "exiting");
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["--run", "--emit", "asm", "tests/hello.tig"])
+        .output()
+        .expect("run");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--run expects an executable to be linked"));
+}
+
+#[test]
+fn test_watch() {
+    fs::create_dir_all("./target/watch").expect("create directory");
+    fs::write("./target/watch/program.tig", "print(\"watched\")\n").expect("write");
+    let mut child = Command::new("./target/debug/tiger")
+        .args(["--watch", "--check", "target/watch/program.tig"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr"));
+    let mut read_until = |status: &str| -> String {
+        let mut output = String::new();
+        while !output.ends_with(status) {
+            assert_ne!(stderr.read_line(&mut output).expect("read"), 0, "{}", output);
+        }
+        output
+    };
+    assert_eq!(read_until("waiting for changes\n"), "compiled, waiting for changes\n");
+
+    fs::write("./target/watch/program.tig", "print(1)\n").expect("write");
+    let output = read_until("waiting for changes\n");
+    assert!(output.starts_with("target/watch/program.tig changed, compiling again\n"), "{}", output);
+    assert!(output.contains("Unexpected type int, expecting string"), "{}", output);
+    assert!(output.ends_with("failed, waiting for changes\n"), "{}", output);
+
+    fs::write("./target/watch/program.tig", "print(\"fixed\")\n").expect("write");
+    let output = read_until("waiting for changes\n");
+    assert!(output.ends_with("compiled, waiting for changes\n"), "{}", output);
+    child.kill().expect("kill");
+    child.wait().expect("wait");
+}
+
+#[test]
+fn test_command_line() {
+    let output = Command::new("./target/debug/tiger")
+        .arg("--help")
+        .output()
+        .expect("help");
+    assert!(output.status.success());
+    let usage = String::from_utf8_lossy(&output.stdout);
+    assert!(usage.starts_with("Usage: tiger [COMMAND] [OPTIONS] PROGRAM.tig"), "{}", usage);
+    assert!(usage.lines().any(|line| line.trim_start().starts_with("--target TRIPLE ")), "{}", usage);
+    assert!(usage.lines().any(|line| line.trim_start().starts_with("--emit-asm ")), "{}", usage);
+
+    let output = Command::new("./target/debug/tiger")
+        .arg("--version")
+        .output()
+        .expect("version");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("tiger {}\n", env!("CARGO_PKG_VERSION")));
+
+    let errors = |args: &[&str]| -> String {
+        let output = Command::new("./target/debug/tiger")
+            .args(args)
+            .output()
+            .expect("compile");
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    assert!(errors(&["--optimize", "tests/hello.tig"]).contains("unknown option --optimize, see tiger --help"));
+    assert!(errors(&["--pedantic=yes", "tests/hello.tig"]).contains("--pedantic expects no value, found yes"));
+    assert!(errors(&[]).contains("no input file"));
+    assert!(errors(&["check", "--dialect=object-tiger", "tests/error/cascade.tig"]).contains("Undefined type `pointss`"));
+}
+
+#[test]
+fn test_opt_levels() {
+    let instruction_count = |opt_level: &str| -> usize {
+        let output = Command::new("./target/debug/tiger")
+            .args([opt_level, "--stats", "--emit", "asm", "-o", "target/queens.s", "tests/queens.tig"])
+            .output()
+            .expect("compile");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        // Sum of the instructions after the register allocation.
+        String::from_utf8_lossy(&output.stdout).lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().nth(4).expect("after").parse::<usize>().expect("count"))
+            .sum()
+    };
+    let counts = [instruction_count("-O0"), instruction_count("-O1"), instruction_count("-O2")];
+    assert!(counts[0] > counts[1] && counts[1] > counts[2], "{:?}", counts);
+
+    let _ = fs::create_dir_all("./target/opt");
+    for file in &["conditions", "functions", "pressure", "pure_calls", "pure_functions", "queens", "record",
+        "record_fields"] {
+        println!("{}", file);
+        let executable = format!("target/opt/{}", file);
+        let status = Command::new("./target/debug/tiger")
+            .args(["-O2", "-o", &executable])
+            .arg(format!("tests/{}.tig", file))
+            .status()
+            .expect("compile");
+        assert!(status.success(), "{}.tig", file);
+        let output = Command::new(format!("./{}", executable)).output().expect("run");
+        let expected_output = fs::read_to_string(format!("./tests/{}.stdout", file)).expect("read");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output, "{}.tig", file);
+    }
+
+    // The calls to pure functions with constant arguments are evaluated by the compiler, except
+    // when the recursion is too deep.
+    let assembly = |opt_level: &str, file: &str| -> String {
+        let output = format!("target/opt/{}.s", file);
+        let status = Command::new("./target/debug/tiger")
+            .args([opt_level, "--emit", "asm", "-o", &output, &format!("tests/{}.tig", file)])
+            .status()
+            .expect("compile");
+        assert!(status.success());
+        fs::read_to_string(output).expect("read assembly")
+    };
+    let optimized = assembly("-O2", "pure_functions");
+    let main = &optimized[optimized.find("\nmain:").expect("main")..];
+    assert!(main.contains(", 6765\n") && !main.contains("call fib\n") && main.contains("call sum\n"), "{}", main);
+    assert!(!assembly("-O1", "pure_functions").contains(", 6765\n"));
+
+    // The calls to pure functions with the same arguments are done once, but not those to a
+    // function assigning a global variable.
+    let calls = |opt_level: &str, function: &str| {
+        assembly(opt_level, "pure_calls").matches(&format!("call {}\n", function)).count()
+    };
+    assert_eq!((calls("-O1", "square"), calls("-O1", "digits"), calls("-O1", "counted")), (4, 2, 2));
+    assert_eq!((calls("-O2", "square"), calls("-O2", "digits"), calls("-O2", "counted")), (2, 1, 2));
+
+    let output = Command::new("./target/debug/tiger")
+        .args(["-O3", "tests/hello.tig"])
+        .output()
+        .expect("compile");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("-O expects 0, 1 or 2"));
+}
+
+#[test]
+fn test_redundant_loads() {
+    // Number of loads of each frame slot in the function `move`.
+    let slot_loads = |opt_level: &str| -> Vec<usize> {
+        let output = format!("target/reload{}.s", opt_level);
+        let status = Command::new("./target/debug/tiger")
+            .args([opt_level, "--emit", "asm", "-o", &output, "tests/reload.tig"])
+            .status()
+            .expect("compile");
+        assert!(status.success());
+        let assembly = fs::read_to_string(output).expect("read assembly");
+        let mut loads: Vec<(String, usize)> = vec![];
+        for line in assembly.lines().skip_while(|line| *line != "move:").take_while(|line| line.trim() != "ret") {
+            if let Some(slot) = line.split_once(", [rbp + ").map(|(_, slot)| slot.to_string()) {
+                match loads.iter_mut().find(|(loaded_slot, _)| *loaded_slot == slot) {
+                    Some(&mut (_, ref mut count)) => *count += 1,
+                    None => loads.push((slot, 1)),
+                }
+            }
+        }
+        loads.into_iter().map(|(_, count)| count).collect()
+    };
+    // -O0 loads the parameter again after each store, while -O1 reuses the value it stored.
+    assert!(slot_loads("-O0").iter().any(|&count| count > 1));
+    assert!(slot_loads("-O1").iter().all(|&count| count == 1));
+}
+
+#[test]
+fn test_opt_levels_agree() {
+    // Every program with an expected output prints the same thing at -O0 and at -O2, so that a
+    // pass reordering or dropping a side effect is caught whatever the expected output says.
+    let _ = fs::create_dir_all("./target/opt_agree");
+    let mut files: Vec<_> = fs::read_dir("./tests").expect("read tests")
+        .map(|entry| entry.expect("entry").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "tig") &&
+            path.with_extension("stdout").exists())
+        .collect();
+    files.sort();
+    assert!(files.len() > 40);
+    for file in files {
+        let name = file.file_stem().expect("name").to_string_lossy().to_string();
+        let flags: &[&str] =
+            match name.as_str() {
+                "pointers" => &["--dialect", "unsafe"],
+                "unicode" => &["--unicode-idents"],
+                "use_library" => &["tests/library.tig"],
+                _ => &[],
+            };
+        let outputs: Vec<_> = ["-O0", "-O2"].iter()
+            .map(|opt_level| {
+                let executable = format!("target/opt_agree/{}{}", name, opt_level);
+                let status = Command::new("./target/debug/tiger")
+                    .args([opt_level, "-o", &executable])
+                    .arg(&file)
+                    .args(flags)
+                    .status()
+                    .expect("compile");
+                assert!(status.success(), "{} {}", file.display(), opt_level);
+                let mut child = Command::new(format!("./{}", executable))
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .expect("spawn");
+                let input = fs::read(file.with_extension("stdin")).unwrap_or_default();
+                child.stdin.take().expect("stdin").write_all(&input).expect("write stdin");
+                let output = child.wait_with_output().expect("run");
+                (output.status.code(), String::from_utf8_lossy(&output.stdout).to_string())
+            })
+            .collect();
+        assert_eq!(outputs[0], outputs[1], "{}", file.display());
+    }
+}
+
+#[test]
+fn test_register_pressure() {
+    let spills = |opt_level: &str| -> usize {
+        let output = Command::new("./target/debug/tiger")
+            .args([opt_level, "--stats", "--emit", "asm", "-o", "target/pressure.s", "tests/pressure.tig"])
+            .output()
+            .expect("compile");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stats = String::from_utf8_lossy(&output.stdout);
+        let line = stats.lines().find(|line| line.starts_with("pressure ")).expect("pressure");
+        line.split_whitespace().nth(5).expect("spills").parse().expect("count")
+    };
+    // Without the scheduling, the operands of the subtractions are all live at the end.
+    assert!(spills("-O0") > 0);
+    assert_eq!(spills("-O1"), 0);
+}
+
+#[test]
+fn test_stack_probe() {
+    // The program is compiled once, keeping its assembly, since the liveness of its big functions
+    // is slow in debug builds.
+    let _ = fs::remove_dir_all("./target/stack_probe");
+    fs::create_dir_all("./target/stack_probe").expect("create output directory");
+    let output = Command::new("./target/debug/tiger")
+        .args(["--keep-intermediates", "--max-frame-size", "4096", "-o", "target/stack_probe/big_frame",
+            "tests/big_frame.tig"])
+        .output()
+        .expect("compile");
+    assert!(output.status.success());
+    // Only the frame of main is bigger than the limit.
+    let warnings = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(warnings, "warning: stack frame of function `main` is 4224 bytes (limit is 4096 bytes)\n");
+
+    // The frame of main is bigger than a page, so each page is touched while the stack grows.
+    let assembly = fs::read_to_string("./target/stack_probe/big_frame.s").expect("read assembly");
+    assert!(assembly.contains("    sub rsp, 4096\n    or qword [rsp], 0\n"), "{}", assembly);
+
+    let output = Command::new("./target/stack_probe/big_frame").output().expect("run");
+    assert!(output.status.success());
+    let expected_output = fs::read_to_string("./tests/big_frame.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_compare() {
+    let _ = fs::remove_dir_all("./target/compare");
+    fs::create_dir_all("./target/compare").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--emit", "asm", "-o", "target/compare/compare.s", "tests/compare.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let assembly = fs::read_to_string("./target/compare/compare.s").expect("read assembly");
+    for setcc in ["setl al", "sete al", "setge al"] {
+        assert!(assembly.contains(setcc), "{}", assembly);
+    }
+
+    let status = Command::new("./target/debug/tiger")
+        .args(["-o", "target/compare/compare", "tests/compare.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./target/compare/compare").output().expect("run");
+    let expected_output = fs::read_to_string("./tests/compare.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_select() {
+    let _ = fs::remove_dir_all("./target/select");
+    fs::create_dir_all("./target/select").expect("create output directory");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--emit", "asm", "-o", "target/select/select.s", "tests/select.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let assembly = fs::read_to_string("./target/select/select.s").expect("read assembly");
+    assert!(assembly.contains("cmovl "), "{}", assembly);
+    assert!(assembly.contains("cmovge "), "{}", assembly);
+
+    let status = Command::new("./target/debug/tiger")
+        .args(["-o", "target/select/select", "tests/select.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let output = Command::new("./target/select/select").output().expect("run");
+    let expected_output = fs::read_to_string("./tests/select.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_verbose() {
+    let output = Command::new("./target/debug/tiger")
+        .args(["--verbose", "--emit", "asm", "tests/functions.tig"])
+        .output()
+        .expect("compile");
+    assert!(output.status.success());
+    let timings = String::from_utf8_lossy(&output.stderr);
+    let phases: Vec<_> = timings.lines()
+        .skip(1)
+        .map(|line| line.rsplit_once(' ').expect("phase time").0.trim_end())
+        .collect();
+    assert_eq!(phases, ["lexing", "parsing", "escape analysis", "semantic analysis", "canonicalization",
+        "instruction selection", "liveness", "register allocation", "assembly", "total"]);
+    remove_file("./tests/functions.s").expect("remove assembly");
+}
+
+#[test]
+fn test_trap_on_div_min() {
+    let _ = remove_file("./tests/div_min");
+    Command::new("./target/debug/tiger")
+        .args(["--trap-on-div-min", "tests/div_min.tig"])
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/div_min")
+        .output()
+        .expect("run");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "-7\n");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("tests/div_min.tig:5:12: division overflow"), "{}", errors);
+}
+
+#[test]
+fn test_stack_trace() {
+    let _ = remove_file("./tests/stack_trace");
+    Command::new("./target/debug/tiger")
+        .arg("tests/stack_trace.tig")
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/stack_trace")
+        .output()
+        .expect("run");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5\n2\n");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.starts_with("tests/stack_trace.tig:3:9: array size -1 is negative\nstack trace:\n"), "{}", errors);
+    assert!(errors.contains("    grow (tests/stack_trace.tig:6)\n    main (tests/stack_trace.tig:10)\n"), "{}", errors);
+}
+
+#[test]
+fn test_pointers() {
+    let _ = remove_file("./tests/pointers");
+    Command::new("./target/debug/tiger")
+        .args(["--dialect", "unsafe", "tests/pointers.tig"])
+        .status()
+        .expect("compile");
+    let output = Command::new("./tests/pointers")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/pointers.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_http_fetch() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut request = String::new();
+        let mut reader = BufReader::new(&mut stream);
+        // Read up to the empty line ending the headers.
+        while !request.ends_with("\n\n") {
+            reader.read_line(&mut request).expect("read request");
+        }
+        stream.write_all(b"HTTP/1.0 200 OK\n\nHello from Tiger over TCP\n").expect("write response");
+        request
+    });
+    let _ = remove_file("./tests/http_fetch");
+    let status = Command::new("./target/debug/tiger")
+        .arg("tests/http_fetch.tig")
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    let mut child = Command::new("./tests/http_fetch")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("run");
+    child.stdin.take().expect("stdin").write_all(format!("{}\n", port).as_bytes()).expect("write port");
+    let output = child.wait_with_output().expect("wait");
+    assert_eq!(server.join().expect("server"), "GET / HTTP/1.0\n\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "HTTP/1.0 200 OK\n\nHello from Tiger over TCP\n");
+}
+
+#[test]
+fn test_library() {
+    let _ = remove_file("./tests/use_library");
+    let status = Command::new("./target/debug/tiger")
+        .args(["--keep-intermediates", "tests/use_library.tig", "tests/library.tig"])
+        .status()
+        .expect("compile");
+    assert!(status.success());
+    assert!(Path::new("./tests/library.o").exists());
+    assert!(!Path::new("./tests/library").exists());
+    let output = Command::new("./tests/use_library")
+        .output()
+        .expect("run");
+    let expected_output = fs::read_to_string("./tests/use_library.stdout").expect("read");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), &*expected_output);
+}
+
+#[test]
+fn test_concurrent_assembly() {
+    let _ = fs::remove_dir_all("./target/concurrent_assembly");
+    fs::create_dir_all("./target/concurrent_assembly").expect("create output directory");
+    // The program takes longer to assemble than the library, and both fail: the diagnostics must
+    // still follow the order of the files on the command line.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--assembler", "tests/failing_assembler.sh", "--temp-dir", "target/concurrent_assembly",
+            "-o", "target/concurrent_assembly/use_library", "tests/use_library.tig", "tests/library.tig"])
+        .output()
+        .expect("compile");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    let library = errors.find("cannot assemble target/concurrent_assembly/library.s").expect("library error");
+    let program = errors.find("cannot assemble target/concurrent_assembly/use_library.s").expect("program error");
+    assert!(program < library, "{}", errors);
+    assert!(!output.status.success());
+    assert!(!Path::new("./target/concurrent_assembly/use_library").exists());
+}
+
+#[test]
+fn test_concurrent_errors() {
+    // The files are analyzed on their own threads: the errors of each of them are shown, in the
+    // order of the files on the command line.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--check", "tests/error/token.tig", "tests/error/library_variable.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    let program = errors.find("Unexpected start of token").expect("program error");
+    let library = errors.find("A library can only declare types and functions").expect("library error");
+    assert!(program < library, "{}", errors);
+
+    // A program calling a library with errors is not analyzed.
+    let output = Command::new("./target/debug/tiger")
+        .args(["--check", "tests/use_library.tig", "tests/error/library_variable.tig", "tests/library.tig"])
+        .output()
+        .expect("compile");
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(errors.matches("error:").count(), 2, "{}", errors);
+}
+
+#[test]
+fn test_reproducible_library_asm() {
+    let _ = fs::remove_dir_all("./target/reproducible");
+    let mut assemblies = vec![];
+    for directory in &["target/reproducible/first", "target/reproducible/second"] {
+        fs::create_dir_all(directory).expect("create output directory");
+        let status = Command::new("./target/debug/tiger")
+            .args(["--emit", "asm", "-o", &format!("{}/use_library", directory), "tests/use_library.tig",
+                "tests/library.tig"])
+            .status()
+            .expect("compile");
+        assert!(status.success());
+        let program = fs::read_to_string(format!("./{}/use_library.s", directory)).expect("read assembly");
+        let library = fs::read_to_string(format!("./{}/library.s", directory)).expect("read assembly");
+        assemblies.push((program, library));
+    }
+    assert_eq!(assemblies[0], assemblies[1]);
+}
+
+#[test]
+fn test_library_errors() {
+    let files = [
+        ("error/library_duplicate.tig", "library.tig", "Function `repeat` is already declared in `tests/library.tig`"),
+        ("use_library.tig", "error/library_variable.tig", "A library can only declare types and functions"),
+        ("use_library.tig", "library.frag", "library tests/library.frag must be a Tiger source file"),
+    ];
+
+    for &(program, library, message) in &files {
+        println!("{} {}", program, library);
+        let output = Command::new("./target/debug/tiger")
+            .arg(format!("tests/{}", program))
+            .arg(format!("tests/{}", library))
+            .output()
+            .expect("compile");
+        assert!(!output.status.success(), "{} {}", program, library);
+        let errors = String::from_utf8_lossy(&output.stderr);
+        assert!(errors.contains(message), "{} {}: {}", program, library, errors);
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright (c) 2019-2020 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Tasks of the repository run with `cargo xtask`:
+//!
+//! * `install [--prefix DIRECTORY]` builds the compiler and the runtime in release mode and installs
+//!   them in a sysroot: the compiler in `bin/tiger` and the runtime in
+//!   `lib/tiger/<target>/libruntime.a`, where the compiler finds it.
+
+use std::env::{self, args, consts};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const DEFAULT_PREFIX: &str = "/usr/local";
+const RUNTIME_DIRECTORY: &str = "lib/tiger";
+const RUNTIME_LIBRARY: &str = "libruntime.a";
+const USAGE: &str = "Usage: cargo xtask install [--prefix DIRECTORY]";
+
+fn main() {
+    let mut args = args().skip(1);
+    let result =
+        match args.next().as_deref() {
+            Some("install") => {
+                let prefix =
+                    match (args.next().as_deref(), args.next()) {
+                        (None, _) => Ok(PathBuf::from(DEFAULT_PREFIX)),
+                        (Some("--prefix"), Some(prefix)) => Ok(PathBuf::from(prefix)),
+                        _ => Err(USAGE.to_string()),
+                    };
+                prefix.and_then(|prefix| install(&prefix))
+            },
+            _ => Err(USAGE.to_string()),
+        };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+/// Build the compiler and the runtime in release mode and copy them in the sysroot.
+fn install(prefix: &Path) -> Result<(), String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("workspace directory");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(&cargo)
+        .args(["build", "--release", "--package", "tiger"])
+        .current_dir(root)
+        .status()
+        .map_err(|error| format!("cannot run {}: {}", cargo, error))?;
+    if !status.success() {
+        return Err("building the compiler failed".to_string());
+    }
+
+    let release = root.join("target/release");
+    let compiler = format!("tiger{}", consts::EXE_SUFFIX);
+    let runtime_directory = prefix.join(RUNTIME_DIRECTORY).join(host_target());
+    copy(&release.join(&compiler), &prefix.join("bin"), &compiler)?;
+    copy(&release.join(RUNTIME_LIBRARY), &runtime_directory, RUNTIME_LIBRARY)
+}
+
+fn copy(source: &Path, directory: &Path, name: &str) -> Result<(), String> {
+    let destination = directory.join(name);
+    fs::create_dir_all(directory)
+        .and_then(|()| fs::copy(source, &destination))
+        .map_err(|error| format!("cannot install {}: {}", destination.display(), error))?;
+    println!("installed {}", destination.display());
+    Ok(())
+}
+
+/// Triple of the target the runtime was built for, written like the compiler writes the targets.
+fn host_target() -> String {
+    let (vendor, os) =
+        match consts::OS {
+            "macos" => ("apple", "darwin"),
+            "windows" => ("pc", "windows"),
+            _ => ("unknown", "linux-gnu"),
+        };
+    format!("{}-{}-{}", consts::ARCH, vendor, os)
+}